@@ -0,0 +1,27 @@
+//! Feed arbitrary bytes to everything which parses an untrusted header, none of which may panic.
+//!
+//! Run from `lib/` with `cargo +nightly fuzz run parse_header`.
+
+#![no_main]
+
+use ed25519_dalek::SigningKey;
+use libfuzzer_sys::fuzz_target;
+use provenance_rs::{armor, binary, offline, raw_layers, split_layers, stable_document_id};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = offline::parse_header_bytes(data);
+    let _ = binary::decode_header(data);
+
+    let Ok(doc) = std::str::from_utf8(data) else {
+        return;
+    };
+    let verification_key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+    let _ = offline::verify_with_key(doc, &verification_key);
+    let _ = offline::verify_with_embedded_key(doc);
+    let _ = raw_layers(doc);
+    let _ = split_layers(doc);
+    let _ = stable_document_id(doc);
+    let _ = armor::dearmor(doc);
+    // Only resolves keys locally, so nothing is fetched over the network
+    let _ = provenance_rs::verify_all_with(doc, |_| Ok(verification_key));
+});
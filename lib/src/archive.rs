@@ -0,0 +1,181 @@
+//! Verifying provenanced files which have been bundled into a zip archive or gzipped.
+
+use crate::{outermost_signer_url, verify_chain, ProvenanceChain, VerifyOptions};
+use anyhow::anyhow;
+use std::io::{Read, Seek};
+
+/// The largest file (after decompression) which will be read out of an archive. Without a limit,
+/// a small archive could decompress to fill all of memory.
+pub const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The result of verifying a single file from an archive.
+#[derive(Debug)]
+pub enum ArchiveEntry {
+    /// The file doesn't have any provenance. This isn't an error: archives often contain files
+    /// (like a README) which were never signed.
+    Unsigned,
+    /// The file has provenance, and this is the result of verifying it.
+    Signed(ProvenanceChain),
+}
+
+/// Verify every file in a zip archive, returning the name of each file along with the result of
+/// verifying it. Directories are skipped.
+///
+/// Fails if any file is larger than [`MAX_ENTRY_SIZE`] once decompressed.
+pub fn verify_archive<R: Read + Seek>(reader: R) -> anyhow::Result<Vec<(String, ArchiveEntry)>> {
+    verify_archive_with_limit(reader, MAX_ENTRY_SIZE)
+}
+
+fn verify_archive_with_limit<R: Read + Seek>(
+    reader: R,
+    limit: u64,
+) -> anyhow::Result<Vec<(String, ArchiveEntry)>> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut entries = vec![];
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+
+        let bytes = read_limited(&mut file, limit)
+            .map_err(|e| e.context(format!("Couldn't read '{}' from the archive", file.name())))?;
+        entries.push((file.name().to_string(), verify_entry(bytes)));
+    }
+
+    Ok(entries)
+}
+
+/// Verify a single gzipped file.
+///
+/// Fails if the file is larger than [`MAX_ENTRY_SIZE`] once decompressed.
+pub fn verify_gzip<R: Read>(reader: R) -> anyhow::Result<ArchiveEntry> {
+    verify_gzip_with_limit(reader, MAX_ENTRY_SIZE)
+}
+
+fn verify_gzip_with_limit<R: Read>(reader: R, limit: u64) -> anyhow::Result<ArchiveEntry> {
+    let bytes = read_limited(flate2::read::GzDecoder::new(reader), limit)?;
+
+    Ok(verify_entry(bytes))
+}
+
+/// Read all of `reader`, failing if it holds more than `limit` bytes.
+fn read_limited(reader: impl Read, limit: u64) -> anyhow::Result<Vec<u8>> {
+    // Read one byte past the limit, to tell a file of exactly `limit` bytes from a longer one
+    let mut bytes = vec![];
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        return Err(anyhow!("File is larger than the limit of {limit} bytes"));
+    }
+
+    Ok(bytes)
+}
+
+fn verify_entry(bytes: Vec<u8>) -> ArchiveEntry {
+    // Text provenance can only be attached to text documents
+    let Ok(doc) = String::from_utf8(bytes) else {
+        return ArchiveEntry::Unsigned;
+    };
+
+    if outermost_signer_url(&doc).is_none() {
+        return ArchiveEntry::Unsigned;
+    }
+
+    ArchiveEntry::Signed(verify_chain(&doc, &VerifyOptions::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sign, test_util, VerifyStatus};
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn verify_archive_reports_each_entry() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let forger = test_util::deterministic_signing_key("forger");
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        writer.add_directory("photos/", options).unwrap();
+        writer.start_file("photos/signed.txt", options).unwrap();
+        writer
+            .write_all(sign("A signed document", &signing_key, url).as_bytes())
+            .unwrap();
+        writer.start_file("README.md", options).unwrap();
+        writer.write_all(b"Not signed\nat all").unwrap();
+        writer.start_file("photos/forged.txt", options).unwrap();
+        writer
+            .write_all(sign("A forged document", &forger, url).as_bytes())
+            .unwrap();
+        let archive = writer.finish().unwrap();
+
+        let entries = verify_archive(Cursor::new(archive.into_inner())).unwrap();
+
+        let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["photos/signed.txt", "README.md", "photos/forged.txt"]
+        );
+        let ArchiveEntry::Signed(signed) = &entries[0].1 else {
+            panic!("Expected photos/signed.txt to be signed")
+        };
+        assert_eq!(signed.layers[0].status, VerifyStatus::Verified);
+        assert!(matches!(entries[1].1, ArchiveEntry::Unsigned));
+        let ArchiveEntry::Signed(forged) = &entries[2].1 else {
+            panic!("Expected photos/forged.txt to be signed")
+        };
+        assert_eq!(forged.layers[0].status, VerifyStatus::Forged);
+    }
+
+    #[test]
+    fn verify_gzip_works() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder
+            .write_all(sign("A signed document", &signing_key, url).as_bytes())
+            .unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        let ArchiveEntry::Signed(chain) = verify_gzip(gzipped.as_slice()).unwrap() else {
+            panic!("Expected the gzipped document to be signed")
+        };
+        assert_eq!(chain.layers[0].status, VerifyStatus::Verified);
+        assert_eq!(chain.remainder, "A signed document");
+    }
+
+    #[test]
+    fn oversized_entries_are_rejected() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("small.txt", options).unwrap();
+        writer.write_all(b"Small").unwrap();
+        writer.start_file("big.txt", options).unwrap();
+        writer.write_all(&[b'a'; 100]).unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+
+        let error = verify_archive_with_limit(Cursor::new(&archive), 99).unwrap_err();
+        assert!(error.to_string().contains("big.txt"));
+        assert_eq!(
+            verify_archive_with_limit(Cursor::new(&archive), 100)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&[b'a'; 100]).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert!(verify_gzip_with_limit(gzipped.as_slice(), 99).is_err());
+        assert!(matches!(
+            verify_gzip_with_limit(gzipped.as_slice(), 100).unwrap(),
+            ArchiveEntry::Unsigned
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+//! An ASCII-armored form of a signed document, for channels which mangle emoji or binary data.
+//!
+//! The whole signed document (every header and the body) is base64-encoded and wrapped between
+//! a recognizable first and last line, like PGP armor:
+//!
+//! ```text
+//! -----BEGIN PROVENANCE DOCUMENT-----
+//! fn7wn5SPIDAuMy4wIGh0dHBzOi8vZXhhbXBsZS5jb20vbWUgT0VBZGMxWnV2MFZ5
+//! ...
+//! -----END PROVENANCE DOCUMENT-----
+//! ```
+//!
+//! The armor only changes how the document travels, not what was signed, so
+//! [`verify`](crate::verify) and the other verification functions take it off before parsing.
+//! Whitespace around the armor and between the base64 lines is ignored, so it survives
+//! re-wrapping by email clients.
+
+use alloc::{string::String, vec::Vec};
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+/// The first line of an armored document.
+pub const BEGIN: &str = "-----BEGIN PROVENANCE DOCUMENT-----";
+
+/// The last line of an armored document.
+pub const END: &str = "-----END PROVENANCE DOCUMENT-----";
+
+/// How many base64 characters go on each line of an armored document.
+const LINE_LENGTH: usize = 64;
+
+/// Returns true if `text` is an armored document (ignoring surrounding whitespace).
+pub fn is_armored(text: &str) -> bool {
+    text.trim_start().starts_with(BEGIN)
+}
+
+/// Armor `signed_doc`, which can be a text or binary signed document.
+pub fn armor(signed_doc: &[u8]) -> String {
+    let encoded = STANDARD.encode(signed_doc);
+    let mut armored = String::with_capacity(encoded.len() * 65 / 64 + BEGIN.len() + END.len() + 3);
+    armored.push_str(BEGIN);
+    armored.push('\n');
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        // base64 is ASCII, so any split is on a character boundary
+        armored.push_str(core::str::from_utf8(line).expect("base64 is ASCII"));
+        armored.push('\n');
+    }
+    armored.push_str(END);
+    armored.push('\n');
+    armored
+}
+
+/// Take the armor off `text`, returning the signed document inside it.
+pub fn dearmor(text: &str) -> anyhow::Result<Vec<u8>> {
+    let Some(rest) = text.trim().strip_prefix(BEGIN) else {
+        return Err(anyhow!("Armored document doesn't start with '{BEGIN}'"));
+    };
+    let Some(encoded) = rest.strip_suffix(END) else {
+        return Err(anyhow!("Armored document doesn't end with '{END}'"));
+    };
+    let encoded: String = encoded.split_whitespace().collect();
+
+    STANDARD
+        .decode(encoded)
+        .map_err(|e| anyhow!("Couldn't decode armored document from base64: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn armor_round_trips() {
+        for doc in [
+            &b""[..],
+            b"short",
+            "~~🔏 0.3.0 https://example.com/me c2ln 🔏~~\nline one\r\nline two\n".as_bytes(),
+            &[0x89, b'P', b'V', b'N', 0, 0xff, 0xfe],
+            &[b'x'; 1000],
+        ] {
+            let armored = armor(doc);
+            assert!(is_armored(&armored));
+            assert!(armored.is_ascii());
+            assert!(armored
+                .lines()
+                .all(|line| line.len() <= LINE_LENGTH || line == BEGIN || line == END));
+            assert_eq!(dearmor(&armored).unwrap(), doc);
+        }
+    }
+
+    #[test]
+    fn dearmor_tolerates_rewrapping() {
+        let doc = "~~🔏 0.3.0 https://example.com/me c2ln 🔏~~\nsome document".repeat(10);
+        let armored = armor(doc.as_bytes());
+        let rewrapped = format!(
+            "\n\n  {}  \n",
+            armored
+                .replace('\n', "\r\n")
+                .replacen("\r\n", "\r\n   \r\n", 3)
+        );
+        assert_eq!(dearmor(&rewrapped).unwrap(), doc.as_bytes());
+    }
+
+    #[test]
+    fn malformed_armor_is_rejected() {
+        let armored = armor(b"some document");
+        assert!(!is_armored("some document"));
+        assert!(dearmor("some document").is_err());
+        assert!(dearmor(armored.trim_end().strip_suffix(END).unwrap()).is_err());
+        assert!(dearmor(&armored.replacen('\n', "\n!!", 1)).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Signing many documents with a single signature.
+//!
+//! Signing each of 500 photos separately means 500 signatures. Instead, [`sign_batch`] builds a
+//! Merkle tree over the documents and signs only its root. Each document gets its own header,
+//! holding that one signature plus an inclusion proof (as `batch=<proof>`) which shows the
+//! document is a leaf of the signed tree. Every document can then be verified on its own: the
+//! verifier rebuilds the root from the document and its proof, and checks the signature over the
+//! root.
+//!
+//! The tree hashes leaves and inner nodes with different domain tags (like the prefixes of
+//! RFC 9162), so a document can never be passed off as an inner node. When a level has an odd number of nodes,
+//! the last one is carried up to the next level unchanged.
+
+use crate::offline::find_extension;
+use alloc::{format, vec::Vec};
+#[cfg(feature = "std")]
+use alloc::{string::String, vec};
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use sha2::{Digest, Sha256};
+
+/// The header extension holding a batch-signed document's inclusion proof.
+pub const BATCH_EXTENSION: &str = "batch";
+
+/// A fixed tag at the start of every leaf hash, distinct from [`NODE_TAG`] and from
+/// [`DOMAIN_SEPARATOR`](crate::DOMAIN_SEPARATOR).
+const LEAF_TAG: &[u8] = b"provenance-rs:batch-leaf:v1:";
+
+/// A fixed tag at the start of every inner node hash, distinct from [`LEAF_TAG`].
+const NODE_TAG: &[u8] = b"provenance-rs:batch-node:v1:";
+
+type Hash = [u8; 32];
+
+fn leaf_hash(doc: &[u8]) -> Hash {
+    Sha256::new()
+        .chain_update(LEAF_TAG)
+        .chain_update(doc)
+        .finalize()
+        .into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update(NODE_TAG)
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// Where a document sits in a batch, and the sibling hashes needed to get from it to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InclusionProof {
+    index: usize,
+    count: usize,
+    /// The siblings on the path from the leaf to the root, starting at the leaf.
+    siblings: Vec<Hash>,
+}
+
+impl InclusionProof {
+    /// Encode the proof as a single header token: `<index>.<count>.<base64 siblings>`.
+    #[cfg(feature = "std")]
+    fn to_token(&self) -> String {
+        format!(
+            "{}.{}.{}",
+            self.index,
+            self.count,
+            URL_SAFE.encode(self.siblings.concat())
+        )
+    }
+
+    fn from_token(token: &str) -> anyhow::Result<Self> {
+        let parts = token.split('.').collect::<Vec<_>>();
+        let [index, count, siblings] = parts[..] else {
+            return Err(anyhow!("Batch proof '{token}' doesn't have three parts"));
+        };
+        let (Ok(index), Ok(count)) = (index.parse(), count.parse()) else {
+            return Err(anyhow!(
+                "Couldn't parse the position in batch proof '{token}'"
+            ));
+        };
+        if index >= count {
+            return Err(anyhow!(
+                "Batch proof '{token}' is for a document outside the batch"
+            ));
+        }
+        let Ok(siblings) = URL_SAFE.decode(siblings) else {
+            return Err(anyhow!("Couldn't decode batch proof '{token}' from base64"));
+        };
+        if siblings.len() % 32 != 0 {
+            return Err(anyhow!("Batch proof '{token}' has a truncated hash"));
+        }
+
+        Ok(InclusionProof {
+            index,
+            count,
+            siblings: siblings
+                .chunks_exact(32)
+                .map(|chunk| chunk.try_into().unwrap())
+                .collect(),
+        })
+    }
+
+    /// Walk from `leaf` up to the root of the tree, or fail if the proof has the wrong number of
+    /// siblings for its position.
+    fn root(&self, leaf: Hash) -> anyhow::Result<Hash> {
+        let mut hash = leaf;
+        let mut siblings = self.siblings.iter();
+        let (mut index, mut count) = (self.index, self.count);
+        while count > 1 {
+            // The last node of an odd-sized level has no sibling, and is carried up unchanged
+            if index % 2 == 1 || index + 1 < count {
+                let Some(sibling) = siblings.next() else {
+                    return Err(anyhow!("Batch proof is missing hashes"));
+                };
+                hash = if index % 2 == 1 {
+                    node_hash(sibling, &hash)
+                } else {
+                    node_hash(&hash, sibling)
+                };
+            }
+            index /= 2;
+            count = count.div_ceil(2);
+        }
+        if siblings.next().is_some() {
+            return Err(anyhow!("Batch proof has too many hashes"));
+        }
+
+        Ok(hash)
+    }
+}
+
+/// The root of the Merkle tree over `docs`, and an inclusion proof for each document.
+#[cfg(feature = "std")]
+fn build_tree(docs: &[&[u8]]) -> (Hash, Vec<InclusionProof>) {
+    let count = docs.len();
+    let mut proofs: Vec<_> = (0..count)
+        .map(|index| InclusionProof {
+            index,
+            count,
+            siblings: vec![],
+        })
+        .collect();
+
+    let mut level: Vec<Hash> = docs.iter().map(|doc| leaf_hash(doc)).collect();
+    // Which node of the current level each document's path passes through
+    let mut positions: Vec<usize> = (0..count).collect();
+    while level.len() > 1 {
+        for (proof, position) in proofs.iter_mut().zip(&mut positions) {
+            if let Some(sibling) = level.get(*position ^ 1) {
+                proof.siblings.push(*sibling);
+            }
+            *position /= 2;
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [last] => *last,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+
+    (level.first().copied().unwrap_or_default(), proofs)
+}
+
+/// The bytes a batch signature actually signs, in place of a layer's `extensions` and `body`,
+/// if the layer was batch-signed: the extensions are just [`BATCH_EXTENSION`] and the body is
+/// the root of the tree, rebuilt from `body` and the layer's proof.
+///
+/// Returns `Ok(None)` for layers which weren't batch-signed.
+pub(crate) fn signed_root(extensions: &str, body: &[u8]) -> anyhow::Result<Option<Hash>> {
+    let Some(token) = find_extension(extensions, BATCH_EXTENSION) else {
+        return Ok(None);
+    };
+    if extensions != format!("{BATCH_EXTENSION}={token}") {
+        return Err(anyhow!(
+            "Batch-signed layers can't have other header extensions"
+        ));
+    }
+
+    InclusionProof::from_token(token)?
+        .root(leaf_hash(body))
+        .map(Some)
+}
+
+/// Sign every document in `docs` with a single signature over the root of a Merkle tree,
+/// returning a provenance header for each document (in the same order).
+///
+/// Each header can be verified against just its own document, with
+/// [`verify_parts`](crate::verify_parts), or by putting it on the line above a text document
+/// and using [`verify`](crate::verify).
+#[cfg(feature = "std")]
+pub fn sign_batch(
+    docs: &[&[u8]],
+    signing_key: &ed25519_dalek::SigningKey,
+    url: &str,
+) -> Vec<String> {
+    use crate::offline::{signature_to_b64, signed_message_with_extensions, PROVENANCE_VERSION};
+    use crate::provenance::format_header_with_extensions;
+    use ed25519_dalek::Signer;
+
+    let (root, proofs) = build_tree(docs);
+    let signature = signature_to_b64(&signing_key.sign(&signed_message_with_extensions(
+        PROVENANCE_VERSION,
+        url,
+        BATCH_EXTENSION,
+        &root,
+    )));
+
+    proofs
+        .iter()
+        .map(|proof| {
+            let extensions = format!("{BATCH_EXTENSION}={}", proof.to_token());
+            format_header_with_extensions(url, &signature, &extensions)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offline::PROVENANCE_VERSION;
+    use crate::{test_util, verify, verify_parts, verify_with, VerifyStatus};
+
+    #[test]
+    fn proofs_lead_to_the_root() {
+        for count in 1..=9 {
+            let docs: Vec<Vec<u8>> = (0..count)
+                .map(|i| format!("doc {i}").into_bytes())
+                .collect();
+            let docs: Vec<&[u8]> = docs.iter().map(|doc| doc.as_slice()).collect();
+            let (root, proofs) = build_tree(&docs);
+
+            for (doc, proof) in docs.iter().zip(&proofs) {
+                let proof = InclusionProof::from_token(&proof.to_token()).unwrap();
+                assert_eq!(proof.root(leaf_hash(doc)).unwrap(), root);
+                assert_ne!(proof.root(leaf_hash(b"other doc")).unwrap(), root);
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_and_nodes_are_hashed_apart() {
+        let (left, right) = (leaf_hash(b"left"), leaf_hash(b"right"));
+        assert_ne!(leaf_hash(&[left, right].concat()), node_hash(&left, &right));
+        assert_ne!(leaf_hash(b""), node_hash(&[0; 32], &[0; 32]));
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let docs: [&[u8]; 3] = [b"a", b"b", b"c"];
+        let (_, proofs) = build_tree(&docs);
+        let token = proofs[0].to_token();
+
+        for bad in [
+            "0.3",
+            "3.3.",
+            "x.3.",
+            "0.3.!!!",
+            "0.3.AAAA",
+            // Right hashes, wrong position, so the wrong number of hashes is needed
+            &token.replacen("0.3", "2.3", 1),
+        ] {
+            assert!(signed_root(&format!("batch={bad}"), b"a").is_err(), "{bad}");
+        }
+        assert_eq!(signed_root("", b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn batch_signed_documents_verify_independently() {
+        let key = test_util::deterministic_signing_key("photographer");
+        let url = "https://example.com/provenance/photographer";
+        let docs: [&[u8]; 5] = [
+            b"photo 1",
+            b"photo 2",
+            b"photo 3",
+            b"photo 4",
+            b"\xffphoto 5",
+        ];
+        let headers = sign_batch(&docs, &key, url);
+        assert_eq!(headers.len(), docs.len());
+
+        let resolve = |_: &str| Ok(key.verifying_key());
+        for (header, doc) in headers.iter().zip(&docs[..4]) {
+            let signed_doc = format!("{header}\n{}", std::str::from_utf8(doc).unwrap());
+            assert!(verify_with(&signed_doc, resolve).0.is_ok(), "{signed_doc}");
+            assert!(verify_with(&signed_doc.replace("photo", "fake"), resolve)
+                .0
+                .is_err());
+        }
+        // A header only verifies with its own document
+        let swapped = format!("{}\nphoto 2", headers[0]);
+        assert!(verify_with(&swapped, resolve).0.is_err());
+
+        // The signature is over the root, not the document, so it can't be lifted into an
+        // ordinary layer
+        let signature = headers[0].split(' ').nth(3).unwrap();
+        let lifted = format!("~~🔏 {PROVENANCE_VERSION} {url} {signature} 🔏~~\nphoto 1");
+        assert!(verify_with(&lifted, resolve).0.is_err());
+    }
+
+    #[test]
+    fn batch_signed_documents_verify_against_the_server() {
+        let key = test_util::beyarkay_key();
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let docs: [&[u8]; 3] = [b"first", b"second", b"\x00\xffbinary"];
+        let headers = sign_batch(&docs, &key, url);
+
+        let (result, remainder) = verify(&format!("{}\nsecond", headers[1]));
+        assert_eq!(VerifyStatus::of(&result), VerifyStatus::Verified);
+        assert_eq!(remainder, "second");
+        assert!(verify_parts(&headers[2], docs[2]).is_ok());
+        assert!(verify_parts(&headers[2], docs[0]).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! A compact binary encoding of a provenance layer.
+//!
+//! The text header (`~~🔏 <version> <url> <base64 signature> 🔏~~`) is easy for humans to read,
+//! but the emoji and the base64 signature add a fair amount of overhead when documents are only
+//! ever passed between machines. The binary header is laid out as:
+//!
+//! ```text
+//! MAGIC | varint len | version | varint len | url | varint len | signature (64 bytes) | document
+//! ```
+//!
+//! where each varint is an unsigned LEB128 integer. The magic prefix can never be the start of a
+//! text header, so the two encodings can be told apart automatically.
+
+use alloc::{string::String, vec::Vec};
+use anyhow::anyhow;
+use ed25519_dalek::Signature;
+
+/// Bytes which every binary-encoded provenance layer starts with.
+///
+/// `0x89` isn't valid as the first byte of UTF-8, so a binary layer is never confused with a text
+/// one.
+pub const MAGIC: &[u8] = b"\x89PVN";
+
+/// The fields of a binary-encoded provenance layer.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BinaryHeader {
+    pub version: String,
+    pub url: String,
+    pub signature: Signature,
+}
+
+/// Returns true if `bytes` starts with a binary-encoded provenance layer.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Encode a provenance layer (including the magic prefix) into bytes, ready to be prepended to
+/// the document.
+pub fn encode_header(version: &str, url: &str, signature: &Signature) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    for field in [version.as_bytes(), url.as_bytes(), &signature.to_bytes()] {
+        write_varint(&mut bytes, field.len());
+        bytes.extend_from_slice(field);
+    }
+    bytes
+}
+
+/// Decode a binary-encoded provenance layer, returning the header and the remainder of the
+/// document.
+pub fn decode_header(bytes: &[u8]) -> anyhow::Result<(BinaryHeader, &[u8])> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Err(anyhow!(
+            "Document doesn't start with the binary provenance magic bytes"
+        ));
+    };
+
+    let (version, rest) = read_field(rest)?;
+    let (url, rest) = read_field(rest)?;
+    let (signature, rest) = read_field(rest)?;
+
+    let Ok(version) = String::from_utf8(version.to_vec()) else {
+        return Err(anyhow!("Binary header version isn't valid UTF-8"));
+    };
+    let Ok(url) = String::from_utf8(url.to_vec()) else {
+        return Err(anyhow!("Binary header URL isn't valid UTF-8"));
+    };
+    let Ok(signature) = <&[u8; ed25519_dalek::SIGNATURE_LENGTH]>::try_from(signature) else {
+        return Err(anyhow!(
+            "Binary header signature needs to be {} bytes long, but is {} bytes long",
+            ed25519_dalek::SIGNATURE_LENGTH,
+            signature.len()
+        ));
+    };
+
+    Ok((
+        BinaryHeader {
+            version,
+            url,
+            signature: Signature::from_bytes(signature),
+        },
+        rest,
+    ))
+}
+
+/// Append `value` as an unsigned LEB128 varint.
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Read an unsigned LEB128 varint, returning it and the remaining bytes.
+fn read_varint(bytes: &[u8]) -> anyhow::Result<(usize, &[u8])> {
+    let mut value: usize = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        let shift = 7 * i as u32;
+        if shift >= usize::BITS {
+            return Err(anyhow!("Varint in binary header is too large"));
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, &bytes[i + 1..]));
+        }
+    }
+    Err(anyhow!("Binary header ended in the middle of a varint"))
+}
+
+/// Read a varint-length-prefixed field, returning it and the remaining bytes.
+fn read_field(bytes: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    let (len, rest) = read_varint(bytes)?;
+    if rest.len() < len {
+        return Err(anyhow!(
+            "Binary header field is {len} bytes long, but only {} bytes remain",
+            rest.len()
+        ));
+    }
+    Ok(rest.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, 16_384, usize::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(read_varint(&bytes).unwrap(), (value, &[][..]));
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let signature = Signature::from_bytes(&[7; ed25519_dalek::SIGNATURE_LENGTH]);
+        let mut bytes = encode_header("0.3.0", "http://localhost:8000/provenance/a", &signature);
+        bytes.extend_from_slice(b"the document");
+
+        let (header, doc) = decode_header(&bytes).unwrap();
+
+        assert_eq!(header.version, "0.3.0");
+        assert_eq!(header.url, "http://localhost:8000/provenance/a");
+        assert_eq!(header.signature, signature);
+        assert_eq!(doc, b"the document");
+    }
+
+    #[test]
+    fn truncated_header_is_an_error() {
+        let signature = Signature::from_bytes(&[7; ed25519_dalek::SIGNATURE_LENGTH]);
+        let bytes = encode_header("0.3.0", "http://localhost:8000/provenance/a", &signature);
+
+        for len in 0..bytes.len() {
+            assert!(decode_header(&bytes[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Bundling a signed document with the keys needed to verify it, for recipients who are offline.
+//!
+//! A `.provbundle` file is a signed document together with the verification key for every URL in
+//! its chain, so nothing needs to be fetched from a provenance server. It is a text file laid out
+//! as:
+//!
+//! ```text
+//! ~~🔏 <version> <packer url> <signature> 🔏~~
+//! <url> <base64 verification key>
+//! <url> <base64 verification key>
+//!
+//! <signed document>
+//! ```
+//!
+//! The list of keys is itself signed by whoever packed the bundle, and the packer's own key is
+//! always in the list. A key read from the bundle can't vouch for the bundle though, since
+//! anyone can swap the keys and re-sign the list with their own key: [`unpack`] and
+//! [`verify_bundle`] take the packer's key from the caller instead, who must already trust it
+//! (or can fetch it from the packer's provenance server with [`fetch_key`](crate::fetch_key)
+//! while still online).
+
+use crate::offline::parse_header;
+use crate::{
+    outermost_signer_url, sign, verify_with, Base64VerifyingKey, ChainLayer, ProvenanceChain,
+    VerifyError, VerifyStatus,
+};
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The conventional file extension for bundles, without the leading `.`.
+pub const BUNDLE_EXTENSION: &str = "provbundle";
+
+/// The contents of a bundle, once the signature over its keys has been checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    /// The signed document.
+    pub document: String,
+    /// The verification key for each URL, including the packer's.
+    pub keys: HashMap<String, VerifyingKey>,
+    /// The URL of whoever signed the list of keys.
+    pub packer_url: String,
+}
+
+/// Pack `document` together with `keys` into a bundle, signing the keys with `packer_key`.
+///
+/// The packer's verification key is added to the bundle under `packer_url`.
+pub fn pack(
+    document: &str,
+    keys: &HashMap<String, VerifyingKey>,
+    packer_key: &SigningKey,
+    packer_url: &str,
+) -> anyhow::Result<String> {
+    // Sorted, so that packing the same keys always gives the same bundle
+    let mut all_keys: BTreeMap<&str, VerifyingKey> =
+        keys.iter().map(|(url, key)| (url.as_str(), *key)).collect();
+    all_keys.insert(packer_url, packer_key.verifying_key());
+
+    let mut lines = vec![];
+    for (url, key) in all_keys {
+        if url.is_empty() || url.contains([' ', '\n']) {
+            return Err(anyhow!(
+                "Can't bundle a key for URL '{url}', which is empty or contains whitespace"
+            ));
+        }
+        lines.push(format!("{url} {}", URL_SAFE.encode(key.to_bytes())));
+    }
+
+    let signed_keys = sign(&lines.join("\n"), packer_key, packer_url);
+    Ok(format!("{signed_keys}\n\n{document}"))
+}
+
+/// Unpack a bundle, checking that its list of keys was signed with `packer_key`, the trusted key
+/// of whoever packed it.
+pub fn unpack(bundle: &str, packer_key: &VerifyingKey) -> anyhow::Result<Bundle> {
+    let Some((signed_keys, document)) = bundle.split_once("\n\n") else {
+        return Err(anyhow!("Bundle doesn't have a list of keys"));
+    };
+    let Some((header, key_lines)) = signed_keys.split_once('\n') else {
+        return Err(anyhow!("Bundle's list of keys isn't signed"));
+    };
+    let packer_url = parse_header(header)?.url.to_string();
+
+    let mut keys = HashMap::new();
+    for line in key_lines.lines() {
+        let Some((url, key_b64)) = line.split_once(' ') else {
+            return Err(anyhow!("Couldn't parse bundled key '{line}'"));
+        };
+        let key: VerifyingKey = Base64VerifyingKey(key_b64.to_string()).try_into()?;
+        if keys.insert(url.to_string(), key).is_some() {
+            return Err(anyhow!("Bundle has more than one key for URL '{url}'"));
+        }
+    }
+
+    if keys.get(&packer_url) != Some(packer_key) {
+        return Err(anyhow!(
+            "Bundle's list of keys wasn't packed with the trusted key for '{packer_url}'"
+        ));
+    }
+    let (result, _) = verify_with(signed_keys, |_| Ok(*packer_key));
+    result.map_err(|e| anyhow!("Bundle's list of keys could not be verified: {e}"))?;
+
+    Ok(Bundle {
+        document: document.to_string(),
+        keys,
+        packer_url,
+    })
+}
+
+/// Read and [`unpack`] the bundle at `path` (packed with `packer_key`), then verify every layer of
+/// its document against the bundled keys without touching the network.
+///
+/// Layers whose URL has no key in the bundle fail with [`VerifyError::KeyNotBundled`], and so
+/// are reported as [`VerifyStatus::Unreachable`]: there is no way to check them offline, but
+/// nothing shows they're forged either.
+pub fn verify_bundle(
+    path: impl AsRef<Path>,
+    packer_key: &VerifyingKey,
+) -> anyhow::Result<(Bundle, ProvenanceChain)> {
+    let bundle = unpack(&std::fs::read_to_string(path)?, packer_key)?;
+
+    let mut layers = vec![];
+    let mut doc = bundle.document.clone();
+    while let Some(verification_url) = outermost_signer_url(&doc) {
+        let (result, remainder) = verify_with(&doc, |url| {
+            bundle.keys.get(url).copied().ok_or_else(|| {
+                VerifyError::KeyNotBundled {
+                    url: url.to_string(),
+                }
+                .into()
+            })
+        });
+        layers.push(ChainLayer {
+            verification_url,
+            status: VerifyStatus::of(&result),
+            result,
+            fetch_duration: None,
+            from_cache: false,
+            covered_bytes: remainder.len(),
+        });
+        doc = remainder;
+    }
+
+    let chain = ProvenanceChain {
+        layers,
+        remainder: doc,
+        stopped_early: false,
+    };
+    Ok((bundle, chain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn bundles_verify_offline() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let bob = test_util::deterministic_signing_key("bob");
+        let packer = test_util::deterministic_signing_key("packer");
+        // Nothing is listening on this port, so any network access would fail
+        let alice_url = "http://localhost:1/alice";
+        let bob_url = "http://localhost:1/bob";
+
+        let doc = sign("document text here", &alice, alice_url);
+        let doc = sign(&doc, &bob, bob_url);
+        let keys = HashMap::from([
+            (alice_url.to_string(), alice.verifying_key()),
+            (bob_url.to_string(), bob.verifying_key()),
+        ]);
+        let bundle = pack(&doc, &keys, &packer, "https://packer.example").unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "bundles_verify_offline_{}.{BUNDLE_EXTENSION}",
+            rand::random::<u32>()
+        ));
+        std::fs::write(&path, &bundle).unwrap();
+        let (unpacked, chain) = verify_bundle(&path, &packer.verifying_key()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(unpacked.document, doc);
+        assert_eq!(unpacked.packer_url, "https://packer.example");
+        assert_eq!(unpacked.keys.len(), 3);
+        assert_eq!(chain.count(VerifyStatus::Verified), 2);
+        assert_eq!(chain.remainder, "document text here");
+
+        // A bundle without one of the signers' keys can't verify that layer
+        let keys = HashMap::from([(bob_url.to_string(), bob.verifying_key())]);
+        let partial = pack(&doc, &keys, &packer, "https://packer.example").unwrap();
+        std::fs::write(&path, &partial).unwrap();
+        let (_, chain) = verify_bundle(&path, &packer.verifying_key()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let statuses: Vec<_> = chain.layers.iter().map(|layer| layer.status).collect();
+        assert_eq!(
+            statuses,
+            vec![VerifyStatus::Verified, VerifyStatus::Unreachable]
+        );
+        assert!(matches!(
+            chain.layers[1].result.as_ref().unwrap_err().downcast_ref(),
+            Some(VerifyError::KeyNotBundled { url }) if url == alice_url
+        ));
+    }
+
+    #[test]
+    fn tampered_keys_are_rejected() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let mallory = test_util::deterministic_signing_key("mallory");
+        let packer = test_util::deterministic_signing_key("packer");
+        let keys = HashMap::from([("https://a.example".to_string(), alice.verifying_key())]);
+        let bundle = pack("document", &keys, &packer, "https://packer.example").unwrap();
+        let packer_key = packer.verifying_key();
+        assert!(unpack(&bundle, &packer_key).is_ok());
+
+        // Swapping in a different key breaks the packer's signature
+        let tampered = bundle.replace(
+            &URL_SAFE.encode(alice.verifying_key().to_bytes()),
+            &URL_SAFE.encode(mallory.verifying_key().to_bytes()),
+        );
+        assert!(unpack(&tampered, &packer_key).is_err());
+        assert!(unpack("no keys here", &packer_key).is_err());
+    }
+
+    #[test]
+    fn repacked_bundles_are_rejected() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let mallory = test_util::deterministic_signing_key("mallory");
+        let packer = test_util::deterministic_signing_key("packer");
+        let alice_url = "https://a.example";
+        let doc = sign("document text here", &alice, alice_url);
+        let keys = HashMap::from([(alice_url.to_string(), alice.verifying_key())]);
+        let bundle = pack(&doc, &keys, &packer, "https://packer.example").unwrap();
+
+        // Mallory forges alice's layer, then repacks the bundle under the packer's URL with her
+        // own keys, which makes a bundle that is signed consistently with itself
+        let forged = sign("forged text", &mallory, alice_url);
+        let keys = HashMap::from([(alice_url.to_string(), mallory.verifying_key())]);
+        let repacked = pack(&forged, &keys, &mallory, "https://packer.example").unwrap();
+
+        assert!(unpack(&bundle, &packer.verifying_key()).is_ok());
+        let error = unpack(&repacked, &packer.verifying_key()).unwrap_err();
+        assert!(error.to_string().contains("trusted key"), "{error}");
+        // Naming the packer's key under another URL doesn't help either
+        let keys = HashMap::from([
+            (alice_url.to_string(), mallory.verifying_key()),
+            ("https://packer.example".to_string(), packer.verifying_key()),
+        ]);
+        let repacked = pack(&forged, &keys, &mallory, "https://mallory.example").unwrap();
+        assert!(unpack(&repacked, &packer.verifying_key()).is_err());
+    }
+}
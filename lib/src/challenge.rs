@@ -0,0 +1,108 @@
+//! Proving that a signer controls their key right now, rather than at some point in the past.
+//!
+//! A signed document only shows that its signer held their key when they signed it. To check
+//! they still hold it (for example, "prove you're the real creator of this photo"), the verifier
+//! issues a fresh random [`nonce`] and the signer [`respond`]s by signing the nonce together with
+//! the hash of the document. [`verify_challenge`] then checks the response against the key served
+//! at the signer's URL.
+//!
+//! Responses are bound to both the nonce and the document, so an old response can't be replayed
+//! against a new challenge, and a response can never be passed off as a document signature (or
+//! vice versa). The verifier must only accept a response to a nonce it issued itself and hasn't
+//! accepted a response to before.
+
+use crate::offline::signature_from_b64;
+use crate::provenance::fetch_key;
+use crate::{signature_to_b64, SignerDetails, VerifyOptions};
+use anyhow::anyhow;
+use ed25519_dalek::{Signer, SigningKey};
+use sha2::{Digest, Sha256};
+
+/// A fixed tag at the start of every challenge response, distinct from
+/// [`DOMAIN_SEPARATOR`](crate::DOMAIN_SEPARATOR).
+const CHALLENGE_SEPARATOR: &[u8] = b"provenance-rs:challenge:v1:";
+
+/// A new random nonce for a challenge.
+pub fn nonce() -> [u8; 32] {
+    rand::random()
+}
+
+/// The exact bytes a signer at `url` signs to answer the challenge `nonce` for `doc`.
+fn challenge_message(doc: &[u8], nonce: &[u8; 32], url: &str) -> Vec<u8> {
+    let mut message = CHALLENGE_SEPARATOR.to_vec();
+    message.extend_from_slice(format!("{url}\n").as_bytes());
+    message.extend_from_slice(nonce);
+    message.extend_from_slice(&Sha256::digest(doc));
+    message
+}
+
+/// Answer the challenge `nonce` for `doc` as the signer at `url`, returning the base64 signature
+/// to send back to the verifier.
+pub fn respond(doc: &[u8], nonce: &[u8; 32], signing_key: &SigningKey, url: &str) -> String {
+    signature_to_b64(&signing_key.sign(&challenge_message(doc, nonce, url)))
+}
+
+/// Check that `signature_b64` answers the challenge `nonce` for `doc`, using the verification key
+/// served at `url`.
+pub fn verify_challenge(
+    doc: &[u8],
+    nonce: &[u8; 32],
+    signature_b64: &str,
+    url: &str,
+) -> anyhow::Result<SignerDetails> {
+    let signature = signature_from_b64(signature_b64)?;
+    let verification_key = fetch_key(url, &VerifyOptions::default())?;
+
+    if verification_key
+        .verify_strict(&challenge_message(doc, nonce, url), &signature)
+        .is_err()
+    {
+        return Err(anyhow!(
+            "Challenge response '{signature_b64}' could not be verified"
+        ));
+    }
+
+    Ok(SignerDetails::new(url, verification_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sign, test_util, verify};
+
+    fn beyarkay() -> SigningKey {
+        test_util::beyarkay_key()
+    }
+
+    #[test]
+    fn responses_are_bound_to_the_nonce_and_document() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = sign("document text here", &beyarkay(), url);
+        let challenge = nonce();
+        let response = respond(doc.as_bytes(), &challenge, &beyarkay(), url);
+
+        let details = verify_challenge(doc.as_bytes(), &challenge, &response, url).unwrap();
+        assert_eq!(details.verification_key, beyarkay().verifying_key());
+
+        // A response can't be replayed for another nonce or another document, and only the key
+        // served at the URL can respond
+        assert_ne!(nonce(), challenge);
+        assert!(verify_challenge(doc.as_bytes(), &nonce(), &response, url).is_err());
+        assert!(verify_challenge(b"other document", &challenge, &response, url).is_err());
+        let impostor = test_util::deterministic_signing_key("impostor");
+        let forged = respond(doc.as_bytes(), &challenge, &impostor, url);
+        assert!(verify_challenge(doc.as_bytes(), &challenge, &forged, url).is_err());
+    }
+
+    #[test]
+    fn responses_are_not_document_signatures() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let challenge = nonce();
+        let response = respond(b"document text here", &challenge, &beyarkay(), url);
+
+        let header = sign("document text here", &beyarkay(), url);
+        let signature = header.split(' ').nth(3).unwrap();
+        let spliced = header.replace(signature, &response);
+        assert!(verify(&spliced).0.is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Signing documents too large to hold in memory, a chunk at a time.
+//!
+//! A [`Signer`] hashes the document as it is fed in (with [`Signer::update`]) and signs the hash
+//! with Ed25519ph when [`Signer::finalize`]d, exactly as [`sign_with_threshold`] does for
+//! documents over the threshold. The caller decides how big the chunks are, so it can read a
+//! multi-gigabyte file piece by piece and report progress along the way.
+//!
+//! Only the header is returned: write it, a newline, and then the document, to get the same
+//! signed document [`sign_with_threshold`] would have made. The header can also be kept apart
+//! from the document and checked with [`verify_parts`](crate::verify_parts).
+//!
+//! [`sign_with_threshold`]: crate::sign_with_threshold
+
+use crate::offline::{
+    prehashed_message, signature_to_b64, ALGORITHM_EXTENSION, PREHASHED_ALGORITHM,
+    PROVENANCE_VERSION,
+};
+use crate::provenance::format_header_with_extensions;
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha512};
+
+/// The state of a document which is being signed a chunk at a time.
+pub struct Signer {
+    signing_key: SigningKey,
+    url: String,
+    extensions: String,
+    hash: Sha512,
+    length: u64,
+}
+
+impl Signer {
+    /// Start signing a document as the signer at `url`.
+    ///
+    /// The URL is part of the signed message and comes before the document, so it has to be
+    /// known before the first chunk is hashed.
+    pub fn new(signing_key: SigningKey, url: &str) -> Self {
+        let extensions = format!("{ALGORITHM_EXTENSION}={PREHASHED_ALGORITHM}");
+        Signer {
+            hash: prehashed_message(PROVENANCE_VERSION, url, &extensions, &[]),
+            signing_key,
+            url: url.to_string(),
+            extensions,
+            length: 0,
+        }
+    }
+
+    /// Add the next `chunk` of the document.
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hash.update(chunk);
+        self.length += chunk.len() as u64;
+    }
+
+    /// The number of bytes of the document added so far.
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    /// Whether nothing has been added yet.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Sign everything added so far, returning the provenance header (without a newline).
+    pub fn finalize(self) -> String {
+        let signature = self
+            .signing_key
+            .sign_prehashed(self.hash, None)
+            .expect("signing without a context can't fail");
+        format_header_with_extensions(&self.url, &signature_to_b64(&signature), &self.extensions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offline::verify_with_key;
+    use crate::{sign_with_threshold, test_util, verify, verify_parts};
+
+    #[test]
+    fn chunked_signing_matches_one_shot_signing() {
+        let key = test_util::deterministic_signing_key("chunked");
+        let url = "https://example.com/provenance/chunked";
+        let doc = "a fairly long line of text\n".repeat(1000);
+
+        for chunk_size in [1, 7, 4096, doc.len()] {
+            let mut signer = Signer::new(key.clone(), url);
+            for chunk in doc.as_bytes().chunks(chunk_size) {
+                signer.update(chunk);
+            }
+            assert_eq!(signer.len(), doc.len() as u64);
+            assert_eq!(
+                format!("{}\n{doc}", signer.finalize()),
+                sign_with_threshold(&doc, &key, url, 0)
+            );
+        }
+
+        let empty = Signer::new(key.clone(), url);
+        assert!(empty.is_empty());
+        let signed_doc = format!("{}\n", empty.finalize());
+        assert!(verify_with_key(&signed_doc, &key.verifying_key()).is_ok());
+    }
+
+    #[test]
+    fn chunked_signatures_verify_against_the_server() {
+        let key = test_util::beyarkay_key();
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let body: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+
+        let mut signer = Signer::new(key.clone(), url);
+        body.chunks(333).for_each(|chunk| signer.update(chunk));
+        let header = signer.finalize();
+
+        assert!(verify_parts(&header, &body).is_ok());
+        assert!(verify_parts(&header, &body[1..]).is_err());
+
+        let mut signer = Signer::new(key, url);
+        signer.update(b"document ");
+        signer.update(b"text here");
+        let (result, doc) = verify(&format!("{}\ndocument text here", signer.finalize()));
+        assert!(result.is_ok());
+        assert_eq!(doc, "document text here");
+    }
+}
@@ -1,10 +1,20 @@
 use colored::Colorize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use anyhow::anyhow;
-use clap::{Parser, Subcommand};
-use ed25519_dalek::SigningKey;
-use provenance_rs::{sign, verify_all, Base64SigningKey};
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use provenance_rs::archive::{verify_archive, verify_gzip, ArchiveEntry};
+use provenance_rs::git;
+use provenance_rs::{
+    fetch_key, import_keys, offline, outermost_signer_url, sign, sign_detached, verify_all_with,
+    verify_all_with_options, verify_parts, verify_parts_with, Base64SigningKey, Base64VerifyingKey,
+    SignerDetails, VerifyOptions,
+};
+use sha2::{Digest, Sha256};
 
 /// Usage:
 ///
@@ -12,8 +22,24 @@ use provenance_rs::{sign, verify_all, Base64SigningKey};
 ///     --doc <DOCUMENT_IN> \
 ///     --b64_signature <BASE64_SIGNATURE> \
 ///     --url <PROVENANCE_URL> \
+///     [--format <inline|detached|sidecar>] \
+///     [--armor] \
 ///     --out <DOCUMENT_OUT>
-/// $ pvnc verify <SIGNED_DOCUMENT>
+/// $ pvnc verify [--cache-dir <DIR> [--cache-ttl <SECONDS>] | --no-cache] <SIGNED_DOCUMENT>
+/// $ pvnc rotate-key \
+///     --old-key <OLD_KEY_FILE> \
+///     --new-key <NEW_KEY_FILE> \
+///     --url <PROVENANCE_URL> \
+///     <SIGNED_DOCUMENTS>...
+/// $ pvnc git-note \
+///     --object <GIT_OBJECT_HASH> \
+///     --signing-key <BASE64_SIGNING_KEY> \
+///     --url <PROVENANCE_URL>
+/// $ pvnc import-keys \
+///     --server <SERVER_BASE_URL> \
+///     --admin-token <ADMIN_TOKEN> \
+///     <CSV_FILE>
+/// $ pvnc completions <bash|zsh|fish|powershell|elvish>
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Cli {
@@ -36,18 +62,95 @@ enum Commands {
         /// Provenance URL from which checkers can verify that you signed this document
         #[arg(short = 'u', long)]
         url: String,
-        /// Path which the signed document will be written to
+        /// How the provenance should be written out
+        #[arg(short = 'f', long, alias = "output-format", value_enum, default_value_t = OutputFormat::Inline)]
+        format: OutputFormat,
+        /// Path which the signed document (or detached header) will be written to. Not allowed
+        /// for sidecar output, which is always written to `<DOCUMENT>.prov`
         #[arg(short = 'o', long)]
-        out: PathBuf,
+        out: Option<PathBuf>,
+        /// Base64-encode the whole signed document between BEGIN/END lines, so it survives
+        /// channels which mangle emoji. Only for the inline output format
+        #[arg(long)]
+        armor: bool,
     },
     /// Verify that a given document has provenance
     #[clap(alias = "v")]
     Verify {
-        /// Path of the document to check
+        /// Path of the document to check. A detached header in `<PATH>.prov` (from signing with
+        /// the sidecar format) is checked too
         path: PathBuf,
+        /// Directory to cache fetched verification keys in, so that later runs don't fetch them
+        /// again. Not used for .zip and .gz archives. Cached keys are only plain keys, so seals
+        /// and layers signed with a passkey don't verify with them
+        #[arg(long)]
+        cache_dir: Option<PathBuf>,
+        /// How long (in seconds) a cached verification key is used before being fetched again
+        #[arg(long, default_value_t = 24 * 60 * 60)]
+        cache_ttl: u64,
+        /// Fetch every verification key, ignoring (and not writing to) --cache-dir
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Replace your outermost layer of provenance on each document with one signed by a new key.
+    /// Documents are only rewritten if every one of them was signed with the old key.
+    RotateKey {
+        /// File containing the old signing key (base64 encoded)
+        #[arg(long)]
+        old_key: PathBuf,
+        /// File containing the new signing key (base64 encoded)
+        #[arg(long)]
+        new_key: PathBuf,
+        /// Provenance URL from which checkers can verify the new key
+        #[arg(short = 'u', long)]
+        url: String,
+        /// Documents to re-sign, in place
+        #[arg(required = true)]
+        documents: Vec<PathBuf>,
+    },
+    /// Sign a git object, printing a line for its note under refs/notes/provenance. Add it with
+    /// `git notes --ref=provenance append --file=- <OBJECT>`
+    GitNote {
+        /// Full hash of the blob or commit to sign
+        #[arg(long)]
+        object: String,
+        /// Signing key (base64 encoded)
+        #[arg(short = 'k', long)]
+        signing_key: String,
+        /// Provenance URL from which checkers can verify that you signed this object
+        #[arg(short = 'u', long)]
+        url: String,
+    },
+    /// Import existing signing keys into a provenance server, from a CSV file with a
+    /// `username,signing_key` row per signer. Nothing is imported unless every row is valid
+    ImportKeys {
+        /// Base URL of the provenance server, like `http://localhost:8000`
+        #[arg(short = 's', long)]
+        server: String,
+        /// The server's admin token
+        #[arg(long)]
+        admin_token: String,
+        /// CSV file of usernames and their (base64 encoded) signing keys
+        csv: PathBuf,
+    },
+    /// Print a tab-completion script for `pvnc` to stdout, for example with
+    /// `pvnc completions bash > ~/.local/share/bash-completion/completions/pvnc`
+    Completions {
+        /// Shell to generate the completion script for
+        shell: Shell,
     },
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Write the provenance header and the document together to `--out`
+    Inline,
+    /// Write only the provenance header to `--out`
+    Detached,
+    /// Write only the provenance header to `<DOCUMENT>.prov`, leaving the document untouched
+    Sidecar,
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
@@ -56,10 +159,31 @@ fn main() -> anyhow::Result<()> {
             document,
             signing_key,
             url,
+            format,
             out,
+            armor,
         } => {
+            if armor && format != OutputFormat::Inline {
+                return Err(anyhow!(
+                    "--armor can only be used with the inline output format"
+                ));
+            }
+            let out = output_path(&document, format, out)?;
             let doc_string = std::fs::read_to_string(document.clone())?;
-            let output = sign_string(doc_string, Base64SigningKey(signing_key), &url)?;
+            let output = match format {
+                OutputFormat::Inline => {
+                    let signed_doc = sign_string(doc_string, Base64SigningKey(signing_key), &url)?;
+                    if armor {
+                        provenance_rs::armor::armor(signed_doc.as_bytes())
+                    } else {
+                        signed_doc
+                    }
+                }
+                OutputFormat::Detached | OutputFormat::Sidecar => {
+                    let signing_key: SigningKey = Base64SigningKey(signing_key).try_into()?;
+                    format!("{}\n", sign_detached(&doc_string, &signing_key, &url))
+                }
+            };
             std::fs::write(out.clone(), output)?;
             eprintln!(
                 "[{}] added provenance to {document:?} {}",
@@ -67,50 +191,42 @@ fn main() -> anyhow::Result<()> {
                 format!("(output written to {out:?})").dimmed()
             );
         }
-        Commands::Verify { path } => {
-            let signed_doc = std::fs::read_to_string(path.clone())?;
-            let verifications = verify_all(&signed_doc);
-            let num_verified = verifications.0.iter().filter(|v| v.is_ok()).count();
-            let total = verifications.0.len();
-
-            if total == 1 {
-                if let Ok(signer_details) = &verifications.0[0] {
-                    eprintln!(
-                        "[{}] '{}' has confirmed authorship of {path:?}",
-                        "Success".green().bold(),
-                        signer_details.verification_url,
-                    );
-                } else {
-                    eprintln!(
-                        "[{}] couldn't verify {path:?} with provenance server",
-                        "Failure".red().bold(),
-                    )
+        Commands::Verify {
+            path,
+            cache_dir,
+            cache_ttl,
+            no_cache,
+        } => {
+            let cache = cache_dir.filter(|_| !no_cache).map(|dir| KeyCache {
+                dir,
+                ttl: Duration::from_secs(cache_ttl),
+            });
+            let all_verified = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("zip") => {
+                    let entries = verify_archive(std::fs::File::open(&path)?)?;
+                    let mut all_verified = true;
+                    for (name, entry) in entries {
+                        all_verified &= report_archive_entry(&path.join(name), entry);
+                    }
+                    all_verified
                 }
-            } else {
-                eprintln!(
-                    "[{}] {}/{} ({:.2}%) provenance servers have confirmed authorship of '{}'",
-                    "Information".blue().bold(),
-                    num_verified,
-                    total,
-                    (num_verified as f64 / total as f64) * 100.0,
-                    path.to_string_lossy(),
-                );
-                for verification in verifications.0 {
-                    if let Ok(signer_details) = verification {
+                Some("gz") => {
+                    report_archive_entry(&path, verify_gzip(std::fs::File::open(&path)?)?)
+                }
+                _ => {
+                    let mut all_verified = true;
+                    for (source, verifications) in verify_file(&path, cache.as_ref())? {
                         eprintln!(
-                            "[{}] '{}' has confirmed authorship of {path:?}",
-                            "Success".green().bold(),
-                            signer_details.verification_url,
+                            "[{}] checking {source} provenance of {path:?}",
+                            "Information".blue().bold(),
                         );
-                    } else {
-                        eprintln!(
-                            "[{}] couldn't verify {path:?} with provenance server",
-                            "Failure".red().bold(),
-                        )
+                        all_verified &= report_verifications(&path, &verifications);
                     }
+                    all_verified
                 }
-            }
-            if total == num_verified {
+            };
+
+            if all_verified {
                 return Ok(());
             } else {
                 return Err(anyhow!(
@@ -119,18 +235,321 @@ fn main() -> anyhow::Result<()> {
                 ));
             }
         }
+        Commands::RotateKey {
+            old_key,
+            new_key,
+            url,
+            documents,
+        } => {
+            let old_key = read_signing_key(&old_key)?;
+            let new_key = read_signing_key(&new_key)?;
+
+            // Check every document before writing any of them, so that a document which wasn't
+            // signed with the old key doesn't leave the set half-rotated.
+            let mut rotated = vec![];
+            for document in &documents {
+                let signed_doc = std::fs::read_to_string(document)?;
+                let output = rotate_key_string(&signed_doc, &old_key, &new_key, &url)
+                    .map_err(|e| anyhow!("Refusing to rotate {document:?}: {e}"))?;
+                rotated.push((document, output));
+            }
+
+            for (document, output) in rotated {
+                std::fs::write(document, output)?;
+                eprintln!(
+                    "[{}] re-signed {document:?} with the new key",
+                    "Success".green().bold(),
+                );
+            }
+        }
+        Commands::GitNote {
+            object,
+            signing_key,
+            url,
+        } => {
+            let signing_key: SigningKey = Base64SigningKey(signing_key).try_into()?;
+            print!("{}", git::git_note(&object, &signing_key, &url)?);
+        }
+        Commands::ImportKeys {
+            server,
+            admin_token,
+            csv,
+        } => {
+            let csv =
+                std::fs::read_to_string(&csv).map_err(|e| anyhow!("Couldn't read {csv:?}: {e}"))?;
+            let client = reqwest::blocking::Client::new();
+            let imported = import_keys(&server, &admin_token, &csv, &client)?;
+            eprintln!(
+                "[{}] imported {imported} signing keys into {server}",
+                "Success".green().bold(),
+            );
+        }
+        Commands::Completions { shell } => {
+            print!("{}", completion_script(shell));
+        }
     };
 
     Ok(())
 }
 
+/// Where the provenance of a file on disk was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ProvenanceSource {
+    /// In the file itself
+    Inline,
+    /// In a detached header in `<FILE>.prov`, see [`sidecar_path`]
+    Sidecar,
+}
+
+impl std::fmt::Display for ProvenanceSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProvenanceSource::Inline => write!(f, "inline"),
+            ProvenanceSource::Sidecar => write!(f, "sidecar"),
+        }
+    }
+}
+
+/// Verification keys cached on disk by `pvnc verify --cache-dir`, one file per URL.
+struct KeyCache {
+    dir: PathBuf,
+    /// How long after a key is cached it is fetched again
+    ttl: Duration,
+}
+
+impl KeyCache {
+    /// The file caching the key served at `url`, named by the URL's hash so that any URL makes a
+    /// valid file name.
+    fn path(&self, url: &str) -> PathBuf {
+        let hash: String = Sha256::digest(url.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        self.dir.join(hash)
+    }
+
+    /// The key served at `url`, from the cache if it was cached less than `ttl` ago, otherwise
+    /// from `fetch` (and then cached).
+    fn resolve(
+        &self,
+        url: &str,
+        fetch: impl Fn(&str) -> anyhow::Result<VerifyingKey>,
+    ) -> anyhow::Result<VerifyingKey> {
+        if let Some(key) = self.cached(url) {
+            return Ok(key);
+        }
+
+        let key = fetch(url)?;
+        let cached = std::fs::create_dir_all(&self.dir)
+            .and_then(|()| std::fs::write(self.path(url), URL_SAFE.encode(key.as_bytes())));
+        if let Err(e) = cached {
+            eprintln!(
+                "[{}] couldn't cache the verification key for '{url}' in {:?}: {e}",
+                "Warning".yellow().bold(),
+                self.dir,
+            );
+        }
+        Ok(key)
+    }
+
+    /// The cached key for `url`, if there's one which is still fresh. Unreadable cache files are
+    /// ignored, and overwritten once the key is fetched again.
+    fn cached(&self, url: &str) -> Option<VerifyingKey> {
+        let path = self.path(url);
+        let age = SystemTime::now()
+            .duration_since(std::fs::metadata(&path).ok()?.modified().ok()?)
+            .unwrap_or_default();
+        if age >= self.ttl {
+            return None;
+        }
+
+        let key_b64 = std::fs::read_to_string(path).ok()?;
+        Base64VerifyingKey(key_b64.trim().to_string())
+            .try_into()
+            .ok()
+    }
+}
+
+/// Verify the provenance of the file at `path`, both inline and in its sidecar if it has one,
+/// getting verification keys through `cache` if there is one.
+///
+/// Without a cache, the file is verified exactly as [`verify_all`](provenance_rs::verify_all)
+/// would, seals and all. Keys from the cache are only plain keys, so layers which need more than
+/// a key (like seals and passkey layers) fail to verify when there is one.
+///
+/// Inline provenance is always checked when there's no sidecar, so that an unsigned file without
+/// a sidecar is reported as unverified.
+fn verify_file(
+    path: &Path,
+    cache: Option<&KeyCache>,
+) -> anyhow::Result<Vec<(ProvenanceSource, Vec<anyhow::Result<SignerDetails>>)>> {
+    let options = VerifyOptions::default();
+    let resolve = |cache: &KeyCache, url: &str| cache.resolve(url, |url| fetch_key(url, &options));
+    let document = std::fs::read(path)?;
+    let sidecar_header = match std::fs::read_to_string(sidecar_path(path)) {
+        Ok(header) => Some(header),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let has_inline = std::str::from_utf8(&document).is_ok_and(|document| {
+        outermost_signer_url(document).is_some() || provenance_rs::armor::is_armored(document)
+    });
+
+    let mut sources = vec![];
+    if has_inline || sidecar_header.is_none() {
+        let signed_doc = String::from_utf8(document.clone())?;
+        let verifications = match cache {
+            Some(cache) => verify_all_with(&signed_doc, |url| resolve(cache, url)).0,
+            None => verify_all_with_options(&signed_doc, &options).0,
+        };
+        sources.push((ProvenanceSource::Inline, verifications));
+    }
+    if let Some(header) = sidecar_header {
+        let verification = match cache {
+            Some(cache) => verify_parts_with(&header, &document, |url| resolve(cache, url)),
+            None => verify_parts(&header, &document),
+        };
+        sources.push((ProvenanceSource::Sidecar, vec![verification]));
+    }
+
+    Ok(sources)
+}
+
+/// Print the result of verifying each layer of provenance on `path`, returning true if every layer
+/// was verified.
+fn report_verifications(path: &Path, verifications: &[anyhow::Result<SignerDetails>]) -> bool {
+    let num_verified = verifications.iter().filter(|v| v.is_ok()).count();
+    let total = verifications.len();
+
+    if total == 1 {
+        if let Ok(signer_details) = &verifications[0] {
+            eprintln!(
+                "[{}] '{}' has confirmed authorship of {path:?}",
+                "Success".green().bold(),
+                signer_details.verification_url,
+            );
+        } else {
+            eprintln!(
+                "[{}] couldn't verify {path:?} with provenance server",
+                "Failure".red().bold(),
+            )
+        }
+    } else {
+        eprintln!(
+            "[{}] {}/{} ({:.2}%) provenance servers have confirmed authorship of '{}'",
+            "Information".blue().bold(),
+            num_verified,
+            total,
+            (num_verified as f64 / total as f64) * 100.0,
+            path.to_string_lossy(),
+        );
+        for verification in verifications {
+            if let Ok(signer_details) = verification {
+                eprintln!(
+                    "[{}] '{}' has confirmed authorship of {path:?}",
+                    "Success".green().bold(),
+                    signer_details.verification_url,
+                );
+            } else {
+                eprintln!(
+                    "[{}] couldn't verify {path:?} with provenance server",
+                    "Failure".red().bold(),
+                )
+            }
+        }
+    }
+
+    total == num_verified
+}
+
+/// Print the result of verifying a file from an archive, returning false if it has provenance
+/// which couldn't be verified.
+fn report_archive_entry(path: &Path, entry: ArchiveEntry) -> bool {
+    match entry {
+        ArchiveEntry::Unsigned => {
+            eprintln!(
+                "[{}] {path:?} has no provenance",
+                "Information".blue().bold(),
+            );
+            true
+        }
+        ArchiveEntry::Signed(chain) => {
+            let verifications: Vec<_> =
+                chain.layers.into_iter().map(|layer| layer.result).collect();
+            report_verifications(path, &verifications)
+        }
+    }
+}
+
+/// Read a base64 encoded signing key from a file, ignoring surrounding whitespace.
+fn read_signing_key(path: &Path) -> anyhow::Result<SigningKey> {
+    let base64_signing_key = std::fs::read_to_string(path)?;
+    Base64SigningKey(base64_signing_key.trim().to_string()).try_into()
+}
+
+/// Strip the outermost layer of provenance (which must have been signed with `old_key`) from
+/// `signed_doc`, and sign what's left with `new_key` instead.
+fn rotate_key_string(
+    signed_doc: &str,
+    old_key: &SigningKey,
+    new_key: &SigningKey,
+    url: &str,
+) -> anyhow::Result<String> {
+    let (_header, doc) = offline::verify_with_key(signed_doc, &old_key.verifying_key())
+        .map_err(|e| anyhow!("outermost layer wasn't signed with the old key ({e})"))?;
+
+    Ok(sign(doc, new_key, url))
+}
+
+/// Path that a sidecar provenance header for `document` is written to.
+fn sidecar_path(document: &Path) -> PathBuf {
+    let mut path = document.as_os_str().to_owned();
+    path.push(".prov");
+    PathBuf::from(path)
+}
+
+/// Work out where the output of `pvnc sign` should go, checking that `--out` makes sense for the
+/// requested output format.
+fn output_path(
+    document: &Path,
+    format: OutputFormat,
+    out: Option<PathBuf>,
+) -> anyhow::Result<PathBuf> {
+    match (format, out) {
+        (OutputFormat::Inline, Some(out)) => Ok(out),
+        (OutputFormat::Detached, Some(out)) => {
+            if out == document {
+                return Err(anyhow!(
+                    "Refusing to overwrite {document:?} with its own detached provenance header"
+                ));
+            }
+            Ok(out)
+        }
+        (OutputFormat::Inline | OutputFormat::Detached, None) => Err(anyhow!(
+            "--out is required when using the {format:?} output format"
+        )),
+        (OutputFormat::Sidecar, Some(out)) => Err(anyhow!(
+            "--out cannot be used with the sidecar output format (output would go to {:?}, not {out:?})",
+            sidecar_path(document)
+        )),
+        (OutputFormat::Sidecar, None) => Ok(sidecar_path(document)),
+    }
+}
+
+/// The tab-completion script for `pvnc` in the given shell.
+fn completion_script(shell: Shell) -> String {
+    let mut script = vec![];
+    clap_complete::generate(shell, &mut Cli::command(), "pvnc", &mut script);
+    String::from_utf8_lossy(&script).into_owned()
+}
+
 fn sign_string(
     document: String,
     base64_signing_key: Base64SigningKey,
     url: &str,
 ) -> anyhow::Result<String> {
     let signing_key: SigningKey = base64_signing_key.try_into()?;
-    Ok(sign(&document, signing_key, url))
+    Ok(sign(&document, &signing_key, url))
 }
 
 #[cfg(test)]
@@ -148,7 +567,220 @@ mod tests {
         let provenance_version: &str = env!("CARGO_PKG_VERSION");
         assert_eq!(
             signed_string,
-            format!("~~🔏 {provenance_version} http://localhost:8000/provenance/beyarkay 01_e1TwyaDlWnvv7DO9KewhqsfFHP-mAMy74oUwjqB9Vpxa8kHNDg1SRFotz14bIwwws997HICGf2A5Ab98MBg== 🔏~~\nSome document that I definitely wrote")
+            format!("~~🔏 {provenance_version} http://localhost:8000/provenance/beyarkay OEAdc1Zuv0VypaZvDysTg_eMOc3s9Zb9LIZVu8XPXjThhHVxZKY0-IIXPdcvqL6hfCLMj2v5W43GDKi2WS-5Ag== 🔏~~\nSome document that I definitely wrote")
             );
     }
+
+    #[test]
+    fn output_path_validates_out() {
+        let document = PathBuf::from("photo.jpg");
+        let out = PathBuf::from("signed.jpg");
+
+        assert_eq!(
+            output_path(&document, OutputFormat::Inline, Some(out.clone())).unwrap(),
+            out
+        );
+        assert_eq!(
+            output_path(&document, OutputFormat::Detached, Some(out.clone())).unwrap(),
+            out
+        );
+        assert_eq!(
+            output_path(&document, OutputFormat::Sidecar, None).unwrap(),
+            PathBuf::from("photo.jpg.prov")
+        );
+
+        assert!(output_path(&document, OutputFormat::Inline, None).is_err());
+        assert!(output_path(&document, OutputFormat::Detached, None).is_err());
+        assert!(output_path(&document, OutputFormat::Detached, Some(document.clone())).is_err());
+        assert!(output_path(&document, OutputFormat::Sidecar, Some(out)).is_err());
+    }
+
+    #[test]
+    fn rotate_key_string_resigns_with_new_key() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let old_key = provenance_rs::test_util::deterministic_signing_key("old");
+        let new_key = provenance_rs::test_util::deterministic_signing_key("new");
+        let inner_doc = sign("Some document", &old_key, "http://example.com/someone");
+        let signed_doc = sign(&inner_doc, &old_key, url);
+
+        let rotated = rotate_key_string(&signed_doc, &old_key, &new_key, url).unwrap();
+
+        // Only the outermost layer is replaced
+        assert_eq!(rotated, sign(&inner_doc, &new_key, url));
+
+        // Rotating again with the old key is refused, since the document is now signed with the
+        // new key
+        assert!(rotate_key_string(&rotated, &old_key, &new_key, url).is_err());
+        // As are unsigned documents
+        assert!(rotate_key_string("Some document", &old_key, &new_key, url).is_err());
+    }
+
+    #[test]
+    fn completion_scripts_cover_every_shell() {
+        for &shell in Shell::value_variants() {
+            let script = completion_script(shell);
+            assert!(script.contains("pvnc"), "{shell}: {script}");
+            assert!(script.contains("rotate-key"), "{shell}: {script}");
+        }
+    }
+
+    #[test]
+    fn verify_file_finds_inline_and_sidecar_provenance() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = provenance_rs::test_util::beyarkay_key();
+        let dir = std::env::temp_dir().join(format!("pvnc_verify_file_{}", rand::random::<u32>()));
+        std::fs::create_dir(&dir).unwrap();
+        let sources = |path: &Path| -> Vec<(ProvenanceSource, bool)> {
+            verify_file(path, None)
+                .unwrap()
+                .into_iter()
+                .map(|(source, verifications)| {
+                    (source, verifications.iter().all(|result| result.is_ok()))
+                })
+                .collect()
+        };
+
+        let inline = dir.join("inline.txt");
+        std::fs::write(&inline, sign("document", &signing_key, url)).unwrap();
+        assert_eq!(sources(&inline), vec![(ProvenanceSource::Inline, true)]);
+
+        let detached = dir.join("detached.txt");
+        std::fs::write(&detached, "document").unwrap();
+        let header = sign_detached("document", &signing_key, url);
+        std::fs::write(sidecar_path(&detached), format!("{header}\n")).unwrap();
+        assert_eq!(sources(&detached), vec![(ProvenanceSource::Sidecar, true)]);
+
+        // Both are checked (and can disagree) when a signed document also has a sidecar
+        std::fs::write(sidecar_path(&inline), format!("{header}\n")).unwrap();
+        assert_eq!(
+            sources(&inline),
+            vec![
+                (ProvenanceSource::Inline, true),
+                (ProvenanceSource::Sidecar, false)
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn key_cache_reuses_fresh_keys() {
+        let key = provenance_rs::test_util::deterministic_signing_key("cached").verifying_key();
+        let dir = std::env::temp_dir().join(format!("pvnc_key_cache_{}", rand::random::<u32>()));
+        let fetches = std::cell::Cell::new(0);
+        let fetch = |_: &str| {
+            fetches.set(fetches.get() + 1);
+            Ok(key)
+        };
+        let url = "http://example.com/provenance/cached";
+        let cache = KeyCache {
+            dir: dir.join("keys"),
+            ttl: Duration::from_secs(60),
+        };
+
+        assert_eq!(cache.resolve(url, fetch).unwrap(), key);
+        assert_eq!(cache.resolve(url, fetch).unwrap(), key);
+        assert_eq!(fetches.get(), 1);
+        // A different URL has its own entry
+        cache.resolve("http://example.com/other", fetch).unwrap();
+        assert_eq!(fetches.get(), 2);
+
+        // Stale or corrupt entries are fetched again
+        let stale = KeyCache {
+            dir: dir.join("keys"),
+            ttl: Duration::ZERO,
+        };
+        stale.resolve(url, fetch).unwrap();
+        assert_eq!(fetches.get(), 3);
+        std::fs::write(cache.path(url), "not a key").unwrap();
+        assert_eq!(cache.resolve(url, fetch).unwrap(), key);
+        assert_eq!(fetches.get(), 4);
+        cache.resolve(url, fetch).unwrap();
+        assert_eq!(fetches.get(), 4);
+
+        // Failed fetches aren't cached
+        assert!(cache
+            .resolve("http://example.com/down", |_| Err(anyhow!("down")))
+            .is_err());
+        assert!(!cache.path("http://example.com/down").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_file_uses_the_key_cache() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = provenance_rs::test_util::beyarkay_key();
+        let dir =
+            std::env::temp_dir().join(format!("pvnc_cached_verify_{}", rand::random::<u32>()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("signed.txt");
+        std::fs::write(&path, sign("document", &signing_key, url)).unwrap();
+        let cache = KeyCache {
+            dir: dir.join("cache"),
+            ttl: Duration::from_secs(60),
+        };
+
+        let verified = |cache| {
+            verify_file(&path, cache).unwrap()[0]
+                .1
+                .iter()
+                .all(|result| result.is_ok())
+        };
+        assert!(verified(Some(&cache)));
+        assert!(cache.path(url).exists());
+
+        // A cached key is trusted as-is, so a poisoned cache shows it's really used
+        let other = provenance_rs::test_util::deterministic_signing_key("other").verifying_key();
+        std::fs::write(cache.path(url), URL_SAFE.encode(other.as_bytes())).unwrap();
+        assert!(!verified(Some(&cache)));
+        assert!(verified(None));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_file_dearmors_documents() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = provenance_rs::test_util::beyarkay_key();
+        let signed_doc = sign("document", &signing_key, url);
+        let path = std::env::temp_dir().join(format!("pvnc_armored_{}.txt", rand::random::<u32>()));
+        std::fs::write(&path, provenance_rs::armor::armor(signed_doc.as_bytes())).unwrap();
+
+        let sources = verify_file(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(
+            sources[0].1[0].as_ref().unwrap().verification_url,
+            url.to_string()
+        );
+    }
+
+    #[test]
+    fn verify_file_understands_seals() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = provenance_rs::test_util::beyarkay_key();
+        let sealed = provenance_rs::seal(&sign("document", &signing_key, url), &signing_key, url);
+        let dir = std::env::temp_dir().join(format!("pvnc_sealed_{}", rand::random::<u32>()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("sealed.txt");
+        std::fs::write(&path, sealed).unwrap();
+
+        // The seal covers the inner layer, so it's the only one verified
+        let sources = verify_file(&path, None).unwrap();
+        assert_eq!(sources.len(), 1);
+        let (source, verifications) = &sources[0];
+        assert_eq!(*source, ProvenanceSource::Inline);
+        assert_eq!(verifications.len(), 1);
+        assert_eq!(verifications[0].as_ref().unwrap().verification_url, url);
+
+        // Cached keys are only plain keys, which can't verify a seal
+        let cache = KeyCache {
+            dir: dir.join("cache"),
+            ttl: Duration::from_secs(60),
+        };
+        assert!(verify_file(&path, Some(&cache)).unwrap()[0].1[0].is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,175 @@
+//! Tools for analysing documents after the fact, without verifying them.
+
+use crate::offline::{parse_header, parse_header_with_markers, split_header, ParsedHeader};
+use crate::provenance::{SEAL_POSTAMBLE, SEAL_PREAMBLE};
+
+/// The result of comparing an original document with a suspect copy of it, see
+/// [`detect_stripping`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrippingReport {
+    /// Whether both documents have the same content once every provenance header is removed.
+    pub same_root: bool,
+    /// Whether every layer of the original is still present, unchanged, as the innermost layers
+    /// of the suspect. This is what legitimately building on the original looks like.
+    pub original_chain_preserved: bool,
+    /// The URLs of the original's signers whose layers are missing from the suspect.
+    pub stripped_signers: Vec<String>,
+    /// The URLs of the suspect's signers who didn't sign the original.
+    pub added_signers: Vec<String>,
+}
+
+impl StrippingReport {
+    /// True if the suspect has the original's content but not the original's provenance: someone
+    /// may have removed the provenance and re-signed the document as their own work.
+    pub fn is_suspicious(&self) -> bool {
+        self.same_root && !self.original_chain_preserved
+    }
+}
+
+/// Compare an original provenanced document with a suspect document, to detect whether the
+/// suspect is the original's content with its provenance removed (and possibly re-signed by
+/// someone else).
+///
+/// Only the headers are compared: nothing is verified and nothing is fetched, so verify both
+/// documents separately before trusting who signed them.
+pub fn detect_stripping(original: &str, suspect: &str) -> StrippingReport {
+    let (original_headers, original_root) = peel_headers(original);
+    let (suspect_headers, suspect_root) = peel_headers(suspect);
+
+    let urls = |headers: &[ParsedHeader<'_>]| -> Vec<String> {
+        headers
+            .iter()
+            .map(|header| header.url.to_string())
+            .collect()
+    };
+    let original_urls = urls(&original_headers);
+    let suspect_urls = urls(&suspect_headers);
+
+    StrippingReport {
+        same_root: original_root == suspect_root,
+        original_chain_preserved: suspect_headers.ends_with(&original_headers),
+        stripped_signers: original_headers
+            .iter()
+            .filter(|header| !suspect_headers.contains(header))
+            .map(|header| header.url.to_string())
+            .collect(),
+        added_signers: suspect_urls
+            .into_iter()
+            .filter(|url| !original_urls.contains(url))
+            .collect(),
+    }
+}
+
+/// Whether `after` is exactly `before` with one more layer of provenance (an ordinary header or a
+/// seal) added on top, as it should be after countersigning.
+///
+/// The content under the new layer must be byte-for-byte identical to `before`, so this catches a
+/// countersigner who altered the document while claiming to only add their signature, even in
+/// ways which leave `before`'s own layers verifying. Like [`detect_stripping`], nothing is
+/// verified, so verify `after` separately before trusting who added the layer.
+pub fn verify_content_preserved(before: &str, after: &str) -> bool {
+    split_header(after).is_some_and(|(header, rest)| {
+        let is_header = parse_header(header).is_ok()
+            || parse_header_with_markers(header, SEAL_PREAMBLE, SEAL_POSTAMBLE).is_ok();
+        is_header && rest == before
+    })
+}
+
+/// Split the provenance headers (ordinary and seal) off the top of `doc`, returning them from the
+/// outermost to the innermost along with the document underneath.
+fn peel_headers(mut doc: &str) -> (Vec<ParsedHeader<'_>>, &str) {
+    let mut headers = vec![];
+    while let Some((first, rest)) = split_header(doc) {
+        let Ok(header) = parse_header(first)
+            .or_else(|_| parse_header_with_markers(first, SEAL_PREAMBLE, SEAL_POSTAMBLE))
+        else {
+            break;
+        };
+        headers.push(header);
+        doc = rest;
+    }
+    (headers, doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{seal, sign, test_util};
+
+    #[test]
+    fn building_on_the_original_is_not_stripping() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let bob = test_util::deterministic_signing_key("bob");
+        let original = sign("the photo", &alice, "https://a.example/alice");
+        let edited = sign(&original, &bob, "https://b.example/bob");
+
+        let report = detect_stripping(&original, &edited);
+        assert!(report.same_root);
+        assert!(report.original_chain_preserved);
+        assert!(report.stripped_signers.is_empty());
+        assert_eq!(report.added_signers, vec!["https://b.example/bob"]);
+        assert!(!report.is_suspicious());
+
+        // A different document signed by someone else isn't suspicious either
+        let unrelated = sign("another photo", &bob, "https://b.example/bob");
+        assert!(!detect_stripping(&original, &unrelated).is_suspicious());
+    }
+
+    #[test]
+    fn re_signing_stripped_content_is_suspicious() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let mallory = test_util::deterministic_signing_key("mallory");
+        let original = sign(
+            &sign("the photo", &alice, "https://a.example/alice"),
+            &alice,
+            "https://a.example/alice-editor",
+        );
+        let laundered = sign("the photo", &mallory, "https://m.example/mallory");
+
+        let report = detect_stripping(&original, &laundered);
+        assert!(report.same_root);
+        assert!(!report.original_chain_preserved);
+        assert_eq!(
+            report.stripped_signers,
+            vec!["https://a.example/alice-editor", "https://a.example/alice"]
+        );
+        assert_eq!(report.added_signers, vec!["https://m.example/mallory"]);
+        assert!(report.is_suspicious());
+
+        // Removing provenance without re-signing is just as suspicious, as is hiding it in a seal
+        assert!(detect_stripping(&original, "the photo").is_suspicious());
+        let sealed = seal("the photo", &mallory, "https://m.example/mallory");
+        assert!(detect_stripping(&original, &sealed).is_suspicious());
+    }
+
+    #[test]
+    fn countersigning_must_preserve_the_content() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let bob = test_util::deterministic_signing_key("bob");
+        let before = sign("the photo", &alice, "https://a.example/alice");
+
+        let countersigned = sign(&before, &bob, "https://b.example/bob");
+        assert!(verify_content_preserved(&before, &countersigned));
+        let sealed = seal(&before, &bob, "https://b.example/bob");
+        assert!(verify_content_preserved(&before, &sealed));
+
+        // A sneaky countersigner who edits the content before signing over it is caught
+        let tampered = before.replace("the photo", "the doctored photo");
+        let sneaky = sign(&tampered, &bob, "https://b.example/bob");
+        assert!(!verify_content_preserved(&before, &sneaky));
+
+        // Even an edit which leaves Alice's layer verifying, like her header's line ending
+        let crlf = before.replacen('\n', "\r\n", 1);
+        let sneaky = sign(&crlf, &bob, "https://b.example/bob");
+        assert!(!verify_content_preserved(&before, &sneaky));
+
+        // Adding no layer, or more than one, isn't countersigning
+        assert!(!verify_content_preserved(&before, &before));
+        let twice = sign(&countersigned, &bob, "https://b.example/bob");
+        assert!(!verify_content_preserved(&before, &twice));
+        assert!(!verify_content_preserved(
+            &before,
+            &format!("not a header\n{before}")
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+//! Provenance for documents with YAML front matter, like the Markdown pages of a static site.
+//!
+//! Instead of a header line above the document (which would break the front matter for site
+//! generators), [`sign_front_matter`] stores the provenance header as a [`FIELD`] inside the
+//! `---`-delimited front matter:
+//!
+//! ```text
+//! ---
+//! title: Hello world
+//! provenance: '~~🔏 ... 🔏~~'
+//! ---
+//! Post body
+//! ```
+//!
+//! The signature covers the whole document with the `provenance:` line removed, so the rest of
+//! the front matter (title, dates, tags, ...) is signed along with the body. A document without
+//! front matter gets a new front matter block holding just the field.
+
+use crate::offline::PROVENANCE_VERSION;
+use crate::{
+    format_header, signature_to_b64, signed_message, verify_parts, Base64Signature, SignerDetails,
+};
+use anyhow::anyhow;
+use ed25519_dalek::{Signer as _, SigningKey};
+use std::ops::Range;
+
+/// The front matter field holding the provenance header.
+pub const FIELD: &str = "provenance";
+
+/// The byte ranges of the front matter in `doc`: its contents (between the delimiter lines), and
+/// its closing delimiter line.
+fn front_matter(doc: &str) -> Option<(Range<usize>, Range<usize>)> {
+    let contents_start = ["---\n", "---\r\n"]
+        .iter()
+        .find(|opening| doc.starts_with(*opening))?
+        .len();
+    let mut offset = contents_start;
+    for line in doc[contents_start..].split_inclusive('\n') {
+        if matches!(line.trim_end_matches(['\r', '\n']), "---" | "...") {
+            return Some((contents_start..offset, offset..offset + line.len()));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// The byte range of the top-level [`FIELD`] line within the front matter `contents` of `doc`,
+/// and the field's value.
+fn find_field(doc: &str, contents: Range<usize>) -> Option<(Range<usize>, &str)> {
+    let mut offset = contents.start;
+    for line in doc[contents].split_inclusive('\n') {
+        let value = line
+            .strip_prefix(FIELD)
+            .and_then(|rest| rest.strip_prefix(':'));
+        if let Some(value) = value {
+            return Some((offset..offset + line.len(), value.trim()));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Unquote a YAML scalar holding a provenance header. Double-quoted scalars with escapes aren't
+/// supported, since a header never needs them.
+fn unquote(value: &str) -> anyhow::Result<String> {
+    if let Some(quoted) = value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+    {
+        Ok(quoted.replace("''", "'"))
+    } else if let Some(quoted) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        if quoted.contains('\\') {
+            return Err(anyhow!("Escapes in the {FIELD} field aren't supported"));
+        }
+        Ok(quoted.to_string())
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// Sign `doc`, storing the provenance header in its front matter (see the [module
+/// docs](self)).
+///
+/// Fails if the front matter already has a [`FIELD`], since only one signer can sign a document
+/// this way.
+pub fn sign_front_matter(doc: &str, signing_key: &SigningKey, url: &str) -> anyhow::Result<String> {
+    let (unsigned, insert_at) = match front_matter(doc) {
+        Some((contents, closing)) => {
+            if find_field(doc, contents).is_some() {
+                return Err(anyhow!("Front matter already has a {FIELD} field"));
+            }
+            (doc.to_string(), closing.start)
+        }
+        None => (format!("---\n---\n{doc}"), "---\n".len()),
+    };
+
+    let signature = signing_key.sign(&signed_message(
+        PROVENANCE_VERSION,
+        url,
+        unsigned.as_bytes(),
+    ));
+    let header = format_header(url, Base64Signature(signature_to_b64(&signature)));
+
+    let mut signed = unsigned;
+    signed.insert_str(
+        insert_at,
+        &format!("{FIELD}: '{}'\n", header.replace('\'', "''")),
+    );
+    Ok(signed)
+}
+
+/// Verify a document signed with [`sign_front_matter`], returning the result and the document
+/// without the [`FIELD`].
+///
+/// If removing the field leaves the front matter empty, the front matter is removed from the
+/// returned document too. Documents without the field are returned unchanged, with an error.
+pub fn verify_front_matter(doc: &str) -> (anyhow::Result<SignerDetails>, String) {
+    let Some((contents, _)) = front_matter(doc) else {
+        return (
+            Err(anyhow!("Document doesn't have front matter")),
+            doc.to_string(),
+        );
+    };
+    let Some((line, value)) = find_field(doc, contents) else {
+        return (
+            Err(anyhow!("Front matter doesn't have a {FIELD} field")),
+            doc.to_string(),
+        );
+    };
+
+    let unsigned = format!("{}{}", &doc[..line.start], &doc[line.end..]);
+    let result = unquote(value).and_then(|header| verify_parts(&header, unsigned.as_bytes()));
+    let remainder = match front_matter(&unsigned) {
+        Some((contents, closing)) if contents.is_empty() => unsigned[closing.end..].to_string(),
+        _ => unsigned,
+    };
+    (result, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn front_matter_round_trip() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let post = "---\ntitle: Hello world\ntags: [rust, provenance]\n---\n# Hello\n\nPost body\n";
+
+        let signed = sign_front_matter(post, &signing_key, url).unwrap();
+        assert!(signed
+            .starts_with("---\ntitle: Hello world\ntags: [rust, provenance]\nprovenance: '~~🔏 "));
+        assert!(signed.ends_with("🔏~~'\n---\n# Hello\n\nPost body\n"));
+        let (result, remainder) = verify_front_matter(&signed);
+        assert_eq!(result.unwrap().verification_url, url);
+        assert_eq!(remainder, post);
+        assert!(sign_front_matter(&signed, &signing_key, url).is_err());
+
+        // Both the body and the rest of the front matter are signed
+        let (result, _) = verify_front_matter(&signed.replace("Post body", "Edited body"));
+        assert!(result.is_err());
+        let (result, _) = verify_front_matter(&signed.replace("Hello world", "Goodbye world"));
+        assert!(result.is_err());
+
+        // Documents without front matter get one
+        let signed = sign_front_matter("Just text\n", &signing_key, url).unwrap();
+        assert!(signed.starts_with("---\nprovenance: '~~🔏 "));
+        let (result, remainder) = verify_front_matter(&signed);
+        assert!(result.is_ok());
+        assert_eq!(remainder, "Just text\n");
+
+        let (result, remainder) = verify_front_matter(post);
+        assert!(result.is_err());
+        assert_eq!(remainder, post);
+    }
+
+    #[test]
+    fn field_values_are_unquoted() {
+        assert_eq!(unquote("'it''s'").unwrap(), "it's");
+        assert_eq!(unquote("\"quoted\"").unwrap(), "quoted");
+        assert_eq!(unquote("plain").unwrap(), "plain");
+        assert!(unquote("\"esc\\\"aped\"").is_err());
+        assert!(front_matter("---\nunclosed: true\n").is_none());
+        assert!(front_matter("--- not front matter\n---\n").is_none());
+    }
+}
@@ -0,0 +1,130 @@
+//! Provenance for git objects, stored in git notes so that no tracked file changes.
+//!
+//! A note signs the hash of a blob or commit, so it attests to exactly that object (and, for a
+//! commit, to its whole tree and history). Notes are kept under [`NOTES_REF`], for example:
+//!
+//! ```text
+//! pvnc git-note --object <HASH> --signing-key <KEY> --url <URL> \
+//!     | git notes --ref=provenance append --file=- <HASH>
+//! ```
+//!
+//! Each line of a note is a detached provenance header (see
+//! [`sign_detached`](crate::sign_detached)) signed over the object's hash, so several people
+//! can sign the same object by appending to its note. The hash is signed along with
+//! [`NOTES_REF`], so a note can't be passed off as the signature of a document which happens to
+//! hold just the hash, or the other way around.
+
+use crate::{sign_detached, verify_parts, SignerDetails};
+use anyhow::anyhow;
+use ed25519_dalek::SigningKey;
+
+/// The ref under which provenance notes are stored.
+pub const NOTES_REF: &str = "refs/notes/provenance";
+
+/// Check that `object_hash` is the full hex hash of a git object (SHA-1 or SHA-256), returning
+/// it in lowercase as git prints it.
+fn object_id(object_hash: &str) -> anyhow::Result<String> {
+    let object_hash = object_hash.trim();
+    if !matches!(object_hash.len(), 40 | 64) || !object_hash.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Err(anyhow!(
+            "'{object_hash}' isn't the full hash of a git object (40 or 64 hex characters)"
+        ));
+    }
+    Ok(object_hash.to_ascii_lowercase())
+}
+
+/// The document which a note for the object `object_id` signs.
+fn signed_object(object_id: &str) -> String {
+    format!("{NOTES_REF} {object_id}")
+}
+
+/// Sign the git object with hash `object_hash`, returning a line to add to its note under
+/// [`NOTES_REF`].
+///
+/// The hash must be given in full, since an abbreviated hash can come to match other objects as
+/// the repository grows.
+pub fn git_note(object_hash: &str, signing_key: &SigningKey, url: &str) -> anyhow::Result<String> {
+    let object = signed_object(&object_id(object_hash)?);
+    Ok(format!("{}\n", sign_detached(&object, signing_key, url)))
+}
+
+/// Verify every header in `note_content`, the note attached to the git object with hash
+/// `object_hash`, returning one result per header in the order they appear in the note.
+///
+/// Blank lines are skipped, so a note with no headers gives no results. A header signed for any
+/// other object fails to verify, so a note can't be copied onto a different object.
+pub fn verify_git_note(
+    object_hash: &str,
+    note_content: &str,
+) -> anyhow::Result<Vec<anyhow::Result<SignerDetails>>> {
+    let object = signed_object(&object_id(object_hash)?);
+    Ok(note_content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|header| verify_parts(header, object.as_bytes()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// The hash of the blob `hello world\n`, from `echo 'hello world' | git hash-object --stdin`.
+    const BLOB_HASH: &str = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+
+    #[test]
+    fn notes_verify_for_their_object() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+
+        let note = git_note(BLOB_HASH, &signing_key, url).unwrap();
+        assert_eq!(note.lines().count(), 1);
+        assert_eq!(
+            git_note(&BLOB_HASH.to_ascii_uppercase(), &signing_key, url).unwrap(),
+            note
+        );
+        let results = verify_git_note(BLOB_HASH, &note).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap().verification_url, url);
+
+        // Appending to a note adds another signer, and blank lines are skipped
+        let appended = format!(
+            "{note}\n{}",
+            git_note(
+                BLOB_HASH,
+                &test_util::deterministic_signing_key("forger"),
+                url
+            )
+            .unwrap()
+        );
+        let results = verify_git_note(BLOB_HASH, &appended).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+
+        // The note doesn't verify for any other object
+        let other = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        assert!(verify_git_note(other, &note).unwrap()[0].is_err());
+        assert!(verify_git_note(BLOB_HASH, "").unwrap().is_empty());
+
+        // A signature over a document holding just the hash isn't a note for the object
+        let detached = sign_detached(BLOB_HASH, &signing_key, url);
+        assert!(verify_git_note(BLOB_HASH, &detached).unwrap()[0].is_err());
+        assert!(verify_parts(note.trim_end(), BLOB_HASH.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn object_hashes_must_be_complete() {
+        let signing_key = test_util::deterministic_signing_key("git");
+        let url = "https://example.com/git";
+
+        for object_hash in ["3b18e51", "HEAD", &BLOB_HASH.replace('3', "g"), ""] {
+            assert!(git_note(object_hash, &signing_key, url).is_err());
+            assert!(verify_git_note(object_hash, "").is_err());
+        }
+        // SHA-256 repositories have 64 character hashes
+        assert!(git_note(&"ab".repeat(32), &signing_key, url).is_ok());
+    }
+}
@@ -0,0 +1,167 @@
+//! Normalizing a document before it is signed or verified.
+//!
+//! Some documents pick up harmless changes on their way between the signer and the verifier:
+//! trailing whitespace gets trimmed, or line endings get converted. A [`DocumentNormalizer`]
+//! transforms the document before it is signed, and the normalizer's ID is recorded in the
+//! provenance header (as `norm=<id>`) so that the verifier knows to apply the same transform
+//! before checking the signature. Only the signed bytes are normalized: the document itself is
+//! left as it was.
+
+use crate::offline::ParsedHeader;
+#[cfg(feature = "std")]
+use alloc::string::String;
+use alloc::{borrow::Cow, sync::Arc, vec::Vec};
+use anyhow::anyhow;
+
+/// The header extension which records the normalizer applied to a layer.
+pub const NORMALIZER_EXTENSION: &str = "norm";
+
+/// A transform applied to a document before signing and before verifying.
+pub trait DocumentNormalizer: Send + Sync {
+    /// A short identifier for this normalizer, recorded in the provenance header. It must not
+    /// contain spaces, newlines or `=`.
+    fn id(&self) -> &str;
+
+    /// Transform `doc` into the bytes which are actually signed.
+    fn normalize(&self, doc: &[u8]) -> Vec<u8>;
+}
+
+impl core::fmt::Debug for dyn DocumentNormalizer + '_ {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "DocumentNormalizer({})", self.id())
+    }
+}
+
+/// Leaves the document unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl DocumentNormalizer for Identity {
+    fn id(&self) -> &str {
+        "identity"
+    }
+
+    fn normalize(&self, doc: &[u8]) -> Vec<u8> {
+        doc.to_vec()
+    }
+}
+
+/// Removes spaces and tabs from the end of every line, and trailing newlines from the end of the
+/// document.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrimTrailingWhitespace;
+
+impl DocumentNormalizer for TrimTrailingWhitespace {
+    fn id(&self) -> &str {
+        "trim-trailing-whitespace"
+    }
+
+    fn normalize(&self, doc: &[u8]) -> Vec<u8> {
+        let is_blank = |byte: &u8| *byte == b' ' || *byte == b'\t' || *byte == b'\r';
+        let mut lines: Vec<&[u8]> = doc
+            .split(|&byte| byte == b'\n')
+            .map(|line| match line.iter().rposition(|byte| !is_blank(byte)) {
+                Some(last) => &line[..=last],
+                None => &line[..0],
+            })
+            .collect();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines.join(&b'\n')
+    }
+}
+
+/// Converts Windows (`\r\n`) line endings to Unix (`\n`) line endings.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrlfToLf;
+
+impl DocumentNormalizer for CrlfToLf {
+    fn id(&self) -> &str {
+        "crlf-to-lf"
+    }
+
+    fn normalize(&self, doc: &[u8]) -> Vec<u8> {
+        let mut normalized = Vec::with_capacity(doc.len());
+        for (i, &byte) in doc.iter().enumerate() {
+            if byte == b'\r' && doc.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            normalized.push(byte);
+        }
+        normalized
+    }
+}
+
+/// The normalizers built into this crate, which every verifier understands.
+pub fn builtin(id: &str) -> Option<&'static dyn DocumentNormalizer> {
+    match id {
+        "identity" => Some(&Identity),
+        "trim-trailing-whitespace" => Some(&TrimTrailingWhitespace),
+        "crlf-to-lf" => Some(&CrlfToLf),
+        _ => None,
+    }
+}
+
+/// The header extension recording that `normalizer` was applied, checking its ID can be written
+/// into a header.
+#[cfg(feature = "std")]
+pub(crate) fn extension_for(normalizer: &dyn DocumentNormalizer) -> anyhow::Result<String> {
+    let id = normalizer.id();
+    if id.is_empty() || id.contains([' ', '\n', '=']) {
+        return Err(anyhow!(
+            "Normalizer ID '{id}' cannot be empty or contain spaces, newlines or '='"
+        ));
+    }
+    Ok(alloc::format!("{NORMALIZER_EXTENSION}={id}"))
+}
+
+/// The bytes of `body` which were signed for the layer with `header`: either `body` itself, or
+/// `body` after applying the normalizer recorded in the header.
+///
+/// The normalizer is looked up in `custom` first, then in the [built-in](builtin) normalizers.
+pub fn normalize_body<'a>(
+    header: &ParsedHeader<'_>,
+    body: &'a [u8],
+    custom: &[Arc<dyn DocumentNormalizer>],
+) -> anyhow::Result<Cow<'a, [u8]>> {
+    let Some(id) = header.extension(NORMALIZER_EXTENSION) else {
+        return Ok(Cow::Borrowed(body));
+    };
+
+    let normalizer = custom
+        .iter()
+        .map(|normalizer| normalizer.as_ref())
+        .find(|normalizer| normalizer.id() == id)
+        .or_else(|| builtin(id));
+    match normalizer {
+        Some(normalizer) => Ok(Cow::Owned(normalizer.normalize(body))),
+        None => Err(anyhow!(
+            "Document was signed with unknown normalizer '{id}'"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_normalizers() {
+        assert_eq!(Identity.normalize(b"a \r\nb"), b"a \r\nb");
+        assert_eq!(
+            TrimTrailingWhitespace.normalize(b"a  \r\nb\t\n\n  \n"),
+            b"a\nb"
+        );
+        assert_eq!(CrlfToLf.normalize(b"a\r\nb\rc\r\n"), b"a\nb\rc\n");
+
+        for normalizer in [
+            &Identity as &dyn DocumentNormalizer,
+            &TrimTrailingWhitespace,
+            &CrlfToLf,
+        ] {
+            assert_eq!(builtin(normalizer.id()).unwrap().id(), normalizer.id());
+        }
+        assert!(builtin("unknown").is_none());
+    }
+}
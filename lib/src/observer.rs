@@ -0,0 +1,154 @@
+//! Telling other systems whenever a layer of provenance is verified, for example to keep an
+//! audit trail.
+//!
+//! Every [`VerifyObserver`] in [`VerifyOptions::observers`](crate::VerifyOptions::observers)
+//! hears about each layer verified with those options. [`WebhookObserver`] is a ready-made
+//! observer which POSTs each result to an HTTP endpoint.
+
+use crate::{SignerDetails, VerifyStatus};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{self, Sender};
+use std::time::{Duration, SystemTime};
+
+/// Something which wants to know about every layer that is verified.
+pub trait VerifyObserver: Send + Sync {
+    /// Called once for each layer verified, with the URL in the layer's header (empty if the
+    /// header couldn't be parsed) and the result of verifying it. This is called on the
+    /// verifying thread, so should return quickly.
+    fn on_verify(&self, url: &str, result: &anyhow::Result<SignerDetails>);
+}
+
+impl std::fmt::Debug for dyn VerifyObserver + '_ {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VerifyObserver")
+    }
+}
+
+/// The JSON body [`WebhookObserver`] POSTs for each layer verified.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    /// The URL in the layer's header.
+    pub url: String,
+    /// Whether the layer verified. Layers whose server was unreachable haven't verified, whatever
+    /// the [`NetworkFailureMode`](crate::NetworkFailureMode).
+    pub verified: bool,
+    /// When the layer was verified, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+/// How long [`WebhookObserver`] waits for the endpoint to respond to each event.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends a [`WebhookEvent`] to an HTTP endpoint for every layer verified.
+///
+/// Events are POSTed in order from a background thread, so verification never waits on the
+/// endpoint. Delivery is best-effort: events the endpoint fails to accept are dropped, not
+/// retried.
+#[derive(Debug)]
+pub struct WebhookObserver {
+    events: Sender<WebhookEvent>,
+}
+
+impl WebhookObserver {
+    /// Start sending events to `endpoint`.
+    pub fn new(endpoint: &str) -> Self {
+        let (events, received) = mpsc::channel::<WebhookEvent>();
+        let endpoint = endpoint.to_string();
+        // The thread stops once the observer (and so the sending half) is dropped
+        std::thread::spawn(move || {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default();
+            for event in received {
+                let _ = client.post(&endpoint).json(&event).send();
+            }
+        });
+
+        WebhookObserver { events }
+    }
+}
+
+impl VerifyObserver for WebhookObserver {
+    fn on_verify(&self, url: &str, result: &anyhow::Result<SignerDetails>) {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // Sending only fails once the background thread has died, and then there's nobody to
+        // deliver the event anyway
+        let _ = self.events.send(WebhookEvent {
+            url: url.to_string(),
+            verified: VerifyStatus::of(result) == VerifyStatus::Verified,
+            timestamp,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sign, test_util, verify_all_with_options, verify_with_options, VerifyOptions};
+    use std::sync::Arc;
+
+    /// A webhook endpoint which passes the body of every request it receives to the returned
+    /// channel.
+    fn webhook_receiver() -> (String, mpsc::Receiver<WebhookEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        let addr = test_util::mock_server_with(move |request| {
+            let _ = sender.send(serde_json::from_slice(&request.body).unwrap());
+            test_util::MockResponse::new(204, "")
+        });
+
+        (format!("http://{addr}/events"), receiver)
+    }
+
+    #[test]
+    fn webhook_receives_an_event_per_layer() {
+        let (endpoint, events) = webhook_receiver();
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let forger = test_util::deterministic_signing_key("forger");
+        let options = VerifyOptions {
+            observers: vec![Arc::new(WebhookObserver::new(&endpoint))],
+            ..Default::default()
+        };
+        let before = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let doc = sign(
+            &sign(
+                &sign("document", &signing_key, url),
+                &forger,
+                "http://localhost:8000/provenance/beyarkay",
+            ),
+            &signing_key,
+            "http://localhost:1/provenance/down",
+        );
+        let (results, _) = verify_all_with_options(&doc, &options);
+        assert_eq!(results.len(), 3);
+        assert!(verify_with_options("not signed\nat all", &options)
+            .0
+            .is_err());
+
+        let received: Vec<_> = (0..4)
+            .map(|_| events.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect();
+        assert!(received.iter().all(|event| event.timestamp >= before));
+        let received: Vec<_> = received
+            .into_iter()
+            .map(|event| (event.url, event.verified))
+            .collect();
+        assert_eq!(
+            received,
+            [
+                ("http://localhost:1/provenance/down".to_string(), false),
+                (url.to_string(), false),
+                (url.to_string(), true),
+                (String::new(), false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1147 @@
+//! Parse provenance headers and verify them against a verification key you already have.
+//!
+//! Nothing in this module touches the network or needs the standard library (only `alloc`), so
+//! it can be used by embedded verifiers which have the signer's key preloaded. Build the crate
+//! with `default-features = false` to get just this module (and [`crate::binary`]).
+
+use crate::batch::{self, BATCH_EXTENSION};
+use crate::normalize::normalize_body;
+use alloc::{format, string::String, vec::Vec};
+use anyhow::anyhow;
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE},
+    Engine as _,
+};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256, Sha512};
+
+pub(crate) const PROVENANCE_PREAMBLE: &str = "~~🔏";
+pub(crate) const PROVENANCE_POSTAMBLE: &str = "🔏~~";
+pub(crate) const PROVENANCE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The header extension naming the signature algorithm of a layer. Layers without it are signed
+/// with ed25519.
+pub(crate) const ALGORITHM_EXTENSION: &str = "alg";
+
+/// The value of the `alg` header extension for layers signed with Ed25519ph (RFC 8032), which
+/// signs the SHA-512 hash of the message instead of the message itself.
+pub(crate) const PREHASHED_ALGORITHM: &str = "ed25519ph";
+
+/// The value of the `alg` header extension for layers which sign the SHA-256 hash of the
+/// document with plain ed25519, see [`HashAlgorithm::Sha256`].
+pub(crate) const SHA256_ALGORITHM: &str = "ed25519-sha256";
+
+/// The value of the `alg` header extension for layers which sign the BLAKE3 hash of the document
+/// with plain ed25519, see [`HashAlgorithm::Blake3`].
+pub(crate) const BLAKE3_ALGORITHM: &str = "ed25519-blake3";
+
+/// The hash function a layer's document is hashed with before it is signed, recorded in the
+/// header's `alg` extension so verifiers use the same one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// SHA-512, signed with Ed25519ph (RFC 8032) as `alg=ed25519ph`.
+    Sha512,
+    /// SHA-256. The hash is signed with plain ed25519, as `alg=ed25519-sha256`.
+    Sha256,
+    /// BLAKE3, which is much faster than SHA-2 on large documents. The hash is signed with plain
+    /// ed25519, as `alg=ed25519-blake3`.
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// The value of the `alg` header extension for layers hashed with this algorithm.
+    pub fn algorithm(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha512 => PREHASHED_ALGORITHM,
+            HashAlgorithm::Sha256 => SHA256_ALGORITHM,
+            HashAlgorithm::Blake3 => BLAKE3_ALGORITHM,
+        }
+    }
+
+    /// The hash algorithm named by the `alg` header extension `algorithm`, if it names one.
+    pub fn from_algorithm(algorithm: &str) -> Option<Self> {
+        [
+            HashAlgorithm::Sha512,
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+        ]
+        .into_iter()
+        .find(|hash| hash.algorithm() == algorithm)
+    }
+}
+
+/// The header extension holding the signer's verification key, base64-encoded like the keys
+/// served by provenance servers. See [`verify_with_embedded_key`].
+pub(crate) const EMBEDDED_KEY_EXTENSION: &str = "key";
+
+/// The tokens of a provenance header line, as written in the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedHeader<'a> {
+    pub version: &'a str,
+    pub url: &'a str,
+    pub signature_b64: &'a str,
+    /// Extra `key=value` tokens between the signature and the postamble, separated by spaces (or
+    /// an empty string if there are none). These are signed along with the document, see
+    /// [`signed_message_with_extensions`].
+    pub extensions: &'a str,
+}
+
+impl<'a> ParsedHeader<'a> {
+    /// The value of the header extension `key`, if the header has one.
+    pub fn extension(&self, key: &str) -> Option<&'a str> {
+        find_extension(self.extensions, key)
+    }
+}
+
+/// The value of the extension `key` in a header's space-separated `extensions`.
+pub(crate) fn find_extension<'a>(extensions: &'a str, key: &str) -> Option<&'a str> {
+    extensions
+        .split(' ')
+        .filter_map(|token| token.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| value)
+}
+
+/// Parse a single provenance header line, checking that it is well-formed.
+///
+/// This only checks the structure of the header, it doesn't check the version or the signature.
+pub fn parse_header(header: &str) -> anyhow::Result<ParsedHeader<'_>> {
+    parse_header_with_markers(header, PROVENANCE_PREAMBLE, PROVENANCE_POSTAMBLE)
+}
+
+/// Like [`parse_header`], for a header line read as raw bytes from an untrusted source.
+///
+/// Bytes which aren't valid UTF-8 (including an emoji marker cut off partway through) are an
+/// error rather than being decoded lossily, since the header is signed exactly as written.
+pub fn parse_header_bytes(header: &[u8]) -> anyhow::Result<ParsedHeader<'_>> {
+    let header = core::str::from_utf8(header)
+        .map_err(|e| anyhow!("Header isn't valid UTF-8 (from byte {})", e.valid_up_to()))?;
+    parse_header(header)
+}
+
+/// Parse `header` using only the layout every version of the protocol shares: the markers, then
+/// the version, URL and signature, with any words between the signature and the postamble taken
+/// as-is as the extensions. A newer version of the protocol may add words which [`parse_header`]
+/// rejects, so this is used to check the core signature of layers with such versions.
+#[cfg(feature = "std")]
+pub(crate) fn parse_header_layout(header: &str) -> Option<ParsedHeader<'_>> {
+    let raw = parse_raw_header(header, 0..header.len())?;
+    let is_valid = raw.preamble == PROVENANCE_PREAMBLE
+        && raw.postamble == PROVENANCE_POSTAMBLE
+        && !raw.url.is_empty()
+        && !raw.signature_b64.is_empty();
+    is_valid.then_some(ParsedHeader {
+        version: raw.version,
+        url: raw.url,
+        signature_b64: raw.signature_b64,
+        extensions: raw.extensions,
+    })
+}
+
+/// Like [`parse_header`], but for headers which use a different preamble and postamble.
+pub(crate) fn parse_header_with_markers<'a>(
+    header: &'a str,
+    expected_preamble: &str,
+    expected_postamble: &str,
+) -> anyhow::Result<ParsedHeader<'a>> {
+    let words = header.split(' ').collect::<Vec<_>>();
+    let [preamble, version, url, signature_b64, ref extension_words @ .., postamble] = words[..]
+    else {
+        return Err(anyhow!(
+            "Document doesn't have at least five space-separated words in first line"
+        ));
+    };
+    if url.is_empty() {
+        return Err(anyhow!("URL cannot be empty"));
+    }
+    if signature_b64.is_empty() {
+        return Err(anyhow!("Signature cannot be empty"));
+    }
+    if preamble != expected_preamble {
+        return Err(anyhow!(
+            "Document preamble is '{preamble}', not '{expected_preamble}'"
+        ));
+    }
+    if postamble != expected_postamble {
+        return Err(anyhow!(
+            "Document postamble is '{postamble}', not '{expected_postamble}'"
+        ));
+    }
+
+    let mut keys: Vec<&str> = Vec::new();
+    for word in extension_words {
+        let Some((key, _)) = word.split_once('=').filter(|(key, _)| !key.is_empty()) else {
+            return Err(anyhow!(
+                "Header extension '{word}' isn't of the form 'key=value'"
+            ));
+        };
+        if keys.contains(&key) {
+            return Err(anyhow!("Header extension '{key}' appears more than once"));
+        }
+        keys.push(key);
+    }
+    let extensions = if extension_words.is_empty() {
+        ""
+    } else {
+        let start = [preamble, version, url, signature_b64]
+            .iter()
+            .map(|word| word.len() + 1)
+            .sum::<usize>();
+        &header[start..header.len() - postamble.len() - 1]
+    };
+
+    Ok(ParsedHeader {
+        version,
+        url,
+        signature_b64,
+        extensions,
+    })
+}
+
+/// The tokens of a header line exactly as they appear in a document, without any validation.
+/// See [`raw_layers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawHeader<'a> {
+    pub preamble: &'a str,
+    pub version: &'a str,
+    pub url: &'a str,
+    pub signature_b64: &'a str,
+    /// Any tokens between the signature and the postamble, or an empty string if there are none.
+    pub extensions: &'a str,
+    pub postamble: &'a str,
+    /// The byte range of the header line within the document, not including its newline.
+    pub range: core::ops::Range<usize>,
+}
+
+/// Split every header line off the top of `doc`, from the outermost inwards, reporting the tokens
+/// of each exactly as written.
+///
+/// Nothing is validated, so unknown versions, unrecognised preambles (like seals, or markers from
+/// other tools) and malformed signatures are all reported as they are. A line counts as a header
+/// if it is followed by a newline, has at least five space-separated words, and its first and last
+/// words contain no letters or digits (so ordinary text isn't mistaken for a header). The first
+/// line which doesn't look like a header ends the list.
+pub fn raw_layers(doc: &str) -> Vec<RawHeader<'_>> {
+    let mut layers = Vec::new();
+    let mut rest = doc;
+    while let Some((line, remainder)) = split_header(rest) {
+        // The range runs up to the newline, including any `\r` before it
+        let start = doc.len() - rest.len();
+        let end = doc.len() - remainder.len() - 1;
+        let Some(header) = parse_raw_header(line, start..end) else {
+            break;
+        };
+        layers.push(header);
+        rest = remainder;
+    }
+
+    layers
+}
+
+/// Whether two documents have the same provenance chain over the same content: the same layers,
+/// in the same order, each with the same URL and signature, and the same document underneath.
+///
+/// Nothing is verified or fetched. Signatures are compared by their bytes, so a layer whose
+/// signature was re-encoded with the other base64 alphabet still counts as the same layer.
+pub fn chains_equal(doc_a: &str, doc_b: &str) -> bool {
+    fn canonical(doc: &str) -> (Vec<(&str, Vec<u8>)>, &str) {
+        let layers = raw_layers(doc);
+        let content = content_under(doc, &layers);
+        let layers = layers
+            .iter()
+            .map(|layer| {
+                // Signatures which aren't base64 (like passkey assertions) are compared as written
+                let signature = decode_base64_token(layer.signature_b64)
+                    .unwrap_or_else(|_| layer.signature_b64.as_bytes().to_vec());
+                (layer.url, signature)
+            })
+            .collect();
+        (layers, content)
+    }
+
+    canonical(doc_a) == canonical(doc_b)
+}
+
+/// The content of `doc` underneath its `layers` (as found by [`raw_layers`]).
+fn content_under<'a>(doc: &'a str, layers: &[RawHeader<'_>]) -> &'a str {
+    layers
+        .last()
+        .map_or(doc, |layer| &doc[layer.range.end + 1..])
+}
+
+/// An identifier for the content of `doc` which stays the same as layers of provenance are added
+/// on top: the URL-safe base64 SHA-256 hash of the content underneath every layer.
+///
+/// Layers are found as in [`raw_layers`], without verifying anything, so the ID says nothing
+/// about who signed the document. An unsigned document's ID is the hash of the whole document.
+pub fn stable_document_id(doc: &str) -> String {
+    let content = content_under(doc, &raw_layers(doc));
+    URL_SAFE.encode(Sha256::digest(content.as_bytes()))
+}
+
+/// Split a document with N layers into the N documents which existed while it was being signed:
+/// the first has just the innermost layer over the content, the second adds the next layer, and
+/// so on, until the last is `doc` itself.
+///
+/// Each layer signs everything below it, so every document returned verifies on its own, and
+/// attests to exactly one signer (the outermost layer of that document) plus everyone before
+/// them. Layers are found as in [`raw_layers`], and an unsigned document splits into nothing.
+pub fn split_layers(doc: &str) -> Vec<String> {
+    raw_layers(doc)
+        .iter()
+        .rev()
+        .map(|layer| String::from(&doc[layer.range.start..]))
+        .collect()
+}
+
+/// Split the header line off the top of `signed_doc`, returning it (without its line ending) and
+/// the rest of the document. The header can end with either `\n` or `\r\n`, since the line
+/// ending isn't signed.
+pub(crate) fn split_header(signed_doc: &str) -> Option<(&str, &str)> {
+    let (first, doc) = signed_doc.split_once('\n')?;
+    Some((first.strip_suffix('\r').unwrap_or(first), doc))
+}
+
+/// Returns true if `doc` starts with a line which looks like a header, in the sense of
+/// [`raw_layers`].
+#[cfg(feature = "std")]
+pub(crate) fn starts_with_header(doc: &str) -> bool {
+    split_header(doc).is_some_and(|(line, _)| parse_raw_header(line, 0..line.len()).is_some())
+}
+
+/// Split `line` (which is at `range` in its document) into the tokens of a header, if it looks
+/// like one.
+pub(crate) fn parse_raw_header(
+    line: &str,
+    range: core::ops::Range<usize>,
+) -> Option<RawHeader<'_>> {
+    let is_marker = |word: &str| !word.is_empty() && !word.chars().any(|c| c.is_alphanumeric());
+
+    let words = line.split(' ').collect::<Vec<_>>();
+    let [preamble, version, url, signature_b64, ref extension_words @ .., postamble] = words[..]
+    else {
+        return None;
+    };
+    if !is_marker(preamble) || !is_marker(postamble) {
+        return None;
+    }
+
+    let extensions = if extension_words.is_empty() {
+        ""
+    } else {
+        let extensions_start = [preamble, version, url, signature_b64]
+            .iter()
+            .map(|word| word.len() + 1)
+            .sum::<usize>();
+        &line[extensions_start..line.len() - postamble.len() - 1]
+    };
+    Some(RawHeader {
+        preamble,
+        version,
+        url,
+        signature_b64,
+        extensions,
+        postamble,
+        range,
+    })
+}
+
+/// Check that `signature` over a layer with the given `version` and `url` was made over `body`
+/// by the signing key belonging to `verification_key`.
+///
+/// This uses ed25519's strict verification, which rejects non-canonical (malleable) signatures
+/// and weak (small-order) verification keys. Otherwise a forger could produce a second, distinct
+/// signature which verifies the same content, or a key which "verifies" any document at all.
+pub fn verify_signature(
+    version: &str,
+    url: &str,
+    signature: &Signature,
+    body: &[u8],
+    verification_key: &VerifyingKey,
+) -> anyhow::Result<()> {
+    verify_signature_with_extensions(version, url, "", signature, body, verification_key)
+}
+
+/// Like [`verify_signature`], for a layer whose header has [extensions](ParsedHeader::extensions).
+pub fn verify_signature_with_extensions(
+    version: &str,
+    url: &str,
+    extensions: &str,
+    signature: &Signature,
+    body: &[u8],
+    verification_key: &VerifyingKey,
+) -> anyhow::Result<()> {
+    if version != PROVENANCE_VERSION {
+        return Err(anyhow!(
+            "Document version is '{version}', not '{PROVENANCE_VERSION}'"
+        ));
+    }
+
+    check_signature(version, url, extensions, signature, body, verification_key)
+}
+
+/// Like [`verify_signature_with_extensions`], but without checking the version. Callers must
+/// have already checked the version is one they accept.
+pub(crate) fn check_signature(
+    version: &str,
+    url: &str,
+    extensions: &str,
+    signature: &Signature,
+    body: &[u8],
+    verification_key: &VerifyingKey,
+) -> anyhow::Result<()> {
+    // A batch signature is over the root of a tree of documents, rather than the document itself
+    let root = batch::signed_root(extensions, body)?;
+    let (extensions, body) = match &root {
+        Some(root) => (BATCH_EXTENSION, root.as_slice()),
+        None => (extensions, body),
+    };
+
+    let hash =
+        find_extension(extensions, ALGORITHM_EXTENSION).and_then(HashAlgorithm::from_algorithm);
+    let verified = match hash {
+        Some(HashAlgorithm::Sha512) => verification_key.verify_prehashed_strict(
+            prehashed_message(version, url, extensions, body),
+            None,
+            signature,
+        ),
+        Some(hash) => verification_key.verify_strict(
+            &hashed_message(version, url, extensions, hash, body),
+            signature,
+        ),
+        None => verification_key.verify_strict(
+            &signed_message_with_extensions(version, url, extensions, body),
+            signature,
+        ),
+    };
+    if verified.is_err() {
+        return Err(anyhow!(
+            "Document signature '{}' could not be verified",
+            signature_to_b64(signature)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Verify the outermost provenance layer of `signed_doc` against a known verification key,
+/// without fetching anything from the layer's URL.
+///
+/// Returns the parsed header and the remainder of the document. Only the
+/// [built-in](crate::normalize::builtin) normalizers are understood.
+pub fn verify_with_key<'a>(
+    signed_doc: &'a str,
+    verification_key: &VerifyingKey,
+) -> anyhow::Result<(ParsedHeader<'a>, &'a str)> {
+    let Some((first, doc)) = split_header(signed_doc) else {
+        return Err(anyhow!(
+            "Document has only one line, therefore cannot be signed"
+        ));
+    };
+    let header = parse_header(first)?;
+
+    let signature = signature_from_b64(header.signature_b64).map_err(|e| {
+        anyhow!(
+            "Couldn't convert base64 signature '{}' into a signature: {e}",
+            header.signature_b64
+        )
+    })?;
+
+    verify_signature_with_extensions(
+        header.version,
+        header.url,
+        header.extensions,
+        &signature,
+        &normalize_body(&header, doc.as_bytes(), &[])?,
+        verification_key,
+    )?;
+
+    Ok((header, doc))
+}
+
+/// Whether the outermost layer of `doc` is signed by `verification_key` with a canonical
+/// signature, written exactly as [`signature_to_b64`] writes it.
+///
+/// Verification already rejects malleable signatures and weak keys, but it accepts signatures
+/// re-encoded with the standard base64 alphabet, so a document can verify even though its
+/// signature isn't the one its signer wrote. This diagnostic flags those too, without fetching
+/// anything, for platforms which want to quarantine them.
+pub fn is_outer_signature_canonical(doc: &str, verification_key: &VerifyingKey) -> bool {
+    let Some(header) = split_header(doc).and_then(|(first, _)| parse_header(first).ok()) else {
+        return false;
+    };
+    let is_canonically_encoded = signature_from_b64(header.signature_b64)
+        .is_ok_and(|signature| signature_to_b64(&signature) == header.signature_b64);
+    is_canonically_encoded && verify_with_key(doc, verification_key).is_ok()
+}
+
+/// The verification key embedded in a header's `extensions`, if it has one.
+pub(crate) fn embedded_key(extensions: &str) -> anyhow::Result<Option<VerifyingKey>> {
+    find_extension(extensions, EMBEDDED_KEY_EXTENSION)
+        .map(|key_b64| {
+            VerifyingKey::try_from(Base64VerifyingKey(key_b64.into()))
+                .map_err(|e| anyhow!("Couldn't parse the embedded verification key: {e}"))
+        })
+        .transpose()
+}
+
+/// Verify the outermost provenance layer of `signed_doc` against the verification key embedded
+/// in its own header, returning the key along with the header and the rest of the document.
+///
+/// This only shows the layer is internally consistent: whoever holds the embedded key signed
+/// the document. Anyone can embed their own key, so it says nothing about who that is. To trust
+/// the signer, check the returned key against one you already trust, or verify with
+/// [`crate::verify`], which also checks the embedded key against the layer's URL.
+pub fn verify_with_embedded_key(
+    signed_doc: &str,
+) -> anyhow::Result<(ParsedHeader<'_>, &str, VerifyingKey)> {
+    let first = split_header(signed_doc).map_or(signed_doc, |(first, _)| first);
+    let Some(verification_key) = embedded_key(parse_header(first)?.extensions)? else {
+        return Err(anyhow!("Header doesn't have an embedded verification key"));
+    };
+
+    let (header, doc) = verify_with_key(signed_doc, &verification_key)?;
+    Ok((header, doc, verification_key))
+}
+
+/// Like [`verify_with_key`], for a verification key held as its raw 32 bytes (for example from a
+/// database or a binary protocol) rather than as base64.
+pub fn verify_against_raw_key<'a>(
+    signed_doc: &'a str,
+    key_bytes: &[u8; 32],
+) -> anyhow::Result<(ParsedHeader<'a>, &'a str)> {
+    let Ok(verification_key) = VerifyingKey::from_bytes(key_bytes) else {
+        return Err(anyhow!(
+            "Verification key '{}' isn't a valid ed25519 public key",
+            URL_SAFE.encode(key_bytes)
+        ));
+    };
+
+    verify_with_key(signed_doc, &verification_key)
+}
+
+/// A fixed tag at the start of every signed message, see [`signed_message`].
+pub const DOMAIN_SEPARATOR: &[u8] = b"provenance-rs:v1:";
+
+/// The exact bytes which are signed for a single provenance layer.
+///
+/// The version and URL of the layer are signed along with the document, so that the URL can't
+/// be swapped out for another one without invalidating the signature. Neither the version nor the
+/// URL may contain spaces or newlines, so the message is unambiguous.
+///
+/// The message starts with [`DOMAIN_SEPARATOR`], so a provenance signature is never a signature
+/// over the raw document and can't be replayed into some other protocol which signs raw bytes
+/// with the same key (or vice versa).
+pub fn signed_message(version: &str, url: &str, doc: &[u8]) -> Vec<u8> {
+    signed_message_with_extensions(version, url, "", doc)
+}
+
+/// Like [`signed_message`], for a layer whose header has extensions (see
+/// [`ParsedHeader::extensions`]). The extensions are signed too, so they can't be added, removed
+/// or changed without invalidating the signature.
+pub fn signed_message_with_extensions(
+    version: &str,
+    url: &str,
+    extensions: &str,
+    doc: &[u8],
+) -> Vec<u8> {
+    let mut message = signed_message_prefix(version, url, extensions);
+    message.extend_from_slice(doc);
+    message
+}
+
+/// Everything in [`signed_message_with_extensions`] before the document.
+fn signed_message_prefix(version: &str, url: &str, extensions: &str) -> Vec<u8> {
+    let mut prefix = DOMAIN_SEPARATOR.to_vec();
+    if extensions.is_empty() {
+        prefix.extend_from_slice(format!("{version} {url}\n").as_bytes());
+    } else {
+        prefix.extend_from_slice(format!("{version} {url} {extensions}\n").as_bytes());
+    }
+    prefix
+}
+
+/// The SHA-512 hash of [`signed_message_with_extensions`], which is what an Ed25519ph layer
+/// signs (see [`PREHASHED_ALGORITHM`]). The document is hashed in place rather than copied into
+/// the message first.
+pub(crate) fn prehashed_message(version: &str, url: &str, extensions: &str, doc: &[u8]) -> Sha512 {
+    Sha512::new()
+        .chain_update(signed_message_prefix(version, url, extensions))
+        .chain_update(doc)
+}
+
+/// What a layer hashed with [`HashAlgorithm::Sha256`] or [`HashAlgorithm::Blake3`] signs:
+/// [`signed_message_with_extensions`] with the hash of the document in place of the document.
+///
+/// [`HashAlgorithm::Sha512`] layers use Ed25519ph instead, see [`prehashed_message`].
+pub(crate) fn hashed_message(
+    version: &str,
+    url: &str,
+    extensions: &str,
+    hash: HashAlgorithm,
+    doc: &[u8],
+) -> Vec<u8> {
+    let digest = match hash {
+        HashAlgorithm::Sha512 => Sha512::digest(doc).to_vec(),
+        HashAlgorithm::Sha256 => Sha256::digest(doc).to_vec(),
+        HashAlgorithm::Blake3 => blake3::hash(doc).as_bytes().to_vec(),
+    };
+    signed_message_with_extensions(version, url, extensions, &digest)
+}
+
+/// A stable identifier for a verification key: the SHA-256 hash of the key's bytes.
+///
+/// Two layers with the same fingerprint were signed by the same key, whatever their URLs say.
+pub fn key_fingerprint(verification_key: &VerifyingKey) -> [u8; 32] {
+    Sha256::digest(verification_key.as_bytes()).into()
+}
+
+/// Decode a single base64 token, accepting either the URL-safe or the standard alphabet.
+///
+/// Signatures are written with the URL-safe alphabet, but documents which have passed through
+/// other tools might have some layers encoded with the standard alphabet. The alphabet is
+/// detected per token, so a chain can freely mix the two.
+pub(crate) fn decode_base64_token(token: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    URL_SAFE
+        .decode(token.as_bytes())
+        .or_else(|_| STANDARD.decode(token.as_bytes()))
+}
+
+pub struct Base64Signature(pub String);
+
+impl TryFrom<Base64Signature> for Signature {
+    type Error = anyhow::Error;
+
+    fn try_from(base64_signature: Base64Signature) -> Result<Self, Self::Error> {
+        // Check that the string inside Base64Signature can be decoded into bytes
+        let Ok(bytes_of_base64) = decode_base64_token(&base64_signature.0) else {
+            return Err(anyhow!(
+                "Couldn't convert {} into bytes",
+                base64_signature.0
+            ));
+        };
+
+        // Check that the decoded bytes are the correct length
+        if bytes_of_base64.len() != ed25519_dalek::SIGNATURE_LENGTH {
+            return Err(anyhow!(
+                "Base64Signature needs to be {} bytes long, but is {} bytes long",
+                ed25519_dalek::SIGNATURE_LENGTH,
+                bytes_of_base64.len(),
+            ));
+        }
+
+        // Convert the unknown-length-slice into a known-length-slice
+        // This will always succeed because of the length-check above
+        let known_length_slice: &[u8; ed25519_dalek::SIGNATURE_LENGTH] =
+            bytes_of_base64.as_slice().try_into()?;
+
+        // Convert the slice of bytes into a Signature
+        Ok(Signature::from_bytes(known_length_slice))
+    }
+}
+
+/// Encode `signature` the way it is written in a provenance header.
+pub fn signature_to_b64(signature: &Signature) -> String {
+    URL_SAFE.encode(signature.to_bytes())
+}
+
+/// Decode a signature from a provenance header. Both the URL-safe and the standard base64
+/// alphabets are accepted, so `signature_from_b64(&signature_to_b64(&s))` is always `s`.
+pub fn signature_from_b64(signature_b64: &str) -> anyhow::Result<Signature> {
+    Base64Signature(signature_b64.into()).try_into()
+}
+
+pub struct Base64VerifyingKey(pub String);
+
+impl TryFrom<Base64VerifyingKey> for VerifyingKey {
+    type Error = anyhow::Error;
+
+    fn try_from(base64_verifying_key: Base64VerifyingKey) -> Result<Self, Self::Error> {
+        // Check that the string inside Base64VerifyingKey can be decoded into bytes
+        let Ok(bytes_of_base64) = URL_SAFE.decode(base64_verifying_key.0.as_bytes()) else {
+            return Err(anyhow!(
+                "Couldn't convert {} into bytes",
+                base64_verifying_key.0
+            ));
+        };
+
+        // Check that the decoded bytes are the correct length
+        if bytes_of_base64.len() != ed25519_dalek::SECRET_KEY_LENGTH {
+            return Err(anyhow!(
+                "Base64VerifyingKey needs to be {} bytes long, but is {} bytes long",
+                ed25519_dalek::SECRET_KEY_LENGTH,
+                bytes_of_base64.len(),
+            ));
+        }
+
+        // Convert the unknown-length-slice into a known-length-slice
+        // This will always succeed because of the length-check above
+        let known_length_slice: &[u8; ed25519_dalek::SECRET_KEY_LENGTH] =
+            bytes_of_base64.as_slice().try_into()?;
+
+        // Convert the slice of bytes into a VerifyingKey
+        VerifyingKey::from_bytes(known_length_slice).map_err(|e| anyhow!(e))
+    }
+}
+
+pub struct Base64SigningKey(pub String);
+
+impl TryFrom<Base64SigningKey> for SigningKey {
+    type Error = anyhow::Error;
+
+    fn try_from(base64_signing_key: Base64SigningKey) -> Result<Self, Self::Error> {
+        // Check that the string inside Base64SigningKey can be decoded into bytes
+        let Ok(bytes_of_base64) = URL_SAFE.decode(base64_signing_key.0.as_bytes()) else {
+            return Err(anyhow!(
+                "Couldn't convert {} into bytes",
+                base64_signing_key.0
+            ));
+        };
+
+        // Check that the decoded bytes are the correct length
+        if bytes_of_base64.len() != ed25519_dalek::PUBLIC_KEY_LENGTH {
+            return Err(anyhow!(
+                "Base64SigningKey needs to be {} bytes long, but is {} bytes long",
+                ed25519_dalek::PUBLIC_KEY_LENGTH,
+                bytes_of_base64.len(),
+            ));
+        }
+
+        // Convert the unknown-length-slice into a known-length-slice
+        // This will always succeed because of the length-check above
+        let known_length_slice: &[u8; ed25519_dalek::PUBLIC_KEY_LENGTH] =
+            bytes_of_base64.as_slice().try_into()?;
+
+        // Convert the slice of bytes into a SigningKey
+        Ok(SigningKey::from_bytes(known_length_slice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::beyarkay_key;
+    use ed25519_dalek::Signer;
+
+    fn sign(doc: &str, signing_key: &SigningKey, url: &str) -> String {
+        let signature = signing_key.sign(&signed_message(PROVENANCE_VERSION, url, doc.as_bytes()));
+        format!(
+            "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} {url} {} {PROVENANCE_POSTAMBLE}\n{doc}",
+            signature_to_b64(&signature)
+        )
+    }
+
+    #[test]
+    fn raw_layers_reports_tokens_verbatim() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let inner = sign("document text here\nsecond line", &beyarkay_key(), url);
+        let doc = format!("~~🔐 9.9.9 {url} not!base64 k=v extra 🔐~~\n{inner}");
+        let header = inner.lines().next().unwrap();
+
+        let layers = raw_layers(&doc);
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].preamble, "~~🔐");
+        assert_eq!(layers[0].version, "9.9.9");
+        assert_eq!(layers[0].signature_b64, "not!base64");
+        assert_eq!(layers[0].extensions, "k=v extra");
+        assert_eq!(layers[0].postamble, "🔐~~");
+        assert_eq!(&doc[layers[1].range.clone()], header);
+        assert_eq!(layers[1].url, url);
+        assert_eq!(layers[1].extensions, "");
+
+        // Ordinary text, or a header with nothing after it, isn't a layer
+        assert!(raw_layers("The quick brown fox jumps\nover").is_empty());
+        assert!(raw_layers(header).is_empty());
+    }
+
+    #[test]
+    fn chains_equal_compares_layers_and_content() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let other_url = "http://localhost:8000/provenance/someone";
+        let doc = sign(
+            &sign("document text here", &beyarkay_key(), url),
+            &beyarkay_key(),
+            other_url,
+        );
+
+        assert!(chains_equal(&doc, &doc.clone()));
+        assert!(!chains_equal(&doc, "document text here"));
+        assert!(chains_equal("unsigned", "unsigned"));
+
+        // The same signatures in the standard base64 alphabet are still the same chain
+        let restandardized = raw_layers(&doc).iter().fold(doc.clone(), |doc, layer| {
+            let signature = STANDARD.encode(URL_SAFE.decode(layer.signature_b64).unwrap());
+            doc.replace(layer.signature_b64, &signature)
+        });
+        assert_ne!(restandardized, doc);
+        assert!(chains_equal(&doc, &restandardized));
+
+        // Swapping the order of the layers, the signer or the content makes a different chain
+        let reordered = sign(
+            &sign("document text here", &beyarkay_key(), other_url),
+            &beyarkay_key(),
+            url,
+        );
+        assert!(!chains_equal(&doc, &reordered));
+        let other_signer = crate::test_util::deterministic_signing_key("not beyarkay");
+        let resigned = sign(
+            &sign("document text here", &other_signer, url),
+            &beyarkay_key(),
+            other_url,
+        );
+        assert!(!chains_equal(&doc, &resigned));
+        let other_content = sign(
+            &sign("other text", &beyarkay_key(), url),
+            &beyarkay_key(),
+            other_url,
+        );
+        assert!(!chains_equal(&doc, &other_content));
+    }
+
+    #[test]
+    fn split_layers_reconstructs_each_signing_step() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let keys = ["first", "second", "third"].map(crate::test_util::deterministic_signing_key);
+        let mut snapshots = vec![];
+        let mut doc = String::from("document text here\nsecond line");
+        for key in &keys {
+            doc = sign(&doc, key, url);
+            snapshots.push(doc.clone());
+        }
+
+        let split = split_layers(&doc);
+        assert_eq!(split, snapshots);
+        for (layer, key) in split.iter().zip(&keys) {
+            let (_, content) = verify_with_key(layer, &key.verifying_key()).unwrap();
+            assert!(content.ends_with("document text here\nsecond line"));
+        }
+
+        assert!(split_layers("unsigned").is_empty());
+    }
+
+    #[test]
+    fn stable_document_id_survives_signing() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let keys = ["first", "second", "third"].map(crate::test_util::deterministic_signing_key);
+        let content = "document text here\nsecond line";
+        let id = stable_document_id(content);
+        assert_eq!(id, URL_SAFE.encode(Sha256::digest(content.as_bytes())));
+
+        let mut doc = String::from(content);
+        for key in &keys {
+            doc = sign(&doc, key, url);
+            assert_eq!(stable_document_id(&doc), id);
+        }
+        // The same content signed by someone else is the same document
+        assert_eq!(
+            stable_document_id(&sign(content, &keys[2], "https://example.com")),
+            id
+        );
+
+        assert_ne!(stable_document_id(&doc.replace("second", "2nd")), id);
+        assert_ne!(stable_document_id(""), id);
+    }
+
+    #[test]
+    fn embedded_keys_verify_only_their_own_signatures() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let key_b64 = URL_SAFE.encode(beyarkay_key().verifying_key().as_bytes());
+        let extensions = format!("{EMBEDDED_KEY_EXTENSION}={key_b64}");
+        let sign_embedding = |extensions: &str, signer: &SigningKey| {
+            let signature = signer.sign(&signed_message_with_extensions(
+                PROVENANCE_VERSION,
+                url,
+                extensions,
+                b"document text here",
+            ));
+            format!(
+                "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} {url} {} {extensions} {PROVENANCE_POSTAMBLE}\ndocument text here",
+                signature_to_b64(&signature)
+            )
+        };
+
+        let signed_doc = sign_embedding(&extensions, &beyarkay_key());
+        let (_, doc, key) = verify_with_embedded_key(&signed_doc).unwrap();
+        assert_eq!(
+            (doc, key),
+            ("document text here", beyarkay_key().verifying_key())
+        );
+
+        let other = crate::test_util::deterministic_signing_key("other");
+        assert!(verify_with_embedded_key(&sign_embedding(&extensions, &other)).is_err());
+        assert!(verify_with_embedded_key(&sign_embedding("key=AAAA", &other)).is_err());
+        assert!(
+            verify_with_embedded_key(&sign("document text here", &beyarkay_key(), url)).is_err()
+        );
+    }
+
+    #[test]
+    fn verify_with_key_works() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signed_doc = sign("document text here", &beyarkay_key(), url);
+
+        let (header, doc) = verify_with_key(&signed_doc, &beyarkay_key().verifying_key()).unwrap();
+
+        assert_eq!(header.url, url);
+        assert_eq!(header.version, PROVENANCE_VERSION);
+        assert_eq!(doc, "document text here");
+    }
+
+    #[test]
+    fn signature_b64_round_trips_and_appears_in_errors() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signed_doc = sign("document text here", &beyarkay_key(), url);
+        let header = parse_header(signed_doc.lines().next().unwrap()).unwrap();
+
+        let signature = signature_from_b64(header.signature_b64).unwrap();
+        assert_eq!(signature_to_b64(&signature), header.signature_b64);
+        assert!(signature_from_b64("not base64!").is_err());
+
+        // The error shows the signature as it's written in the header, not in hex
+        let tampered = signed_doc.replace("document", "tampered");
+        let wrong_key = SigningKey::from_bytes(&[1; 32]).verifying_key();
+        let error = verify_with_key(&tampered, &wrong_key).unwrap_err();
+        assert!(error.to_string().contains(header.signature_b64));
+    }
+
+    #[test]
+    fn verify_against_raw_key_works() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signed_doc = sign("document text here", &beyarkay_key(), url);
+        let key_bytes = beyarkay_key().verifying_key().to_bytes();
+
+        let (header, doc) = verify_against_raw_key(&signed_doc, &key_bytes).unwrap();
+        assert_eq!(header.url, url);
+        assert_eq!(doc, "document text here");
+
+        let other_key = crate::test_util::deterministic_signing_key("not beyarkay");
+        assert!(
+            verify_against_raw_key(&signed_doc, &other_key.verifying_key().to_bytes()).is_err()
+        );
+
+        // Not every 32 bytes are a point on the curve: there is no point with y = 2
+        let mut not_a_point = [0; 32];
+        not_a_point[0] = 2;
+        let error = verify_against_raw_key(&signed_doc, &not_a_point).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("isn't a valid ed25519 public key"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn verify_with_key_fails_with_wrong_key() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signed_doc = sign("document text here", &beyarkay_key(), url);
+        let other_key = crate::test_util::deterministic_signing_key("not beyarkay");
+
+        assert!(verify_with_key(&signed_doc, &other_key.verifying_key()).is_err());
+    }
+
+    #[test]
+    fn verify_with_key_fails_if_doc_is_mutated() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signed_doc = sign("document text here", &beyarkay_key(), url);
+        let mutated_doc = format!("{signed_doc} and then some extra data");
+
+        assert!(verify_with_key(&mutated_doc, &beyarkay_key().verifying_key()).is_err());
+    }
+
+    #[test]
+    fn verify_signature_rejects_weak_keys() {
+        use ed25519_dalek::Verifier;
+
+        // The identity point is a small-order key: with R also the identity and S = 0, the
+        // signature satisfies the (non-strict) verification equation for *any* message
+        let mut identity = [0; 32];
+        identity[0] = 1;
+        let weak_key = VerifyingKey::from_bytes(&identity).unwrap();
+        let mut forged = [0; ed25519_dalek::SIGNATURE_LENGTH];
+        forged[..32].copy_from_slice(&identity);
+        let forged = Signature::from_bytes(&forged);
+
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let message = signed_message(PROVENANCE_VERSION, url, b"anything at all");
+        assert!(weak_key.verify(&message, &forged).is_ok());
+
+        assert!(verify_signature(
+            PROVENANCE_VERSION,
+            url,
+            &forged,
+            b"anything at all",
+            &weak_key
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn re_encoded_and_weak_signatures_are_not_canonical() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let verification_key = beyarkay_key().verifying_key();
+        // Find a signature whose encoding differs between the two base64 alphabets
+        let signed_doc = (0..)
+            .map(|i| sign(&format!("document {i}"), &beyarkay_key(), url))
+            .find(|doc| doc.contains(['-', '_']))
+            .unwrap();
+        assert!(is_outer_signature_canonical(&signed_doc, &verification_key));
+
+        let header = parse_header(signed_doc.split_once('\n').unwrap().0).unwrap();
+        let standard =
+            STANDARD.encode(signature_from_b64(header.signature_b64).unwrap().to_bytes());
+        let re_encoded = signed_doc.replacen(header.signature_b64, &standard, 1);
+        assert!(verify_with_key(&re_encoded, &verification_key).is_ok());
+        assert!(!is_outer_signature_canonical(
+            &re_encoded,
+            &verification_key
+        ));
+
+        // The weak key and signature which satisfy the non-strict equation for any message
+        let mut identity = [0; 32];
+        identity[0] = 1;
+        let weak_key = VerifyingKey::from_bytes(&identity).unwrap();
+        let mut forged = [0; ed25519_dalek::SIGNATURE_LENGTH];
+        forged[..32].copy_from_slice(&identity);
+        let forged = format!(
+            "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} {url} {} {PROVENANCE_POSTAMBLE}\nanything",
+            signature_to_b64(&Signature::from_bytes(&forged))
+        );
+        assert!(!is_outer_signature_canonical(&forged, &weak_key));
+
+        let other_key = crate::test_util::deterministic_signing_key("not beyarkay").verifying_key();
+        assert!(!is_outer_signature_canonical(&signed_doc, &other_key));
+        assert!(!is_outer_signature_canonical(
+            "document text here",
+            &verification_key
+        ));
+    }
+
+    #[test]
+    fn verify_signature_rejects_malleable_signatures() {
+        // The order of the ed25519 base point, little-endian
+        const L: [u8; 32] = [
+            0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9,
+            0xde, 0x14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+        ];
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let body = b"document text here";
+        let signing_key = beyarkay_key();
+        let signature = signing_key.sign(&signed_message(PROVENANCE_VERSION, url, body));
+
+        // Adding L to S gives a non-canonical S which is equivalent modulo L
+        let mut malleated = signature.to_bytes();
+        let mut carry = 0u16;
+        for (s, l) in malleated[32..].iter_mut().zip(L) {
+            let sum = *s as u16 + l as u16 + carry;
+            *s = sum as u8;
+            carry = sum >> 8;
+        }
+        let malleated = Signature::from_bytes(&malleated);
+        assert_ne!(malleated, signature);
+
+        let verification_key = signing_key.verifying_key();
+        assert!(
+            verify_signature(PROVENANCE_VERSION, url, &signature, body, &verification_key).is_ok()
+        );
+        assert!(
+            verify_signature(PROVENANCE_VERSION, url, &malleated, body, &verification_key).is_err()
+        );
+    }
+
+    #[test]
+    fn parse_header_reads_extensions() {
+        let header = format!(
+            "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} url signature a=1 b=two {PROVENANCE_POSTAMBLE}"
+        );
+        let parsed = parse_header(&header).unwrap();
+        assert_eq!(parsed.signature_b64, "signature");
+        assert_eq!(parsed.extensions, "a=1 b=two");
+        assert_eq!(parsed.extension("b"), Some("two"));
+        assert_eq!(parsed.extension("c"), None);
+
+        let plain = format!(
+            "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} url signature {PROVENANCE_POSTAMBLE}"
+        );
+        assert_eq!(parse_header(&plain).unwrap().extensions, "");
+
+        for bad in ["not-a-pair", "=value", "a=1 a=2"] {
+            let header = format!(
+                "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} url signature {bad} {PROVENANCE_POSTAMBLE}"
+            );
+            assert!(parse_header(&header).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn adversarial_headers_are_errors_not_panics() {
+        let key = beyarkay_key().verifying_key();
+        let header = format!(
+            "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} url signature a=1 {PROVENANCE_POSTAMBLE}"
+        );
+        let bytes = header.as_bytes();
+        assert!(parse_header_bytes(bytes).is_ok());
+
+        // Every truncation, including ones which cut an emoji marker in half
+        for end in 0..bytes.len() {
+            assert!(parse_header_bytes(&bytes[..end]).is_err(), "{end}");
+            if let Ok(truncated) = core::str::from_utf8(&bytes[..end]) {
+                let doc = format!("{truncated}\ndocument");
+                assert!(parse_header(truncated).is_err());
+                assert!(verify_with_key(&doc, &key).is_err());
+                assert!(raw_layers(&doc).len() <= 1);
+            }
+        }
+        for bad in [
+            &b""[..],
+            b" ",
+            b"    ",
+            b"\r",
+            b"\xf0\x9f\x94 0.3.0 url sig \xf0\x9f\x94",
+            b"\xff\xfe\xfd\xfc\xfb",
+            "🔏 🔏 🔏 🔏 🔏".as_bytes(),
+            "~~🔏 0.3.0 url sig = 🔏~~".as_bytes(),
+            "~~🔏 0.3.0 url sig 🔏 🔏~~".as_bytes(),
+            "~~🔏 0.3.0 url sig ==  🔏~~".as_bytes(),
+        ] {
+            assert!(parse_header_bytes(bad).is_err(), "{bad:?}");
+            let doc = [bad, b"\ndocument"].concat();
+            if let Ok(doc) = core::str::from_utf8(&doc) {
+                assert!(verify_with_key(doc, &key).is_err());
+                raw_layers(doc);
+                split_layers(doc);
+                stable_document_id(doc);
+            }
+        }
+    }
+
+    #[test]
+    fn signatures_without_the_domain_separator_are_rejected() {
+        let signing_key = crate::test_util::deterministic_signing_key("alice");
+        let verification_key = signing_key.verifying_key();
+        let url = "https://example.com/alice";
+        let doc = "document text here";
+
+        // A signature over the raw document, as some other protocol might make
+        let raw_signature = signing_key.sign(doc.as_bytes());
+        // A signature over the message as it was signed before the domain separator was added
+        let undomained_signature =
+            signing_key.sign(format!("{PROVENANCE_VERSION} {url}\n{doc}").as_bytes());
+
+        for signature in [raw_signature, undomained_signature] {
+            let signed_doc = format!(
+                "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} {url} {} {PROVENANCE_POSTAMBLE}\n{doc}",
+                signature_to_b64(&signature)
+            );
+            assert!(verify_with_key(&signed_doc, &verification_key).is_err());
+        }
+
+        assert!(
+            signed_message(PROVENANCE_VERSION, url, doc.as_bytes()).starts_with(DOMAIN_SEPARATOR)
+        );
+        assert!(
+            signed_message_with_extensions(PROVENANCE_VERSION, url, "a=b", doc.as_bytes())
+                .starts_with(DOMAIN_SEPARATOR)
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Private claims: metadata about a layer which only chosen recipients can read.
+//!
+//! [`sign_with_private_claims`] encrypts some claims (any text, for example JSON describing how
+//! the document was made) to one or more [age](https://age-encryption.org) recipients, and embeds
+//! the ciphertext in the provenance header (as `claims=<ciphertext>`). The ciphertext is covered
+//! by the signer's signature like any other header extension, so anyone can still
+//! [`verify`](crate::verify) the layer, but only a recipient holding a matching identity can
+//! [`decrypt_claims`].
+//!
+//! Recipients and identities are given in age's usual text encoding (`age1...` and
+//! `AGE-SECRET-KEY-1...`), as printed by `age-keygen`. Only X25519 recipients are understood.
+
+use crate::offline::{find_extension, parse_header, split_header};
+use crate::provenance::sign_with_extensions;
+use age::x25519;
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use ed25519_dalek::SigningKey;
+use std::io::{Read, Write};
+
+/// The header extension holding a layer's encrypted claims.
+pub const CLAIMS_EXTENSION: &str = "claims";
+
+/// Sign a document like [`sign`](crate::sign), with `claims` encrypted to every one of
+/// `recipients` and embedded in the header.
+pub fn sign_with_private_claims(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    claims: &str,
+    recipients: &[&str],
+) -> anyhow::Result<String> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| {
+            recipient
+                .parse::<x25519::Recipient>()
+                .map_err(|e| anyhow!("Invalid age recipient '{recipient}': {e}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )?;
+    let mut ciphertext = vec![];
+    let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+    writer.write_all(claims.as_bytes())?;
+    writer.finish()?;
+
+    let claims = URL_SAFE.encode(ciphertext);
+    Ok(sign_with_extensions(
+        doc,
+        signing_key,
+        url,
+        &[(CLAIMS_EXTENSION, &claims)],
+    ))
+}
+
+/// Returns true if the outermost layer of `signed_doc` has encrypted claims.
+pub fn has_private_claims(signed_doc: &str) -> bool {
+    split_header(signed_doc)
+        .and_then(|(first, _)| parse_header(first).ok())
+        .is_some_and(|header| header.extension(CLAIMS_EXTENSION).is_some())
+}
+
+/// Decrypt the claims in the outermost layer of `signed_doc` with `identity`.
+///
+/// This doesn't verify the layer: claims from a layer which fails [`verify`](crate::verify)
+/// can't be trusted to come from its signer, even though they decrypt.
+pub fn decrypt_claims(signed_doc: &str, identity: &str) -> anyhow::Result<String> {
+    let identity = identity
+        .parse::<x25519::Identity>()
+        .map_err(|e| anyhow!("Invalid age identity: {e}"))?;
+    let Some((first, _)) = split_header(signed_doc) else {
+        return Err(anyhow!("Document doesn't have a provenance header"));
+    };
+    let Some(claims_b64) = find_extension(parse_header(first)?.extensions, CLAIMS_EXTENSION) else {
+        return Err(anyhow!("Layer doesn't have any private claims"));
+    };
+    let ciphertext = URL_SAFE
+        .decode(claims_b64)
+        .map_err(|e| anyhow!("Couldn't decode private claims from base64: {e}"))?;
+
+    let decryptor = age::Decryptor::new_buffered(&ciphertext[..])?;
+    let mut claims = String::new();
+    decryptor
+        .decrypt(std::iter::once(&identity as &dyn age::Identity))
+        .map_err(|e| anyhow!("Couldn't decrypt private claims: {e}"))?
+        .read_to_string(&mut claims)?;
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sign, test_util, verify};
+    use age::secrecy::ExposeSecret;
+
+    #[test]
+    fn only_recipients_can_read_the_claims() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let [alice, bob, eve] = [(); 3].map(|()| x25519::Identity::generate());
+        let [alice_secret, bob_secret, eve_secret] =
+            [&alice, &bob, &eve].map(|identity| identity.to_string().expose_secret().to_string());
+        let claims = r#"{"camera": "X100V", "location": "Cape Town"}"#;
+
+        let signed_doc = sign_with_private_claims(
+            "document text here",
+            &signing_key,
+            url,
+            claims,
+            &[&alice.to_public().to_string(), &bob.to_public().to_string()],
+        )
+        .unwrap();
+        assert!(has_private_claims(&signed_doc));
+        assert!(!signed_doc.contains("Cape Town"));
+
+        // Anyone can verify the signature, but only recipients can read the claims
+        let (result, remainder) = verify(&signed_doc);
+        assert_eq!(result.unwrap().verification_url, url);
+        assert_eq!(remainder, "document text here");
+        assert_eq!(decrypt_claims(&signed_doc, &alice_secret).unwrap(), claims);
+        assert_eq!(decrypt_claims(&signed_doc, &bob_secret).unwrap(), claims);
+        assert!(decrypt_claims(&signed_doc, &eve_secret).is_err());
+
+        // The ciphertext is signed, so it can't be swapped out
+        let (header, _) = signed_doc.split_once('\n').unwrap();
+        let claims_b64 =
+            find_extension(parse_header(header).unwrap().extensions, CLAIMS_EXTENSION).unwrap();
+        let other = sign_with_private_claims(
+            "document text here",
+            &signing_key,
+            url,
+            "forged claims",
+            &[&eve.to_public().to_string()],
+        )
+        .unwrap();
+        let (other_header, _) = other.split_once('\n').unwrap();
+        let other_b64 = find_extension(
+            parse_header(other_header).unwrap().extensions,
+            CLAIMS_EXTENSION,
+        )
+        .unwrap();
+        assert!(verify(&signed_doc.replacen(claims_b64, other_b64, 1))
+            .0
+            .is_err());
+
+        let plain = sign("document text here", &signing_key, url);
+        assert!(!has_private_claims(&plain));
+        assert!(decrypt_claims(&plain, &alice_secret).is_err());
+        assert!(sign_with_private_claims("doc", &signing_key, url, claims, &[]).is_err());
+        assert!(
+            sign_with_private_claims("doc", &signing_key, url, claims, &["age1notakey"]).is_err()
+        );
+    }
+}
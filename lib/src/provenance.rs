@@ -0,0 +1,5894 @@
+//! Signing documents, and verifying them by fetching keys from provenance servers.
+//!
+//! Everything in this module needs the standard library (and the network), so it is only
+//! available with the default `std` feature. The items are re-exported from the crate root.
+
+use crate::armor;
+use crate::binary;
+use crate::normalize::{self, normalize_body, DocumentNormalizer};
+use crate::observer::VerifyObserver;
+use crate::offline::{
+    check_signature, decode_base64_token, embedded_key, find_extension, hashed_message,
+    key_fingerprint, parse_header, parse_header_layout, parse_header_with_markers,
+    parse_raw_header, prehashed_message, raw_layers, signature_to_b64, signed_message,
+    signed_message_with_extensions, split_header, starts_with_header, verify_with_key,
+    Base64Signature, Base64VerifyingKey, HashAlgorithm, ParsedHeader, ALGORITHM_EXTENSION,
+    EMBEDDED_KEY_EXTENSION, PREHASHED_ALGORITHM, PROVENANCE_POSTAMBLE, PROVENANCE_PREAMBLE,
+    PROVENANCE_VERSION,
+};
+use crate::version::{is_newer_than_current, VersionSpec};
+#[cfg(feature = "webauthn")]
+use crate::webauthn;
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, SIGNATURE_LENGTH};
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::{Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How the provenance header is encoded when signing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SigningMethod {
+    /// A human-readable line of text, see [`format_header`].
+    #[default]
+    Text,
+    /// A compact binary encoding, see [`binary`].
+    Binary,
+}
+
+/// Preamble and postamble of a seal header, see [`seal`].
+pub(crate) const SEAL_PREAMBLE: &str = "~~🔐";
+pub(crate) const SEAL_POSTAMBLE: &str = "🔐~~";
+
+/// Whether the URL in a provenance header is covered by that header's signature.
+///
+/// The URL (and version) of each layer are signed along with the document (see
+/// [`signed_message`]), so the URL of a layer can't be changed without invalidating that layer's
+/// signature: [`rehome_url`] refuses to rewrite URLs, and [`rehome_and_resign`] has to re-sign
+/// every layer it rewrites.
+pub const URL_IS_SIGNED: bool = true;
+
+/// Documents longer than this many bytes are signed with Ed25519ph by [`sign`], see
+/// [`sign_with_threshold`].
+pub const DEFAULT_PREHASH_THRESHOLD: usize = 4 * 1024 * 1024;
+
+/// How far ahead of the verifier's clock a layer's timestamp can be by default, see
+/// [`VerifyOptions::clock_skew`].
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Options which control how documents are verified.
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// If set, send a cheap HEAD request to each provenance URL (with this timeout) before
+    /// fetching its verification key. Servers which don't respond in time are reported as
+    /// [`VerifyError::Unreachable`] straight away, rather than waiting for the full GET request
+    /// to time out.
+    pub preflight_timeout: Option<Duration>,
+    /// Normalizers (besides the [built-in](normalize::builtin) ones) which layers might have been
+    /// signed with, see [`sign_normalized`].
+    pub normalizers: Vec<Arc<dyn DocumentNormalizer>>,
+    /// The protocol versions to accept. Layers signed with any other version fail with
+    /// [`VerifyError::UnsupportedVersion`] (unless [`VerifyOptions::newer_version_grace`] is
+    /// turned on). Defaults to only this crate's version.
+    pub accepted_versions: VersionSpec,
+    /// Give layers signed with a version newer than this crate's (and not otherwise accepted) a
+    /// grace period: they're parsed using only the layout every version shares, and if their
+    /// signature checks out they verify with the status
+    /// [`VerifyStatus::VerifiedWithUnknownVersion`], since anything the newer version added was
+    /// ignored. If it doesn't check out, they fail with [`VerifyError::UnsupportedVersion`] as
+    /// usual, since the newer version may have changed how layers are signed.
+    ///
+    /// Defaults to false. Callers who turn it on must check [`SignerDetails::status`], since a
+    /// layer verified under the grace period is still `Ok`.
+    pub newer_version_grace: bool,
+    /// What to do when a layer's provenance server can't be reached. Defaults to
+    /// [`NetworkFailureMode::FailClosed`].
+    pub network_failure_mode: NetworkFailureMode,
+    /// Stop at the first layer which is [forged](VerifyStatus::Forged), without verifying (or
+    /// fetching keys for) the layers inside it. Layers whose server is unreachable don't stop
+    /// verification. See [`ProvenanceChain::stopped_early`].
+    pub stop_on_first_failure: bool,
+    /// How far the verifier's clock can be behind the clocks of timestamping authorities. Layers
+    /// timestamped further in the future than this fail with [`VerifyError::TimestampInFuture`].
+    /// Defaults to [`DEFAULT_CLOCK_SKEW`].
+    pub clock_skew: Duration,
+    /// If set, only accept layers signed at most this long ago, going by their trusted
+    /// timestamps (see the `timestamp` module). Older layers fail with [`VerifyError::TooOld`],
+    /// and layers without a trusted timestamp fail with [`VerifyError::AgeUnknown`].
+    pub max_age: Option<Duration>,
+    /// Told about every layer verified with these options, see the [`observer`](crate::observer)
+    /// module.
+    pub observers: Vec<Arc<dyn VerifyObserver>>,
+    /// Timestamping authorities whose timestamps are trusted, see the `timestamp` module.
+    /// Without the `timestamp` feature there's no way to make one, and timestamps aren't checked.
+    pub timestamp_authorities: Vec<TimestampAuthority>,
+    /// Root certificates to trust when fetching verification keys over HTTPS, on top of the
+    /// system's own, for provenance servers whose certificates come from a private CA. Trusting
+    /// them needs the `custom-ca` feature: without it, fetching keys with any root certificates
+    /// here fails, rather than quietly not trusting them.
+    pub root_certificates: Vec<reqwest::Certificate>,
+}
+
+/// A timestamping authority whose tokens are trusted, identified by its public key. Made with
+/// the constructors in the `timestamp` module (behind the `timestamp` feature).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampAuthority {
+    /// The authority's DER-encoded `SubjectPublicKeyInfo`, which the constructors have checked
+    /// parses.
+    pub(crate) public_key_der: Vec<u8>,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            preflight_timeout: None,
+            normalizers: vec![],
+            accepted_versions: VersionSpec::default(),
+            newer_version_grace: false,
+            network_failure_mode: NetworkFailureMode::default(),
+            stop_on_first_failure: false,
+            clock_skew: DEFAULT_CLOCK_SKEW,
+            max_age: None,
+            observers: vec![],
+            timestamp_authorities: vec![],
+            root_certificates: vec![],
+        }
+    }
+}
+
+#[cfg(feature = "custom-ca")]
+impl VerifyOptions {
+    /// Also trust the PEM-encoded root certificate `pem`, see [`VerifyOptions::root_certificates`].
+    pub fn with_root_certificate_pem(mut self, pem: &[u8]) -> anyhow::Result<Self> {
+        let certificate = reqwest::Certificate::from_pem(pem)
+            .map_err(|e| anyhow!("Couldn't parse PEM root certificate: {e}"))?;
+        self.root_certificates.push(certificate);
+        Ok(self)
+    }
+
+    /// Like [`VerifyOptions::with_root_certificate_pem`], reading the certificate from `path`.
+    pub fn with_root_certificate_file(
+        self,
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow!("Couldn't read root certificate from {path:?}: {e}"))?;
+        self.with_root_certificate_pem(&pem)
+    }
+}
+
+/// What verifying reports for a layer whose provenance server can't be reached, see
+/// [`VerifyOptions::network_failure_mode`].
+///
+/// Either way the layer is an error, since nothing was verified, and [`VerifyStatus::of`]
+/// reports it as [`VerifyStatus::Unreachable`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NetworkFailureMode {
+    /// The layer fails with [`VerifyError::Unreachable`], like any other layer which doesn't
+    /// verify.
+    #[default]
+    FailClosed,
+    /// The layer fails with [`VerifyError::Unverified`], which carries what the layer's header
+    /// claims about it, so that callers can show the document with a warning rather than
+    /// rejecting it outright.
+    SoftWarn,
+}
+
+/// Apply `options.network_failure_mode` to the `result` of verifying the layer with `header`.
+fn apply_network_failure_mode(
+    result: anyhow::Result<SignerDetails>,
+    header: &str,
+    options: &VerifyOptions,
+) -> anyhow::Result<SignerDetails> {
+    if options.network_failure_mode == NetworkFailureMode::FailClosed {
+        return result;
+    }
+
+    let Err(e) = result else {
+        return result;
+    };
+    match (
+        e.downcast_ref::<VerifyError>(),
+        parse_raw_header(header, 0..header.len()),
+    ) {
+        (Some(VerifyError::Unreachable { url }), Some(header)) => Err(VerifyError::Unverified {
+            url: url.clone(),
+            kind: LayerKind::of(header.extensions).unwrap_or_default(),
+            group: GroupSignerDetails::of(header.url, header.extensions),
+            terms: layer_terms(header.extensions).unwrap_or_default(),
+        }
+        .into()),
+        _ => Err(e),
+    }
+}
+
+/// Errors which callers might want to handle specifically when verification fails.
+///
+/// These are returned inside an [`anyhow::Error`], so use
+/// [`downcast_ref`](anyhow::Error::downcast_ref) to check for them.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum VerifyError {
+    /// The provenance server at `url` couldn't be reached, so the layer could neither be
+    /// verified nor shown to be forged.
+    #[error("Provenance server at '{url}' is unreachable")]
+    Unreachable { url: String },
+    /// Like [`VerifyError::Unreachable`], for a layer verified from a bundle (see the `bundle`
+    /// module) which doesn't contain a key for `url`. There's no way to check the layer offline,
+    /// but it isn't known to be forged either.
+    #[error("Bundle doesn't contain a key for '{url}'")]
+    KeyNotBundled { url: String },
+    /// Like [`VerifyError::Unreachable`], under [`NetworkFailureMode::SoftWarn`]: the provenance
+    /// server at `url` couldn't be reached, so the layer is unverified. It carries what the
+    /// layer's header claims (see [`SignerDetails`] for what each means), none of which has been
+    /// checked, so it must only be shown alongside a warning.
+    #[error("Layer claiming to be signed by '{url}' is unverified, since its provenance server is unreachable")]
+    Unverified {
+        url: String,
+        kind: LayerKind,
+        group: Option<GroupSignerDetails>,
+        terms: Option<String>,
+    },
+    /// The layer was signed with a protocol version which isn't in
+    /// [`VerifyOptions::accepted_versions`].
+    #[error("Document version '{found}' isn't accepted (expected {accepted})")]
+    UnsupportedVersion {
+        found: String,
+        accepted: VersionSpec,
+    },
+    /// The layer was signed with version `found`, but [`verify_expecting_version`] was told to
+    /// expect exactly `expected`.
+    #[error("Document version '{found}' isn't the expected version '{expected}'")]
+    VersionMismatch { expected: String, found: String },
+    /// The layer's signature is valid base64, but decodes to `got` bytes rather than the
+    /// `expected` length of an ed25519 signature.
+    #[error("Signature is {got} bytes long, but should be {expected} bytes long")]
+    SignatureWrongLength { got: usize, expected: usize },
+    /// The provenance server at `url` presented a TLS certificate which isn't trusted. If it
+    /// comes from a private CA, trust the CA with [`VerifyOptions::root_certificates`] (behind
+    /// the `custom-ca` feature).
+    #[error("TLS certificate of provenance server at '{url}' isn't trusted")]
+    UntrustedCertificate { url: String },
+    /// The layer's trusted timestamp is later than the verifier's clock, by more than
+    /// [`VerifyOptions::clock_skew`].
+    #[error("Layer signed by '{url}' is timestamped {ahead:?} in the future")]
+    TimestampInFuture { url: String, ahead: Duration },
+    /// The layer's signature is valid, but under a different key than the one served at `url`:
+    /// the server is misconfigured, or someone is passing off their signature as another's.
+    ///
+    /// This can only be told apart from a tampered document when the header embeds the key
+    /// which made the signature (see [`sign_with_embedded_key`]). Otherwise the signature just
+    /// doesn't verify under the served key.
+    #[error("Layer is validly signed, but not with the key served at '{url}'")]
+    UrlKeyMismatch { url: String },
+    /// The layer was signed at `signed_at` (by its trusted timestamp), longer ago than
+    /// [`VerifyOptions::max_age`] allows.
+    #[error("Layer signed by '{url}' is older than the maximum age of {max_age:?}")]
+    TooOld {
+        url: String,
+        signed_at: SystemTime,
+        max_age: Duration,
+    },
+    /// [`VerifyOptions::max_age`] is set, but the layer has no trusted timestamp, so there's no
+    /// telling how long ago it was signed.
+    #[error("Layer signed by '{url}' has no trusted timestamp, so its age is unknown")]
+    AgeUnknown { url: String },
+    /// The layer's URL isn't an absolute `http` or `https` URL with a host, so no key could ever
+    /// be fetched from it.
+    #[error("URL '{url}' is malformed: {reason}")]
+    MalformedUrl { url: String, reason: String },
+    /// The provenance server at `url` says its signer has been deactivated (with `410 Gone`),
+    /// for example because their signing key was compromised.
+    #[error("Signer at '{url}' has been deactivated: {reason}")]
+    SignerDeactivated { url: String, reason: String },
+    /// The layer signed by `url` doesn't verify, but neither does the layer signed by
+    /// `mutated_by` outside it, which most likely changed the content after it was signed. Every
+    /// layer inside an edit fails, so this points at the edit rather than at each layer it broke.
+    /// See [`ProvenanceChain::mutated_by`].
+    #[error("Layer signed by '{url}' doesn't verify, most likely because '{mutated_by}' changed the document")]
+    FailedDueToUpstreamMutation { url: String, mutated_by: String },
+    /// The layer is validly signed, but its nonce was already seen in another layer (see
+    /// [`verify_with_seen_nonces`]), so the signature is being replayed.
+    #[error("Layer signed by '{url}' reuses the nonce '{nonce_b64}'")]
+    NonceReused { url: String, nonce_b64: String },
+}
+
+/// Who signed a layer, as established by verifying it.
+///
+/// There's deliberately no `Default`: an empty URL and an all-zero key would look like a real
+/// signer. Build one with [`SignerDetails::new`] instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignerDetails {
+    pub verification_url: String,
+    pub verification_key: VerifyingKey,
+    /// How the layer verified: [`VerifyStatus::Verified`], or
+    /// [`VerifyStatus::VerifiedWithUnknownVersion`] under
+    /// [`VerifyOptions::newer_version_grace`]. Layers which didn't verify are errors instead, see
+    /// [`VerifyStatus::of`].
+    pub status: VerifyStatus,
+    /// The time by which a trusted timestamping authority attests the layer existed, if the
+    /// layer has a timestamp (see the `timestamp` module, behind the `timestamp` feature).
+    pub timestamp: Option<SystemTime>,
+    /// The group and member, if the layer was signed by a group on behalf of one of its members
+    /// (see [`sign_as_member`]).
+    pub group: Option<GroupSignerDetails>,
+    /// What the signer did to the document, see [`sign_with_kind`].
+    pub kind: LayerKind,
+    /// Whether the header embeds the verification key, and if so whether it was confirmed
+    /// against the layer's URL, see [`sign_with_embedded_key`].
+    pub embedded_key: EmbeddedKey,
+    /// The usage terms the signer bound to the layer (like an SPDX license expression), see
+    /// [`sign_with_terms`]. They're only reported once the signature has verified, so they're
+    /// as trustworthy as the signer.
+    pub terms: Option<String>,
+}
+
+/// Whether a layer's header embeds its verification key, see [`SignerDetails::embedded_key`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum EmbeddedKey {
+    /// The header doesn't embed a key.
+    #[default]
+    Absent,
+    /// The signature matches the embedded key, which is also the key served at the layer's URL.
+    Confirmed,
+}
+
+/// The header extension recording what the signer of a layer did to the document.
+pub(crate) const KIND_EXTENSION: &str = "kind";
+
+/// What the signer of a layer did to the document, so that a chain can read "Joe captured it,
+/// then PhotoShack edited it, then joeblogs uploaded it". Recorded in the header, and signed,
+/// by [`sign_with_kind`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LayerKind {
+    /// The layer doesn't say. This is the kind of every layer signed with [`sign`], including
+    /// all layers signed before kinds were recorded.
+    #[default]
+    Unspecified,
+    /// The signer created the document, for example by taking the photo.
+    Captured,
+    /// The signer changed the document.
+    Edited,
+    /// The signer published the document as they received it.
+    Uploaded,
+}
+
+impl LayerKind {
+    /// The value of the `kind` header extension for this kind, or `None` for
+    /// [`LayerKind::Unspecified`], which isn't written into the header.
+    fn id(self) -> Option<&'static str> {
+        match self {
+            LayerKind::Unspecified => None,
+            LayerKind::Captured => Some("captured"),
+            LayerKind::Edited => Some("edited"),
+            LayerKind::Uploaded => Some("uploaded"),
+        }
+    }
+
+    /// The kind recorded in a layer's `extensions`.
+    pub(crate) fn of(extensions: &str) -> anyhow::Result<Self> {
+        let Some(id) = find_extension(extensions, KIND_EXTENSION) else {
+            return Ok(LayerKind::Unspecified);
+        };
+        [LayerKind::Captured, LayerKind::Edited, LayerKind::Uploaded]
+            .into_iter()
+            .find(|kind| kind.id() == Some(id))
+            .ok_or_else(|| anyhow!("Layer has unknown kind '{id}'"))
+    }
+}
+
+/// Who signed a layer signed with a group's key, see [`sign_as_member`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupSignerDetails {
+    /// The URL of the group's verification key, which is the layer's URL.
+    pub group_url: String,
+    /// The member of the group who the group attests signed the layer.
+    pub member_id: String,
+}
+
+impl GroupSignerDetails {
+    /// The group details recorded in a layer's `extensions`, if it has any.
+    pub(crate) fn of(url: &str, extensions: &str) -> Option<Self> {
+        find_extension(extensions, MEMBER_EXTENSION).map(|member_id| GroupSignerDetails {
+            group_url: url.to_string(),
+            member_id: member_id.to_string(),
+        })
+    }
+}
+
+impl SignerDetails {
+    /// The details of a verified layer signed by `verification_key`, served at
+    /// `verification_url`, with none of the optional details (timestamp, group, kind, embedded
+    /// key or terms) set.
+    pub fn new(verification_url: &str, verification_key: VerifyingKey) -> Self {
+        SignerDetails {
+            verification_url: verification_url.to_string(),
+            verification_key,
+            status: VerifyStatus::Verified,
+            timestamp: None,
+            group: None,
+            kind: LayerKind::Unspecified,
+            embedded_key: EmbeddedKey::Absent,
+            terms: None,
+        }
+    }
+
+    /// The fingerprint of this signer's verification key, see [`key_fingerprint`].
+    pub fn fingerprint(&self) -> [u8; 32] {
+        key_fingerprint(&self.verification_key)
+    }
+
+    /// The verification key in URL-safe base64, the same form provenance servers serve it in (as
+    /// `verification_key_b64`).
+    pub fn verification_key_b64(&self) -> String {
+        URL_SAFE.encode(self.verification_key.to_bytes())
+    }
+
+    /// The verification URL, parsed. This is always `Some` for details returned by verification,
+    /// which rejects malformed URLs with [`VerifyError::MalformedUrl`].
+    pub fn url(&self) -> Option<Url> {
+        parse_url(&self.verification_url).ok()
+    }
+
+    /// The host of the verification URL, such as `example.com`.
+    pub fn host(&self) -> Option<String> {
+        Some(self.url()?.host_str()?.to_string())
+    }
+
+    /// The scheme of the verification URL, `http` or `https`.
+    pub fn scheme(&self) -> Option<String> {
+        Some(self.url()?.scheme().to_string())
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct SignerDetailsFromServer {
+    pub verification_url: String,
+    pub verification_key_b64: String,
+    pub metadata: HashMap<String, String>,
+    /// The SEC1-encoded public key of the signer's passkey, if they have one (see
+    /// `crate::webauthn`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webauthn_key_b64: Option<String>,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct KeyDetails {
+    pub verification: String,
+    pub signing: String,
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone)]
+pub struct Username(String);
+
+/// Errors from [`request_new_key`] which callers might want to handle specifically.
+///
+/// These are returned inside an [`anyhow::Error`], so use
+/// [`downcast_ref`](anyhow::Error::downcast_ref) to check for them.
+#[derive(Debug, thiserror::Error)]
+pub enum KeyRequestError {
+    /// The server already has a key for `username`, so it won't make another one.
+    #[error("Username {username:?} already has a key on '{server}'")]
+    UsernameExists { server: String, username: String },
+    /// The server refused to make a key for some other reason, like an invalid username.
+    #[error("Provenance server at '{server}' refused to make a key for {username:?}: {status}")]
+    Rejected {
+        server: String,
+        username: String,
+        status: StatusCode,
+    },
+}
+
+/// Ask the provenance server at `server_base` (like `http://localhost:8000`) to make a new key
+/// pair for `username`, returning both keys.
+///
+/// Fails with [`KeyRequestError::UsernameExists`] if `username` already has a key.
+pub fn request_new_key(
+    server_base: &str,
+    username: &str,
+    client: &Client,
+) -> anyhow::Result<KeyDetails> {
+    let response = client
+        .get(format!(
+            "{}/generate_key/{username}",
+            server_base.trim_end_matches('/')
+        ))
+        .send()?;
+
+    let status = response.status();
+    if status == StatusCode::CONFLICT {
+        return Err(KeyRequestError::UsernameExists {
+            server: server_base.to_string(),
+            username: username.to_string(),
+        }
+        .into());
+    }
+    if !status.is_success() {
+        return Err(KeyRequestError::Rejected {
+            server: server_base.to_string(),
+            username: username.to_string(),
+            status,
+        }
+        .into());
+    }
+
+    response
+        .json()
+        .map_err(|e| anyhow!("Couldn't parse the keys from '{server_base}': {e}"))
+}
+
+/// The response to a successful [`import_keys`].
+#[derive(Debug, Deserialize)]
+struct ImportReport {
+    imported: usize,
+}
+
+/// Import existing signing keys into the provenance server at `server_base`, for migrating
+/// signers from another system without giving them new keys. Returns how many were imported.
+///
+/// `csv` has a `username,signing_key` row per signer, with base64 signing keys, and `admin_token`
+/// is the server's admin token. The server imports either every row or, if any row is invalid
+/// or any of the usernames already has a key, none of them, failing with its reasons.
+pub fn import_keys(
+    server_base: &str,
+    admin_token: &str,
+    csv: &str,
+    client: &Client,
+) -> anyhow::Result<usize> {
+    let response = client
+        .post(format!("{}/import", server_base.trim_end_matches('/')))
+        .bearer_auth(admin_token)
+        .header(CONTENT_TYPE, "text/csv")
+        .body(csv.to_string())
+        .send()?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let reason = response
+            .json::<ServerErrorBody>()
+            .ok()
+            .and_then(|body| body.error)
+            .unwrap_or_else(|| status.to_string());
+        return Err(anyhow!(
+            "Provenance server at '{server_base}' refused the import: {reason}"
+        ));
+    }
+
+    let report: ImportReport = response
+        .json()
+        .map_err(|e| anyhow!("Couldn't parse the import report from '{server_base}': {e}"))?;
+    Ok(report.imported)
+}
+
+/// What every request signed with [`sign_server_request`] starts with, as the provenance server
+/// expects, so that a signed request can never be mistaken for a signed document.
+const REQUEST_SIGNATURE_PREFIX: &str = "provenance-server request\n";
+
+/// The `Authorization` header for a request which changes a signer's state on their provenance
+/// server (like registering a passkey or deactivating them), signed with the signer's own key.
+///
+/// `method` and `path` are those of the request, like `POST` and `/deactivate/alice`, and
+/// `body` is its body. Anyone who sees the header can only replay that exact request.
+pub fn sign_server_request(
+    method: &str,
+    path: &str,
+    body: &str,
+    signing_key: &SigningKey,
+) -> String {
+    let message = format!("{REQUEST_SIGNATURE_PREFIX}{method} {path}\n{body}");
+    let signature = signing_key.sign(message.as_bytes());
+    format!("Signature {}", URL_SAFE.encode(signature.to_bytes()))
+}
+
+/// Errors from [`verify_by_signer_fingerprint`] which callers might want to handle specifically.
+///
+/// Like [`KeyRequestError`], these are returned inside an [`anyhow::Error`].
+#[derive(Debug, thiserror::Error)]
+pub enum FingerprintError {
+    /// The server has no signer whose verification key has this fingerprint.
+    #[error("No signer on '{server}' has a key with fingerprint '{fingerprint_b64}'")]
+    NotRegistered {
+        server: String,
+        fingerprint_b64: String,
+    },
+    /// The signer was found at `url`, but didn't sign any verified layer of the document.
+    #[error("Document has no verified layer signed by '{url}'")]
+    NotInChain { url: String },
+}
+
+/// Verify that `doc` was signed by the signer whose verification key has the given
+/// [fingerprint](key_fingerprint), asking the provenance server at `server_base` (like
+/// `http://localhost:8000`) for their URL.
+///
+/// This is for when a signer has published their fingerprint, but not their provenance URL.
+/// Every layer of `doc` is verified, and the details of the outermost verified layer signed by
+/// that signer are returned. Fails with [`FingerprintError::NotRegistered`] if the server doesn't
+/// know the fingerprint, or [`FingerprintError::NotInChain`] if the signer didn't sign `doc`.
+pub fn verify_by_signer_fingerprint(
+    doc: &str,
+    fingerprint: &[u8; 32],
+    server_base: &str,
+) -> anyhow::Result<SignerDetails> {
+    let fingerprint_b64 = URL_SAFE.encode(fingerprint);
+    let lookup_url = format!(
+        "{}/lookup/{fingerprint_b64}",
+        server_base.trim_end_matches('/')
+    );
+    let response = Client::new().get(&lookup_url).send()?;
+    match response.status() {
+        StatusCode::NOT_FOUND => {
+            return Err(FingerprintError::NotRegistered {
+                server: server_base.to_string(),
+                fingerprint_b64,
+            }
+            .into())
+        }
+        StatusCode::GONE => {
+            let body = response.json::<ServerErrorBody>().ok();
+            return Err(deactivated(&lookup_url, body).into());
+        }
+        status if !status.is_success() => {
+            return Err(anyhow!("GET request to {lookup_url} failed: {status}"))
+        }
+        _ => {}
+    }
+    let signer: SignerDetailsFromServer = response.json()?;
+
+    // The server could answer with anyone, so only trust the URL if the key it serves matches
+    let key: VerifyingKey = Base64VerifyingKey(signer.verification_key_b64).try_into()?;
+    if key_fingerprint(&key) != *fingerprint {
+        return Err(anyhow!(
+            "'{server_base}' answered the lookup for '{fingerprint_b64}' with a different key"
+        ));
+    }
+
+    verify_all(doc)
+        .0
+        .into_iter()
+        .flatten()
+        .find(|details| {
+            details.verification_url == signer.verification_url
+                && details.fingerprint() == *fingerprint
+        })
+        .ok_or_else(|| {
+            FingerprintError::NotInChain {
+                url: signer.verification_url,
+            }
+            .into()
+        })
+}
+
+/// Given a provenance endpoint, retrieve the signer's details
+fn get_signer_details_from_url(
+    url: &str,
+    client: &Client,
+) -> anyhow::Result<SignerDetailsFromServer> {
+    // Get the server response
+    let response = client.get(url).send()?;
+    if response.status() == StatusCode::GONE {
+        let body = response.json::<ServerErrorBody>().ok();
+        return Err(deactivated(url, body).into());
+    }
+    // Check if it was successful
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "GET request to {url} failed: {}",
+            response.status()
+        ));
+    }
+
+    // If it was successful, convert the JSON blob into an object
+    Ok(response.json()?)
+}
+
+/// The JSON body a provenance server sends along with an error status.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ServerErrorBody {
+    /// What went wrong, as the server describes it.
+    error: Option<String>,
+    /// Why the signer was deactivated, for `410 Gone` responses.
+    reason: Option<String>,
+}
+
+/// The error for a `410 Gone` response from `url`, which means its signer has been deactivated.
+pub(crate) fn deactivated(url: &str, body: Option<ServerErrorBody>) -> VerifyError {
+    VerifyError::SignerDeactivated {
+        url: url.to_string(),
+        reason: body
+            .and_then(|body| body.reason)
+            .unwrap_or_else(|| "no reason given".to_string()),
+    }
+}
+
+/// Check that the server behind `url` is responding at all, using a HEAD request with a short
+/// timeout. Any HTTP response (even an error status) counts as the server being reachable.
+fn preflight(url: &str, client: &Client, timeout: Duration) -> Result<(), VerifyError> {
+    match client.head(url).timeout(timeout).send() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(preflight_error(url, &e)),
+    }
+}
+
+/// The error for a preflight request to `url` which got no response at all.
+pub(crate) fn preflight_error(url: &str, error: &reqwest::Error) -> VerifyError {
+    if is_certificate_error(error) {
+        VerifyError::UntrustedCertificate {
+            url: url.to_string(),
+        }
+    } else {
+        VerifyError::Unreachable {
+            url: url.to_string(),
+        }
+    }
+}
+
+/// Returns true if fetching from a provenance server failed because its TLS certificate wasn't
+/// trusted. The TLS backend doesn't give these errors a type of their own, so this looks for
+/// them by message.
+fn is_certificate_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    // Skip the outermost error, which includes the URL and so could say anything
+    let mut source = error.source();
+    while let Some(e) = source {
+        if e.to_string().to_lowercase().contains("certificate") {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// The client used to fetch verification keys, trusting any extra root certificates in
+/// `options`.
+#[cfg(feature = "custom-ca")]
+fn key_fetch_client(options: &VerifyOptions) -> anyhow::Result<Client> {
+    let client = options
+        .root_certificates
+        .iter()
+        .fold(Client::builder(), |builder, certificate| {
+            builder.add_root_certificate(certificate.clone())
+        })
+        .build()?;
+    Ok(client)
+}
+
+#[cfg(not(feature = "custom-ca"))]
+fn key_fetch_client(options: &VerifyOptions) -> anyhow::Result<Client> {
+    check_no_root_certificates(options)?;
+    Ok(Client::builder().build()?)
+}
+
+/// Fail if `options` has root certificates, which can't be trusted without the `custom-ca`
+/// feature (see [`VerifyOptions::root_certificates`]).
+#[cfg(not(feature = "custom-ca"))]
+pub(crate) fn check_no_root_certificates(options: &VerifyOptions) -> anyhow::Result<()> {
+    if !options.root_certificates.is_empty() {
+        return Err(anyhow!(
+            "Trusting extra root certificates needs the 'custom-ca' feature"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns true if fetching from a provenance server failed because the server couldn't be
+/// reached at all, rather than because it responded with something unexpected.
+fn is_unreachable(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.is_connect() || e.is_timeout())
+}
+
+/// Verify that a given document has been signed, and return the signatory's details.
+///
+/// The process for verifying a document has been properly signed is:
+///
+/// - Extract the provenance version, url, base64-encoded signature, and underlying document from
+///   the signed document
+/// - decode the signature from base64 into a sequence of bytes
+/// - query the URL to get the information about the signer such as the verification key, username,
+///   display name, and details about how the image came to be ("captured", "edited", etc)
+/// - use the verification key to verify that the signer did indeed sign the unmodified document
+/// - Return the details of the signing and signer.
+///
+/// [Armored](crate::armor) documents are taken out of their armor first, and the remainder
+/// returned is then the remainder of the document inside the armor.
+pub fn verify(signed_doc: &str) -> (anyhow::Result<SignerDetails>, String) {
+    verify_with_options(signed_doc, &VerifyOptions::default())
+}
+
+/// Like [`verify`], but with [`VerifyOptions`] to control how the verification is done.
+pub fn verify_with_options(
+    signed_doc: &str,
+    options: &VerifyOptions,
+) -> (anyhow::Result<SignerDetails>, String) {
+    let signed_doc = match dearmored(signed_doc) {
+        Ok(signed_doc) => signed_doc,
+        Err(e) => return (Err(e), signed_doc.to_string()),
+    };
+    verify_outermost(&signed_doc, options, &mut KeyFetch::default())
+}
+
+/// `signed_doc` taken out of its [armor](crate::armor), or as-is if it isn't armored.
+pub(crate) fn dearmored(signed_doc: &str) -> anyhow::Result<Cow<'_, str>> {
+    if !armor::is_armored(signed_doc) {
+        return Ok(Cow::Borrowed(signed_doc));
+    }
+    String::from_utf8(armor::dearmor(signed_doc)?)
+        .map(Cow::Owned)
+        .map_err(|_| anyhow!("Armored document isn't valid UTF-8, use `verify_bytes` instead"))
+}
+
+/// Like [`verify`], but fail with [`VerifyError::VersionMismatch`] unless the outermost layer
+/// was signed with exactly `version`, for callers who know which version a document should have
+/// (say, from metadata sent alongside it).
+///
+/// This is stricter than [`VerifyOptions::accepted_versions`]: `version` must match the header
+/// character for character. Armored documents are dearmored first, and documents whose header
+/// can't be parsed fail, since there's no telling which version they were signed with.
+pub fn verify_expecting_version(
+    signed_doc: &str,
+    version: &str,
+) -> (anyhow::Result<SignerDetails>, String) {
+    let signed_doc = match dearmored(signed_doc) {
+        Ok(signed_doc) => signed_doc,
+        Err(e) => return (Err(e), signed_doc.to_string()),
+    };
+    let Some((first, doc)) = split_header(&signed_doc) else {
+        return (
+            Err(anyhow!(
+                "Document has only one line, therefore cannot be signed"
+            )),
+            signed_doc.to_string(),
+        );
+    };
+    let found = match parse_header(first) {
+        Ok(header) => header.version,
+        // Newer versions may only parse with the layout every version shares
+        Err(e) => match parse_header_layout(first) {
+            Some(header) => header.version,
+            None => return (Err(e), doc.to_string()),
+        },
+    };
+    if found != version {
+        let mismatch = VerifyError::VersionMismatch {
+            expected: version.to_string(),
+            found: found.to_string(),
+        };
+        return (Err(mismatch.into()), doc.to_string());
+    }
+
+    verify(&signed_doc)
+}
+
+/// A single condition checked by [`inspect_validity`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValidityCheck {
+    /// The header is well-formed, with a supported algorithm and a signature of the right length.
+    Header,
+    /// The layer's protocol version is in [`VerifyOptions::accepted_versions`].
+    Version,
+    /// The layer's kind (see [`sign_with_kind`]) is one this crate knows.
+    Kind,
+    /// The verification key could be fetched from the layer's URL.
+    KeyFetched,
+    /// The signature matches the document under the fetched key.
+    Signature,
+    /// The key embedded in the header (see [`sign_with_embedded_key`]) is the fetched key.
+    EmbeddedKey,
+    /// The member a group signed on behalf of (see [`sign_as_member`]) is in the group.
+    Membership,
+    /// The layer's trusted timestamp (see the `timestamp` module) is valid, and not in the
+    /// future by more than [`VerifyOptions::clock_skew`].
+    Timestamp,
+    /// The layer is no older than [`VerifyOptions::max_age`].
+    Age,
+}
+
+/// Every condition [`inspect_validity`] checked on a layer, and whether each one held.
+#[derive(Debug)]
+pub struct ValidityReport {
+    /// The URL in the layer's header, or `None` if the header couldn't be parsed.
+    pub verification_url: Option<String>,
+    /// The outcome of each check, in the order they were made. Checks which don't apply to the
+    /// layer (like [`ValidityCheck::Membership`] for a layer not signed by a group), or which
+    /// couldn't be made because something they need failed (like [`ValidityCheck::Signature`]
+    /// without a key), are left out.
+    pub checks: Vec<(ValidityCheck, anyhow::Result<()>)>,
+}
+
+impl ValidityReport {
+    /// Whether every check passed, in which case [`verify_with_options`] would succeed too.
+    pub fn is_valid(&self) -> bool {
+        self.checks.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// The checks which failed, and why.
+    pub fn failures(&self) -> impl Iterator<Item = (ValidityCheck, &anyhow::Error)> {
+        self.checks
+            .iter()
+            .filter_map(|(check, result)| result.as_ref().err().map(|e| (*check, e)))
+    }
+}
+
+/// Run every check [`verify_with_options`] makes on the outermost layer of `signed_doc`, without
+/// stopping at the first failure, so that a document failing for several reasons at once
+/// ("signature valid, but too old and from an unsupported version") reports all of them.
+///
+/// Layers signed with a passkey aren't understood, and fail [`ValidityCheck::Header`].
+pub fn inspect_validity(signed_doc: &str, options: &VerifyOptions) -> ValidityReport {
+    let mut report = ValidityReport {
+        verification_url: None,
+        checks: vec![],
+    };
+    let Some((first, doc)) = split_header(signed_doc) else {
+        report.checks.push((
+            ValidityCheck::Header,
+            Err(anyhow!(
+                "Document has only one line, therefore cannot be signed"
+            )),
+        ));
+        return report;
+    };
+    let (header, signature) = match decode_header(first) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            report.checks.push((ValidityCheck::Header, Err(e)));
+            return report;
+        }
+    };
+    report.verification_url = Some(header.url.to_string());
+    report.checks.push((ValidityCheck::Header, Ok(())));
+
+    let body = normalize_body(&header, doc.as_bytes(), &options.normalizers);
+    let layer = check_layer_fully(
+        &header,
+        &signature,
+        body.as_deref().map(SignedBody::Bytes),
+        options,
+        |url| fetch_verification_key(url, options, &mut KeyFetch::default()),
+        |group| check_membership(group, options),
+        true,
+    );
+    report.checks.extend(layer.checks);
+
+    report
+}
+
+/// Like [`verify_with_options`], recording how the verification key was fetched in `fetch`.
+fn verify_outermost(
+    signed_doc: &str,
+    options: &VerifyOptions,
+    fetch: &mut KeyFetch,
+) -> (anyhow::Result<SignerDetails>, String) {
+    let Some((first, doc)) = split_header(signed_doc) else {
+        return (
+            Err(anyhow!(
+                "Document has only one line, therefore cannot be signed"
+            )),
+            signed_doc.to_string(),
+        );
+    };
+
+    let result = apply_network_failure_mode(
+        verify_header(first, doc.as_bytes(), options, fetch),
+        first,
+        options,
+    );
+    notify_observers(signed_doc, &result, options);
+
+    (result, doc.to_string())
+}
+
+/// Tell each of [`VerifyOptions::observers`] the result of verifying the outermost layer of
+/// `signed_doc`.
+pub(crate) fn notify_observers(
+    signed_doc: &str,
+    result: &anyhow::Result<SignerDetails>,
+    options: &VerifyOptions,
+) {
+    if options.observers.is_empty() {
+        return;
+    }
+    let url = outermost_signer_url(signed_doc).unwrap_or_default();
+    for observer in &options.observers {
+        observer.on_verify(&url, result);
+    }
+}
+
+/// Verify a provenance header and the document body it signs, when the two are supplied
+/// separately (for example as two fields of a multipart upload, or from a detached signature).
+///
+/// This runs the same checks as [`verify`], but without needing to first concatenate the header
+/// and the body into a single signed document. A single trailing line ending (`\n` or `\r\n`)
+/// on the header is ignored.
+pub fn verify_parts(header: &str, body: &[u8]) -> anyhow::Result<SignerDetails> {
+    let header = match split_header(header) {
+        Some((header, "")) => header,
+        Some(_) => return Err(anyhow!("Header must be a single line")),
+        None => header,
+    };
+
+    verify_header(
+        header,
+        body,
+        &VerifyOptions::default(),
+        &mut KeyFetch::default(),
+    )
+}
+
+/// Verify a document which might have either a text or a binary provenance header.
+///
+/// Binary headers are detected by their magic prefix (see [`binary::MAGIC`]), anything else is
+/// treated as a document with a text header. Like [`verify`], the remainder of the document is
+/// returned alongside the result.
+pub fn verify_bytes(signed_doc: &[u8]) -> (anyhow::Result<SignerDetails>, Vec<u8>) {
+    if let Some(text) = std::str::from_utf8(signed_doc)
+        .ok()
+        .filter(|text| armor::is_armored(text))
+    {
+        return match armor::dearmor(text) {
+            Ok(dearmored) => verify_bytes(&dearmored),
+            Err(e) => (Err(e), signed_doc.to_vec()),
+        };
+    }
+    if binary::is_binary(signed_doc) {
+        return match binary::decode_header(signed_doc) {
+            Ok((header, doc)) => {
+                let options = VerifyOptions::default();
+                let layer = ParsedHeader {
+                    version: &header.version,
+                    url: &header.url,
+                    signature_b64: &signature_to_b64(&header.signature),
+                    extensions: "",
+                };
+                let result = verify_layer(
+                    &layer,
+                    &header.signature,
+                    SignedBody::Bytes(doc),
+                    &options,
+                    |url| fetch_verification_key(url, &options, &mut KeyFetch::default()),
+                    |group| check_membership(group, &options),
+                );
+                (result, doc.to_vec())
+            }
+            Err(e) => (Err(e), signed_doc.to_vec()),
+        };
+    }
+
+    let Some(newline) = signed_doc.iter().position(|&byte| byte == b'\n') else {
+        return (
+            Err(anyhow!(
+                "Document has only one line, therefore cannot be signed"
+            )),
+            signed_doc.to_vec(),
+        );
+    };
+    let (first, doc) = (&signed_doc[..newline], &signed_doc[newline + 1..]);
+    let Ok(first) = std::str::from_utf8(first) else {
+        return (
+            Err(anyhow!("Document header isn't valid UTF-8")),
+            doc.to_vec(),
+        );
+    };
+
+    (
+        verify_header(
+            first,
+            doc,
+            &VerifyOptions::default(),
+            &mut KeyFetch::default(),
+        ),
+        doc.to_vec(),
+    )
+}
+
+/// Check that `header` is a well-formed provenance header, and that the signature it contains
+/// was made over `body` by the key served at the header's URL.
+fn verify_header(
+    header: &str,
+    body: &[u8],
+    options: &VerifyOptions,
+    fetch: &mut KeyFetch,
+) -> anyhow::Result<SignerDetails> {
+    #[cfg(feature = "webauthn")]
+    if let Some(header) = passkey_header(header) {
+        let body = normalize_body(&header, body, &options.normalizers)?;
+        check_layer(header.version, header.url, options)?;
+        let signer_details = fetch_signer_details(header.url, options, fetch)?;
+        return verify_passkey_layer(&header, &body, &signer_details);
+    }
+
+    verify_header_with(
+        header,
+        body,
+        options,
+        |url| fetch_verification_key(url, options, fetch),
+        |group| check_membership(group, options),
+    )
+}
+
+/// Ask the group's provenance server whether the member a layer was signed on behalf of is
+/// actually in the group. The server lists members at `<group url>/members/<member id>`.
+///
+/// The request is made like a key fetch, so with the preflight request and root certificates
+/// from `options`.
+fn check_membership(group: &GroupSignerDetails, options: &VerifyOptions) -> anyhow::Result<()> {
+    let url = format!("{}/members/{}", group.group_url, group.member_id);
+    let client = key_fetch_client(options)?;
+    if let Some(timeout) = options.preflight_timeout {
+        preflight(&group.group_url, &client, timeout)?;
+    }
+
+    membership(
+        group,
+        client.get(&url).send().map(|response| response.status()),
+    )
+}
+
+/// Whether the member of `group` is in it, given the status the group's server answered the
+/// membership request with (see [`check_membership`]).
+pub(crate) fn membership(
+    group: &GroupSignerDetails,
+    status: reqwest::Result<StatusCode>,
+) -> anyhow::Result<()> {
+    match status {
+        Ok(status) if status.is_success() => Ok(()),
+        Ok(_) => Err(anyhow!(
+            "'{}' isn't a member of the group at '{}'",
+            group.member_id,
+            group.group_url
+        )),
+        Err(e) if is_certificate_error(&e) => Err(VerifyError::UntrustedCertificate {
+            url: group.group_url.clone(),
+        }
+        .into()),
+        Err(e) if e.is_connect() || e.is_timeout() => Err(VerifyError::Unreachable {
+            url: group.group_url.clone(),
+        }
+        .into()),
+        Err(_) => Err(anyhow!(
+            "Couldn't check membership at '{}/members/{}'",
+            group.group_url,
+            group.member_id
+        )),
+    }
+}
+
+/// Like [`verify_header`], but getting the verification key from `resolve` instead of fetching it
+/// from the header's URL, and checking group membership with `check_member`.
+pub(crate) fn verify_header_with(
+    header: &str,
+    body: &[u8],
+    options: &VerifyOptions,
+    resolve: impl FnOnce(&str) -> anyhow::Result<VerifyingKey>,
+    check_member: impl FnOnce(&GroupSignerDetails) -> anyhow::Result<()>,
+) -> anyhow::Result<SignerDetails> {
+    let (header, signature) = decode_header_with_grace(header, options)?;
+    let body = normalize_body(&header, body, &options.normalizers)?;
+
+    verify_layer(
+        &header,
+        &signature,
+        SignedBody::Bytes(&body),
+        options,
+        resolve,
+        check_member,
+    )
+}
+
+/// Like [`decode_header`], but also accepting a header from a newer version within
+/// [`VerifyOptions::newer_version_grace`], whose fields may not parse.
+pub(crate) fn decode_header_with_grace<'a>(
+    header: &'a str,
+    options: &VerifyOptions,
+) -> anyhow::Result<(ParsedHeader<'a>, Signature)> {
+    match decode_header(header) {
+        Ok(decoded) => Ok(decoded),
+        Err(e) => match parse_header_layout(header) {
+            Some(header) if in_version_grace(header.version, options) => {
+                let signature = decode_signature(header.signature_b64)?;
+                Ok((header, signature))
+            }
+            _ => Err(e),
+        },
+    }
+}
+
+/// Verify that a given document has been signed, like [`verify`], but get the verification key
+/// for the document's URL from `resolver` instead of fetching it over the network.
+///
+/// The resolver is given the URL in the document's header, and can do whatever it likes with it:
+/// look it up in a map, read a key from a file, or call some other service. Any error it returns
+/// is passed straight through.
+///
+/// ```
+/// use provenance_rs::{sign, verify_with};
+/// use ed25519_dalek::SigningKey;
+///
+/// let signing_key = SigningKey::from_bytes(&[7; 32]);
+/// let verification_key = signing_key.verifying_key();
+/// let signed_doc = sign("Some document", &signing_key, "https://example.com/me");
+///
+/// let (result, _) = verify_with(&signed_doc, |_url| Ok(verification_key));
+/// assert!(result.is_ok());
+/// ```
+pub fn verify_with<F>(signed_doc: &str, resolver: F) -> (anyhow::Result<SignerDetails>, String)
+where
+    F: Fn(&str) -> anyhow::Result<VerifyingKey>,
+{
+    let signed_doc = match dearmored(signed_doc) {
+        Ok(signed_doc) => signed_doc,
+        Err(e) => return (Err(e), signed_doc.to_string()),
+    };
+    let Some((first, doc)) = split_header(&signed_doc) else {
+        return (
+            Err(anyhow!(
+                "Document has only one line, therefore cannot be signed"
+            )),
+            signed_doc.to_string(),
+        );
+    };
+
+    let options = VerifyOptions::default();
+    let result = verify_header_with(first, doc.as_bytes(), &options, resolver, |group| {
+        check_membership(group, &options)
+    });
+    (result, doc.to_string())
+}
+
+/// Like [`verify_all`], but getting the verification key for each layer's URL from `resolver`, as
+/// in [`verify_with`]. Seals aren't understood, and fail to verify.
+pub fn verify_all_with<F>(
+    signed_doc: &str,
+    resolver: F,
+) -> (Vec<anyhow::Result<SignerDetails>>, String)
+where
+    F: Fn(&str) -> anyhow::Result<VerifyingKey>,
+{
+    let mut verifications = vec![];
+    let mut doc = match dearmored(signed_doc) {
+        Ok(signed_doc) => signed_doc.into_owned(),
+        Err(e) => return (vec![Err(e)], signed_doc.to_string()),
+    };
+    while starts_with_header(&doc) {
+        let (result, remainder) = verify_with(&doc, &resolver);
+        verifications.push(result);
+        doc = remainder;
+    }
+
+    (verifications, doc)
+}
+
+/// Like [`verify_all`], but fetching the keys for every layer up front, with at most
+/// `concurrency` fetches in flight at once, before verifying the layers one by one.
+///
+/// Fetching keys is almost all of the time spent verifying a long chain, so this lets the network
+/// phase be tuned on its own. Each URL is fetched once, even if it appears in several layers. A
+/// `concurrency` of zero is treated as one. Seals aren't understood, and fail to verify.
+pub fn verify_all_prefetched(
+    signed_doc: &str,
+    concurrency: usize,
+) -> (Vec<anyhow::Result<SignerDetails>>, String) {
+    let options = VerifyOptions::default();
+    verify_all_prefetched_with(signed_doc, concurrency, |url| fetch_key(url, &options))
+}
+
+/// Like [`verify_all_prefetched`], but getting the verification key for each layer's URL from
+/// `resolver`, as in [`verify_with`]. The resolver is called from several threads at once.
+pub fn verify_all_prefetched_with<F>(
+    signed_doc: &str,
+    concurrency: usize,
+    resolver: F,
+) -> (Vec<anyhow::Result<SignerDetails>>, String)
+where
+    F: Fn(&str) -> anyhow::Result<VerifyingKey> + Sync,
+{
+    let doc = match dearmored(signed_doc) {
+        Ok(signed_doc) => signed_doc,
+        Err(e) => return (vec![Err(e)], signed_doc.to_string()),
+    };
+    let mut urls = vec![];
+    for layer in raw_layers(&doc) {
+        if !urls.contains(&layer.url) {
+            urls.push(layer.url);
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let keys = Mutex::new(HashMap::new());
+    std::thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, urls.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(url) = urls.get(index) else {
+                    break;
+                };
+                let key = resolver(url);
+                keys.lock().unwrap().insert(*url, key);
+            });
+        }
+    });
+
+    let keys = keys.into_inner().unwrap();
+    verify_all_with(&doc, |url| match keys.get(url) {
+        Some(Ok(key)) => Ok(*key),
+        // A URL in several layers fails the same way for each of them
+        Some(Err(e)) => Err(e
+            .downcast_ref::<VerifyError>()
+            .cloned()
+            .map_or_else(|| anyhow!("{e:#}"), Into::into)),
+        None => Err(anyhow!("No verification key was fetched for url '{url}'")),
+    })
+}
+
+/// Like [`verify_parts`], but getting the verification key for the header's URL from `resolver`,
+/// as in [`verify_with`].
+pub fn verify_parts_with<F>(header: &str, body: &[u8], resolver: F) -> anyhow::Result<SignerDetails>
+where
+    F: Fn(&str) -> anyhow::Result<VerifyingKey>,
+{
+    let header = match split_header(header) {
+        Some((header, "")) => header,
+        Some(_) => return Err(anyhow!("Header must be a single line")),
+        None => header,
+    };
+
+    let options = VerifyOptions::default();
+    verify_header_with(header, body, &options, resolver, |group| {
+        check_membership(group, &options)
+    })
+}
+
+/// Parse a text provenance header and decode the signature inside it.
+pub(crate) fn decode_header(header: &str) -> anyhow::Result<(ParsedHeader<'_>, Signature)> {
+    let header = parse_header(header)?;
+    if let Some(algorithm) = header
+        .extension(ALGORITHM_EXTENSION)
+        .filter(|algorithm| HashAlgorithm::from_algorithm(algorithm).is_none())
+    {
+        return Err(anyhow!(
+            "Document was signed with unsupported algorithm '{algorithm}'"
+        ));
+    }
+
+    let signature = decode_signature(header.signature_b64)?;
+
+    Ok((header, signature))
+}
+
+/// Decode the base64 signature from a header, telling apart a signature which isn't base64 from
+/// one which is the wrong length (see [`VerifyError::SignatureWrongLength`]).
+fn decode_signature(signature_b64: &str) -> anyhow::Result<Signature> {
+    let bytes = decode_base64_token(signature_b64)
+        .map_err(|e| anyhow!("Signature '{signature_b64}' isn't valid base64: {e}"))?;
+    let Ok(bytes) = <[u8; SIGNATURE_LENGTH]>::try_from(bytes.as_slice()) else {
+        return Err(VerifyError::SignatureWrongLength {
+            got: bytes.len(),
+            expected: SIGNATURE_LENGTH,
+        }
+        .into());
+    };
+
+    Ok(Signature::from_bytes(&bytes))
+}
+
+/// Verify the outermost layer of a signed document using signer details which have already been
+/// fetched from the provenance server, instead of fetching them again.
+///
+/// The URL in `signer_details` must be the same as the URL in the document's header, otherwise
+/// the details belong to a different signer and verification fails. The layer is otherwise
+/// checked like [`verify`] checks it, so a layer signed on behalf of a group still asks the
+/// group's server whether the member is in it.
+pub fn verify_with_signer_details(
+    signed_doc: &str,
+    signer_details: &SignerDetailsFromServer,
+) -> (anyhow::Result<SignerDetails>, String) {
+    let Some((first, doc)) = split_header(signed_doc) else {
+        return (
+            Err(anyhow!(
+                "Document has only one line, therefore cannot be signed"
+            )),
+            signed_doc.to_string(),
+        );
+    };
+
+    (
+        verify_header_with_signer_details(first, doc.as_bytes(), signer_details),
+        doc.to_string(),
+    )
+}
+
+/// Like [`verify_header`], but using already-fetched signer details instead of the network.
+fn verify_header_with_signer_details(
+    header: &str,
+    body: &[u8],
+    signer_details: &SignerDetailsFromServer,
+) -> anyhow::Result<SignerDetails> {
+    let url = parse_header(header)?.url;
+    if url != signer_details.verification_url {
+        return Err(anyhow!(
+            "Signer details are for '{}', but the document was signed by '{url}'",
+            signer_details.verification_url,
+        ));
+    }
+
+    #[cfg(feature = "webauthn")]
+    if let Some(header) = passkey_header(header) {
+        let body = normalize_body(&header, body, &[])?;
+        return verify_passkey_layer(&header, &body, signer_details);
+    }
+
+    let options = VerifyOptions::default();
+    verify_header_with(
+        header,
+        body,
+        &options,
+        |_| Base64VerifyingKey(signer_details.verification_key_b64.clone()).try_into(),
+        |group| check_membership(group, &options),
+    )
+}
+
+/// The parsed `header`, if it is well-formed and signed with a passkey.
+#[cfg(feature = "webauthn")]
+fn passkey_header(header: &str) -> Option<ParsedHeader<'_>> {
+    parse_header(header)
+        .ok()
+        .filter(|header| header.extension(ALGORITHM_EXTENSION) == Some(webauthn::ALGORITHM))
+}
+
+/// Check a layer signed with a passkey against the passkey advertised in `signer_details`.
+///
+/// Passkeys belong to a single signer, so a passkey layer claiming to be signed on behalf of a
+/// group (see [`sign_as_member`]) is rejected rather than reported without checking membership.
+#[cfg(feature = "webauthn")]
+fn verify_passkey_layer(
+    header: &ParsedHeader<'_>,
+    body: &[u8],
+    signer_details: &SignerDetailsFromServer,
+) -> anyhow::Result<SignerDetails> {
+    if header.extension(MEMBER_EXTENSION).is_some() {
+        return Err(anyhow!(
+            "Passkey layer from '{}' can't be signed on behalf of a group",
+            header.url
+        ));
+    }
+    let Some(passkey) = &signer_details.webauthn_key_b64 else {
+        return Err(anyhow!(
+            "Provenance server at '{}' doesn't advertise a passkey",
+            header.url
+        ));
+    };
+    webauthn::verify_layer(header, body, passkey)?;
+
+    Ok(SignerDetails {
+        verification_url: header.url.to_string(),
+        verification_key: Base64VerifyingKey(signer_details.verification_key_b64.clone())
+            .try_into()?,
+        status: VerifyStatus::Verified,
+        timestamp: None,
+        group: None,
+        kind: LayerKind::of(header.extensions)?,
+        embedded_key: EmbeddedKey::Absent,
+        terms: layer_terms(header.extensions)?,
+    })
+}
+
+/// What a layer's signature was made over, as checked by [`check_layer_fully`].
+#[derive(Clone, Copy)]
+pub(crate) enum SignedBody<'a> {
+    /// The (normalized) document under the layer.
+    Bytes(&'a [u8]),
+    /// The [`prehashed_message`] of an Ed25519ph layer, for documents hashed as they were read
+    /// rather than held in memory.
+    #[cfg(feature = "async")]
+    Prehashed(&'a sha2::Sha512),
+}
+
+impl SignedBody<'_> {
+    /// Check `signature` was made over this body by `key`, see [`check_signature`].
+    fn check_signature(
+        self,
+        header: &ParsedHeader<'_>,
+        signature: &Signature,
+        key: &VerifyingKey,
+    ) -> anyhow::Result<()> {
+        match self {
+            SignedBody::Bytes(body) => check_signature(
+                header.version,
+                header.url,
+                header.extensions,
+                signature,
+                body,
+                key,
+            ),
+            #[cfg(feature = "async")]
+            SignedBody::Prehashed(hash) => key
+                .verify_prehashed_strict(hash.clone(), None, signature)
+                .map_err(|_| {
+                    anyhow!(
+                        "Document signature '{}' could not be verified",
+                        signature_to_b64(signature)
+                    )
+                }),
+        }
+    }
+}
+
+/// Check the fields of a single provenance layer (however they were encoded), getting the
+/// verification key for its URL from `resolve` and using it to check `signature` was made over
+/// `body`. If the layer was signed on behalf of a group, `check_member` says whether the signer
+/// is in it.
+pub(crate) fn verify_layer(
+    header: &ParsedHeader<'_>,
+    signature: &Signature,
+    body: SignedBody<'_>,
+    options: &VerifyOptions,
+    resolve: impl FnOnce(&str) -> anyhow::Result<VerifyingKey>,
+    check_member: impl FnOnce(&GroupSignerDetails) -> anyhow::Result<()>,
+) -> anyhow::Result<SignerDetails> {
+    let (url, extensions) = (header.url, header.extensions);
+    let layer = check_layer_fully(
+        header,
+        signature,
+        Ok(body),
+        options,
+        resolve,
+        check_member,
+        false,
+    );
+    if let Some(e) = layer
+        .checks
+        .into_iter()
+        .find_map(|(_, result)| result.err())
+    {
+        return Err(e);
+    }
+    let verification_key = layer
+        .verification_key
+        .expect("the key was resolved, since every check passed");
+
+    Ok(SignerDetails {
+        verification_url: url.to_string(),
+        verification_key,
+        status: if layer.unknown_version {
+            VerifyStatus::VerifiedWithUnknownVersion
+        } else {
+            VerifyStatus::Verified
+        },
+        timestamp: layer.timestamp,
+        group: GroupSignerDetails::of(url, extensions),
+        kind: LayerKind::of(extensions)?,
+        embedded_key: layer.embedded_key,
+        terms: layer_terms(extensions)?,
+    })
+}
+
+/// The outcome of every check made on a single layer by [`check_layer_fully`], along with what
+/// the checks found out about the layer.
+#[derive(Debug, Default)]
+struct LayerChecks {
+    /// The outcome of each check, in the order they were made (see [`ValidityReport::checks`]).
+    checks: Vec<(ValidityCheck, anyhow::Result<()>)>,
+    verification_key: Option<VerifyingKey>,
+    embedded_key: EmbeddedKey,
+    timestamp: Option<SystemTime>,
+    /// The layer verified under [`VerifyOptions::newer_version_grace`].
+    unknown_version: bool,
+}
+
+impl LayerChecks {
+    fn push(&mut self, check: ValidityCheck, result: anyhow::Result<()>) {
+        self.checks.push((check, result));
+    }
+
+    fn failed(&self) -> bool {
+        self.checks.iter().any(|(_, result)| result.is_err())
+    }
+}
+
+/// Make every check [`verify_layer`] makes on a layer (however it was encoded), getting the
+/// verification key for its URL from `resolve` and checking group membership with
+/// `check_member`. This is the single place those checks live, so that [`inspect_validity`]
+/// reports exactly what verifying would.
+///
+/// Unless `exhaustive` is set, the checks stop at the first failure, so that nothing more is
+/// fetched for a layer which has already failed. Otherwise every check which can be made is,
+/// leaving out those which need something that failed (like [`ValidityCheck::Signature`]
+/// without a key).
+fn check_layer_fully(
+    header: &ParsedHeader<'_>,
+    signature: &Signature,
+    body: Result<SignedBody<'_>, &anyhow::Error>,
+    options: &VerifyOptions,
+    resolve: impl FnOnce(&str) -> anyhow::Result<VerifyingKey>,
+    check_member: impl FnOnce(&GroupSignerDetails) -> anyhow::Result<()>,
+    exhaustive: bool,
+) -> LayerChecks {
+    let (version, url, extensions) = (header.version, header.url, header.extensions);
+    let body_error = |e: &anyhow::Error| anyhow!("{e}");
+    let mut layer = LayerChecks::default();
+    let stop = |layer: &LayerChecks| !exhaustive && layer.failed();
+
+    // Under the grace period, an unknown version is only reported if the signature fails
+    let version_check = layer.checks.len();
+    let grace_error = match check_layer(version, url, options) {
+        Err(e) if is_version_error(&e) && in_version_grace(version, options) => {
+            layer.push(ValidityCheck::Version, Ok(()));
+            Some(e)
+        }
+        result => {
+            layer.push(ValidityCheck::Version, result);
+            None
+        }
+    };
+    if stop(&layer) {
+        return layer;
+    }
+    layer.push(ValidityCheck::Kind, LayerKind::of(extensions).map(|_| ()));
+    if stop(&layer) {
+        return layer;
+    }
+
+    match resolve(url) {
+        Ok(key) => {
+            layer.push(ValidityCheck::KeyFetched, Ok(()));
+            layer.verification_key = Some(key);
+            match embedded_key(extensions) {
+                Ok(None) => {}
+                Ok(Some(embedded)) if embedded == key => {
+                    layer.embedded_key = EmbeddedKey::Confirmed;
+                    layer.push(ValidityCheck::EmbeddedKey, Ok(()));
+                }
+                Ok(Some(embedded)) => {
+                    let mismatch = match body {
+                        Ok(body) => url_key_mismatch(header, signature, body, &embedded),
+                        Err(_) => VerifyError::UrlKeyMismatch {
+                            url: url.to_string(),
+                        }
+                        .into(),
+                    };
+                    layer.push(ValidityCheck::EmbeddedKey, Err(mismatch));
+                }
+                Err(e) => layer.push(ValidityCheck::EmbeddedKey, Err(e)),
+            }
+            if stop(&layer) {
+                return layer;
+            }
+
+            let signature_check = body
+                .map_err(body_error)
+                .and_then(|body| body.check_signature(header, signature, &key));
+            match (signature_check, grace_error) {
+                (Ok(()), grace_error) => {
+                    layer.unknown_version = grace_error.is_some();
+                    layer.push(ValidityCheck::Signature, Ok(()));
+                }
+                (Err(e), Some(grace_error)) => {
+                    // The newer version may have changed how layers are signed
+                    layer.checks[version_check].1 = Err(grace_error);
+                    layer.push(ValidityCheck::Signature, Err(e));
+                }
+                (Err(e), None) => layer.push(ValidityCheck::Signature, Err(e)),
+            }
+        }
+        Err(e) => layer.push(ValidityCheck::KeyFetched, Err(e)),
+    }
+    if stop(&layer) {
+        return layer;
+    }
+
+    #[cfg(feature = "timestamp")]
+    {
+        use crate::timestamp::{attested_time, TIMESTAMP_EXTENSION};
+        let attested = match body {
+            _ if header.extension(TIMESTAMP_EXTENSION).is_none() => Ok(None),
+            Ok(SignedBody::Bytes(body)) => attested_time(
+                version,
+                url,
+                extensions,
+                body,
+                &options.timestamp_authorities,
+            ),
+            #[cfg(feature = "async")]
+            Ok(SignedBody::Prehashed(_)) => Err(anyhow!(
+                "Layers with a '{TIMESTAMP_EXTENSION}' extension can't be verified while streaming"
+            )),
+            Err(e) => Err(body_error(e)),
+        };
+        match attested {
+            Ok(Some(timestamp)) => {
+                let in_future = crate::timestamp::check_not_in_future(
+                    url,
+                    timestamp,
+                    SystemTime::now(),
+                    options.clock_skew,
+                );
+                layer.push(ValidityCheck::Timestamp, in_future.map_err(Into::into));
+                layer.timestamp = Some(timestamp);
+            }
+            Ok(None) => {}
+            Err(e) => layer.push(ValidityCheck::Timestamp, Err(e)),
+        }
+        if stop(&layer) {
+            return layer;
+        }
+    }
+    if let Some(max_age) = options.max_age {
+        let age_check = check_age(url, layer.timestamp, SystemTime::now(), max_age);
+        layer.push(ValidityCheck::Age, age_check);
+        if stop(&layer) {
+            return layer;
+        }
+    }
+
+    if let Some(group) = GroupSignerDetails::of(url, extensions) {
+        layer.push(ValidityCheck::Membership, check_member(&group));
+    }
+
+    layer
+}
+
+/// The error for a layer whose embedded key isn't the key served at its URL: a
+/// [`VerifyError::UrlKeyMismatch`] if the embedded key made the signature, or the signature
+/// error if the document was tampered with.
+fn url_key_mismatch(
+    header: &ParsedHeader<'_>,
+    signature: &Signature,
+    body: SignedBody<'_>,
+    embedded_key: &VerifyingKey,
+) -> anyhow::Error {
+    match body.check_signature(header, signature, embedded_key) {
+        Ok(()) => VerifyError::UrlKeyMismatch {
+            url: header.url.to_string(),
+        }
+        .into(),
+        Err(e) => e,
+    }
+}
+
+/// Fail if a layer signed at `signed_at` is more than `max_age` old at `now`, or if there's no
+/// trusted timestamp to say when it was signed.
+fn check_age(
+    url: &str,
+    signed_at: Option<SystemTime>,
+    now: SystemTime,
+    max_age: Duration,
+) -> anyhow::Result<()> {
+    let Some(signed_at) = signed_at else {
+        return Err(VerifyError::AgeUnknown {
+            url: url.to_string(),
+        }
+        .into());
+    };
+    match now.duration_since(signed_at) {
+        Ok(age) if age > max_age => Err(VerifyError::TooOld {
+            url: url.to_string(),
+            signed_at,
+            max_age,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Keys fetched while verifying a single document, and how the key for the current layer was
+/// obtained (see [`ChainLayer`]).
+#[derive(Debug, Default)]
+pub(crate) struct KeyFetch {
+    /// Signer details which have already been fetched, by URL, so that a URL which appears in
+    /// several layers is only fetched once.
+    cache: HashMap<String, SignerDetailsFromServer>,
+    duration: Option<Duration>,
+    from_cache: bool,
+}
+
+impl KeyFetch {
+    /// Forget how the previous layer's key was obtained, keeping the cache.
+    fn next_layer(&mut self) {
+        self.duration = None;
+        self.from_cache = false;
+    }
+}
+
+/// Check the version and URL of a layer are usable before getting its verification key.
+pub(crate) fn check_layer(version: &str, url: &str, options: &VerifyOptions) -> anyhow::Result<()> {
+    parse_url(url)?;
+    if !options.accepted_versions.accepts(version) {
+        return Err(VerifyError::UnsupportedVersion {
+            found: version.to_string(),
+            accepted: options.accepted_versions.clone(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Whether a layer signed with `version` can be verified under
+/// [`VerifyOptions::newer_version_grace`].
+pub(crate) fn in_version_grace(version: &str, options: &VerifyOptions) -> bool {
+    options.newer_version_grace && is_newer_than_current(version)
+}
+
+/// Whether `error`, from [`check_layer`], rejects the layer's version rather than its URL.
+pub(crate) fn is_version_error(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref(),
+        Some(VerifyError::UnsupportedVersion { .. })
+    )
+}
+
+/// Parse a layer's URL, which must be an absolute `http` or `https` URL with a host.
+pub(crate) fn parse_url(url: &str) -> Result<Url, VerifyError> {
+    let malformed = |reason: &str| VerifyError::MalformedUrl {
+        url: url.to_string(),
+        reason: reason.to_string(),
+    };
+    let parsed = Url::parse(url).map_err(|e| malformed(&e.to_string()))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(malformed("the scheme must be http or https"));
+    }
+    if parsed.host_str().is_none_or(str::is_empty) {
+        return Err(malformed("there is no host"));
+    }
+
+    Ok(parsed)
+}
+
+/// Fetch the verification key served at `url`, just as verifying a layer signed by `url` would
+/// (so with the preflight request, root certificates and so on from `options`).
+///
+/// This is handy for resolvers (see [`verify_with`]) which cache keys, and only sometimes need to
+/// fetch them.
+pub fn fetch_key(url: &str, options: &VerifyOptions) -> anyhow::Result<VerifyingKey> {
+    fetch_verification_key(url, options, &mut KeyFetch::default())
+}
+
+/// Fetch the verification key from `url`.
+///
+/// The time spent on the network is recorded in `fetch`.
+pub(crate) fn fetch_verification_key(
+    url: &str,
+    options: &VerifyOptions,
+    fetch: &mut KeyFetch,
+) -> anyhow::Result<VerifyingKey> {
+    let signer_details = fetch_signer_details(url, options, fetch)?;
+
+    Base64VerifyingKey(signer_details.verification_key_b64)
+        .try_into()
+        .map_err(|_| anyhow!("Couldn't fetch verification key from url '{url}'"))
+}
+
+/// Like [`fetch_verification_key`], but returning everything the server said about the signer.
+fn fetch_signer_details(
+    url: &str,
+    options: &VerifyOptions,
+    fetch: &mut KeyFetch,
+) -> anyhow::Result<SignerDetailsFromServer> {
+    if let Some(signer_details) = fetch.cache.get(url) {
+        fetch.from_cache = true;
+        return Ok(signer_details.clone());
+    }
+
+    let client = key_fetch_client(options)?;
+
+    let start = Instant::now();
+    let fetched = match options.preflight_timeout {
+        Some(timeout) => preflight(url, &client, timeout)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| get_signer_details_from_url(url, &client)),
+        None => get_signer_details_from_url(url, &client),
+    };
+    fetch.duration = Some(start.elapsed());
+
+    let signer_details = fetched.map_err(|e| key_fetch_error(url, e))?;
+    fetch.cache.insert(url.to_string(), signer_details.clone());
+    Ok(signer_details)
+}
+
+/// The error to report when fetching the verification key from `url` failed with `error`.
+pub(crate) fn key_fetch_error(url: &str, error: anyhow::Error) -> anyhow::Error {
+    if error.is::<VerifyError>() {
+        error
+    } else if is_certificate_error(error.as_ref()) {
+        VerifyError::UntrustedCertificate {
+            url: url.to_string(),
+        }
+        .into()
+    } else if is_unreachable(&error) {
+        VerifyError::Unreachable {
+            url: url.to_string(),
+        }
+        .into()
+    } else {
+        anyhow!("Couldn't fetch verification key from url '{url}'")
+    }
+}
+
+/// Given a (possibly signed) document, verify all signers of that document.
+///
+/// This is similar to [`verify`], except it will return *all* signers. Each URL is only fetched
+/// once per call, even if it appears in several layers.
+pub fn verify_all(signed_doc: &str) -> (Vec<anyhow::Result<SignerDetails>>, String) {
+    verify_all_with_options(signed_doc, &VerifyOptions::default())
+}
+
+/// Like [`verify_all`], but with [`VerifyOptions`] to control how each layer is verified.
+///
+/// With [`VerifyOptions::stop_on_first_failure`], the last result is the first forged layer.
+/// Use [`verify_chain`] to tell whether verification stopped there or reached the bottom.
+pub fn verify_all_with_options(
+    signed_doc: &str,
+    options: &VerifyOptions,
+) -> (Vec<anyhow::Result<SignerDetails>>, String) {
+    let chain = verify_chain(signed_doc, options);
+    let verifications = chain.layers.into_iter().map(|layer| layer.result).collect();
+
+    (verifications, chain.remainder)
+}
+
+/// Whether every layer of `signed_doc` verifies, for gating (say, in CI) on a yes or no answer.
+///
+/// Layers are verified from the outside in, and this returns `Ok(false)` at the first one which
+/// doesn't verify, without fetching keys for the layers inside it. A document without any
+/// layers isn't verified, and neither is one with a layer from a newer protocol version (see
+/// [`VerifyStatus::VerifiedWithUnknownVersion`]). If a layer's provenance server can't be
+/// reached, whether the document verifies is unknown, so that is an `Err` with a
+/// [`VerifyError::Unreachable`] inside.
+pub fn is_fully_verified(signed_doc: &str) -> anyhow::Result<bool> {
+    let options = VerifyOptions::default();
+    let Ok(signed_doc) = dearmored(signed_doc) else {
+        return Ok(false);
+    };
+    let mut doc = signed_doc.into_owned();
+    let mut fetch = KeyFetch::default();
+    let mut layers = 0;
+
+    loop {
+        let sealed = is_sealed(&doc);
+        let (result, remainder) = if sealed {
+            verify_seal_with_options(&doc, &options, &mut fetch)
+        } else if starts_with_header(&doc) {
+            verify_outermost(&doc, &options, &mut fetch)
+        } else {
+            break;
+        };
+        match VerifyStatus::of(&result) {
+            VerifyStatus::Verified => {}
+            VerifyStatus::Unreachable => return result.map(|_| false),
+            _ => return Ok(false),
+        }
+
+        layers += 1;
+        doc = remainder;
+        // Nothing under a seal is verified separately, see `verify_chain`
+        if sealed {
+            break;
+        }
+    }
+
+    Ok(layers > 0)
+}
+
+/// Whether any verified layer of `doc_a` was signed with the same key as any verified layer of
+/// `doc_b`.
+///
+/// Keys are compared by [fingerprint](key_fingerprint), not by URL, so this still works if one
+/// signer publishes their key at several URLs (and isn't fooled by two signers sharing a URL).
+/// Returns false if either document has no verified provenance.
+pub fn shares_signer(doc_a: &str, doc_b: &str) -> bool {
+    let fingerprints = |doc: &str| -> HashSet<[u8; 32]> {
+        verify_all(doc)
+            .0
+            .iter()
+            .flatten()
+            .map(SignerDetails::fingerprint)
+            .collect()
+    };
+
+    !fingerprints(doc_a).is_disjoint(&fingerprints(doc_b))
+}
+
+/// Every distinct signer of `doc`, each reported once even if they signed several layers.
+///
+/// Signers are told apart by key [fingerprint](key_fingerprint), and come in the order
+/// [`verify_all`] first sees them, so from the outside in. Only verified layers count, and a
+/// signer who signed several layers is reported with the details of their outermost one.
+pub fn distinct_signers(doc: &str) -> Vec<SignerDetails> {
+    let mut seen = HashSet::new();
+    verify_all(doc)
+        .0
+        .into_iter()
+        .flatten()
+        .filter(|details| seen.insert(details.fingerprint()))
+        .collect()
+}
+
+/// Like [`shares_signer`], but without touching the network: the verification key for each layer
+/// is looked up by URL in `keys`.
+///
+/// Layers are stripped from the outside in, stopping at the first layer whose URL isn't in
+/// `keys` or whose signature doesn't verify.
+pub fn shares_signer_offline(
+    doc_a: &str,
+    doc_b: &str,
+    keys: &HashMap<String, VerifyingKey>,
+) -> bool {
+    let fingerprints = |mut doc: &str| -> HashSet<[u8; 32]> {
+        let mut fingerprints = HashSet::new();
+        while let Some(key) = header_url_key(doc, keys) {
+            let Ok((_, remainder)) = verify_with_key(doc, key) else {
+                break;
+            };
+            fingerprints.insert(key_fingerprint(key));
+            doc = remainder;
+        }
+        fingerprints
+    };
+
+    !fingerprints(doc_a).is_disjoint(&fingerprints(doc_b))
+}
+
+/// The key in `keys` for the URL in the outermost header of `doc`, if there is one.
+fn header_url_key<'a>(
+    doc: &str,
+    keys: &'a HashMap<String, VerifyingKey>,
+) -> Option<&'a VerifyingKey> {
+    let (first, _) = split_header(doc)?;
+    keys.get(parse_header(first).ok()?.url)
+}
+
+/// How far a single layer of provenance could be verified.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum VerifyStatus {
+    /// The signature was checked against the key served at the layer's URL.
+    #[default]
+    Verified,
+    /// The layer is well-formed, but its provenance server couldn't be reached (or, when verifying
+    /// a bundle, the bundle has no key for it). The layer might be fine or might be forged:
+    /// there's no way to know until the key can be had.
+    Unreachable,
+    /// The layer is definitely bad: it is malformed, or its signature doesn't match the document
+    /// under the key served at its URL.
+    Forged,
+    /// The layer's provenance server says its signer has been deactivated, so the layer can't be
+    /// trusted whether or not it was signed before the deactivation. See
+    /// [`VerifyError::SignerDeactivated`].
+    Deactivated,
+    /// The layer doesn't verify, but that's most likely because a forged layer outside it changed
+    /// the document, see [`VerifyError::FailedDueToUpstreamMutation`].
+    FailedDueToUpstreamMutation,
+    /// The layer was signed with a protocol version newer than this crate's, and its signature
+    /// checked out against the key served at its URL, but anything the newer version added to
+    /// the header was ignored. See [`VerifyOptions::newer_version_grace`].
+    VerifiedWithUnknownVersion,
+}
+
+impl VerifyStatus {
+    /// Classify the result of verifying a single layer.
+    pub fn of(result: &anyhow::Result<SignerDetails>) -> Self {
+        match result {
+            Ok(details) => details.status,
+            Err(e) => match e.downcast_ref::<VerifyError>() {
+                Some(
+                    VerifyError::Unreachable { .. }
+                    | VerifyError::Unverified { .. }
+                    | VerifyError::KeyNotBundled { .. },
+                ) => VerifyStatus::Unreachable,
+                Some(VerifyError::SignerDeactivated { .. }) => VerifyStatus::Deactivated,
+                Some(VerifyError::FailedDueToUpstreamMutation { .. }) => {
+                    VerifyStatus::FailedDueToUpstreamMutation
+                }
+                _ => VerifyStatus::Forged,
+            },
+        }
+    }
+}
+
+/// The result of verifying one layer of a [`ProvenanceChain`].
+#[derive(Debug)]
+pub struct ChainLayer {
+    /// The URL in the layer's header, or an empty string if the header couldn't be parsed. This
+    /// is available even when the provenance server is unreachable.
+    pub verification_url: String,
+    pub status: VerifyStatus,
+    pub result: anyhow::Result<SignerDetails>,
+    /// How long it took to fetch the layer's verification key over the network (including any
+    /// preflight request), or `None` if nothing was fetched, for example because the header was
+    /// malformed or the key came from an earlier layer. The cryptographic checks aren't included.
+    pub fetch_duration: Option<Duration>,
+    /// Whether the verification key was reused from earlier in the same chain rather than
+    /// fetched over the network.
+    pub from_cache: bool,
+    /// The number of bytes of document underneath this layer's header, all of which its
+    /// signature covers. This includes the headers of every inner layer: an outer signer attests
+    /// to the inner provenance as well as to the document itself.
+    pub covered_bytes: usize,
+}
+
+/// The result of verifying every layer of a signed document, see [`verify_chain`].
+#[derive(Debug)]
+pub struct ProvenanceChain {
+    /// One entry per layer, from the outermost (most recent) to the innermost (first) signer.
+    pub layers: Vec<ChainLayer>,
+    /// The document left over once every layer has been stripped off.
+    pub remainder: String,
+    /// True if verification stopped at a forged layer because of
+    /// [`VerifyOptions::stop_on_first_failure`]. The remainder then still contains the
+    /// unverified inner layers. (Or, for
+    /// [`remote::verify_url_streaming`](crate::remote::verify_url_streaming), is empty, since the
+    /// rest of the document was never downloaded.)
+    pub stopped_early: bool,
+}
+
+impl ProvenanceChain {
+    /// The number of layers with the given status, for summaries like "3 verified, 1 unknown
+    /// (server down), 0 forged".
+    pub fn count(&self, status: VerifyStatus) -> usize {
+        self.layers
+            .iter()
+            .filter(|layer| layer.status == status)
+            .count()
+    }
+
+    /// Returns true if the document has at least one layer and every layer verified, so that
+    /// nothing in the chain is broken.
+    ///
+    /// Like [`is_fully_verified`], a layer from a newer protocol version (see
+    /// [`VerifyStatus::VerifiedWithUnknownVersion`]) doesn't count as verified, since anything
+    /// the newer version added was ignored. A layer whose server is unreachable breaks the chain
+    /// too, since it might be forged. Use
+    /// [`ProvenanceChain::count`] with [`VerifyStatus::Unreachable`] to tell those layers apart
+    /// from forged ones.
+    pub fn is_intact(&self) -> bool {
+        !self.layers.is_empty()
+            && !self.stopped_early
+            && self
+                .layers
+                .iter()
+                .all(|layer| layer.status == VerifyStatus::Verified)
+    }
+
+    /// The layer which most likely changed the document, breaking every layer inside it: the
+    /// outermost forged layer, if any layer inside it is
+    /// [`VerifyStatus::FailedDueToUpstreamMutation`].
+    pub fn mutated_by(&self) -> Option<&ChainLayer> {
+        let cascaded = self
+            .layers
+            .iter()
+            .any(|layer| layer.status == VerifyStatus::FailedDueToUpstreamMutation);
+        cascaded
+            .then(|| {
+                self.layers
+                    .iter()
+                    .find(|layer| layer.status == VerifyStatus::Forged)
+            })
+            .flatten()
+    }
+
+    /// The signer of the innermost layer, who signed the document first, if that layer verified.
+    ///
+    /// Returns `None` for a document without any layers, or if the innermost layer didn't verify
+    /// (so whoever it names can't be trusted to be the original signer).
+    pub fn oldest_signer(&self) -> Option<&SignerDetails> {
+        self.layers.last()?.result.as_ref().ok()
+    }
+
+    /// The signer of the outermost layer, who signed the document most recently, if that layer
+    /// verified.
+    ///
+    /// Returns `None` for a document without any layers, or if the outermost layer didn't
+    /// verify.
+    pub fn newest_signer(&self) -> Option<&SignerDetails> {
+        self.layers.first()?.result.as_ref().ok()
+    }
+}
+
+/// A layer whose trusted timestamp is earlier than that of a layer inside it, see
+/// [`check_temporal_consistency`]. Indexes are positions in [`ProvenanceChain::layers`], so the
+/// outer layer always has the smaller index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemporalViolation {
+    pub outer_index: usize,
+    pub outer_signed_at: SystemTime,
+    /// The layer inside the outer one with the latest timestamp.
+    pub inner_index: usize,
+    pub inner_signed_at: SystemTime,
+}
+
+/// The result of [`check_temporal_consistency`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemporalReport {
+    /// Every layer dated before a layer inside it, outermost first.
+    pub violations: Vec<TemporalViolation>,
+    /// How many layers had a trusted timestamp to check. Layers without one can't be checked,
+    /// so a chain with fewer than two says nothing about the order the layers were signed in.
+    pub timestamped_layers: usize,
+}
+
+impl TemporalReport {
+    /// True if no layer is dated before a layer inside it.
+    pub fn is_consistent(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Check that the layers of `chain` were signed in order: every layer's trusted timestamp (see
+/// [`SignerDetails::timestamp`]) must be no earlier than those of the layers inside it, since a
+/// layer can only be added on top of a document which already exists. A layer dated before one
+/// of its inner layers means the chain was backdated or its layers reordered.
+///
+/// Only layers which verified and have a timestamp are compared, since the timestamp of any
+/// other layer can't be trusted.
+pub fn check_temporal_consistency(chain: &ProvenanceChain) -> TemporalReport {
+    let mut violations = vec![];
+    let mut timestamped_layers = 0;
+    // The latest timestamp of the layers inside the current one, and its layer's index
+    let mut latest: Option<(usize, SystemTime)> = None;
+    for (index, layer) in chain.layers.iter().enumerate().rev() {
+        let Some(signed_at) = layer
+            .result
+            .as_ref()
+            .ok()
+            .and_then(|details| details.timestamp)
+        else {
+            continue;
+        };
+        timestamped_layers += 1;
+        match latest {
+            Some((inner_index, inner_signed_at)) if signed_at < inner_signed_at => {
+                violations.push(TemporalViolation {
+                    outer_index: index,
+                    outer_signed_at: signed_at,
+                    inner_index,
+                    inner_signed_at,
+                });
+            }
+            _ => latest = Some((index, signed_at)),
+        }
+    }
+    violations.reverse();
+
+    TemporalReport {
+        violations,
+        timestamped_layers,
+    }
+}
+
+/// Once a layer has been forged (by the layer signed by `mutated_by`), blame any signature
+/// failure in a layer inside it on that layer having changed the document. Failures with a
+/// specific cause, like an unreachable server, are left alone.
+fn attribute_mutation(
+    result: anyhow::Result<SignerDetails>,
+    url: &str,
+    mutated_by: Option<&str>,
+) -> anyhow::Result<SignerDetails> {
+    match (result, mutated_by) {
+        (Err(e), Some(mutated_by)) if !e.is::<VerifyError>() => {
+            Err(e.context(VerifyError::FailedDueToUpstreamMutation {
+                url: url.to_string(),
+                mutated_by: mutated_by.to_string(),
+            }))
+        }
+        (result, _) => result,
+    }
+}
+
+/// A layer whose status differs between two verifications of the same document, see
+/// [`reverify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusChange {
+    /// The position of the layer in the chain, counting from 0 at the outermost layer.
+    pub index: usize,
+    pub verification_url: String,
+    pub before: VerifyStatus,
+    pub after: VerifyStatus,
+}
+
+/// The result of [`reverify`]: the new verification of the document, and how it differs from
+/// the previous one.
+#[derive(Debug)]
+pub struct Reverification {
+    pub chain: ProvenanceChain,
+    /// The layers whose status changed, outermost first. Empty if nothing changed.
+    pub changes: Vec<StatusChange>,
+}
+
+/// Verify `signed_doc` again, and compare the result against `previous`, an earlier
+/// verification of the same document.
+///
+/// Keys get rotated and servers go up and down, so a layer which verified before might not
+/// anymore (or vice versa). This is meant for monitoring: periodically re-check archived
+/// documents, and act on [`Reverification::changes`]. Only layers which were verified both times
+/// are compared, since [`VerifyOptions::stop_on_first_failure`] can make either chain shorter.
+/// Fails if the layers' URLs don't match `previous`, which means it was a different document.
+pub fn reverify(
+    previous: &ProvenanceChain,
+    signed_doc: &str,
+    options: &VerifyOptions,
+) -> anyhow::Result<Reverification> {
+    let chain = verify_chain(signed_doc, options);
+
+    let mut changes = vec![];
+    for (index, (before, after)) in previous.layers.iter().zip(&chain.layers).enumerate() {
+        if before.verification_url != after.verification_url {
+            return Err(anyhow!(
+                "Layer {index} is from '{}', but was previously from '{}'. Is this the same document?",
+                after.verification_url,
+                before.verification_url
+            ));
+        }
+        if before.status != after.status {
+            changes.push(StatusChange {
+                index,
+                verification_url: after.verification_url.clone(),
+                before: before.status,
+                after: after.status,
+            });
+        }
+    }
+
+    Ok(Reverification { chain, changes })
+}
+
+/// Given a (possibly signed) document, verify every layer of provenance, recording for each layer
+/// whether it verified, was forged, or couldn't be checked because its server is unreachable.
+///
+/// This is like [`verify_all_with_options`], but with more detail about each layer.
+pub fn verify_chain(signed_doc: &str, options: &VerifyOptions) -> ProvenanceChain {
+    verify_chain_with_fetch(signed_doc, options, KeyFetch::default())
+}
+
+/// Like [`verify_chain`], reusing the keys already fetched into `fetch`.
+pub(crate) fn verify_chain_with_fetch(
+    signed_doc: &str,
+    options: &VerifyOptions,
+    mut fetch: KeyFetch,
+) -> ProvenanceChain {
+    let mut layers = vec![];
+
+    let mut doc = match dearmored(signed_doc) {
+        Ok(signed_doc) => signed_doc.into_owned(),
+        Err(e) => {
+            return ProvenanceChain {
+                layers: vec![ChainLayer {
+                    verification_url: String::new(),
+                    status: VerifyStatus::Forged,
+                    result: Err(e),
+                    fetch_duration: None,
+                    from_cache: false,
+                    covered_bytes: 0,
+                }],
+                remainder: signed_doc.to_string(),
+                stopped_early: false,
+            }
+        }
+    };
+    let mut stopped_early = false;
+    // The URL of the outermost forged layer, which every layer inside it might be broken by
+    let mut mutated_by: Option<String> = None;
+
+    loop {
+        let verification_url = outermost_signer_url(&doc).unwrap_or_default();
+        fetch.next_layer();
+
+        // A seal attests to everything below it exactly as-is, so there is nothing further to
+        // strip and verify once we reach one.
+        if is_sealed(&doc) {
+            let (result, remainder) = verify_seal_with_options(&doc, options, &mut fetch);
+            let result = attribute_mutation(result, &verification_url, mutated_by.as_deref());
+            layers.push(ChainLayer {
+                verification_url,
+                status: VerifyStatus::of(&result),
+                result,
+                fetch_duration: fetch.duration,
+                from_cache: fetch.from_cache,
+                covered_bytes: remainder.len(),
+            });
+            doc = remainder;
+            break;
+        }
+
+        // Once the document no longer starts with a header, there is no signature left on it and
+        // we have exhausted all the provenance checking we can do. Whatever is left (even if it
+        // is empty, or has several lines) is the document the first signer signed.
+        if !starts_with_header(&doc) {
+            break;
+        }
+
+        // Try to verify the provenance of the document
+        let verified: (anyhow::Result<SignerDetails>, String) =
+            verify_outermost(&doc, options, &mut fetch);
+
+        let result = attribute_mutation(verified.0, &verification_url, mutated_by.as_deref());
+        let status = VerifyStatus::of(&result);
+        if status == VerifyStatus::Forged && mutated_by.is_none() {
+            mutated_by = Some(verification_url.clone());
+        }
+
+        // If this is not the final signer, push the verification and move onto the next one
+        layers.push(ChainLayer {
+            verification_url,
+            status,
+            result,
+            fetch_duration: fetch.duration,
+            from_cache: fetch.from_cache,
+            covered_bytes: verified.1.len(),
+        });
+
+        // Now reassign `doc` to whatever the remainder was after verifying the document. This
+        // allows one document to be signed multiple times by (potentially different) signers.
+        doc = verified.1;
+
+        if options.stop_on_first_failure && layers.last().unwrap().status == VerifyStatus::Forged {
+            stopped_early = true;
+            break;
+        }
+    }
+
+    ProvenanceChain {
+        layers,
+        remainder: doc,
+        stopped_early,
+    }
+}
+
+/// The URL of the most recent signer of `doc`, taken from its outermost header (ordinary or
+/// seal), or `None` if the document isn't signed or the header is malformed.
+///
+/// Only the first line is parsed: nothing is verified and nothing is fetched, so this is cheap
+/// enough to call while rendering a UI. Don't trust the URL until the document is verified.
+pub fn outermost_signer_url(doc: &str) -> Option<String> {
+    let (first, _) = split_header(doc)?;
+    parse_header(first)
+        .or_else(|_| parse_header_with_markers(first, SEAL_PREAMBLE, SEAL_POSTAMBLE))
+        .ok()
+        .or_else(|| parse_header_layout(first))
+        .map(|header| header.url.to_string())
+}
+
+/// Add a layer of provenance to `doc`, signed with `signing_key` and verifiable with the key
+/// served at `url`.
+///
+/// Documents longer than [`DEFAULT_PREHASH_THRESHOLD`] are signed with Ed25519ph, see
+/// [`sign_with_threshold`].
+pub fn sign(doc: &str, signing_key: &SigningKey, url: &str) -> String {
+    sign_with_threshold(doc, signing_key, url, DEFAULT_PREHASH_THRESHOLD)
+}
+
+/// The line ending written after a provenance header, see [`sign_with_line_ending`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, as written by [`sign`].
+    #[default]
+    Lf,
+    /// `\r\n`, as used on Windows.
+    CrLf,
+    /// [`LineEnding::CrLf`] on Windows, and [`LineEnding::Lf`] everywhere else.
+    Native,
+}
+
+impl LineEnding {
+    /// The characters of this line ending.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Native if cfg!(windows) => "\r\n",
+            LineEnding::Native => "\n",
+        }
+    }
+}
+
+/// Like [`sign`], but ending the header line with `line_ending`, so that the signed document
+/// matches the line endings of the file it's written to (and an editor doesn't rewrite it).
+///
+/// Only the header's own line ending changes. The document is signed exactly as given, and the
+/// line ending after the header isn't signed, so [`verify`] accepts either.
+pub fn sign_with_line_ending(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    line_ending: LineEnding,
+) -> String {
+    let signed_doc = sign(doc, signing_key, url);
+    let (header, doc) = signed_doc
+        .split_once('\n')
+        .expect("signed documents have a header line");
+    format!("{header}{}{doc}", line_ending.as_str())
+}
+
+/// Like [`sign`], but documents longer than `prehash_threshold` bytes are signed with Ed25519ph
+/// instead of plain ed25519.
+///
+/// Ed25519ph signs a hash of the document, which is computed in a single pass, whereas plain
+/// ed25519 has to go over the whole document twice. The choice is recorded in the header (as
+/// `alg=ed25519ph`), so verifiers handle either automatically.
+pub fn sign_with_threshold(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    prehash_threshold: usize,
+) -> String {
+    sign_layer(doc, signing_key, url, &[], prehash_threshold)
+}
+
+/// Like [`sign`], but hashing the document with `hash` and signing the hash, rather than signing
+/// the whole document.
+///
+/// The hash algorithm is recorded in the header (in the `alg` extension), so verifiers use the
+/// matching hash automatically. [`HashAlgorithm::Sha512`] signs exactly as
+/// [`sign_with_threshold`] does above its threshold.
+pub fn sign_with_hash(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    hash: HashAlgorithm,
+) -> String {
+    let extensions = format!("{ALGORITHM_EXTENSION}={}", hash.algorithm());
+    let signature = match hash {
+        HashAlgorithm::Sha512 => signing_key
+            .sign_prehashed(
+                prehashed_message(PROVENANCE_VERSION, url, &extensions, doc.as_bytes()),
+                None,
+            )
+            .expect("signing without a context can't fail"),
+        hash => signing_key.sign(&hashed_message(
+            PROVENANCE_VERSION,
+            url,
+            &extensions,
+            hash,
+            doc.as_bytes(),
+        )),
+    };
+
+    format!(
+        "{}\n{doc}",
+        format_header_with_extensions(url, &signature_to_b64(&signature), &extensions)
+    )
+}
+
+/// The number of bytes [`sign`] adds to a document of `doc_len` bytes for a signer at `url`: the
+/// length of the provenance header line, including its newline.
+///
+/// Signatures always encode to the same length, so only the document's length matters (documents
+/// longer than [`DEFAULT_PREHASH_THRESHOLD`] also get ` alg=ed25519ph` in their header), and
+/// nothing needs to be signed to compute it.
+pub fn provenance_overhead(url: &str, doc_len: usize) -> usize {
+    // 64 bytes of signature is 88 characters of base64, including padding
+    let signature_length = SIGNATURE_LENGTH.div_ceil(3) * 4;
+    let extensions = prehash_extensions(doc_len);
+    format_header_with_extensions(url, &"A".repeat(signature_length), &extensions).len() + 1
+}
+
+/// The header extension recording the member of a group who signed a layer with the group's key.
+pub(crate) const MEMBER_EXTENSION: &str = "member";
+
+/// Sign a document with a group's key, attesting that the group's member `member_id` signed it.
+///
+/// The member ID is recorded in the header and covered by the group's signature. Verifying the
+/// document reports both in [`SignerDetails::group`], and checks with the group's provenance
+/// server that `member_id` is still a member. Member IDs can only contain letters, digits, `_`,
+/// `-` and `.`, so that they can be used in headers and URLs as-is.
+pub fn sign_as_member(
+    doc: &str,
+    group_key: &SigningKey,
+    group_url: &str,
+    member_id: &str,
+) -> anyhow::Result<String> {
+    let is_valid_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.';
+    if member_id.is_empty() || !member_id.chars().all(is_valid_char) {
+        return Err(anyhow!(
+            "Member ID '{member_id}' must be letters, digits, '_', '-' or '.'"
+        ));
+    }
+
+    Ok(sign_with_extensions(
+        doc,
+        group_key,
+        group_url,
+        &[(MEMBER_EXTENSION, member_id)],
+    ))
+}
+
+/// Sign a document like [`sign`], recording in the header what the signer did to it (see
+/// [`LayerKind`]). Verifying the document reports the kind in [`SignerDetails::kind`].
+pub fn sign_with_kind(doc: &str, signing_key: &SigningKey, url: &str, kind: LayerKind) -> String {
+    let Some(id) = kind.id() else {
+        return sign(doc, signing_key, url);
+    };
+
+    sign_with_extensions(doc, signing_key, url, &[(KIND_EXTENSION, id)])
+}
+
+/// The header extension holding a layer's usage terms, see [`sign_with_terms`].
+pub(crate) const TERMS_EXTENSION: &str = "terms";
+
+/// Sign a document like [`sign`], binding usage `terms` to the signature, like an SPDX license
+/// expression (`CC-BY-4.0`) or a flag such as `no-ai-training`. The terms are signed along with
+/// the document, so they travel with it and can't be changed without breaking the signature.
+/// Verifying the document reports them in [`SignerDetails::terms`].
+///
+/// Terms can be any text: they're written into the header in URL-safe base64.
+pub fn sign_with_terms(doc: &str, signing_key: &SigningKey, url: &str, terms: &str) -> String {
+    let terms = URL_SAFE.encode(terms);
+    sign_with_extensions(doc, signing_key, url, &[(TERMS_EXTENSION, &terms)])
+}
+
+/// The usage terms recorded in a layer's `extensions`, if it has any.
+pub(crate) fn layer_terms(extensions: &str) -> anyhow::Result<Option<String>> {
+    let Some(terms_b64) = find_extension(extensions, TERMS_EXTENSION) else {
+        return Ok(None);
+    };
+    let terms = URL_SAFE
+        .decode(terms_b64)
+        .ok()
+        .and_then(|terms| String::from_utf8(terms).ok())
+        .ok_or_else(|| anyhow!("Layer's terms '{terms_b64}' aren't base64-encoded UTF-8"))?;
+    Ok(Some(terms))
+}
+
+/// The header extension holding a layer's random nonce, see [`sign_with_nonce`].
+pub(crate) const NONCE_EXTENSION: &str = "nonce";
+
+/// The number of bytes in a layer's nonce, see [`sign_with_nonce`].
+pub const NONCE_LENGTH: usize = 16;
+
+/// Sign a document like [`sign`], with a fresh random nonce recorded (and signed) in the header.
+///
+/// The nonce makes every signature unique, even over the same document, so a verifier which
+/// remembers the nonces it has seen can tell a replayed layer from a new one. See
+/// [`verify_with_seen_nonces`].
+pub fn sign_with_nonce(doc: &str, signing_key: &SigningKey, url: &str) -> String {
+    let nonce: [u8; NONCE_LENGTH] = rand::random();
+    let nonce = URL_SAFE.encode(nonce);
+    sign_with_extensions(doc, signing_key, url, &[(NONCE_EXTENSION, &nonce)])
+}
+
+/// The nonce recorded in a layer's `extensions`, if it has one.
+fn layer_nonce(extensions: &str) -> anyhow::Result<Option<[u8; NONCE_LENGTH]>> {
+    let Some(nonce_b64) = find_extension(extensions, NONCE_EXTENSION) else {
+        return Ok(None);
+    };
+    URL_SAFE
+        .decode(nonce_b64)
+        .ok()
+        .and_then(|nonce| nonce.try_into().ok())
+        .map(Some)
+        .ok_or_else(|| anyhow!("Nonce '{nonce_b64}' isn't {NONCE_LENGTH} bytes of base64"))
+}
+
+/// Verify every layer of `signed_doc` like [`verify_all`], checking each layer's nonce (see
+/// [`sign_with_nonce`]) against `seen`, the nonces of every layer verified so far.
+///
+/// A verified layer whose nonce is already in `seen` fails with [`VerifyError::NonceReused`],
+/// otherwise its nonce is added to `seen`. Pass the same set when verifying a batch of
+/// documents to catch a layer replayed from one document onto another. Only nonces of verified
+/// layers are recorded, so a forged layer can't get a genuine one rejected by claiming its nonce
+/// first. Layers without a nonce can't be checked, and are verified as usual.
+pub fn verify_with_seen_nonces(
+    signed_doc: &str,
+    seen: &mut HashSet<[u8; NONCE_LENGTH]>,
+) -> (Vec<anyhow::Result<SignerDetails>>, String) {
+    let (results, remainder) = verify_all(signed_doc);
+    let doc = dearmored(signed_doc).unwrap_or(Cow::Borrowed(signed_doc));
+    let layers = raw_layers(&doc);
+
+    let results = results
+        .into_iter()
+        .zip(layers.iter().map(Some).chain(std::iter::repeat(None)))
+        .map(|(result, layer)| {
+            let (Ok(details), Some(layer)) = (result.as_ref(), layer) else {
+                return result;
+            };
+            if layer.url != details.verification_url {
+                return result;
+            }
+            match layer_nonce(layer.extensions)? {
+                Some(nonce) if !seen.insert(nonce) => Err(VerifyError::NonceReused {
+                    url: details.verification_url.clone(),
+                    nonce_b64: URL_SAFE.encode(nonce),
+                }
+                .into()),
+                _ => result,
+            }
+        })
+        .collect();
+
+    (results, remainder)
+}
+
+/// Like [`sign`], but also embeds the verification key of `signing_key` in the header (as
+/// `key=<base64 key>`), so that the document is self-contained.
+///
+/// Anyone can check such a layer is internally consistent without a provenance server, using
+/// [`offline::verify_with_embedded_key`](crate::offline::verify_with_embedded_key). Verifying
+/// it with [`verify`] also checks that the embedded key is the key served at `url`, failing with
+/// [`VerifyError::UrlKeyMismatch`] if it isn't, and reports the outcome in
+/// [`SignerDetails::embedded_key`]. The embedded key is never trusted on its own: if the server
+/// at `url` can't be reached, verifying fails just as it would without one.
+pub fn sign_with_embedded_key(doc: &str, signing_key: &SigningKey, url: &str) -> String {
+    let key = URL_SAFE.encode(signing_key.verifying_key().as_bytes());
+    sign_with_extensions(doc, signing_key, url, &[(EMBEDDED_KEY_EXTENSION, &key)])
+}
+
+/// What [`sign_checked`] does when the document's outermost layer is already a signature by the
+/// same key and URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Sign anyway, like [`sign`].
+    Allow,
+    /// Fail with an error.
+    Refuse,
+    /// Return the document unchanged.
+    Skip,
+}
+
+/// Like [`sign`], but first checks whether the outermost layer of `doc` was already signed by
+/// `signing_key` for `url`. Another layer from the same signer would add no information, so
+/// `policy` decides whether to add it anyway, fail, or leave the document as it is.
+///
+/// Nothing is fetched: the existing layer is checked against `signing_key`'s own verification
+/// key.
+pub fn sign_checked(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    policy: DuplicatePolicy,
+) -> anyhow::Result<String> {
+    let is_duplicate = verify_with_key(doc, &signing_key.verifying_key())
+        .is_ok_and(|(header, _)| header.url == url);
+
+    match (is_duplicate, policy) {
+        (true, DuplicatePolicy::Refuse) => Err(anyhow!(
+            "Document's outermost layer is already signed by this key for '{url}'"
+        )),
+        (true, DuplicatePolicy::Skip) => Ok(doc.to_string()),
+        _ => Ok(sign(doc, signing_key, url)),
+    }
+}
+
+/// How [`sign_and_publish`] uploads a signed document.
+#[derive(Debug, Clone)]
+pub struct PublishOptions {
+    /// The HTTP method of the upload, `PUT` by default.
+    pub method: Method,
+    /// Headers sent with the upload. By default this is just a `Content-Type` of UTF-8 text.
+    pub headers: HeaderMap,
+}
+
+impl Default for PublishOptions {
+    fn default() -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        );
+        PublishOptions {
+            method: Method::PUT,
+            headers,
+        }
+    }
+}
+
+/// Why [`sign_and_publish`] failed. Signing itself can't fail, so these are all about the upload.
+#[derive(Debug, thiserror::Error)]
+pub enum PublishError {
+    /// The upload couldn't be sent, or no response was received.
+    #[error("Couldn't upload signed document to '{destination}': {source}")]
+    Upload {
+        destination: String,
+        source: reqwest::Error,
+    },
+    /// The destination responded, but with an unsuccessful status.
+    #[error("Upload of signed document to '{destination}' was rejected: {status}")]
+    Rejected {
+        destination: String,
+        status: StatusCode,
+    },
+}
+
+/// Sign a document like [`sign`], then upload the signed document to `destination` (for example
+/// a pre-signed object storage URL), returning it once the upload has succeeded.
+///
+/// The upload is a `PUT` by default, see [`PublishOptions`] to change the method or headers.
+pub fn sign_and_publish(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    destination: &str,
+    client: &Client,
+    options: &PublishOptions,
+) -> Result<String, PublishError> {
+    let signed_doc = sign(doc, signing_key, url);
+
+    let response = client
+        .request(options.method.clone(), destination)
+        .headers(options.headers.clone())
+        .body(signed_doc.clone())
+        .send()
+        .map_err(|source| PublishError::Upload {
+            destination: destination.to_string(),
+            source,
+        })?;
+    if !response.status().is_success() {
+        return Err(PublishError::Rejected {
+            destination: destination.to_string(),
+            status: response.status(),
+        });
+    }
+
+    Ok(signed_doc)
+}
+
+/// Sign a document, applying `normalizer` to it first.
+///
+/// Only the signed bytes are normalized: the returned document contains `doc` exactly as given.
+/// The normalizer's ID is recorded in the header, and verifiers apply the same normalizer before
+/// checking the signature. Custom normalizers need to be passed to the verifier in
+/// [`VerifyOptions::normalizers`]. Fails if the normalizer's ID can't be written into a header.
+pub fn sign_normalized(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    normalizer: &dyn DocumentNormalizer,
+) -> anyhow::Result<String> {
+    let extensions = normalize::extension_for(normalizer)?;
+    let normalized = normalizer.normalize(doc.as_bytes());
+    let signature = signing_key.sign(&signed_message_with_extensions(
+        PROVENANCE_VERSION,
+        url,
+        &extensions,
+        &normalized,
+    ));
+
+    Ok(format!(
+        "{}\n{doc}",
+        format_header_with_extensions(url, &signature_to_b64(&signature), &extensions)
+    ))
+}
+
+/// Sign a document, but return only the provenance header line instead of the signed document.
+///
+/// The header can be stored or transmitted separately from the document (for example in a
+/// sidecar file), and the document itself is left untouched.
+pub fn sign_detached(doc: &str, signing_key: &SigningKey, url: &str) -> String {
+    let signature = signing_key.sign(&signed_message(PROVENANCE_VERSION, url, doc.as_bytes()));
+    let encoded_signature = Base64Signature(signature_to_b64(&signature));
+
+    format_header(url, encoded_signature)
+}
+
+/// Sign a document, encoding the provenance header with the given [`SigningMethod`].
+///
+/// The result can be verified with [`verify_bytes`], which detects the encoding automatically.
+pub fn sign_with_method(
+    doc: &[u8],
+    signing_key: &SigningKey,
+    url: &str,
+    method: SigningMethod,
+) -> Vec<u8> {
+    let signature = signing_key.sign(&signed_message(PROVENANCE_VERSION, url, doc));
+
+    let mut signed_doc = match method {
+        SigningMethod::Text => {
+            let encoded_signature = Base64Signature(signature_to_b64(&signature));
+            format!("{}\n", format_header(url, encoded_signature)).into_bytes()
+        }
+        SigningMethod::Binary => binary::encode_header(PROVENANCE_VERSION, url, &signature),
+    };
+    signed_doc.extend_from_slice(doc);
+    signed_doc
+}
+
+pub fn format_doc(url: &str, encoded_signature: Base64Signature, doc: &str) -> String {
+    format!("{}\n{doc}", format_header(url, encoded_signature))
+}
+
+/// Format the provenance header line (without a trailing newline).
+pub fn format_header(url: &str, encoded_signature: Base64Signature) -> String {
+    format_header_with_extensions(url, &encoded_signature.0, "")
+}
+
+/// Sign `doc` like [`sign`], with the header `extensions` (`key`, `value` pairs) written into the
+/// header and signed along with the document. Like [`sign`], documents longer than
+/// [`DEFAULT_PREHASH_THRESHOLD`] are signed with Ed25519ph, recorded before the other extensions
+/// (see [`prehash_extensions`]).
+pub(crate) fn sign_with_extensions(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    extensions: &[(&str, &str)],
+) -> String {
+    sign_layer(doc, signing_key, url, extensions, DEFAULT_PREHASH_THRESHOLD)
+}
+
+/// The extensions [`sign_with_extensions`] adds to the header of a document of `doc_len` bytes,
+/// before any it was given: `alg=ed25519ph` if the document is prehashed, and none otherwise.
+pub(crate) fn prehash_extensions(doc_len: usize) -> String {
+    if doc_len > DEFAULT_PREHASH_THRESHOLD {
+        format!("{ALGORITHM_EXTENSION}={PREHASHED_ALGORITHM}")
+    } else {
+        String::new()
+    }
+}
+
+/// Sign `doc` with the header `extensions`, using Ed25519ph if it is longer than
+/// `prehash_threshold` bytes (see [`sign_with_threshold`]).
+fn sign_layer(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    extensions: &[(&str, &str)],
+    prehash_threshold: usize,
+) -> String {
+    let prehashed = doc.len() > prehash_threshold;
+    let extensions = prehashed
+        .then_some((ALGORITHM_EXTENSION, PREHASHED_ALGORITHM))
+        .iter()
+        .chain(extensions)
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let signature = if prehashed {
+        signing_key
+            .sign_prehashed(
+                prehashed_message(PROVENANCE_VERSION, url, &extensions, doc.as_bytes()),
+                None,
+            )
+            .expect("signing without a context can't fail")
+    } else {
+        signing_key.sign(&signed_message_with_extensions(
+            PROVENANCE_VERSION,
+            url,
+            &extensions,
+            doc.as_bytes(),
+        ))
+    };
+    format!(
+        "{}\n{doc}",
+        format_header_with_extensions(url, &signature_to_b64(&signature), &extensions)
+    )
+}
+
+/// Like [`format_header`], for a layer with header extensions (see [`ParsedHeader::extensions`]).
+pub(crate) fn format_header_with_extensions(
+    url: &str,
+    signature_token: &str,
+    extensions: &str,
+) -> String {
+    if extensions.is_empty() {
+        format!("{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} {url} {signature_token} {PROVENANCE_POSTAMBLE}")
+    } else {
+        format!("{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} {url} {signature_token} {extensions} {PROVENANCE_POSTAMBLE}")
+    }
+}
+
+/// The bytes which are signed by a seal: like [`signed_message`], but marked as a seal so that
+/// a seal's signature can't be passed off as an ordinary layer's signature (or vice versa).
+fn sealed_message(version: &str, url: &str, extensions: &str, doc: &[u8]) -> Vec<u8> {
+    let mut message = b"seal ".to_vec();
+    message.extend(signed_message_with_extensions(
+        version, url, extensions, doc,
+    ));
+    message
+}
+
+/// Seal a (possibly already signed) document, asserting "I produced this entire document as-is".
+///
+/// A seal is signed over the complete document, including any provenance headers inside it, just
+/// like an ordinary layer. The difference is in how it is verified: [`verify_all`] verifies a
+/// seal against the whole remainder of the document and then stops, rather than stripping off
+/// and verifying each inner layer in turn. The inner layers are part of what was sealed, not
+/// separate claims to be checked.
+pub fn seal(doc: &str, signing_key: &SigningKey, url: &str) -> String {
+    let signature = signing_key.sign(&sealed_message(PROVENANCE_VERSION, url, "", doc.as_bytes()));
+    format!(
+        "{SEAL_PREAMBLE} {PROVENANCE_VERSION} {url} {} {SEAL_POSTAMBLE}\n{doc}",
+        signature_to_b64(&signature)
+    )
+}
+
+/// Returns true if the outermost header of `doc` is a seal (see [`seal`]).
+pub fn is_sealed(doc: &str) -> bool {
+    split_header(doc).is_some_and(|(first, _)| {
+        parse_header_with_markers(first, SEAL_PREAMBLE, SEAL_POSTAMBLE).is_ok()
+    })
+}
+
+/// Verify a document which was sealed with [`seal`], returning the signer's details and the
+/// sealed document (which is left intact, inner provenance headers and all).
+pub fn verify_seal(signed_doc: &str) -> (anyhow::Result<SignerDetails>, String) {
+    verify_seal_with_options(
+        signed_doc,
+        &VerifyOptions::default(),
+        &mut KeyFetch::default(),
+    )
+}
+
+fn verify_seal_with_options(
+    signed_doc: &str,
+    options: &VerifyOptions,
+    fetch: &mut KeyFetch,
+) -> (anyhow::Result<SignerDetails>, String) {
+    let Some((first, doc)) = split_header(signed_doc) else {
+        return (
+            Err(anyhow!(
+                "Document has only one line, therefore cannot be sealed"
+            )),
+            signed_doc.to_string(),
+        );
+    };
+
+    let result = apply_network_failure_mode(
+        verify_seal_header(first, doc, options, fetch),
+        first,
+        options,
+    );
+    notify_observers(signed_doc, &result, options);
+
+    (result, doc.to_string())
+}
+
+fn verify_seal_header(
+    header: &str,
+    doc: &str,
+    options: &VerifyOptions,
+    fetch: &mut KeyFetch,
+) -> anyhow::Result<SignerDetails> {
+    let header = parse_header_with_markers(header, SEAL_PREAMBLE, SEAL_POSTAMBLE)?;
+    let signature = decode_signature(header.signature_b64)?;
+
+    check_layer(header.version, header.url, options)?;
+    let verification_key = fetch_verification_key(header.url, options, fetch)?;
+    let message = sealed_message(
+        header.version,
+        header.url,
+        header.extensions,
+        doc.as_bytes(),
+    );
+    if verification_key
+        .verify_strict(&message, &signature)
+        .is_err()
+    {
+        return Err(anyhow!(
+            "Seal signature '{}' could not be verified",
+            signature_to_b64(&signature)
+        ));
+    }
+
+    Ok(SignerDetails {
+        verification_url: header.url.to_string(),
+        verification_key,
+        status: VerifyStatus::Verified,
+        timestamp: None,
+        group: None,
+        kind: LayerKind::of(header.extensions)?,
+        embedded_key: EmbeddedKey::Absent,
+        terms: layer_terms(header.extensions)?,
+    })
+}
+
+/// The result of re-homing the provenance URLs of a document with [`rehome_url`] or
+/// [`rehome_and_resign`].
+#[derive(Debug)]
+pub struct RehomeReport {
+    /// The document with the matching URLs rewritten.
+    pub doc: String,
+    /// The layers whose URL was rewritten, where layer 0 is the outermost (most recent) layer.
+    pub rewritten_layers: Vec<usize>,
+    /// The layers whose signatures no longer verify because of the rewrite.
+    ///
+    /// Every layer outside a rewritten one signed the old header as part of its document, so
+    /// those layers are invalidated, unless [`rehome_and_resign`] re-signed them. Rewriting only
+    /// the outermost layer never invalidates anything.
+    pub invalidated_layers: Vec<usize>,
+}
+
+/// Rewrite the URL of every provenance layer whose URL is `old_url` to be `new_url`, without
+/// re-signing the document.
+///
+/// This is useful when a provenance server moves to a new domain but keeps serving the same keys.
+/// Returns an error if URLs are covered by signatures (see [`URL_IS_SIGNED`]), since then
+/// re-homing would always invalidate the rewritten layers. Use [`rehome_and_resign`] instead.
+pub fn rehome_url(signed_doc: &str, old_url: &str, new_url: &str) -> anyhow::Result<RehomeReport> {
+    if URL_IS_SIGNED {
+        return Err(anyhow!(
+            "Provenance URLs are signed, so they can't be rewritten without re-signing"
+        ));
+    }
+    check_rehome_url(new_url)?;
+
+    let (layers, doc) = peel_layers(signed_doc);
+    let mut rewritten_layers = vec![];
+    let mut rehomed = String::new();
+    for (layer, (line, header, _)) in layers.iter().enumerate() {
+        if header.url != old_url {
+            rehomed.push_str(line);
+            continue;
+        }
+        rewritten_layers.push(layer);
+        rehomed.push_str(&line.replacen(
+            &format!(" {old_url} {} ", header.signature_b64),
+            &format!(" {new_url} {} ", header.signature_b64),
+            1,
+        ));
+    }
+    rehomed.push_str(doc);
+
+    // Every layer outside the innermost rewritten layer signed over a modified header
+    let invalidated_layers = match rewritten_layers.last() {
+        Some(&innermost) => (0..innermost).collect(),
+        None => vec![],
+    };
+
+    Ok(RehomeReport {
+        doc: rehomed,
+        rewritten_layers,
+        invalidated_layers,
+    })
+}
+
+/// Like [`rehome_url`], but re-signing each rewritten layer with `signing_key`, which works even
+/// though URLs are signed (see [`URL_IS_SIGNED`]).
+///
+/// Only the signer of a layer can re-sign it: fails if `signing_key` didn't sign a layer at
+/// `old_url`, or if such a layer has header extensions (which would need signing the way that
+/// layer was).
+pub fn rehome_and_resign(
+    signed_doc: &str,
+    old_url: &str,
+    new_url: &str,
+    signing_key: &SigningKey,
+) -> anyhow::Result<RehomeReport> {
+    check_rehome_url(new_url)?;
+
+    let (layers, doc) = peel_layers(signed_doc);
+    let rewritten_layers: Vec<usize> = (0..layers.len())
+        .filter(|&layer| layers[layer].1.url == old_url)
+        .collect();
+
+    // Each layer signs everything inside it, so re-sign from the inside out
+    let mut rehomed = doc.to_string();
+    for (layer, (line, header, body)) in layers.iter().enumerate().rev() {
+        if header.url != old_url {
+            rehomed = format!("{line}{rehomed}");
+            continue;
+        }
+        if !header.extensions.is_empty() {
+            return Err(anyhow!(
+                "Layer {layer} has header extensions, so can't be re-signed"
+            ));
+        }
+        let signature = decode_signature(header.signature_b64)?;
+        let signed_by_key = check_signature(
+            header.version,
+            old_url,
+            "",
+            &signature,
+            body.as_bytes(),
+            &signing_key.verifying_key(),
+        );
+        if signed_by_key.is_err() {
+            return Err(anyhow!(
+                "Layer {layer} wasn't signed by the given key, so can't be re-homed with it"
+            ));
+        }
+
+        let signature =
+            signing_key.sign(&signed_message(header.version, new_url, rehomed.as_bytes()));
+        // The line ending isn't signed, so keep whichever the layer had
+        let line_ending = if line.ends_with("\r\n") { "\r\n" } else { "\n" };
+        rehomed = format!(
+            "{PROVENANCE_PREAMBLE} {} {new_url} {} {PROVENANCE_POSTAMBLE}{line_ending}{rehomed}",
+            header.version,
+            signature_to_b64(&signature)
+        );
+    }
+
+    // Every layer outside the innermost rewritten layer signed over a modified header
+    let invalidated_layers = match rewritten_layers.last() {
+        Some(&innermost) => (0..innermost)
+            .filter(|layer| !rewritten_layers.contains(layer))
+            .collect(),
+        None => vec![],
+    };
+
+    Ok(RehomeReport {
+        doc: rehomed,
+        rewritten_layers,
+        invalidated_layers,
+    })
+}
+
+/// Check `new_url` can be written into a header by [`rehome_url`] or [`rehome_and_resign`].
+fn check_rehome_url(new_url: &str) -> anyhow::Result<()> {
+    if new_url.is_empty() || new_url.contains(char::is_whitespace) {
+        return Err(anyhow!(
+            "URL '{new_url}' cannot be empty or contain whitespace"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Peel off each provenance header of `signed_doc` in turn, returning each header line (as
+/// written, with its line ending, and parsed) with the rest of the document under it, and the
+/// document under them all.
+fn peel_layers(signed_doc: &str) -> (Vec<(&str, ParsedHeader<'_>, &str)>, &str) {
+    let mut layers = vec![];
+    let mut doc = signed_doc;
+    while let Some((first, rest)) = split_header(doc) {
+        let Ok(header) = parse_header(first) else {
+            break;
+        };
+        layers.push((&doc[..doc.len() - rest.len()], header, rest));
+        doc = rest;
+    }
+
+    (layers, doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offline::{BLAKE3_ALGORITHM, SHA256_ALGORITHM};
+    use crate::test_util;
+    use crate::Base64SigningKey;
+    use base64::engine::general_purpose::STANDARD;
+    use exif::Reader;
+    use rand::rngs::OsRng;
+    use rand::Rng;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[test]
+    fn verification_fails_if_no_newline() {
+        assert!(verify("document text here").0.is_err());
+    }
+
+    #[test]
+    fn verification_fails_if_bad_start() {
+        assert!(
+            verify(format!("<!PROVENANCE_PREAMBLE!> {PROVENANCE_VERSION} url signature {PROVENANCE_POSTAMBLE}\ndocument text here").as_str())
+                .0.is_err()
+        );
+    }
+
+    #[test]
+    fn verification_fails_if_bad_ending() {
+        assert!(
+            verify(format!("{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} url signature <!PROVENANCE_POSTAMBLE!>\ndocument text here").as_str())
+                .0.is_err()
+        );
+    }
+
+    #[test]
+    fn verification_fails_if_bad_version() {
+        assert!(verify(
+            format!("{PROVENANCE_PREAMBLE} <!PROVENANCE_VERSION!> url signature {PROVENANCE_POSTAMBLE}\ndocument text here").as_str(),
+        ).0.is_err());
+    }
+
+    #[test]
+    fn verification_fails_if_signature_is_empty() {
+        assert!(verify(
+            format!("{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} url  {PROVENANCE_POSTAMBLE}\ndocument text here").as_str(),
+        ).0.is_err());
+    }
+
+    #[test]
+    fn verification_fails_if_url_is_empty() {
+        assert!(verify(
+            format!("{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION}  signature {PROVENANCE_POSTAMBLE}\ndocument text here").as_str(),
+        ).0.is_err());
+    }
+
+    #[test]
+    fn verification_fails_if_wrong_number_of_args() {
+        assert!(verify("one two three four\ndocument text here").0.is_err());
+    }
+
+    #[test]
+    fn verification_fails_during_signature_from_slice() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let encoded_signature =
+            Base64Signature(URL_SAFE.encode("not a valid signature".as_bytes()));
+        let doc = "Document text here";
+
+        assert!(verify(format_doc(url, encoded_signature, doc).as_str())
+            .0
+            .is_err());
+    }
+
+    #[test]
+    fn verification_fails_during_base64_decoding() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let badly_encoded_signature = Base64Signature("!exclamations!arent!base64!".to_string());
+        let doc = "Document text here";
+
+        assert!(
+            verify(format_doc(url, badly_encoded_signature, doc).as_str())
+                .0
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn verification_fails_if_bad_key() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = "document text here";
+        // This key won't be the same as the correct key for the user beyarkay
+        let signing_key = test_util::deterministic_signing_key("not beyarkay");
+        let encoded_signature = Base64Signature(signature_to_b64(
+            &signing_key.sign(&signed_message(PROVENANCE_VERSION, url, doc.as_bytes())),
+        ));
+
+        assert!(verify(format_doc(url, encoded_signature, doc).as_str())
+            .0
+            .is_err());
+    }
+
+    #[test]
+    fn verification_fails_if_bad_doc() {
+        // Verification should fail if the document was modified after being signed
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = "document text here";
+        let client = reqwest::blocking::Client::new();
+        // Generate a new key and retrieve the (signing, verifying) keypair
+        let mut random_numbers = OsRng;
+        let key_details = request_new_key(
+            "http://localhost:8000",
+            &format!("user_{}", random_numbers.gen_range(0..1_000_000)),
+            &client,
+        )
+        .unwrap();
+
+        // Convert the base64 string into a SigningKey object
+        let signing_key: SigningKey = Base64SigningKey(key_details.signing).try_into().unwrap();
+
+        // Sign the document
+        let signature = signing_key.sign(&signed_message(PROVENANCE_VERSION, url, doc.as_bytes()));
+        // base64-encode the signature
+        let encoded_signature = Base64Signature(signature_to_b64(&signature));
+
+        let mutated_doc = format!("{doc}and then some extra data");
+
+        assert!(
+            verify(format_doc(url, encoded_signature, &mutated_doc).as_str())
+                .0
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn verification_succeeds() {
+        let mut random_numbers = OsRng;
+        let username = Username(format!("user_{}", random_numbers.gen_range(0..1_000_000)));
+        let provenance_url = format!("http://localhost:8000/provenance/{}", username.0);
+        let doc = "document text here";
+        let client = reqwest::blocking::Client::new();
+
+        // Generate a new keypair
+        let key_details = request_new_key("http://localhost:8000", &username.0, &client).unwrap();
+        // convert the base64 signing key to a SigningKey
+        let signing_key: SigningKey = Base64SigningKey(key_details.signing).try_into().unwrap();
+        // Sign the document
+        let signature = signing_key.sign(&signed_message(
+            PROVENANCE_VERSION,
+            &provenance_url,
+            doc.as_bytes(),
+        ));
+        // Base64 encode the signature
+        let encoded_signature = Base64Signature(signature_to_b64(&signature));
+
+        assert!(
+            verify(format_doc(&provenance_url, encoded_signature, doc).as_str())
+                .0
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn multiple_signers() {
+        let client = reqwest::blocking::Client::new();
+
+        let mut random_numbers = OsRng;
+
+        let mut usernames: Vec<Username> = (0..10)
+            .map(|_| Username(format!("user_{}", random_numbers.gen_range(0..1_000_000))))
+            .collect();
+
+        let mut signing_keys: Vec<SigningKey> = usernames
+            .iter()
+            .map(|username| {
+                let key_details =
+                    request_new_key("http://localhost:8000", &username.0, &client).unwrap();
+                // convert the base64 signing key to a SigningKey
+                Base64SigningKey(key_details.signing).try_into().unwrap()
+            })
+            .collect();
+
+        let mut doc = "This is the document that's passing through lots of hands".to_string();
+
+        for (signing_key, username) in signing_keys.iter().zip(usernames.iter()) {
+            let provenance_url = format!("http://localhost:8000/provenance/{}", username.0);
+            // Sign the document
+            let signature = signing_key.sign(&signed_message(
+                PROVENANCE_VERSION,
+                &provenance_url,
+                doc.as_bytes(),
+            ));
+            // Base64 encode the signature
+            let encoded_signature = Base64Signature(signature_to_b64(&signature));
+
+            doc = format_doc(&provenance_url, encoded_signature, &doc);
+            assert!(verify(&doc).0.is_ok());
+        }
+
+        usernames.reverse();
+        signing_keys.reverse();
+
+        for (signing_key, username) in signing_keys.iter().zip(usernames.iter()) {
+            let verified = verify(&doc);
+
+            let details = verified.0.unwrap();
+            // We don't use a `let` here so that rustc doesn't think it's a new variable and drop
+            // it at the end of the loop iteration
+            doc = verified.1;
+
+            assert_eq!(
+                details.verification_url,
+                format!("http://localhost:8000/provenance/{}", username.0)
+            );
+            assert_eq!(details.verification_key, signing_key.verifying_key());
+        }
+    }
+
+    fn generate_users_and_signing_keys(number: u8) -> Vec<(Username, SigningKey)> {
+        let client = reqwest::blocking::Client::new();
+
+        let mut random_numbers = OsRng;
+
+        let usernames: Vec<Username> = (0..number)
+            .map(|_| Username(format!("user_{}", random_numbers.gen_range(0..1_000_000))))
+            .collect();
+
+        let signing_keys: Vec<SigningKey> = usernames
+            .iter()
+            .map(|username| {
+                let key_details =
+                    request_new_key("http://localhost:8000", &username.0, &client).unwrap();
+                // convert the base64 signing key to a SigningKey
+                Base64SigningKey(key_details.signing).try_into().unwrap()
+            })
+            .collect();
+
+        usernames.into_iter().zip(signing_keys).collect()
+    }
+
+    #[test]
+    fn verify_all_works() {
+        let (mut usernames, mut signing_keys): (Vec<Username>, Vec<SigningKey>) =
+            generate_users_and_signing_keys(10).into_iter().unzip();
+
+        let original_doc = "This is the document that's passing through lots of hands".to_string();
+        let mut doc = original_doc.clone();
+
+        for (signing_key, username) in signing_keys.iter().zip(usernames.iter()) {
+            let provenance_url = format!("http://localhost:8000/provenance/{}", username.0);
+            // Sign the document
+            let signature = signing_key.sign(&signed_message(
+                PROVENANCE_VERSION,
+                &provenance_url,
+                doc.as_bytes(),
+            ));
+            // Base64 encode the signature
+            let encoded_signature = Base64Signature(signature_to_b64(&signature));
+
+            doc = format_doc(&provenance_url, encoded_signature, &doc);
+            assert!(verify(&doc).0.is_ok());
+        }
+
+        usernames.reverse();
+        signing_keys.reverse();
+        let (results, remainder) = verify_all(&doc);
+
+        assert_eq!(remainder, original_doc);
+
+        for ((result, username), key) in results.iter().zip(usernames).zip(signing_keys) {
+            let Ok(signer_details) = result else {
+                panic!("Result is {result:?} (not OK)")
+            };
+
+            assert_eq!(
+                signer_details.verification_url,
+                format!("http://localhost:8000/provenance/{}", username.0)
+            );
+
+            assert_eq!(signer_details.verification_key, key.verifying_key());
+        }
+    }
+
+    #[test]
+    fn verify_all_but_some_are_bad() {
+        // FIXME so if signer N in a chain of signers K..N..1 edited the underlying data, we cannot
+        // confirm all signers n-1..1 because we can't know for sure what the edit was. Unless we
+        // encode the original *and* the edited file, or the diff between them, but then things get
+        // really tricky.
+        //
+        // And if the signer N pretends to not have edited the file, then we'll see all signers
+        // N..1  (so including N) fail verification because we will be checking signatures N..1
+        // against an edited document.
+        let (mut usernames, mut signing_keys): (Vec<Username>, Vec<SigningKey>) =
+            generate_users_and_signing_keys(4).into_iter().unzip();
+
+        let original_doc = "This is the document that's passing through lots of hands".to_string();
+        let mut doc = original_doc.clone();
+
+        // Hard coded 10 "random" booleans because we don't like flakey tests
+        let mut is_mutated = vec![
+            false, true, false,
+            true, // false, true,
+                 // true, //  keep the non-compliant formatting so
+                 // false, false, false, true, false, // that it's easy to see there's 10 elements
+        ];
+        assert_eq!(
+            usernames.len(),
+            is_mutated.len(),
+            "Usernames and is_mutated should be the same length"
+        );
+        let mutation_string = " got mutated!".to_string();
+
+        let iterator = signing_keys
+            .iter()
+            .zip(usernames.iter())
+            .zip(is_mutated.iter());
+        for ((signing_key, username), mutate) in iterator {
+            let provenance_url = format!("http://localhost:8000/provenance/{}", username.0);
+            // Sign the document
+            let signature = signing_key.sign(&signed_message(
+                PROVENANCE_VERSION,
+                &provenance_url,
+                doc.as_bytes(),
+            ));
+            // Base64 encode the signature
+            let encoded_signature = Base64Signature(signature_to_b64(&signature));
+
+            if *mutate {
+                doc = format_doc(
+                    &provenance_url,
+                    encoded_signature,
+                    &format!("{doc}{mutation_string}"),
+                );
+            } else {
+                doc = format_doc(&provenance_url, encoded_signature, &doc);
+            }
+
+            if *mutate {
+                assert!(verify(&doc).0.is_err());
+            } else {
+                assert!(verify(&doc).0.is_ok());
+            }
+        }
+
+        // Reverse the vectors since we verify in the opposite order to which we sign
+        usernames.reverse();
+        is_mutated.reverse();
+        signing_keys.reverse();
+
+        // Actually do the verification
+        let (results, _remainder) = verify_all(&doc);
+
+        let iterator = results
+            .iter()
+            .zip(usernames)
+            .zip(signing_keys)
+            .zip(is_mutated);
+
+        let mut doc_has_been_mutated = false;
+        for (((result, username), key), mutated) in iterator {
+            doc_has_been_mutated = doc_has_been_mutated || mutated;
+
+            if doc_has_been_mutated {
+                assert!(result.is_err())
+            } else {
+                let Ok(signer_details) = result else {
+                    panic!("Result is {result:?} (not Ok)")
+                };
+
+                assert_eq!(
+                    signer_details.verification_url,
+                    format!("http://localhost:8000/provenance/{}", username.0)
+                );
+
+                assert_eq!(signer_details.verification_key, key.verifying_key());
+            }
+        }
+    }
+
+    #[test]
+    fn signature_decoding_accepts_both_alphabets() {
+        // 0xfb bytes encode to `-` and `_` in the URL-safe alphabet, but `+` and `/` in the standard one
+        let bytes = [0xfb; ed25519_dalek::SIGNATURE_LENGTH];
+        let url_safe: Signature = Base64Signature(URL_SAFE.encode(bytes)).try_into().unwrap();
+        let standard: Signature = Base64Signature(STANDARD.encode(bytes)).try_into().unwrap();
+
+        assert_ne!(URL_SAFE.encode(bytes), STANDARD.encode(bytes));
+        assert_eq!(url_safe, standard);
+    }
+
+    #[test]
+    fn verify_all_with_mixed_base64_alphabets() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+
+        let original_doc = "This document was signed by tools from different ecosystems";
+        let mut doc = original_doc.to_string();
+
+        for layer in 0..6 {
+            let signature = signing_key
+                .sign(&signed_message(PROVENANCE_VERSION, url, doc.as_bytes()))
+                .to_bytes();
+            // Alternate between the URL-safe and the standard base64 alphabets
+            let encoded_signature = if layer % 2 == 0 {
+                Base64Signature(URL_SAFE.encode(signature))
+            } else {
+                Base64Signature(STANDARD.encode(signature))
+            };
+            doc = format_doc(url, encoded_signature, &doc);
+        }
+
+        let (results, remainder) = verify_all(&doc);
+
+        assert_eq!(results.len(), 6);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(remainder, original_doc);
+    }
+
+    #[test]
+    fn verify_parts_works() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = "A document uploaded separately from its provenance";
+        let signing_key = test_util::beyarkay_key();
+
+        let header = sign_detached(doc, &signing_key, url);
+
+        let signer_details = verify_parts(&header, doc.as_bytes()).unwrap();
+        assert_eq!(signer_details.verification_url, url);
+        assert_eq!(signer_details.verification_key, signing_key.verifying_key());
+
+        // A trailing newline (as written to a sidecar file) is fine
+        assert!(verify_parts(&format!("{header}\n"), doc.as_bytes()).is_ok());
+
+        // But the body must match, and the header must be a single line
+        assert!(verify_parts(&header, b"A different document").is_err());
+        assert!(verify_parts(&format!("{header}\n{doc}"), doc.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn sign_with_method_round_trips_both_encodings() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = b"Some document that I definitely wrote";
+        let signing_key = test_util::beyarkay_key();
+
+        let text = sign_with_method(doc, &signing_key, url, SigningMethod::Text);
+        let binary = sign_with_method(doc, &signing_key, url, SigningMethod::Binary);
+
+        // The text encoding is exactly what `sign` produces
+        assert_eq!(
+            text,
+            sign(std::str::from_utf8(doc).unwrap(), &signing_key, url).into_bytes()
+        );
+        assert!(binary::is_binary(&binary));
+        assert!(binary.len() < text.len());
+
+        for signed_doc in [text, binary] {
+            let (result, remainder) = verify_bytes(&signed_doc);
+            let signer_details = result.unwrap();
+            assert_eq!(signer_details.verification_url, url);
+            assert_eq!(signer_details.verification_key, signing_key.verifying_key());
+            assert_eq!(remainder, doc);
+        }
+    }
+
+    #[test]
+    fn verify_bytes_fails_if_binary_doc_is_mutated() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+
+        let mut signed_doc = sign_with_method(
+            b"document text here",
+            &signing_key,
+            url,
+            SigningMethod::Binary,
+        );
+        signed_doc.extend_from_slice(b" and then some extra data");
+
+        assert!(verify_bytes(&signed_doc).0.is_err());
+    }
+
+    #[test]
+    fn exif_testing() -> Result<(), Box<dyn std::error::Error>> {
+        let file = std::fs::File::open("tests/20240317_213020.jpg").unwrap();
+        let Ok(exif_data) = Reader::new().read_from_container(&mut std::io::BufReader::new(&file))
+        else {
+            return Err("No Exif data found".into());
+        };
+
+        for f in exif_data.fields() {
+            println!(
+                "{:30} {:80} {:25}",
+                f.tag,
+                f.display_value().with_unit(&exif_data),
+                f.ifd_num,
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn docstring_test() {
+        use crate::sign;
+        use ed25519_dalek::SigningKey;
+
+        // In reality this would be the server of whomever you're delegating trust. An example
+        // server implementation (which is used for these tests) is available at
+        // https://github.com/beyarkay/provenance-server
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = "Some document that I definitely wrote";
+        let base64_signing_key =
+            Base64SigningKey("-5TaFC0xFOj_hf7mlvVaLKKpVFTaXUrLDzRqaaf7gFw=".to_string());
+        let signing_key: SigningKey = base64_signing_key.try_into().unwrap();
+
+        let _signed_doc = sign(doc, &signing_key, url);
+    }
+
+    #[test]
+    fn sign_detached_is_header_of_sign() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = "Some document that I definitely wrote";
+        let signing_key = test_util::beyarkay_key();
+
+        let header = sign_detached(doc, &signing_key, url);
+        let signed_doc = sign(doc, &signing_key, url);
+
+        assert_eq!(format!("{header}\n{doc}"), signed_doc);
+        assert!(!header.contains('\n'));
+    }
+
+    #[test]
+    fn rehome_url_is_an_error_once_urls_are_signed() {
+        let old_url = "http://old.example.com/provenance/beyarkay";
+        let new_url = "http://localhost:8000/provenance/beyarkay";
+        let signed_doc = sign("document text here", &test_util::beyarkay_key(), old_url);
+
+        let report = rehome_url(&signed_doc, old_url, new_url);
+        assert_eq!(report.is_err(), URL_IS_SIGNED);
+    }
+
+    #[test]
+    fn rehome_and_resign_keeps_outermost_layer_valid() {
+        let old_url = "http://old.example.com/provenance/beyarkay";
+        let new_url = "http://localhost:8000/provenance/beyarkay";
+        let doc = "Some document that I definitely wrote";
+        let signing_key = test_util::beyarkay_key();
+
+        let signed_doc = sign(doc, &signing_key, old_url);
+        // The old server doesn't exist, so this can't be verified
+        assert!(verify(&signed_doc).0.is_err());
+
+        let report = rehome_and_resign(&signed_doc, old_url, new_url, &signing_key).unwrap();
+
+        assert_eq!(report.rewritten_layers, vec![0]);
+        assert!(report.invalidated_layers.is_empty());
+        let (result, remainder) = verify(&report.doc);
+        assert_eq!(result.unwrap().verification_url, new_url);
+        assert_eq!(remainder, doc);
+    }
+
+    #[test]
+    fn rehome_and_resign_reports_invalidated_outer_layers() {
+        let old_url = "http://old.example.com/provenance/beyarkay";
+        let new_url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+
+        // The inner layer uses the old URL, the two outer layers already use the new one
+        let mut signed_doc = sign("document text here", &signing_key, old_url);
+        signed_doc = sign(&signed_doc, &signing_key, new_url);
+        signed_doc = sign(&signed_doc, &signing_key, new_url);
+
+        let report = rehome_and_resign(&signed_doc, old_url, new_url, &signing_key).unwrap();
+
+        assert_eq!(report.rewritten_layers, vec![2]);
+        assert_eq!(report.invalidated_layers, vec![0, 1]);
+
+        let (results, remainder) = verify_all(&report.doc);
+        assert!(results[0].is_err());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+        assert_eq!(remainder, "document text here");
+    }
+
+    #[test]
+    fn rehome_and_resign_only_re_signs_the_signers_own_layers() {
+        let old_url = "http://old.example.com/provenance/beyarkay";
+        let new_url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::deterministic_signing_key("alice");
+        let other_key = test_util::deterministic_signing_key("bob");
+
+        let signed_doc = sign("document text here", &signing_key, old_url);
+        assert!(rehome_and_resign(&signed_doc, old_url, new_url, &other_key).is_err());
+        assert!(rehome_and_resign(&signed_doc, old_url, "not a url", &signing_key).is_err());
+
+        // Rewritten layers are re-signed, so only outer layers from other URLs are invalidated
+        let mut signed_doc = sign(&signed_doc, &other_key, "https://example.com/bob");
+        signed_doc = sign(&signed_doc, &signing_key, old_url);
+        let report = rehome_and_resign(&signed_doc, old_url, new_url, &signing_key).unwrap();
+        assert_eq!(report.rewritten_layers, vec![0, 2]);
+        assert_eq!(report.invalidated_layers, vec![1]);
+        let resolve = |_: &str| Ok(signing_key.verifying_key());
+        assert!(verify_with(&report.doc, resolve).0.is_ok());
+    }
+
+    #[test]
+    fn rehome_and_resign_handles_crlf_headers() {
+        let old_url = "http://old.example.com/provenance/beyarkay";
+        let new_url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let other_key = test_util::deterministic_signing_key("bob");
+
+        let signed_doc = sign_with_line_ending(
+            "document text here",
+            &signing_key,
+            old_url,
+            LineEnding::CrLf,
+        );
+        let signed_doc = sign_with_line_ending(
+            &signed_doc,
+            &other_key,
+            "https://example.com/bob",
+            LineEnding::CrLf,
+        );
+        let signed_doc =
+            sign_with_line_ending(&signed_doc, &signing_key, old_url, LineEnding::CrLf);
+
+        let report = rehome_and_resign(&signed_doc, old_url, new_url, &signing_key).unwrap();
+        assert_eq!(report.rewritten_layers, vec![0, 2]);
+        assert_eq!(report.invalidated_layers, vec![1]);
+        assert_eq!(report.doc.matches("\r\n").count(), 3);
+        let resolve = |_: &str| Ok(signing_key.verifying_key());
+        assert!(verify_with(&report.doc, resolve).0.is_ok());
+    }
+
+    #[test]
+    fn verification_fails_if_url_is_swapped() {
+        // Both of these URLs serve the same key, so before URLs were signed the swapped document
+        // would have verified
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let swapped_url = "http://127.0.0.1:8000/provenance/beyarkay";
+        let doc = "document text here";
+        let signing_key = test_util::beyarkay_key();
+
+        let signed_doc = sign(doc, &signing_key, url);
+        assert!(verify(&signed_doc).0.is_ok());
+
+        let swapped_doc = signed_doc.replacen(url, swapped_url, 1);
+        assert_ne!(swapped_doc, signed_doc);
+        assert!(verify(&swapped_doc).0.is_err());
+    }
+
+    #[test]
+    fn preflight_marks_dead_servers_unreachable() {
+        let signing_key = test_util::beyarkay_key();
+        let options = VerifyOptions {
+            preflight_timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+
+        // A live server passes the preflight check
+        let live_url = "http://localhost:8000/provenance/beyarkay";
+        let signed_doc = sign("document text here", &signing_key, live_url);
+        assert!(verify_with_options(&signed_doc, &options).0.is_ok());
+
+        // A server which accepts connections but never responds is skipped after the timeout,
+        // rather than hanging until the GET request times out
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let hung_url = format!(
+            "http://{}/provenance/beyarkay",
+            listener.local_addr().unwrap()
+        );
+        let signed_doc = sign("document text here", &signing_key, &hung_url);
+
+        let start = std::time::Instant::now();
+        let (results, _remainder) = verify_all_with_options(&signed_doc, &options);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let error = results[0].as_ref().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<VerifyError>(),
+            Some(VerifyError::Unreachable { url }) if *url == hung_url
+        ));
+    }
+
+    #[test]
+    fn verify_with_signer_details_skips_the_network() {
+        // Nothing is listening on this port, so fetching the key would fail
+        let url = "http://localhost:1/provenance/beyarkay";
+        let doc = "document text here";
+        let signing_key = test_util::beyarkay_key();
+        let signed_doc = sign(doc, &signing_key, url);
+
+        let signer_details = SignerDetailsFromServer {
+            verification_url: url.to_string(),
+            verification_key_b64: URL_SAFE.encode(signing_key.verifying_key().to_bytes()),
+            metadata: HashMap::new(),
+            webauthn_key_b64: None,
+        };
+        let (result, remainder) = verify_with_signer_details(&signed_doc, &signer_details);
+        assert_eq!(
+            result.unwrap().verification_key,
+            signing_key.verifying_key()
+        );
+        assert_eq!(remainder, doc);
+
+        // Details for some other URL are rejected, even though the key is correct
+        let other_signer_details = SignerDetailsFromServer {
+            verification_url: "http://localhost:1/provenance/someone_else".to_string(),
+            ..signer_details
+        };
+        assert!(
+            verify_with_signer_details(&signed_doc, &other_signer_details)
+                .0
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn server_signer_details_match_the_document() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let signed_doc = sign("document text here", &signing_key, url);
+
+        let signer_details: SignerDetailsFromServer =
+            reqwest::blocking::get(url).unwrap().json().unwrap();
+
+        assert!(verify_with_signer_details(&signed_doc, &signer_details)
+            .0
+            .is_ok());
+    }
+
+    #[test]
+    fn seal_is_verified_as_a_whole() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+
+        let mut inner_doc = sign("document text here", &signing_key, url);
+        inner_doc = sign(&inner_doc, &signing_key, url);
+        let sealed_doc = seal(&inner_doc, &signing_key, url);
+
+        assert!(is_sealed(&sealed_doc));
+        assert!(!is_sealed(&inner_doc));
+
+        let (result, remainder) = verify_seal(&sealed_doc);
+        assert_eq!(
+            result.unwrap().verification_key,
+            signing_key.verifying_key()
+        );
+        assert_eq!(remainder, inner_doc);
+
+        // verify_all stops at the seal rather than stripping the inner layers
+        let (results, remainder) = verify_all(&sealed_doc);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_ok());
+        assert_eq!(remainder, inner_doc);
+
+        // Any change to the sealed document (including its inner headers) breaks the seal
+        let tampered_doc = sealed_doc.replacen("document text", "tampered text", 1);
+        assert!(verify_seal(&tampered_doc).0.is_err());
+    }
+
+    #[test]
+    fn seal_cannot_be_relabelled_as_a_layer() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+
+        let sealed_doc = seal("document text here", &signing_key, url);
+        let relabelled_doc = sealed_doc
+            .replacen(SEAL_PREAMBLE, PROVENANCE_PREAMBLE, 1)
+            .replacen(SEAL_POSTAMBLE, PROVENANCE_POSTAMBLE, 1);
+
+        assert!(!is_sealed(&relabelled_doc));
+        assert!(verify(&relabelled_doc).0.is_err());
+    }
+
+    #[test]
+    fn verify_chain_distinguishes_unreachable_from_forged() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        // Nothing is listening on this port
+        let down_url = "http://localhost:1/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let forger = test_util::deterministic_signing_key("forger");
+
+        let mut doc = sign("document text here", &signing_key, url);
+        doc = sign(&doc, &signing_key, down_url);
+        doc = sign(&doc, &forger, url);
+        doc = sign(&doc, &signing_key, url);
+
+        let chain = verify_chain(&doc, &VerifyOptions::default());
+
+        let statuses: Vec<_> = chain.layers.iter().map(|layer| layer.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                VerifyStatus::Verified,
+                VerifyStatus::Forged,
+                VerifyStatus::Unreachable,
+                VerifyStatus::Verified,
+            ]
+        );
+        assert_eq!(chain.layers[2].verification_url, down_url);
+        assert_eq!(chain.count(VerifyStatus::Verified), 2);
+        assert_eq!(chain.count(VerifyStatus::Unreachable), 1);
+        assert_eq!(chain.count(VerifyStatus::Forged), 1);
+        assert_eq!(chain.remainder, "document text here");
+    }
+
+    #[test]
+    fn shares_signer_compares_keys_not_urls() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let other_key = test_util::deterministic_signing_key("other");
+
+        let doc_a = sign("first document", &signing_key, url);
+        let doc_b = sign(
+            &sign("second document", &signing_key, url),
+            &other_key,
+            "http://localhost:1/provenance/other",
+        );
+        let doc_c = sign("third document", &other_key, url);
+
+        assert!(shares_signer(&doc_a, &doc_b));
+        // doc_c claims the same URL, but its signature doesn't verify under that URL's key
+        assert!(!shares_signer(&doc_a, &doc_c));
+        assert!(!shares_signer(&doc_a, "not signed\nat all"));
+        assert!(!shares_signer("not signed", "not signed"));
+    }
+
+    #[test]
+    fn shares_signer_offline_uses_the_key_map() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let bob = test_util::deterministic_signing_key("bob");
+        let keys = HashMap::from([
+            ("https://a.example/alice".to_string(), alice.verifying_key()),
+            ("https://b.example/alice".to_string(), alice.verifying_key()),
+            ("https://a.example/bob".to_string(), bob.verifying_key()),
+        ]);
+
+        let doc_a = sign("first document", &alice, "https://a.example/alice");
+        let doc_b = sign(
+            &sign("second document", &alice, "https://b.example/alice"),
+            &bob,
+            "https://a.example/bob",
+        );
+        let doc_c = sign("third document", &bob, "https://a.example/bob");
+        let doc_d = sign("fourth document", &alice, "https://a.example/bob");
+
+        assert!(shares_signer_offline(&doc_a, &doc_b, &keys));
+        assert!(shares_signer_offline(&doc_b, &doc_c, &keys));
+        assert!(!shares_signer_offline(&doc_a, &doc_c, &keys));
+        // doc_d's signature doesn't match the key for its URL
+        assert!(!shares_signer_offline(&doc_a, &doc_d, &keys));
+        assert!(!shares_signer_offline(&doc_a, "not signed", &keys));
+    }
+
+    #[test]
+    fn signer_details_fingerprint_depends_only_on_the_key() {
+        let key = test_util::deterministic_signing_key("alice").verifying_key();
+        let a = SignerDetails::new("https://a.example/alice", key);
+        let b = SignerDetails::new("https://b.example/alice", key);
+
+        assert_ne!(a, b);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_eq!(a.clone(), a);
+    }
+
+    #[test]
+    fn normalized_signatures_survive_normalized_changes() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let doc = "line one  \nline two\n";
+
+        let signed_doc =
+            sign_normalized(doc, &signing_key, url, &normalize::TrimTrailingWhitespace).unwrap();
+        let (header, remainder) = signed_doc.split_once('\n').unwrap();
+        assert_eq!(remainder, doc);
+        assert_eq!(
+            parse_header(header).unwrap().extension("norm"),
+            Some("trim-trailing-whitespace")
+        );
+        assert!(verify(&signed_doc).0.is_ok());
+
+        // Trailing whitespace doesn't matter, but the content still does
+        assert!(verify(&signed_doc.replace("one  ", "one")).0.is_ok());
+        assert!(verify(&signed_doc.replace("one", "1")).0.is_err());
+
+        // The normalizer is covered by the signature
+        let renormalized = signed_doc.replace("trim-trailing-whitespace", "identity");
+        assert!(verify(&renormalized).0.is_err());
+    }
+
+    #[test]
+    fn custom_normalizers_must_be_given_to_the_verifier() {
+        struct Lowercase;
+        impl DocumentNormalizer for Lowercase {
+            fn id(&self) -> &str {
+                "lowercase"
+            }
+
+            fn normalize(&self, doc: &[u8]) -> Vec<u8> {
+                doc.to_ascii_lowercase()
+            }
+        }
+
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let signed_doc = sign_normalized("Some Title", &signing_key, url, &Lowercase).unwrap();
+        let shouted_doc = signed_doc.replace("Some Title", "SOME TITLE");
+
+        assert!(verify(&shouted_doc).0.is_err());
+        let options = VerifyOptions {
+            normalizers: vec![Arc::new(Lowercase)],
+            ..Default::default()
+        };
+        let (result, remainder) = verify_with_options(&shouted_doc, &options);
+        assert!(result.is_ok());
+        assert_eq!(remainder, "SOME TITLE");
+    }
+
+    #[test]
+    fn verify_chain_records_fetch_durations() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let malformed_header = format!(
+            "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} {url} not-base64! {PROVENANCE_POSTAMBLE}"
+        );
+
+        let mut doc = sign("document text here", &signing_key, url);
+        doc = format!("{malformed_header}\n{doc}");
+        doc = sign(&doc, &signing_key, url);
+
+        let chain = verify_chain(&doc, &VerifyOptions::default());
+
+        assert_eq!(chain.layers.len(), 3);
+        assert!(chain.layers[0].fetch_duration.is_some());
+        assert!(!chain.layers[0].from_cache);
+        // The malformed header is rejected before anything is fetched
+        assert_eq!(chain.layers[1].fetch_duration, None);
+        assert!(!chain.layers[1].from_cache);
+        // The key for the innermost layer was already fetched for the outermost layer
+        assert_eq!(chain.layers[2].fetch_duration, None);
+        assert!(chain.layers[2].from_cache);
+    }
+
+    #[test]
+    fn accepted_versions_are_configurable() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        // A layer from an older version of the protocol, which signed the same way
+        let old_version = "0.2.1";
+        let doc = "document text here";
+        let signature = signing_key.sign(&signed_message(old_version, url, doc.as_bytes()));
+        let old_doc = format!(
+            "{PROVENANCE_PREAMBLE} {old_version} {url} {} {PROVENANCE_POSTAMBLE}\n{doc}",
+            signature_to_b64(&signature)
+        );
+
+        let error = verify(&old_doc).0.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<VerifyError>(),
+            Some(VerifyError::UnsupportedVersion { found, accepted })
+                if found == old_version && *accepted == VersionSpec::default()
+        ));
+
+        let options = VerifyOptions {
+            accepted_versions: VersionSpec::Range(">=0.2, <0.4".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(verify_with_options(&old_doc, &options).0.is_ok());
+        let current_doc = sign(doc, &signing_key, url);
+        assert!(verify_with_options(&current_doc, &options).0.is_ok());
+
+        let options = VerifyOptions {
+            accepted_versions: VersionSpec::CurrentMajor,
+            ..Default::default()
+        };
+        assert!(verify_with_options(&old_doc, &options).0.is_err());
+    }
+
+    #[test]
+    fn outermost_signer_url_only_parses_the_first_line() {
+        // Nothing is listening on these ports, so any fetching would fail
+        let signing_key = test_util::deterministic_signing_key("alice");
+        let inner_url = "http://localhost:1/provenance/inner";
+        let outer_url = "http://localhost:1/provenance/outer";
+
+        let signed_doc = sign(
+            &sign("document text here", &signing_key, inner_url),
+            &signing_key,
+            outer_url,
+        );
+        assert_eq!(
+            outermost_signer_url(&signed_doc).as_deref(),
+            Some(outer_url)
+        );
+        assert_eq!(
+            outermost_signer_url(&seal(&signed_doc, &signing_key, inner_url)).as_deref(),
+            Some(inner_url)
+        );
+
+        assert_eq!(outermost_signer_url("document text here"), None);
+        assert_eq!(outermost_signer_url("two lines\nof text"), None);
+        let malformed = format!(
+            "{PROVENANCE_PREAMBLE} {PROVENANCE_VERSION} {outer_url} {PROVENANCE_POSTAMBLE}\nbody"
+        );
+        assert_eq!(outermost_signer_url(&malformed), None);
+    }
+
+    #[test]
+    fn verify_with_uses_the_resolver() {
+        // Nothing is listening on this port, so the key can't come from the network
+        let url = "http://localhost:1/provenance/alice";
+        let alice = test_util::deterministic_signing_key("alice");
+        let keys = HashMap::from([(url.to_string(), alice.verifying_key())]);
+        let signed_doc = sign("document text here", &alice, url);
+
+        let resolver = |url: &str| {
+            keys.get(url)
+                .copied()
+                .ok_or_else(|| anyhow!("No key for {url}"))
+        };
+        let (result, remainder) = verify_with(&signed_doc, resolver);
+        assert_eq!(result.unwrap().verification_url, url);
+        assert_eq!(remainder, "document text here");
+
+        let (result, _) = verify_with(&signed_doc, |url| Err(anyhow!("No key for {url}")));
+        assert_eq!(result.unwrap_err().to_string(), format!("No key for {url}"));
+
+        let bob = test_util::deterministic_signing_key("bob");
+        assert!(verify_with(&signed_doc, |_| Ok(bob.verifying_key()))
+            .0
+            .is_err());
+    }
+
+    /// Serve `signer_details` to every request on a local port, counting the requests.
+    fn counting_server(signer_details: SignerDetailsFromServer) -> (String, Arc<AtomicUsize>) {
+        let (url, count, _) = mutable_server(signer_details);
+        (url, count)
+    }
+
+    /// Like [`counting_server`], but the served details can be changed while the server runs.
+    fn mutable_server(
+        signer_details: SignerDetailsFromServer,
+    ) -> (
+        String,
+        Arc<AtomicUsize>,
+        Arc<Mutex<SignerDetailsFromServer>>,
+    ) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let details = Arc::new(Mutex::new(signer_details));
+
+        let thread_count = Arc::clone(&count);
+        let thread_details = Arc::clone(&details);
+        let addr = test_util::mock_server_with(move |_| {
+            thread_count.fetch_add(1, Ordering::SeqCst);
+            let body = serde_json::to_vec(&*thread_details.lock().unwrap()).unwrap();
+            test_util::MockResponse::new(200, body)
+        });
+
+        (format!("http://{addr}/provenance/counted"), count, details)
+    }
+
+    #[test]
+    fn verify_all_fetches_each_url_once() {
+        let signing_key = test_util::deterministic_signing_key("counted");
+        let (url, count) = counting_server(SignerDetailsFromServer {
+            verification_url: String::new(),
+            verification_key_b64: URL_SAFE.encode(signing_key.verifying_key().to_bytes()),
+            metadata: HashMap::new(),
+            webauthn_key_b64: None,
+        });
+
+        let mut doc = "document text here".to_string();
+        for _ in 0..10 {
+            doc = sign(&doc, &signing_key, &url);
+        }
+
+        let chain = verify_chain(&doc, &VerifyOptions::default());
+        assert_eq!(chain.count(VerifyStatus::Verified), 10);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            chain.layers.iter().filter(|layer| layer.from_cache).count(),
+            9
+        );
+
+        // The cache only lasts for a single call
+        assert!(verify_all(&doc).0.iter().all(|result| result.is_ok()));
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn verify_chain_records_covered_bytes() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let doc = "document text here";
+
+        let inner = sign(doc, &signing_key, url);
+        let outer = sign(&inner, &signing_key, url);
+        let sealed = seal(&outer, &signing_key, url);
+
+        let chain = verify_chain(&outer, &VerifyOptions::default());
+        let covered: Vec<_> = chain
+            .layers
+            .iter()
+            .map(|layer| layer.covered_bytes)
+            .collect();
+        assert_eq!(covered, vec![inner.len(), doc.len()]);
+
+        let chain = verify_chain(&sealed, &VerifyOptions::default());
+        assert_eq!(chain.layers[0].covered_bytes, outer.len());
+    }
+
+    #[test]
+    fn unreachable_servers_are_told_apart_from_forgeries() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let down_url = "http://localhost:1/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let doc = sign("document text here", &signing_key, down_url);
+
+        // Never Ok, since nothing was verified
+        let result = verify(&doc).0;
+        assert_eq!(VerifyStatus::of(&result), VerifyStatus::Unreachable);
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(VerifyError::Unreachable { url }) if url == down_url
+        ));
+
+        // Layers that can be checked are still checked, and forgeries still fail
+        let doc = sign(&doc, &signing_key, url);
+        let (results, _) = verify_all(&doc);
+        let statuses: Vec<_> = results.iter().map(VerifyStatus::of).collect();
+        assert_eq!(
+            statuses,
+            vec![VerifyStatus::Verified, VerifyStatus::Unreachable]
+        );
+        let forged = sign(&doc, &test_util::deterministic_signing_key("forger"), url);
+        assert_eq!(VerifyStatus::of(&verify(&forged).0), VerifyStatus::Forged);
+    }
+
+    #[test]
+    fn soft_warn_reports_what_unreachable_layers_claim() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let down_url = "http://localhost:1/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let doc = sign_with_kind(
+            "document text here",
+            &signing_key,
+            down_url,
+            LayerKind::Edited,
+        );
+        let soft_warn = VerifyOptions {
+            network_failure_mode: NetworkFailureMode::SoftWarn,
+            ..Default::default()
+        };
+
+        // Still an error, but one which says what the layer claims
+        let result = verify_with_options(&doc, &soft_warn).0;
+        assert_eq!(VerifyStatus::of(&result), VerifyStatus::Unreachable);
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(VerifyError::Unverified { url, kind: LayerKind::Edited, group: None, terms: None })
+                if url == down_url
+        ));
+
+        // Layers that can be checked are still checked, and forgeries still fail
+        let doc = sign(&doc, &signing_key, url);
+        let (results, _) = verify_all_with_options(&doc, &soft_warn);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1].as_ref().unwrap_err().downcast_ref(),
+            Some(VerifyError::Unverified { .. })
+        ));
+        let forged = sign(&doc, &test_util::deterministic_signing_key("forger"), url);
+        assert_eq!(
+            VerifyStatus::of(&verify_with_options(&forged, &soft_warn).0),
+            VerifyStatus::Forged
+        );
+    }
+
+    #[test]
+    fn sign_checked_catches_duplicate_layers() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let alice = test_util::deterministic_signing_key("alice");
+        let bob = test_util::deterministic_signing_key("bob");
+        let doc = sign("document text here", &alice, url);
+
+        assert!(sign_checked(&doc, &alice, url, DuplicatePolicy::Refuse).is_err());
+        assert_eq!(
+            sign_checked(&doc, &alice, url, DuplicatePolicy::Skip).unwrap(),
+            doc
+        );
+        assert_eq!(
+            sign_checked(&doc, &alice, url, DuplicatePolicy::Allow).unwrap(),
+            sign(&doc, &alice, url)
+        );
+
+        // A different key, or the same key for a different URL, isn't a duplicate
+        let other_url = "http://localhost:8000/provenance/alice";
+        for (key, url) in [(bob, url), (alice.clone(), other_url)] {
+            assert_eq!(
+                sign_checked(&doc, &key, url, DuplicatePolicy::Refuse).unwrap(),
+                sign(&doc, &key, url)
+            );
+        }
+        // Neither is a changed document
+        let changed = format!("{doc}\nwith an extra line");
+        assert!(sign_checked(&changed, &alice, url, DuplicatePolicy::Refuse).is_ok());
+    }
+
+    #[test]
+    fn stop_on_first_failure_skips_inner_layers() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let forger = test_util::deterministic_signing_key("forger");
+        let (counting_url, requests) = counting_server(SignerDetailsFromServer {
+            verification_url: String::new(),
+            verification_key_b64: URL_SAFE.encode(signing_key.verifying_key().to_bytes()),
+            metadata: HashMap::new(),
+            webauthn_key_b64: None,
+        });
+
+        let mut doc = sign("document text here", &signing_key, &counting_url);
+        doc = sign(&doc, &forger, url);
+        doc = sign(&doc, &signing_key, url);
+        let options = VerifyOptions {
+            stop_on_first_failure: true,
+            ..Default::default()
+        };
+
+        let chain = verify_chain(&doc, &options);
+        let statuses: Vec<_> = chain.layers.iter().map(|layer| layer.status).collect();
+        assert_eq!(statuses, vec![VerifyStatus::Verified, VerifyStatus::Forged]);
+        assert!(chain.stopped_early);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        assert!(chain.remainder.ends_with("document text here"));
+
+        let chain = verify_chain(&doc, &VerifyOptions::default());
+        assert_eq!(chain.layers.len(), 3);
+        assert!(!chain.stopped_early);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn is_intact_requires_every_layer_to_verify() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let down_url = "http://localhost:1/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let intact = |doc: &str, options: &VerifyOptions| verify_chain(doc, options).is_intact();
+        let options = VerifyOptions::default();
+
+        let doc = sign("document text here", &signing_key, url);
+        let doc = sign(&doc, &signing_key, url);
+        assert!(intact(&doc, &options));
+        assert!(!intact("document text here", &options));
+
+        // One broken layer anywhere breaks the whole chain
+        let forged = sign(&doc, &test_util::deterministic_signing_key("forger"), url);
+        assert!(!intact(&sign(&forged, &signing_key, url), &options));
+
+        let unreachable = sign(&doc, &signing_key, down_url);
+        assert!(!intact(&unreachable, &options));
+        let chain = verify_chain(&unreachable, &options);
+        assert_eq!(chain.count(VerifyStatus::Unreachable), 1);
+        assert_eq!(chain.count(VerifyStatus::Verified), 2);
+    }
+
+    #[test]
+    fn bad_base64_and_wrong_length_signatures_are_told_apart() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = "document text here";
+        let error = |signature_b64: &str| {
+            verify(&format_doc(
+                url,
+                Base64Signature(signature_b64.to_string()),
+                doc,
+            ))
+            .0
+            .unwrap_err()
+        };
+
+        let short = error(&URL_SAFE.encode([7; 21]));
+        assert!(matches!(
+            short.downcast_ref::<VerifyError>(),
+            Some(VerifyError::SignatureWrongLength {
+                got: 21,
+                expected: 64
+            })
+        ));
+        let long = error(&URL_SAFE.encode([7; 65]));
+        assert!(matches!(
+            long.downcast_ref::<VerifyError>(),
+            Some(VerifyError::SignatureWrongLength { got: 65, .. })
+        ));
+
+        let not_base64 = error("!exclamations!arent!base64!");
+        assert!(not_base64.downcast_ref::<VerifyError>().is_none());
+        assert!(not_base64.to_string().contains("isn't valid base64"));
+    }
+
+    #[test]
+    fn provenance_overhead_matches_sign() {
+        let signing_key = test_util::deterministic_signing_key("alice");
+        for url in [
+            "http://localhost:8000/provenance/beyarkay",
+            "https://例え.jp/ü",
+        ] {
+            let large = "x".repeat(DEFAULT_PREHASH_THRESHOLD + 1);
+            let at_threshold = "x".repeat(DEFAULT_PREHASH_THRESHOLD);
+            for doc in [
+                "",
+                "document text here",
+                "multi\nline\ndocument",
+                &at_threshold,
+                &large,
+            ] {
+                assert_eq!(
+                    sign(doc, &signing_key, url).len(),
+                    doc.len() + provenance_overhead(url, doc.len())
+                );
+            }
+        }
+        assert!(
+            provenance_overhead("https://example.com", DEFAULT_PREHASH_THRESHOLD + 1)
+                > provenance_overhead("https://example.com", DEFAULT_PREHASH_THRESHOLD)
+        );
+    }
+
+    #[test]
+    fn group_signatures_attest_a_member() {
+        let client = Client::new();
+        let group = format!("group_{}", rand::random::<u32>());
+        let key_details = request_new_key("http://localhost:8000", &group, &client).unwrap();
+        let group_key: SigningKey = Base64SigningKey(key_details.signing).try_into().unwrap();
+        let group_url = format!("http://localhost:8000/provenance/{group}");
+        let path = format!("/add_member/{group}/employee-42");
+        let add_member = client.post(format!("http://localhost:8000{path}"));
+        let response = add_member.try_clone().unwrap().send().unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let authorization = sign_server_request("POST", &path, "", &group_key);
+        let response = add_member
+            .header("Authorization", authorization)
+            .send()
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let doc = "document text here";
+        let signed_doc = sign_as_member(doc, &group_key, &group_url, "employee-42").unwrap();
+        let details = verify(&signed_doc).0.unwrap();
+        assert_eq!(
+            details.group,
+            Some(GroupSignerDetails {
+                group_url: group_url.clone(),
+                member_id: "employee-42".to_string(),
+            })
+        );
+
+        // The member must be in the group, and can't be swapped for another
+        let outsider = sign_as_member(doc, &group_key, &group_url, "outsider").unwrap();
+        assert!(verify(&outsider).0.is_err());
+        // Even when the group's key comes from somewhere else
+        let group_verifying_key = group_key.verifying_key();
+        assert!(verify_with(&outsider, |_| Ok(group_verifying_key))
+            .0
+            .is_err());
+        assert!(verify_with(&signed_doc, |_| Ok(group_verifying_key))
+            .0
+            .is_ok());
+        // Or from already-fetched signer details
+        let signer_details: SignerDetailsFromServer =
+            client.get(&group_url).send().unwrap().json().unwrap();
+        assert!(verify_with_signer_details(&outsider, &signer_details)
+            .0
+            .is_err());
+        assert!(verify_with_signer_details(&signed_doc, &signer_details)
+            .0
+            .is_ok());
+        assert!(verify(&signed_doc.replace("employee-42", "employee-43"))
+            .0
+            .is_err());
+        assert!(sign_as_member(doc, &group_key, &group_url, "bad id").is_err());
+        // Ordinary layers aren't group layers
+        assert_eq!(
+            verify(&sign(doc, &group_key, &group_url)).0.unwrap().group,
+            None
+        );
+    }
+
+    #[test]
+    fn verify_all_stops_at_an_empty_root_document() {
+        let signers = generate_users_and_signing_keys(3);
+
+        let mut doc = String::new();
+        for (username, signing_key) in &signers {
+            let url = format!("http://localhost:8000/provenance/{}", username.0);
+            doc = sign(&doc, signing_key, &url);
+        }
+
+        let (results, remainder) = verify_all(&doc);
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(remainder, "");
+
+        // An unsigned remainder spanning several lines isn't mistaken for another layer
+        let (results, remainder) = verify_all(&sign(
+            "line one\nline two\n",
+            &signers[0].1.clone(),
+            &format!("http://localhost:8000/provenance/{}", signers[0].0 .0),
+        ));
+        assert_eq!(results.len(), 1);
+        assert_eq!(remainder, "line one\nline two\n");
+    }
+
+    /// Respond to requests on a local port with `status`, sending each raw request back over the
+    /// returned channel.
+    fn upload_server(status: u16) -> (String, std::sync::mpsc::Receiver<String>) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let addr = test_util::mock_server_with(move |request| {
+            let body = String::from_utf8(request.body.clone()).unwrap();
+            let _ = sender.send(format!("{}{body}", request.head));
+            test_util::MockResponse::new(status, "")
+        });
+
+        (format!("http://{addr}/bucket/doc.txt"), receiver)
+    }
+
+    #[test]
+    fn sign_and_publish_uploads_the_signed_document() {
+        let signing_key = test_util::deterministic_signing_key("publisher");
+        let url = "https://example.com/provenance/publisher";
+        let client = Client::new();
+
+        let (destination, request) = upload_server(201);
+        let signed_doc = sign_and_publish(
+            "document text here",
+            &signing_key,
+            url,
+            &destination,
+            &client,
+            &PublishOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(signed_doc, sign("document text here", &signing_key, url));
+        let request = request.recv().unwrap();
+        assert!(request.starts_with("PUT /bucket/doc.txt "), "{request}");
+        assert!(
+            request.ends_with(&format!("\r\n\r\n{signed_doc}")),
+            "{request}"
+        );
+
+        let mut options = PublishOptions {
+            method: Method::POST,
+            ..PublishOptions::default()
+        };
+        options
+            .headers
+            .insert("x-upload-token", HeaderValue::from_static("secret"));
+        let (destination, request) = upload_server(200);
+        sign_and_publish("doc", &signing_key, url, &destination, &client, &options).unwrap();
+        let request = request.recv().unwrap();
+        assert!(request.starts_with("POST "), "{request}");
+        assert!(request.contains("x-upload-token: secret\r\n"), "{request}");
+
+        // Upload failures are reported as such
+        let (destination, _) = upload_server(403);
+        let error = sign_and_publish("doc", &signing_key, url, &destination, &client, &options)
+            .unwrap_err();
+        assert!(
+            matches!(error, PublishError::Rejected { status, .. } if status == StatusCode::FORBIDDEN)
+        );
+        let error = sign_and_publish(
+            "doc",
+            &signing_key,
+            url,
+            "http://localhost:1/bucket",
+            &client,
+            &options,
+        )
+        .unwrap_err();
+        assert!(matches!(error, PublishError::Upload { .. }));
+    }
+
+    #[test]
+    fn reverify_reports_layers_which_changed_status() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let rotated = test_util::deterministic_signing_key("alice rotated");
+        let details_for = |key: &SigningKey| SignerDetailsFromServer {
+            verification_url: String::new(),
+            verification_key_b64: URL_SAFE.encode(key.verifying_key().to_bytes()),
+            metadata: HashMap::new(),
+            webauthn_key_b64: None,
+        };
+        let (alice_url, _, served) = mutable_server(details_for(&alice));
+        let beyarkay_url = "http://localhost:8000/provenance/beyarkay";
+        let beyarkay = test_util::beyarkay_key();
+
+        let doc = sign("document text here", &alice, &alice_url);
+        let doc = sign(&doc, &beyarkay, beyarkay_url);
+        let options = VerifyOptions::default();
+        let first = verify_chain(&doc, &options);
+        assert!(first.is_intact());
+
+        let unchanged = reverify(&first, &doc, &options).unwrap();
+        assert!(unchanged.changes.is_empty());
+
+        // Once alice's key is rotated, her layer no longer verifies
+        *served.lock().unwrap() = details_for(&rotated);
+        let reverified = reverify(&first, &doc, &options).unwrap();
+        assert_eq!(
+            reverified.changes,
+            vec![StatusChange {
+                index: 1,
+                verification_url: alice_url.clone(),
+                before: VerifyStatus::Verified,
+                after: VerifyStatus::Forged,
+            }]
+        );
+
+        // Comparing against a different document is an error
+        let other = sign(
+            "other",
+            &test_util::deterministic_signing_key("bob"),
+            &alice_url,
+        );
+        assert!(reverify(&reverified.chain, &other, &options).is_err());
+    }
+
+    /// Serve `signer_details` over HTTPS on a local port, with a certificate for `localhost` from
+    /// the test CA in `tests/private_ca`.
+    #[cfg(feature = "custom-ca")]
+    fn private_ca_server(signer_details: SignerDetailsFromServer) -> String {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/private_ca");
+        let identity = native_tls::Identity::from_pkcs8(
+            &std::fs::read(dir.join("localhost.pem")).unwrap(),
+            &std::fs::read(dir.join("localhost.key")).unwrap(),
+        )
+        .unwrap();
+        let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+
+        let body = serde_json::to_vec(&signer_details).unwrap();
+        let addr = test_util::mock_server_over(
+            // Clients which don't trust the certificate hang up during the handshake
+            move |stream| acceptor.accept(stream).ok(),
+            move |_| test_util::MockResponse::new(200, body.clone()),
+        );
+
+        format!("https://localhost:{}/provenance/private", addr.port())
+    }
+
+    #[test]
+    #[cfg(feature = "custom-ca")]
+    fn keys_can_be_fetched_from_servers_with_a_private_ca() {
+        let signing_key = test_util::deterministic_signing_key("private");
+        let url = private_ca_server(SignerDetailsFromServer {
+            verification_url: String::new(),
+            verification_key_b64: URL_SAFE.encode(signing_key.verifying_key().to_bytes()),
+            metadata: HashMap::new(),
+            webauthn_key_b64: None,
+        });
+        let doc = sign("document text here", &signing_key, &url);
+
+        // The system doesn't trust the private CA
+        let error = verify(&doc).0.unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref::<VerifyError>(),
+                Some(VerifyError::UntrustedCertificate { .. })
+            ),
+            "{error}"
+        );
+
+        let options = VerifyOptions::default()
+            .with_root_certificate_file(
+                Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/private_ca/ca.pem"),
+            )
+            .unwrap();
+        let (result, remainder) = verify_with_options(&doc, &options);
+        assert_eq!(result.unwrap().verification_url, url);
+        assert_eq!(remainder, "document text here");
+
+        assert!(VerifyOptions::default()
+            .with_root_certificate_pem(b"not a certificate")
+            .is_err());
+    }
+
+    #[test]
+    fn layer_kinds_round_trip() {
+        let key = test_util::deterministic_signing_key("alice");
+        let url = "https://example.com/provenance/alice";
+        let resolve = |_: &str| Ok(key.verifying_key());
+
+        for kind in [
+            LayerKind::Unspecified,
+            LayerKind::Captured,
+            LayerKind::Edited,
+            LayerKind::Uploaded,
+        ] {
+            let signed_doc = sign_with_kind("document text here", &key, url, kind);
+            let (result, remainder) = verify_with(&signed_doc, resolve);
+            assert_eq!(result.unwrap().kind, kind);
+            assert_eq!(remainder, "document text here");
+        }
+
+        // Layers signed without a kind are unspecified
+        let signed_doc = sign("document text here", &key, url);
+        assert_eq!(
+            sign_with_kind("document text here", &key, url, LayerKind::Unspecified),
+            signed_doc
+        );
+        assert_eq!(
+            verify_with(&signed_doc, resolve).0.unwrap().kind,
+            LayerKind::Unspecified
+        );
+
+        // The kind is signed, so it can't be changed
+        let edited = sign_with_kind("document text here", &key, url, LayerKind::Edited);
+        assert!(
+            verify_with(&edited.replace("kind=edited", "kind=captured"), resolve)
+                .0
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn layer_kinds_read_along_the_chain() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let key = test_util::beyarkay_key();
+
+        let doc = sign_with_kind("photo", &key, url, LayerKind::Captured);
+        let doc = sign_with_kind(&doc, &key, url, LayerKind::Edited);
+        let doc = sign_with_kind(&doc, &key, url, LayerKind::Uploaded);
+
+        let kinds: Vec<_> = verify_all(&doc)
+            .0
+            .into_iter()
+            .map(|result| result.unwrap().kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![LayerKind::Uploaded, LayerKind::Edited, LayerKind::Captured]
+        );
+    }
+
+    #[test]
+    fn sign_switches_to_prehashed_above_the_threshold() {
+        let key = test_util::deterministic_signing_key("alice");
+        let url = "https://example.com/provenance/alice";
+        let resolve = |_: &str| Ok(key.verifying_key());
+        let doc = "x".repeat(100);
+
+        let at_threshold = sign_with_threshold(&doc, &key, url, 100);
+        let above_threshold = sign_with_threshold(&doc, &key, url, 99);
+        assert_eq!(at_threshold, sign(&doc, &key, url));
+        assert!(!at_threshold.lines().next().unwrap().contains("alg="));
+        assert!(above_threshold
+            .lines()
+            .next()
+            .unwrap()
+            .contains(" alg=ed25519ph "));
+
+        for signed_doc in [&at_threshold, &above_threshold] {
+            let (result, remainder) = verify_with(signed_doc, resolve);
+            assert!(result.is_ok(), "{result:?}");
+            assert_eq!(remainder, doc);
+            assert!(verify_with(&signed_doc.replace("xxx", "yyy"), resolve)
+                .0
+                .is_err());
+        }
+
+        // An Ed25519ph signature isn't a plain ed25519 signature over the same document
+        let stripped = above_threshold.replace(" alg=ed25519ph", "");
+        assert!(verify_with(&stripped, resolve).0.is_err());
+    }
+
+    #[test]
+    fn large_documents_are_prehashed_by_default() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let key = test_util::beyarkay_key();
+        let doc = "x".repeat(DEFAULT_PREHASH_THRESHOLD + 1);
+
+        let signed_doc = sign(&doc, &key, url);
+        assert!(signed_doc.lines().next().unwrap().contains("alg=ed25519ph"));
+        let (result, remainder) = verify(&signed_doc);
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(remainder, doc);
+
+        // Including when the header has other extensions
+        let signed_doc = sign_with_kind(&doc, &key, url, LayerKind::Captured);
+        let header = signed_doc.lines().next().unwrap();
+        assert!(header.contains("alg=ed25519ph kind=captured"), "{header}");
+        assert_eq!(
+            signed_doc.len(),
+            doc.len() + provenance_overhead(url, doc.len()) + " kind=captured".len()
+        );
+        let details = verify(&signed_doc).0.unwrap();
+        assert_eq!(details.kind, LayerKind::Captured);
+    }
+
+    #[test]
+    fn verification_key_b64_matches_the_server() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let key = test_util::beyarkay_key();
+        let details = verify(&sign("document text here", &key, url)).0.unwrap();
+
+        let from_server = get_signer_details_from_url(url, &Client::new()).unwrap();
+        assert_eq!(
+            details.verification_key_b64(),
+            from_server.verification_key_b64
+        );
+        let round_tripped: VerifyingKey = Base64VerifyingKey(details.verification_key_b64())
+            .try_into()
+            .unwrap();
+        assert_eq!(round_tripped, details.verification_key);
+    }
+
+    #[test]
+    fn request_new_key_reports_existing_and_invalid_usernames() {
+        let client = Client::new();
+        let username = format!("user_{}", rand::random::<u32>());
+        let key_details = request_new_key("http://localhost:8000/", &username, &client).unwrap();
+        let signing_key: SigningKey = Base64SigningKey(key_details.signing).try_into().unwrap();
+        let verification_key: VerifyingKey = Base64VerifyingKey(key_details.verification)
+            .try_into()
+            .unwrap();
+        assert_eq!(signing_key.verifying_key(), verification_key);
+
+        let error = request_new_key("http://localhost:8000", &username, &client).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(KeyRequestError::UsernameExists { username: u, .. }) if *u == username
+        ));
+        let error = request_new_key("http://localhost:8000", "bad%20name", &client).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(KeyRequestError::Rejected { status, .. }) if *status == StatusCode::BAD_REQUEST
+        ));
+    }
+
+    #[test]
+    fn import_keys_needs_the_admin_token() {
+        // The development server has no admin token, so it refuses every import
+        let csv = format!(
+            "user_{},{}",
+            rand::random::<u32>(),
+            URL_SAFE.encode([1; 32])
+        );
+        let error = import_keys("http://localhost:8000", "guess", &csv, &Client::new())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("refused the import"), "{error}");
+        assert!(error.contains("no `admin_token` is configured"), "{error}");
+    }
+
+    #[test]
+    fn embedded_keys_are_checked_against_the_url() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let signed_doc = sign_with_embedded_key("document text here", &signing_key, url);
+
+        let (header, doc, key) = crate::offline::verify_with_embedded_key(&signed_doc).unwrap();
+        assert_eq!(key, signing_key.verifying_key());
+        assert_eq!((header.url, doc), (url, "document text here"));
+        let details = verify(&signed_doc).0.unwrap();
+        assert_eq!(details.embedded_key, EmbeddedKey::Confirmed);
+        assert_eq!(details.status, VerifyStatus::Verified);
+        assert_eq!(
+            verify(&sign("document text here", &signing_key, url))
+                .0
+                .unwrap()
+                .embedded_key,
+            EmbeddedKey::Absent
+        );
+
+        // An impostor's key is internally consistent, but isn't the key at the URL
+        let impostor = test_util::deterministic_signing_key("impostor");
+        let forged = sign_with_embedded_key("document text here", &impostor, url);
+        assert!(crate::offline::verify_with_embedded_key(&forged).is_ok());
+        let error = verify(&forged).0.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(VerifyError::UrlKeyMismatch { .. })
+        ));
+
+        // Without the server, the embedded key can't be confirmed, so it isn't trusted
+        let offline =
+            sign_with_embedded_key("document text here", &impostor, "http://localhost:1/me");
+        let error = verify(&offline).0.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(VerifyError::Unreachable { .. })
+        ));
+    }
+
+    #[test]
+    fn layers_older_than_max_age_fail() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let year = Duration::from_secs(365 * 24 * 60 * 60);
+        let check = |age: Duration| check_age("url", Some(now - age), now, year);
+
+        assert!(check(Duration::ZERO).is_ok());
+        assert!(check(year).is_ok());
+        let error = check(year + Duration::from_nanos(1)).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(VerifyError::TooOld { signed_at, max_age, .. })
+                if *signed_at == now - year - Duration::from_nanos(1) && *max_age == year
+        ));
+        // A timestamp from slightly in the future is as fresh as can be
+        assert!(check_age("url", Some(now + Duration::from_secs(60)), now, year).is_ok());
+        assert!(matches!(
+            check_age("url", None, now, year).unwrap_err().downcast_ref(),
+            Some(VerifyError::AgeUnknown { url }) if url == "url"
+        ));
+
+        // Without a trusted timestamp, a layer's age is unknown
+        let signing_key = test_util::beyarkay_key();
+        let signed_doc = sign(
+            "document text here",
+            &signing_key,
+            "http://localhost:8000/provenance/beyarkay",
+        );
+        let options = VerifyOptions {
+            max_age: Some(year),
+            ..Default::default()
+        };
+        assert!(verify(&signed_doc).0.is_ok());
+        let error = verify_with_options(&signed_doc, &options).0.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(VerifyError::AgeUnknown { .. })
+        ));
+    }
+
+    #[test]
+    fn inspect_validity_reports_every_failure() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let options = VerifyOptions {
+            max_age: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+
+        let report = inspect_validity(
+            &sign("document text here", &signing_key, url),
+            &VerifyOptions::default(),
+        );
+        assert!(report.is_valid());
+        assert_eq!(report.verification_url.as_deref(), Some(url));
+
+        // An old version and no timestamp, but a perfectly good signature
+        let signature = signing_key.sign(&signed_message("0.0.1", url, b"document text here"));
+        let old = format!(
+            "{PROVENANCE_PREAMBLE} 0.0.1 {url} {} {PROVENANCE_POSTAMBLE}\ndocument text here",
+            signature_to_b64(&signature)
+        );
+        let report = inspect_validity(&old, &options);
+        assert!(!report.is_valid());
+        let failed: Vec<_> = report.failures().map(|(check, _)| check).collect();
+        assert_eq!(failed, [ValidityCheck::Version, ValidityCheck::Age]);
+        assert!(report
+            .checks
+            .iter()
+            .any(|(check, result)| *check == ValidityCheck::Signature && result.is_ok()));
+        assert!(verify_with_options(&old, &options).0.is_err());
+
+        // Tampered content from an unreachable server: neither the key nor the signature can be
+        // checked
+        let unreachable = sign("document text here", &signing_key, "http://localhost:1/me")
+            .replace("document", "forged");
+        let report = inspect_validity(&unreachable, &options);
+        let failed: Vec<_> = report.failures().map(|(check, _)| check).collect();
+        assert_eq!(failed, [ValidityCheck::KeyFetched, ValidityCheck::Age]);
+        assert!(!report
+            .checks
+            .iter()
+            .any(|(check, _)| *check == ValidityCheck::Signature));
+
+        let report = inspect_validity("not signed", &options);
+        assert_eq!(report.verification_url, None);
+        assert_eq!(
+            report
+                .failures()
+                .map(|(check, _)| check)
+                .collect::<Vec<_>>(),
+            [ValidityCheck::Header]
+        );
+    }
+
+    #[test]
+    fn headers_can_end_with_either_line_ending() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let doc = "first line\r\nsecond line\n";
+
+        let lf = sign_with_line_ending(doc, &signing_key, url, LineEnding::Lf);
+        assert_eq!(lf, sign(doc, &signing_key, url));
+        let crlf = sign_with_line_ending(doc, &signing_key, url, LineEnding::CrLf);
+        assert_eq!(crlf, lf.replacen('\n', "\r\n", 1));
+        let native = sign_with_line_ending(doc, &signing_key, url, LineEnding::Native);
+        assert_eq!(native, if cfg!(windows) { &crlf } else { &lf }.clone());
+
+        for signed_doc in [&lf, &crlf] {
+            let (result, remainder) = verify(signed_doc);
+            assert!(result.is_ok(), "{signed_doc:?}");
+            assert_eq!(remainder, doc);
+            assert_eq!(outermost_signer_url(signed_doc).as_deref(), Some(url));
+        }
+
+        // Layers with different line endings can be stacked
+        let outer = sign_with_line_ending(&crlf, &signing_key, url, LineEnding::Lf);
+        let (results, remainder) = verify_all(&outer);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(remainder, doc);
+    }
+
+    #[test]
+    fn a_server_serving_the_wrong_key_is_a_url_key_mismatch() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let bob = test_util::deterministic_signing_key("bob");
+        let (url, _) = counting_server(SignerDetailsFromServer {
+            verification_key_b64: URL_SAFE.encode(bob.verifying_key().as_bytes()),
+            ..Default::default()
+        });
+        let is_mismatch = |result: &anyhow::Result<SignerDetails>| {
+            matches!(
+                result.as_ref().unwrap_err().downcast_ref(),
+                Some(VerifyError::UrlKeyMismatch { .. })
+            )
+        };
+
+        let signed_doc = sign_with_embedded_key("document text here", &alice, &url);
+        assert!(is_mismatch(&verify(&signed_doc).0));
+        let served = SignerDetailsFromServer {
+            verification_url: url.clone(),
+            verification_key_b64: URL_SAFE.encode(bob.verifying_key().as_bytes()),
+            ..Default::default()
+        };
+        assert!(is_mismatch(
+            &verify_with_signer_details(&signed_doc, &served).0
+        ));
+
+        // Tampered content is still just a bad signature
+        let tampered = signed_doc.replace("document", "forged");
+        let result = verify(&tampered).0;
+        assert!(result.is_err() && !is_mismatch(&result));
+        let result = verify_with_signer_details(&tampered, &served).0;
+        assert!(result.is_err() && !is_mismatch(&result));
+
+        // Without an embedded key, there's no telling which key made the signature
+        let result = verify(&sign("document text here", &alice, &url)).0;
+        assert!(result.is_err() && !is_mismatch(&result));
+    }
+
+    #[test]
+    fn verify_expecting_version_needs_an_exact_match() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let signed_doc = sign("document text here", &signing_key, url);
+
+        let (result, remainder) = verify_expecting_version(&signed_doc, PROVENANCE_VERSION);
+        assert!(result.is_ok());
+        assert_eq!(remainder, "document text here");
+
+        for expected in ["0.1.0", "0.3", ""] {
+            let (result, remainder) = verify_expecting_version(&signed_doc, expected);
+            assert!(matches!(
+                result.unwrap_err().downcast_ref(),
+                Some(VerifyError::VersionMismatch { found, .. }) if found == PROVENANCE_VERSION
+            ));
+            assert_eq!(remainder, "document text here");
+        }
+
+        // Other failures are reported as usual
+        let tampered = signed_doc.replace("document", "forged");
+        let result = verify_expecting_version(&tampered, PROVENANCE_VERSION).0;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().downcast_ref::<VerifyError>().is_none());
+
+        // Armoring a document doesn't hide its version
+        let armored = armor::armor(signed_doc.as_bytes());
+        let (result, remainder) = verify_expecting_version(&armored, PROVENANCE_VERSION);
+        assert!(result.is_ok());
+        assert_eq!(remainder, "document text here");
+        let (result, remainder) = verify_expecting_version(&armored, "0.1.0");
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(VerifyError::VersionMismatch { .. })
+        ));
+        assert_eq!(remainder, "document text here");
+
+        // Neither does mangling its header
+        let mangled = signed_doc.replacen(PROVENANCE_PREAMBLE, "~~", 1);
+        assert!(verify_expecting_version(&mangled, PROVENANCE_VERSION)
+            .0
+            .is_err());
+        assert!(
+            verify_expecting_version("document text here", PROVENANCE_VERSION)
+                .0
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn verify_all_with_resolves_every_layer() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let bob = test_util::deterministic_signing_key("bob");
+        let resolve = |url: &str| match url {
+            "https://example.com/alice" => Ok(alice.verifying_key()),
+            "https://example.com/bob" => Ok(bob.verifying_key()),
+            _ => Err(anyhow!("Unknown signer '{url}'")),
+        };
+        let doc = sign(
+            &sign(
+                &sign("document text here", &alice, "https://example.com/alice"),
+                &bob,
+                "https://example.com/nobody",
+            ),
+            &bob,
+            "https://example.com/bob",
+        );
+
+        let (results, remainder) = verify_all_with(&doc, resolve);
+        let verified: Vec<_> = results.iter().map(|result| result.is_ok()).collect();
+        assert_eq!(verified, [true, false, true]);
+        assert_eq!(remainder, "document text here");
+
+        let header = sign_detached("document text here", &alice, "https://example.com/alice");
+        assert!(verify_parts_with(&header, b"document text here", resolve).is_ok());
+        assert!(verify_parts_with(&header, b"other text", resolve).is_err());
+    }
+
+    #[test]
+    fn distinct_signers_reports_repeated_signers_once() {
+        let signers = generate_users_and_signing_keys(2);
+        let url = |username: &Username| format!("http://localhost:8000/provenance/{}", username.0);
+        let (alice, alice_key) = &signers[0];
+        let (bob, bob_key) = &signers[1];
+
+        let mut doc = "document text here".to_string();
+        for (username, signing_key) in [(alice, alice_key), (bob, bob_key), (alice, alice_key)] {
+            doc = sign(&doc, signing_key, &url(username));
+        }
+        // A forged layer isn't a signer
+        doc = sign(&doc, alice_key, &url(bob));
+
+        let signers: Vec<_> = distinct_signers(&doc)
+            .into_iter()
+            .map(|details| (details.verification_url, details.verification_key))
+            .collect();
+        assert_eq!(
+            signers,
+            [
+                (url(alice), alice_key.verifying_key()),
+                (url(bob), bob_key.verifying_key()),
+            ]
+        );
+        assert!(distinct_signers("not signed").is_empty());
+    }
+
+    #[test]
+    fn armored_documents_verify_transparently() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let signed_doc = sign(
+            &sign("document text here", &signing_key, url),
+            &signing_key,
+            url,
+        );
+        let armored = armor::armor(signed_doc.as_bytes());
+
+        let (result, remainder) = verify(&armored);
+        assert!(result.is_ok());
+        assert_eq!(remainder, verify(&signed_doc).1);
+        let (results, remainder) = verify_all(&armored);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(remainder, "document text here");
+        let resolve = |_: &str| Ok(signing_key.verifying_key());
+        assert!(verify_with(&armored, resolve).0.is_ok());
+        assert_eq!(verify_all_with(&armored, resolve).0.len(), 2);
+
+        let binary = sign_with_method(b"\x00\xffbinary", &signing_key, url, SigningMethod::Binary);
+        let (result, remainder) = verify_bytes(armor::armor(&binary).as_bytes());
+        assert!(result.is_ok());
+        assert_eq!(remainder, b"\x00\xffbinary");
+
+        // The armor doesn't protect a tampered document
+        let tampered = armor::armor(signed_doc.replace("document", "forged").as_bytes());
+        assert!(verify(&tampered).0.is_err());
+        let (result, remainder) = verify(&armored.replacen('\n', "\n!!", 1));
+        assert!(result.is_err());
+        assert!(remainder.starts_with(armor::BEGIN));
+        assert!(
+            !verify_chain(&armored[..armored.len() - 10], &VerifyOptions::default()).is_intact()
+        );
+    }
+
+    #[test]
+    fn malformed_urls_are_rejected_before_fetching() {
+        let signing_key = test_util::deterministic_signing_key("urls");
+        let resolve = |_: &str| Ok(signing_key.verifying_key());
+
+        for url in [
+            "https://example.com/provenance/me",
+            "http://localhost:8000/provenance/beyarkay",
+            "https://[::1]:8443/me?key=1",
+        ] {
+            let details = verify_with(&sign("doc", &signing_key, url), resolve)
+                .0
+                .unwrap();
+            assert_eq!(details.url().unwrap().as_str(), url);
+            assert!(details.host().is_some_and(|host| url.contains(&host)));
+            assert!(url.starts_with(&details.scheme().unwrap()));
+        }
+
+        for url in [
+            "not-a-url",
+            "/provenance/me",
+            "ftp://example.com/me",
+            "mailto:me@example.com",
+            "file:///etc/passwd",
+            "http://[::1/me",
+        ] {
+            let signed_doc = sign("doc", &signing_key, url);
+            let error = verify_with(&signed_doc, |_| panic!("'{url}' shouldn't be resolved"))
+                .0
+                .unwrap_err();
+            assert!(
+                matches!(
+                    error.downcast_ref(),
+                    Some(VerifyError::MalformedUrl { url: found, .. }) if found == url
+                ),
+                "{url}: {error}"
+            );
+            let error = verify(&signed_doc).0.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref(),
+                Some(VerifyError::MalformedUrl { .. })
+            ));
+        }
+
+        let details = SignerDetails::new("garbage", VerifyingKey::default());
+        assert_eq!(details.url(), None);
+        assert_eq!(details.host(), None);
+    }
+
+    /// Answer every request on a local port with `410 Gone`, as a provenance server does for a
+    /// deactivated signer.
+    fn deactivated_server(reason: &'static str) -> String {
+        let body = serde_json::json!({"error": "Deactivated", "reason": reason}).to_string();
+        let addr = test_util::mock_server(410, body);
+
+        format!("http://{addr}/provenance/gone")
+    }
+
+    #[test]
+    fn deactivated_signers_are_reported() {
+        let signing_key = test_util::deterministic_signing_key("gone");
+        let url = deactivated_server("Key leaked");
+        let signed_doc = sign("document text here", &signing_key, &url);
+
+        let result = verify(&signed_doc).0;
+        assert_eq!(VerifyStatus::of(&result), VerifyStatus::Deactivated);
+        match result.unwrap_err().downcast_ref() {
+            Some(VerifyError::SignerDeactivated {
+                url: deactivated_url,
+                reason,
+            }) => {
+                assert_eq!(deactivated_url, &url);
+                assert_eq!(reason, "Key leaked");
+            }
+            other => panic!("Expected SignerDeactivated, got {other:?}"),
+        }
+
+        // Deactivation is a verdict from the server, not a network failure
+        let chain = verify_chain(&signed_doc, &VerifyOptions::default());
+        assert_eq!(chain.count(VerifyStatus::Deactivated), 1);
+        assert_eq!(chain.count(VerifyStatus::Unreachable), 0);
+    }
+
+    #[test]
+    fn verify_chain_blames_the_cascade_on_the_mutating_layer() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let down_url = "http://localhost:1/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+
+        let mut doc = sign("document text here", &signing_key, url);
+        doc = sign(&doc, &signing_key, down_url);
+        doc = sign(&doc, &signing_key, url);
+        // The next signer edits the document after signing it
+        let mutator_url = format!("{url}?mutator");
+        doc = format!("{} got mutated!", sign(&doc, &signing_key, &mutator_url));
+        doc = sign(&doc, &signing_key, url);
+
+        let chain = verify_chain(&doc, &VerifyOptions::default());
+        let statuses: Vec<_> = chain.layers.iter().map(|layer| layer.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                VerifyStatus::Verified,
+                VerifyStatus::Forged,
+                VerifyStatus::FailedDueToUpstreamMutation,
+                VerifyStatus::Unreachable,
+                VerifyStatus::FailedDueToUpstreamMutation,
+            ]
+        );
+        assert_eq!(chain.mutated_by().unwrap().verification_url, mutator_url);
+        match chain.layers[4].result.as_ref().unwrap_err().downcast_ref() {
+            Some(VerifyError::FailedDueToUpstreamMutation {
+                url: failed_url,
+                mutated_by,
+            }) => {
+                assert_eq!(failed_url, url);
+                assert_eq!(mutated_by, &mutator_url);
+            }
+            other => panic!("Expected FailedDueToUpstreamMutation, got {other:?}"),
+        }
+
+        // A forged layer which didn't change the document breaks nothing inside it
+        let forged = sign(
+            &sign("document text here", &signing_key, url),
+            &test_util::deterministic_signing_key("forger"),
+            url,
+        );
+        let chain = verify_chain(&forged, &VerifyOptions::default());
+        assert_eq!(chain.layers[1].status, VerifyStatus::Verified);
+        assert!(chain.mutated_by().is_none());
+    }
+
+    #[test]
+    fn sign_with_hash_round_trips_for_every_hash() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let doc = "document text here\n".repeat(100);
+
+        for hash in [
+            HashAlgorithm::Sha512,
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+        ] {
+            let signed_doc = sign_with_hash(&doc, &signing_key, url, hash);
+            let (header, _) = signed_doc.split_once('\n').unwrap();
+            assert!(header.contains(&format!("alg={}", hash.algorithm())));
+            assert_eq!(HashAlgorithm::from_algorithm(hash.algorithm()), Some(hash));
+
+            let (result, remainder) = verify(&signed_doc);
+            assert!(result.is_ok(), "{hash:?}: {result:?}");
+            assert_eq!(remainder, doc);
+            let (_, remainder) =
+                verify_with_key(&signed_doc, &signing_key.verifying_key()).unwrap();
+            assert_eq!(remainder, doc);
+            assert!(verify(&signed_doc.replacen("document", "forged", 1))
+                .0
+                .is_err());
+        }
+
+        // The hash is signed along with the document, so it can't be swapped for another
+        let sha256 = sign_with_hash(&doc, &signing_key, url, HashAlgorithm::Sha256);
+        let relabelled = sha256.replacen(SHA256_ALGORITHM, BLAKE3_ALGORITHM, 1);
+        assert!(verify(&relabelled).0.is_err());
+        let stripped = sha256.replacen(&format!(" alg={SHA256_ALGORITHM}"), "", 1);
+        assert!(verify(&stripped).0.is_err());
+        assert_eq!(
+            sign_with_hash(&doc, &signing_key, url, HashAlgorithm::Sha512),
+            sign_with_threshold(&doc, &signing_key, url, 0)
+        );
+    }
+
+    #[test]
+    fn verify_by_signer_fingerprint_finds_the_signer() {
+        let server = "http://localhost:8000";
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let fingerprint = key_fingerprint(&signing_key.verifying_key());
+        let (other, other_key) = generate_users_and_signing_keys(1).pop().unwrap();
+        let other_url = format!("http://localhost:8000/provenance/{}", other.0);
+
+        let doc = sign(
+            &sign("document text here", &signing_key, url),
+            &other_key,
+            &other_url,
+        );
+        let details = verify_by_signer_fingerprint(&doc, &fingerprint, server).unwrap();
+        assert_eq!(details.verification_url, url);
+        assert_eq!(details.verification_key, signing_key.verifying_key());
+        let details = verify_by_signer_fingerprint(
+            &doc,
+            &key_fingerprint(&other_key.verifying_key()),
+            &format!("{server}/"),
+        )
+        .unwrap();
+        assert_eq!(details.verification_url, other_url);
+
+        // Registered, but didn't sign this document (or forged the layer claiming to be them)
+        let unsigned = sign("document text here", &other_key, &other_url);
+        let forged = sign("document text here", &other_key, url);
+        for doc in [unsigned, forged] {
+            let error = verify_by_signer_fingerprint(&doc, &fingerprint, server).unwrap_err();
+            assert!(matches!(
+                error.downcast_ref(),
+                Some(FingerprintError::NotInChain { url: found }) if found == url
+            ));
+        }
+
+        let unknown =
+            key_fingerprint(&test_util::deterministic_signing_key("nobody").verifying_key());
+        let error = verify_by_signer_fingerprint(&doc, &unknown, server).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(FingerprintError::NotRegistered { .. })
+        ));
+    }
+
+    #[test]
+    fn crlf_terminated_headers_verify_everywhere() {
+        let signing_key = test_util::deterministic_signing_key("crlf");
+        let url = "https://example.com/crlf";
+        let resolver = |_: &str| Ok(signing_key.verifying_key());
+        let doc = "line one\r\nline two";
+        let signed_doc = sign(doc, &signing_key, url).replacen('\n', "\r\n", 1);
+        assert!(signed_doc.contains("🔏~~\r\n"));
+
+        let (details, remainder) = verify_with(&signed_doc, resolver);
+        assert_eq!(details.unwrap().verification_url, url);
+        assert_eq!(remainder, doc);
+
+        let (header, body) = signed_doc.split_once('\n').unwrap();
+        assert!(verify_parts_with(&format!("{header}\n"), body.as_bytes(), resolver).is_ok());
+
+        let (parsed, remainder) =
+            crate::offline::verify_with_key(&signed_doc, &signing_key.verifying_key()).unwrap();
+        assert_eq!(parsed.url, url);
+        assert_eq!(remainder, doc);
+
+        assert_eq!(crate::offline::raw_layers(&signed_doc).len(), 1);
+        assert_eq!(
+            crate::stable_document_id(&signed_doc),
+            crate::stable_document_id(&sign(doc, &signing_key, url))
+        );
+        assert!(verify_with(&signed_doc.replacen("one", "1", 1), resolver)
+            .0
+            .is_err());
+    }
+
+    #[test]
+    fn oldest_and_newest_signers_are_the_ends_of_the_chain() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let (first, middle, last) = (
+            format!("{url}?first"),
+            format!("{url}?middle"),
+            format!("{url}?last"),
+        );
+        let doc = sign("document text here", &signing_key, &first);
+        let doc = sign(&doc, &signing_key, &middle);
+        let doc = sign(&doc, &signing_key, &last);
+
+        let chain = verify_chain(&doc, &VerifyOptions::default());
+        assert_eq!(chain.oldest_signer().unwrap().verification_url, first);
+        assert_eq!(chain.newest_signer().unwrap().verification_url, last);
+
+        // With a single layer, the oldest and newest signers are the same
+        let chain = verify_chain(
+            &sign("document text here", &signing_key, &first),
+            &VerifyOptions::default(),
+        );
+        assert_eq!(chain.oldest_signer().unwrap().verification_url, first);
+        assert_eq!(chain.newest_signer().unwrap().verification_url, first);
+
+        let chain = verify_chain("document text here", &VerifyOptions::default());
+        assert!(chain.oldest_signer().is_none());
+        assert!(chain.newest_signer().is_none());
+
+        // A forged end of the chain doesn't name a signer
+        let forged = sign(&doc, &test_util::deterministic_signing_key("forger"), url);
+        let chain = verify_chain(&forged, &VerifyOptions::default());
+        assert_eq!(chain.oldest_signer().unwrap().verification_url, first);
+        assert!(chain.newest_signer().is_none());
+    }
+
+    #[test]
+    fn replayed_nonces_are_caught_across_documents() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let mut seen = HashSet::new();
+
+        let first = sign_with_nonce("document text here", &signing_key, url);
+        let second = sign_with_nonce("document text here", &signing_key, url);
+        assert_ne!(first, second);
+        for doc in [&first, &second] {
+            let (results, remainder) = verify_with_seen_nonces(doc, &mut seen);
+            assert!(results[0].is_ok(), "{results:?}");
+            assert_eq!(remainder, "document text here");
+        }
+        assert_eq!(seen.len(), 2);
+
+        // The same layer again, this time wrapped in another signer's layer
+        let other_signer = test_util::deterministic_signing_key("other signer");
+        let (other_url, _) = counting_server(SignerDetailsFromServer {
+            verification_url: String::new(),
+            verification_key_b64: URL_SAFE.encode(other_signer.verifying_key().to_bytes()),
+            metadata: HashMap::new(),
+            webauthn_key_b64: None,
+        });
+        let replayed = sign(&first, &other_signer, &other_url);
+        let (results, _) = verify_with_seen_nonces(&replayed, &mut seen);
+        assert!(results[0].is_ok());
+        match results[1].as_ref().unwrap_err().downcast_ref() {
+            Some(VerifyError::NonceReused {
+                url: reused_url, ..
+            }) => assert_eq!(reused_url, url),
+            other => panic!("Expected NonceReused, got {other:?}"),
+        }
+
+        // Forged layers don't record their nonce, so can't get a genuine layer rejected
+        let genuine = sign_with_nonce("document text here", &signing_key, url);
+        let forged = genuine.replacen("document", "forged", 1);
+        assert!(verify_with_seen_nonces(&forged, &mut seen).0[0].is_err());
+        assert!(verify_with_seen_nonces(&genuine, &mut seen).0[0].is_ok());
+
+        // Layers without a nonce, and armored documents, are verified as usual
+        let plain = sign("document text here", &signing_key, url);
+        assert!(verify_with_seen_nonces(&plain, &mut seen).0[0].is_ok());
+        assert!(verify_with_seen_nonces(&plain, &mut seen).0[0].is_ok());
+        let armored = crate::armor::armor(first.as_bytes());
+        assert!(verify_with_seen_nonces(&armored, &mut seen).0[0].is_err());
+    }
+
+    #[test]
+    fn newer_versions_verify_read_only_during_the_grace_period() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        // A layer from a future version, which added a header field this crate can't parse
+        let future_version = "99.0.0";
+        let inner = sign("document text here", &signing_key, url);
+        let future_layer = |doc: &str| {
+            let message =
+                signed_message_with_extensions(future_version, url, "quorum", doc.as_bytes());
+            format!(
+                "{PROVENANCE_PREAMBLE} {future_version} {url} {} quorum {PROVENANCE_POSTAMBLE}\n{doc}",
+                signature_to_b64(&signing_key.sign(&message))
+            )
+        };
+        let future_doc = future_layer(&inner);
+        assert!(parse_header(future_doc.lines().next().unwrap()).is_err());
+
+        // Without the grace period, newer versions are rejected like any other
+        assert!(verify(&future_doc).0.is_err());
+
+        let grace = VerifyOptions {
+            newer_version_grace: true,
+            ..Default::default()
+        };
+        let (result, remainder) = verify_with_options(&future_doc, &grace);
+        let details = result.unwrap();
+        assert_eq!(details.status, VerifyStatus::VerifiedWithUnknownVersion);
+        assert_eq!(details.verification_url, url);
+        assert_eq!(remainder, inner);
+
+        let chain = verify_chain(&future_doc, &grace);
+        assert_eq!(chain.layers[0].verification_url, url);
+        // Nothing the newer version added was checked, so the chain isn't intact
+        assert!(!chain.is_intact());
+        assert_eq!(
+            chain
+                .layers
+                .iter()
+                .map(|layer| layer.status)
+                .collect::<Vec<_>>(),
+            vec![
+                VerifyStatus::VerifiedWithUnknownVersion,
+                VerifyStatus::Verified
+            ]
+        );
+
+        // A signature which doesn't check out might just be signed differently by the newer
+        // version, so it's reported as an unsupported version rather than a forgery
+        let tampered = future_doc.replace("document text here", "document text there");
+        let error = verify_with_options(&tampered, &grace).0.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<VerifyError>(),
+            Some(VerifyError::UnsupportedVersion { found, .. }) if found == future_version
+        ));
+    }
+
+    #[test]
+    fn is_fully_verified_gates_on_every_layer() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let down = "http://localhost:1/provenance/down";
+        let signing_key = test_util::beyarkay_key();
+        let forger = test_util::deterministic_signing_key("forger");
+
+        let good = sign(
+            &sign("document text here", &signing_key, url),
+            &signing_key,
+            url,
+        );
+        assert!(is_fully_verified(&good).unwrap());
+        assert!(is_fully_verified(&seal(&good, &signing_key, url)).unwrap());
+        assert!(!is_fully_verified("document text here").unwrap());
+
+        // A forged layer anywhere in the chain fails it
+        let forged_inside = sign(&sign("document text here", &forger, url), &signing_key, url);
+        assert!(!is_fully_verified(&forged_inside).unwrap());
+        let forged_outside = sign(&good, &forger, url);
+        assert!(!is_fully_verified(&forged_outside).unwrap());
+
+        // An unreachable server leaves the answer unknown
+        let unreachable = sign(&good, &signing_key, down);
+        let error = is_fully_verified(&unreachable).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(VerifyError::Unreachable { url }) if url == down
+        ));
+        // ...unless an outer layer has already failed, since nothing inside it is fetched
+        let forged_over_unreachable = sign(&unreachable, &forger, url);
+        assert!(!is_fully_verified(&forged_over_unreachable).unwrap());
+    }
+
+    #[test]
+    fn terms_are_only_reported_once_their_signature_verifies() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let terms = "CC-BY-NC-4.0 AND no-ai-training";
+
+        let signed_doc = sign_with_terms("document text here", &signing_key, url, terms);
+        let (result, remainder) = verify(&signed_doc);
+        assert_eq!(result.unwrap().terms.as_deref(), Some(terms));
+        assert_eq!(remainder, "document text here");
+        assert_eq!(
+            verify(&sign("doc", &signing_key, url)).0.unwrap().terms,
+            None
+        );
+
+        // Swapping in more permissive terms breaks the signature
+        let header = parse_header(signed_doc.split_once('\n').unwrap().0).unwrap();
+        let tampered = signed_doc.replacen(
+            header.extension(TERMS_EXTENSION).unwrap(),
+            &URL_SAFE.encode("CC0-1.0"),
+            1,
+        );
+        assert!(verify(&tampered).0.is_err());
+
+        // Terms which don't decode fail the layer, even if they're signed
+        let extensions = format!("{TERMS_EXTENSION}=not_base64!");
+        let message = signed_message_with_extensions(PROVENANCE_VERSION, url, &extensions, b"doc");
+        let undecodable = format!(
+            "{}\ndoc",
+            format_header_with_extensions(
+                url,
+                &signature_to_b64(&signing_key.sign(&message)),
+                &extensions
+            )
+        );
+        assert!(verify(&undecodable).0.is_err());
+    }
+
+    #[test]
+    fn out_of_order_timestamps_are_flagged() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let mut doc = "document text here".to_string();
+        for _ in 0..4 {
+            doc = sign(&doc, &signing_key, url);
+        }
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let chain_with = |timestamps: [Option<u64>; 4]| {
+            let mut chain = verify_chain(&doc, &VerifyOptions::default());
+            for (layer, timestamp) in chain.layers.iter_mut().zip(timestamps) {
+                layer.result.as_mut().unwrap().timestamp = timestamp.map(at);
+            }
+            chain
+        };
+
+        // Layers are listed outermost first, so timestamps should decrease down the list
+        let report = check_temporal_consistency(&chain_with([Some(40), Some(30), None, Some(30)]));
+        assert!(report.is_consistent());
+        assert_eq!(report.timestamped_layers, 3);
+
+        // The second layer claims to be older than the innermost layer it was signed over
+        let report = check_temporal_consistency(&chain_with([Some(40), Some(10), None, Some(20)]));
+        assert!(!report.is_consistent());
+        assert_eq!(
+            report.violations,
+            vec![TemporalViolation {
+                outer_index: 1,
+                outer_signed_at: at(10),
+                inner_index: 3,
+                inner_signed_at: at(20),
+            }]
+        );
+
+        // Each violation is against the latest layer inside it, not just its neighbour
+        let report =
+            check_temporal_consistency(&chain_with([Some(25), Some(30), Some(10), Some(20)]));
+        let pairs: Vec<_> = report
+            .violations
+            .iter()
+            .map(|violation| (violation.outer_index, violation.inner_index))
+            .collect();
+        assert_eq!(pairs, vec![(0, 1), (2, 3)]);
+
+        // Without timestamps there's nothing to check
+        let report = check_temporal_consistency(&chain_with([None; 4]));
+        assert!(report.is_consistent());
+        assert_eq!(report.timestamped_layers, 0);
+    }
+
+    #[test]
+    fn prefetching_limits_concurrent_fetches() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let urls: Vec<String> = (0..6)
+            .map(|i| format!("https://example.com/signer{i}"))
+            .collect();
+        // The first URL signs twice, but is only fetched once
+        let signed_doc = std::iter::once(&urls[0])
+            .chain(&urls)
+            .fold("doc".to_string(), |doc, url| sign(&doc, &signing_key, url));
+
+        let in_flight = AtomicUsize::new(0);
+        let most_in_flight = AtomicUsize::new(0);
+        let fetched = Mutex::new(vec![]);
+        let resolver = |url: &str| {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            most_in_flight.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            fetched.lock().unwrap().push(url.to_string());
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            if url.ends_with("signer5") {
+                Err(VerifyError::Unreachable {
+                    url: url.to_string(),
+                }
+                .into())
+            } else {
+                Ok(signing_key.verifying_key())
+            }
+        };
+
+        let (results, remainder) = verify_all_prefetched_with(&signed_doc, 2, resolver);
+        assert_eq!(remainder, "doc");
+        assert_eq!(results.len(), 7);
+        assert!(matches!(
+            results[0].as_ref().unwrap_err().downcast_ref(),
+            Some(VerifyError::Unreachable { .. })
+        ));
+        assert!(results[1..].iter().all(Result::is_ok));
+        // How many fetches overlap depends on scheduling, but never more than the limit
+        assert!(most_in_flight.load(Ordering::SeqCst) <= 2);
+        let mut fetched = fetched.lock().unwrap().clone();
+        fetched.sort();
+        assert_eq!(fetched, urls);
+
+        most_in_flight.store(0, Ordering::SeqCst);
+        let (results, _) = verify_all_prefetched_with(&signed_doc, 0, resolver);
+        assert_eq!(results.len(), 7);
+        assert_eq!(most_in_flight.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,435 @@
+//! Verifying documents which live at a URL, rather than ones already in memory.
+//!
+//! [`verify_url`] downloads a signed document and verifies every layer of it, and [`verify_cid`]
+//! does the same for a document on IPFS, fetched through an HTTP gateway. What the gateway
+//! serves isn't checked against the CID, so a gateway could serve other content: only the
+//! provenance layers are verified, and they say who the content came from, not which CID it has.
+//!
+//! [`verify_url_streaming`] checks the outermost header as soon as it arrives, and abandons the
+//! download if it already fails, to save bandwidth on large documents.
+//!
+//! Downloads are capped at [`MAX_DOCUMENT_SIZE`] bytes, so a misbehaving server can't fill up
+//! memory.
+
+use crate::offline::{embedded_key, parse_header, split_header, ParsedHeader};
+use crate::provenance::{
+    check_layer, fetch_verification_key, in_version_grace, is_version_error,
+    verify_chain_with_fetch, KeyFetch,
+};
+use crate::{
+    verify_all, ChainLayer, ProvenanceChain, SignerDetails, VerifyError, VerifyOptions,
+    VerifyStatus,
+};
+use reqwest::blocking::{Client, Response};
+use reqwest::StatusCode;
+use std::io::{BufRead, BufReader, Read};
+use std::time::Duration;
+
+/// The largest document [`verify_url`] and [`verify_cid`] will download.
+pub const MAX_DOCUMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How long to wait for a server to respond before giving up on a download.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Why a document couldn't be downloaded to be verified.
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    /// No response was received from `url`.
+    #[error("Couldn't download document from '{url}': {source}")]
+    Unreachable {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// The connection to `url` failed partway through downloading the document.
+    #[error("Download of document from '{url}' was interrupted: {source}")]
+    Interrupted {
+        url: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The server at `url` responded with an unsuccessful status.
+    #[error("Downloading document from '{url}' failed: {status}")]
+    Status { url: String, status: StatusCode },
+    /// The document at `url` is larger than `limit` bytes.
+    #[error("Document at '{url}' is larger than the limit of {limit} bytes")]
+    TooLarge { url: String, limit: u64 },
+    /// The document at `url` isn't valid UTF-8, so can't be a text signed document.
+    #[error("Document at '{url}' isn't valid UTF-8")]
+    NotUtf8 { url: String },
+    /// `cid` can't be a content identifier, so wasn't requested from the gateway.
+    #[error("'{cid}' isn't a valid IPFS content identifier")]
+    InvalidCid { cid: String },
+    /// The gateway has no content with this CID.
+    #[error("No content with CID '{cid}' was found on the IPFS gateway '{gateway}'")]
+    CidNotFound { cid: String, gateway: String },
+}
+
+/// Download the signed document at `doc_url`, and verify every layer of it as in
+/// [`verify_all`].
+///
+/// The outer `Err` is for documents which couldn't be downloaded (see [`DownloadError`]); once
+/// downloaded, each layer's result is reported as by [`verify_all`].
+pub fn verify_url(doc_url: &str) -> anyhow::Result<(Vec<anyhow::Result<SignerDetails>>, String)> {
+    let signed_doc = download(doc_url, MAX_DOCUMENT_SIZE)?;
+    Ok(verify_all(&signed_doc))
+}
+
+/// Fetch the document with content identifier `cid` from the IPFS `gateway`, and verify every
+/// layer of it as in [`verify_url`].
+///
+/// The document is fetched from `https://<gateway>/ipfs/<cid>`, so `gateway` is a host like
+/// `ipfs.io`. A gateway which already includes a scheme (like `http://127.0.0.1:8080` for a local
+/// IPFS node) is used as it is.
+///
+/// The gateway is trusted to serve the content behind `cid`: its hash isn't checked against the
+/// CID, so use a gateway you trust (or your own IPFS node).
+pub fn verify_cid(
+    cid: &str,
+    gateway: &str,
+) -> anyhow::Result<(Vec<anyhow::Result<SignerDetails>>, String)> {
+    // CIDs are base32 or base58 encoded, so anything else would change the path requested
+    if cid.is_empty() || !cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(DownloadError::InvalidCid {
+            cid: cid.to_string(),
+        }
+        .into());
+    }
+    let gateway_url = if gateway.contains("://") {
+        gateway.trim_end_matches('/').to_string()
+    } else {
+        format!("https://{}", gateway.trim_end_matches('/'))
+    };
+
+    let signed_doc = match download(&format!("{gateway_url}/ipfs/{cid}"), MAX_DOCUMENT_SIZE) {
+        Err(DownloadError::Status {
+            status: StatusCode::NOT_FOUND,
+            ..
+        }) => {
+            return Err(DownloadError::CidNotFound {
+                cid: cid.to_string(),
+                gateway: gateway.to_string(),
+            }
+            .into())
+        }
+        result => result?,
+    };
+    Ok(verify_all(&signed_doc))
+}
+
+/// Download the text document at `url`, failing if it is longer than `limit` bytes.
+pub(crate) fn download(url: &str, limit: u64) -> Result<String, DownloadError> {
+    read_document(url, open_document(url, limit)?, limit, vec![])
+}
+
+/// Start downloading the document at `url`, failing straight away if the server says it is
+/// longer than `limit` bytes.
+fn open_document(url: &str, limit: u64) -> Result<Response, DownloadError> {
+    let unreachable = |source| DownloadError::Unreachable {
+        url: url.to_string(),
+        source,
+    };
+
+    let client = Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()
+        .map_err(unreachable)?;
+    let response = client.get(url).send().map_err(unreachable)?;
+    if !response.status().is_success() {
+        return Err(DownloadError::Status {
+            url: url.to_string(),
+            status: response.status(),
+        });
+    }
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit)
+    {
+        return Err(DownloadError::TooLarge {
+            url: url.to_string(),
+            limit,
+        });
+    }
+
+    Ok(response)
+}
+
+/// Read the rest of the document at `url` from `reader` onto the end of `document`, which has
+/// already been read, failing if the whole document is longer than `limit` bytes.
+fn read_document(
+    url: &str,
+    reader: impl Read,
+    limit: u64,
+    mut document: Vec<u8>,
+) -> Result<String, DownloadError> {
+    // Read one byte past the limit, to tell a document of exactly `limit` bytes from a longer one
+    let remaining = (limit + 1).saturating_sub(document.len() as u64);
+    reader
+        .take(remaining)
+        .read_to_end(&mut document)
+        .map_err(|source| DownloadError::Interrupted {
+            url: url.to_string(),
+            source,
+        })?;
+    if document.len() as u64 > limit {
+        return Err(DownloadError::TooLarge {
+            url: url.to_string(),
+            limit,
+        });
+    }
+
+    String::from_utf8(document).map_err(|_| DownloadError::NotUtf8 {
+        url: url.to_string(),
+    })
+}
+
+/// The longest header line [`verify_url_streaming`] reads before giving up on checking it early.
+const MAX_HEADER_LENGTH: u64 = 64 * 1024;
+
+/// Like [`verify_url`], but checking the outermost layer's header as soon as it has been
+/// downloaded, and abandoning the download if it already fails.
+///
+/// The header is checked as far as it can be without the rest of the document: it must be
+/// well-formed, use an accepted version and a valid URL, its signer's key must be fetchable
+/// (so the server is reachable and the signer not deactivated), and any key embedded in the
+/// header must be the one served at its URL. The signatures cover the whole document, so they
+/// are only checked once it has all been downloaded, as by
+/// [`verify_chain`](crate::verify_chain). The signer's key is only fetched once, so the
+/// outermost layer is reported as taking its key from the [cache](ChainLayer::from_cache).
+///
+/// If the download is abandoned, the returned chain has just the failed outermost layer, is
+/// [stopped early](ProvenanceChain::stopped_early), and has an empty remainder, since the rest of
+/// the document was never downloaded. Documents whose first line isn't a provenance header (such
+/// as unsigned or [armored](crate::armor) documents, or seals) are downloaded in full and then
+/// verified.
+pub fn verify_url_streaming(doc_url: &str) -> anyhow::Result<ProvenanceChain> {
+    let options = VerifyOptions::default();
+    let mut reader = BufReader::new(open_document(doc_url, MAX_DOCUMENT_SIZE)?);
+    let mut first = vec![];
+    (&mut reader)
+        .take(MAX_HEADER_LENGTH)
+        .read_until(b'\n', &mut first)
+        .map_err(|source| DownloadError::Interrupted {
+            url: doc_url.to_string(),
+            source,
+        })?;
+
+    let header = std::str::from_utf8(&first)
+        .ok()
+        .and_then(split_header)
+        .and_then(|(first, _)| parse_header(first).ok());
+    let mut fetch = KeyFetch::default();
+    if let Some(header) = header {
+        if let Err(e) = check_header_early(&header, &options, &mut fetch) {
+            let result = Err(e);
+            return Ok(ProvenanceChain {
+                layers: vec![ChainLayer {
+                    verification_url: header.url.to_string(),
+                    status: VerifyStatus::of(&result),
+                    result,
+                    fetch_duration: None,
+                    from_cache: false,
+                    covered_bytes: 0,
+                }],
+                remainder: String::new(),
+                stopped_early: true,
+            });
+        }
+    }
+
+    let signed_doc = read_document(doc_url, reader, MAX_DOCUMENT_SIZE, first)?;
+    Ok(verify_chain_with_fetch(&signed_doc, &options, fetch))
+}
+
+/// Check everything about the outermost layer's `header` which doesn't need the document, see
+/// [`verify_url_streaming`]. The signer's key is fetched into `fetch`.
+fn check_header_early(
+    header: &ParsedHeader,
+    options: &VerifyOptions,
+    fetch: &mut KeyFetch,
+) -> anyhow::Result<()> {
+    match check_layer(header.version, header.url, options) {
+        Err(e) if !(is_version_error(&e) && in_version_grace(header.version, options)) => {
+            return Err(e)
+        }
+        _ => {}
+    }
+    let verification_key = fetch_verification_key(header.url, options, fetch)?;
+    if embedded_key(header.extensions)?.is_some_and(|embedded| embedded != verification_key) {
+        return Err(VerifyError::UrlKeyMismatch {
+            url: header.url.to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sign, test_util};
+    use std::collections::HashMap;
+    use std::sync::mpsc;
+
+    /// An HTTP server which serves each body in `paths` at its path, and 404s everything else.
+    fn serve(paths: HashMap<String, String>) -> String {
+        let addr = test_util::mock_server_with(move |request| match paths.get(&request.path) {
+            Some(body) => test_util::MockResponse::new(200, body.as_str()),
+            None => test_util::MockResponse::new(404, ""),
+        });
+
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn verifies_documents_from_an_ipfs_gateway() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let cid = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+        let signed_doc = sign(
+            &sign("document text here", &signing_key, url),
+            &signing_key,
+            url,
+        );
+        let gateway = serve(HashMap::from([
+            (format!("/ipfs/{cid}"), signed_doc.clone()),
+            (
+                "/ipfs/unsigned".to_string(),
+                "document text here".to_string(),
+            ),
+        ]));
+
+        let (results, remainder) = verify_cid(cid, &gateway).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(remainder, "document text here");
+        let (results, _) = verify_url(&format!("{gateway}/ipfs/{cid}")).unwrap();
+        assert_eq!(results.len(), 2);
+
+        // Content without provenance downloads fine, but has no signers
+        let (results, remainder) = verify_cid("unsigned", &format!("{gateway}/")).unwrap();
+        assert!(results.is_empty());
+        assert_eq!(remainder, "document text here");
+
+        let error = verify_cid("bafkmissing", &gateway).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(DownloadError::CidNotFound { cid, .. }) if cid == "bafkmissing"
+        ));
+        for cid in ["", "../../etc/passwd", "bafk?query"] {
+            assert!(matches!(
+                verify_cid(cid, &gateway).unwrap_err().downcast_ref(),
+                Some(DownloadError::InvalidCid { .. })
+            ));
+        }
+        assert!(matches!(
+            verify_cid(cid, "http://localhost:1")
+                .unwrap_err()
+                .downcast_ref(),
+            Some(DownloadError::Unreachable { .. })
+        ));
+    }
+
+    #[test]
+    fn downloads_are_capped() {
+        let gateway = serve(HashMap::from([("/doc".to_string(), "x".repeat(100))]));
+        let url = format!("{gateway}/doc");
+
+        assert_eq!(download(&url, 100).unwrap().len(), 100);
+        assert!(matches!(
+            download(&url, 99),
+            Err(DownloadError::TooLarge { limit: 99, .. })
+        ));
+        assert!(matches!(
+            download(&format!("{gateway}/missing"), 100),
+            Err(DownloadError::Status {
+                status: StatusCode::NOT_FOUND,
+                ..
+            })
+        ));
+    }
+
+    /// An HTTP server which sends `head` straight away, but only sends `rest` once the returned
+    /// channel is signalled (or after 5 seconds, so a test which wrongly waits for it still ends).
+    fn stalling_server(head: String, rest: String) -> (String, mpsc::Sender<()>) {
+        let (release, released) = mpsc::channel();
+        let mut released = Some(released);
+        let addr = test_util::mock_server_with(move |_| test_util::MockResponse {
+            stall: released.take().map(|released| (head.len(), released)),
+            ..test_util::MockResponse::new(200, format!("{head}{rest}"))
+        });
+
+        (format!("http://{addr}/doc"), release)
+    }
+
+    #[test]
+    fn streaming_verification_stops_at_a_bad_header() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let body = "document text here\n".repeat(10_000);
+
+        // The header already fails, so the body is never waited for
+        let signed_doc = sign(&body, &signing_key, "http://localhost:1/provenance/down");
+        let (head, rest) = signed_doc.split_at(signed_doc.find('\n').unwrap() + 1);
+        let (doc_url, release) = stalling_server(head.to_string(), rest.to_string());
+        let started = std::time::Instant::now();
+        let chain = verify_url_streaming(&doc_url).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(4));
+        drop(release);
+        assert!(chain.stopped_early);
+        assert_eq!(chain.remainder, "");
+        assert_eq!(chain.layers.len(), 1);
+        assert_eq!(chain.layers[0].status, VerifyStatus::Unreachable);
+
+        // Whatever the header's line ending
+        let signed_doc = crate::sign_with_line_ending(
+            &body,
+            &signing_key,
+            "http://localhost:1/provenance/down",
+            crate::LineEnding::CrLf,
+        );
+        let (head, rest) = signed_doc.split_at(signed_doc.find('\n').unwrap() + 1);
+        let (doc_url, release) = stalling_server(head.to_string(), rest.to_string());
+        let started = std::time::Instant::now();
+        let chain = verify_url_streaming(&doc_url).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(4));
+        drop(release);
+        assert!(chain.stopped_early);
+        assert_eq!(chain.layers[0].status, VerifyStatus::Unreachable);
+
+        let embedded = crate::sign_with_embedded_key(
+            &body,
+            &crate::test_util::deterministic_signing_key("impostor"),
+            url,
+        );
+        let (head, rest) = embedded.split_at(embedded.find('\n').unwrap() + 1);
+        let (doc_url, _release) = stalling_server(head.to_string(), rest.to_string());
+        let chain = verify_url_streaming(&doc_url).unwrap();
+        assert!(matches!(
+            chain.layers[0].result.as_ref().unwrap_err().downcast_ref(),
+            Some(VerifyError::UrlKeyMismatch { .. })
+        ));
+
+        // A good header means the whole document is downloaded and verified
+        let signed_doc = sign(&sign(&body, &signing_key, url), &signing_key, url);
+        let (head, rest) = signed_doc.split_at(signed_doc.find('\n').unwrap() + 1);
+        let (doc_url, release) = stalling_server(head.to_string(), rest.to_string());
+        release.send(()).unwrap();
+        let chain = verify_url_streaming(&doc_url).unwrap();
+        assert!(chain.is_intact());
+        assert_eq!(chain.layers.len(), 2);
+        assert_eq!(chain.remainder, body);
+        // The key fetched to check the header is reused to verify the signature
+        assert!(chain.layers.iter().all(|layer| layer.from_cache));
+
+        // Only the signatures can show the body was tampered with
+        let tampered = signed_doc.replacen("document", "forgery!", 1);
+        let (head, rest) = tampered.split_at(tampered.find('\n').unwrap() + 1);
+        let (doc_url, release) = stalling_server(head.to_string(), rest.to_string());
+        release.send(()).unwrap();
+        let chain = verify_url_streaming(&doc_url).unwrap();
+        assert!(!chain.stopped_early);
+        assert_eq!(chain.layers[0].status, VerifyStatus::Forged);
+    }
+}
@@ -0,0 +1,329 @@
+//! Human-readable summaries of a document's provenance, for incident reports and documentation.
+//!
+//! [`report_markdown`] verifies a document and describes every layer in a Markdown table, from
+//! the most recent signer to the first, followed by an overall verdict on whether the document
+//! can be trusted. [`badge`] condenses the same verdict into a [`Badge`] for user interfaces.
+
+use crate::{verify_chain, ProvenanceChain, VerifyOptions, VerifyStatus};
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use std::fmt::Write;
+use std::time::SystemTime;
+
+/// Verify `doc` and describe its provenance as a Markdown section.
+pub fn report_markdown(doc: &str) -> String {
+    report_markdown_for(&verify_chain(doc, &VerifyOptions::default()))
+}
+
+/// Describe the already-verified `chain` as a Markdown section, see [`report_markdown`].
+///
+/// Signers are named by their provenance URL (and the group member who signed, for group
+/// layers). Fingerprints (see [`key_fingerprint`](crate::key_fingerprint)) are only shown for
+/// layers whose key was confirmed, since a forged layer's key says nothing about its signer.
+pub fn report_markdown_for(chain: &ProvenanceChain) -> String {
+    let mut report = String::from("## Provenance report\n\n");
+    if chain.layers.is_empty() {
+        report.push_str("The document has no provenance.\n\n");
+    } else {
+        report.push_str("| Layer | Signer | Fingerprint | Timestamp | Status |\n");
+        report.push_str("| --- | --- | --- | --- | --- |\n");
+        for (index, layer) in chain.layers.iter().enumerate() {
+            let details = layer.result.as_ref().ok();
+            let mut signer = cell(&layer.verification_url);
+            if let Some(group) = details.and_then(|details| details.group.as_ref()) {
+                let _ = write!(signer, " (member `{}`)", cell(&group.member_id));
+            }
+            let fingerprint = details
+                .filter(|details| details.status == VerifyStatus::Verified)
+                .map_or("—".to_string(), |details| {
+                    format!("`{}`", URL_SAFE.encode(details.fingerprint()))
+                });
+            let timestamp = details
+                .and_then(|details| details.timestamp)
+                .map_or("—".to_string(), format_timestamp);
+            let _ = writeln!(
+                report,
+                "| {} | {signer} | {fingerprint} | {timestamp} | {} |",
+                index + 1,
+                status_label(layer.status),
+            );
+        }
+        report.push('\n');
+    }
+
+    let _ = writeln!(report, "**Verdict:** {}", verdict(chain));
+    report
+}
+
+/// A one-line judgement of whether the document in `chain` can be trusted.
+fn verdict(chain: &ProvenanceChain) -> String {
+    let unreachable = chain.count(VerifyStatus::Unreachable);
+    let newer = chain.count(VerifyStatus::VerifiedWithUnknownVersion);
+    let deactivated = chain.count(VerifyStatus::Deactivated);
+    let broken = chain.layers.len()
+        - chain.count(VerifyStatus::Verified)
+        - unreachable
+        - newer
+        - deactivated;
+    let layers = match chain.layers.len() {
+        1 => "1 layer".to_string(),
+        count => format!("{count} layers"),
+    };
+    if chain.layers.is_empty() {
+        "Unverified. Without provenance, there's no telling where the document came from."
+            .to_string()
+    } else if broken > 0 || chain.stopped_early {
+        format!("Untrusted. {broken} of {layers} failed verification.")
+    } else if unreachable > 0 {
+        format!(
+            "Inconclusive. {unreachable} of {layers} couldn't be checked because their \
+             provenance server was unreachable."
+        )
+    } else if deactivated > 0 {
+        format!(
+            "Inconclusive. {deactivated} of {layers} were signed by a signer who has since been \
+             deactivated."
+        )
+    } else if newer > 0 {
+        format!(
+            "Inconclusive. {newer} of {layers} used a newer protocol version, so only their \
+             signatures could be checked."
+        )
+    } else {
+        format!("Trusted. {layers} verified, and nothing failed.")
+    }
+}
+
+/// A compact verdict on a document's provenance, for showing next to the document in a user
+/// interface. Every app which derives its badge with [`badge`] shows the same chain the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Badge {
+    /// Every layer verified.
+    Verified,
+    /// Nothing failed, but only `verified` of the `total` layers could be fully verified (the
+    /// rest were unreachable, used a newer protocol version, or were signed by a signer who has
+    /// since been deactivated).
+    ///
+    /// A deactivated signer's layers aren't shown as [`Badge::Forged`]: the signature may well
+    /// have been made before the signer was deactivated, but there's no telling, so the
+    /// document warrants a warning rather than a rejection.
+    PartiallyVerified { verified: usize, total: usize },
+    /// Nothing failed, but no layer could be checked because their provenance servers were
+    /// unreachable.
+    Unreachable,
+    /// At least one layer failed verification, including layers broken by a later edit.
+    Forged,
+    /// The document has no provenance.
+    Unsigned,
+}
+
+impl Badge {
+    /// The badge's color, or `None` for [`Badge::Unsigned`], which gets no badge.
+    pub fn color(self) -> Option<&'static str> {
+        match self {
+            Badge::Verified => Some("green"),
+            Badge::PartiallyVerified { .. } => Some("yellow"),
+            Badge::Unreachable => Some("gray"),
+            Badge::Forged => Some("red"),
+            Badge::Unsigned => None,
+        }
+    }
+
+    /// An emoji for the badge, or `None` for [`Badge::Unsigned`].
+    pub fn emoji(self) -> Option<&'static str> {
+        match self {
+            Badge::Verified => Some("✅"),
+            Badge::PartiallyVerified { .. } => Some("⚠️"),
+            Badge::Unreachable => Some("❔"),
+            Badge::Forged => Some("❌"),
+            Badge::Unsigned => None,
+        }
+    }
+}
+
+/// The [`Badge`] for the already-verified `chain`, matching the verdict of
+/// [`report_markdown_for`].
+pub fn badge(chain: &ProvenanceChain) -> Badge {
+    let total = chain.layers.len();
+    let verified = chain.count(VerifyStatus::Verified);
+    let unreachable = chain.count(VerifyStatus::Unreachable);
+    let newer = chain.count(VerifyStatus::VerifiedWithUnknownVersion);
+    let deactivated = chain.count(VerifyStatus::Deactivated);
+    if total == 0 {
+        Badge::Unsigned
+    } else if verified + unreachable + newer + deactivated < total || chain.stopped_early {
+        Badge::Forged
+    } else if verified == total {
+        Badge::Verified
+    } else if unreachable == total {
+        Badge::Unreachable
+    } else {
+        Badge::PartiallyVerified { verified, total }
+    }
+}
+
+/// How a layer's status is shown in the report.
+fn status_label(status: VerifyStatus) -> &'static str {
+    match status {
+        VerifyStatus::Verified => "Verified",
+        VerifyStatus::Unreachable => "Unreachable",
+        VerifyStatus::Forged => "Forged",
+        VerifyStatus::Deactivated => "Signer deactivated",
+        VerifyStatus::FailedDueToUpstreamMutation => "Broken by a later edit",
+        VerifyStatus::VerifiedWithUnknownVersion => "Verified (newer version)",
+    }
+}
+
+/// `text` made safe to put in a Markdown table cell.
+fn cell(text: &str) -> String {
+    if text.is_empty() {
+        return "(unreadable header)".to_string();
+    }
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// `time` as an RFC 3339 timestamp in UTC, like `2023-11-14T22:13:20Z`.
+fn format_timestamp(time: SystemTime) -> String {
+    let Ok(since_epoch) = time.duration_since(SystemTime::UNIX_EPOCH) else {
+        return "before 1970".to_string();
+    };
+    let seconds = since_epoch.as_secs();
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // Convert days since the epoch to a civil date, from Howard Hinnant's `civil_from_days`
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sign, test_util};
+    use std::time::Duration;
+
+    #[test]
+    fn report_lists_every_signer_and_a_verdict() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let fingerprint = URL_SAFE.encode(crate::key_fingerprint(&signing_key.verifying_key()));
+
+        let doc = sign(
+            &sign("document text here", &signing_key, url),
+            &signing_key,
+            "http://localhost:1/provenance/down",
+        );
+        let report = report_markdown(&doc);
+        assert!(report.starts_with("## Provenance report\n\n| Layer | Signer |"));
+        assert!(report.contains("| 1 | http://localhost:1/provenance/down | — | — | Unreachable |"));
+        assert!(report.contains(&format!("| 2 | {url} | `{fingerprint}` | — | Verified |")));
+        assert!(report.ends_with("**Verdict:** Inconclusive. 1 of 2 layers couldn't be checked because their provenance server was unreachable.\n"));
+
+        let trusted = report_markdown(&sign("document text here", &signing_key, url));
+        assert!(trusted.ends_with("**Verdict:** Trusted. 1 layer verified, and nothing failed.\n"));
+
+        let forged = sign(
+            "document text here",
+            &test_util::deterministic_signing_key("forger"),
+            url,
+        );
+        let report = report_markdown(&forged);
+        assert!(report.contains(&format!("| 1 | {url} | — | — | Forged |")));
+        assert!(report.contains("**Verdict:** Untrusted. 1 of 1 layer failed verification."));
+
+        let unsigned = report_markdown("document text here");
+        assert!(unsigned.contains("The document has no provenance."));
+        assert!(unsigned.contains("**Verdict:** Unverified."));
+    }
+
+    #[test]
+    fn badges_match_the_chain() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let down_url = "http://localhost:1/provenance/down";
+        let signing_key = test_util::beyarkay_key();
+        let badge_for = |doc: &str| badge(&verify_chain(doc, &VerifyOptions::default()));
+
+        let signed = sign("document text here", &signing_key, url);
+        assert_eq!(badge_for(&signed), Badge::Verified);
+        assert_eq!(Badge::Verified.color(), Some("green"));
+        assert_eq!(
+            badge_for(&sign(&signed, &signing_key, down_url)),
+            Badge::PartiallyVerified {
+                verified: 1,
+                total: 2
+            }
+        );
+        assert_eq!(
+            badge_for(&sign("document text here", &signing_key, down_url)),
+            Badge::Unreachable
+        );
+        assert_eq!(
+            badge_for(&sign(
+                "document text here",
+                &test_util::deterministic_signing_key("forger"),
+                url
+            )),
+            Badge::Forged
+        );
+        assert_eq!(badge_for("document text here"), Badge::Unsigned);
+        assert_eq!(Badge::Unsigned.color(), None);
+
+        // A later signer edits the document, breaking every layer inside it
+        let mutated = format!("{} got mutated!", sign(&signed, &signing_key, url));
+        let chain = verify_chain(
+            &sign(&mutated, &signing_key, url),
+            &VerifyOptions::default(),
+        );
+        assert_eq!(chain.count(VerifyStatus::FailedDueToUpstreamMutation), 1);
+        assert_eq!(badge(&chain), Badge::Forged);
+        assert_eq!(badge(&chain).color(), Some("red"));
+    }
+
+    #[test]
+    fn deactivated_signers_get_a_warning() {
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let signed = sign("document text here", &signing_key, url);
+        let mut chain = verify_chain(&sign(&signed, &signing_key, url), &VerifyOptions::default());
+        chain.layers[0].status = VerifyStatus::Deactivated;
+
+        assert_eq!(
+            badge(&chain),
+            Badge::PartiallyVerified {
+                verified: 1,
+                total: 2
+            }
+        );
+        assert_eq!(badge(&chain).color(), Some("yellow"));
+        assert!(report_markdown_for(&chain).ends_with(
+            "**Verdict:** Inconclusive. 1 of 2 layers were signed by a signer who has since been \
+             deactivated.\n"
+        ));
+    }
+
+    #[test]
+    fn timestamps_are_formatted_in_utc() {
+        let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(format_timestamp(at(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(at(1_700_000_000)), "2023-11-14T22:13:20Z");
+        assert_eq!(format_timestamp(at(951_782_400)), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(at(4_102_444_799)), "2099-12-31T23:59:59Z");
+    }
+}
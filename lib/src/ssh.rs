@@ -0,0 +1,177 @@
+//! Importing verification keys from SSH `authorized_keys` files.
+//!
+//! Provenance uses ed25519 keys, just like `ssh-ed25519` SSH keys, so developers who already
+//! manage their keys with SSH can reuse them for offline verification (for example with
+//! [`crate::shares_signer_offline`] or [`crate::verify_with`]).
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::VerifyingKey;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The only SSH key type which can be used as a verification key.
+const ED25519_KEY_TYPE: &str = "ssh-ed25519";
+
+/// Read the `authorized_keys`-style file at `path`, see [`parse_authorized_keys`].
+pub fn verifying_keys_from_ssh(
+    path: impl AsRef<Path>,
+) -> anyhow::Result<HashMap<String, VerifyingKey>> {
+    parse_authorized_keys(&std::fs::read_to_string(path)?)
+}
+
+/// Parse the lines of an `authorized_keys` file (or of several `.pub` files joined together),
+/// returning each key by its comment.
+///
+/// Blank lines and `#` comments are skipped, as are any options before the key type. Every key
+/// must be an `ssh-ed25519` key with a comment, and no two keys can have the same comment.
+pub fn parse_authorized_keys(text: &str) -> anyhow::Result<HashMap<String, VerifyingKey>> {
+    let mut keys = HashMap::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let Some(type_index) = words.iter().position(|word| is_key_type(word)) else {
+            return Err(anyhow!("Line {}: couldn't find an SSH key type", i + 1));
+        };
+        let key_type = words[type_index];
+        if key_type != ED25519_KEY_TYPE {
+            return Err(anyhow!(
+                "Line {}: key type '{key_type}' isn't supported, only '{ED25519_KEY_TYPE}' keys can be used",
+                i + 1
+            ));
+        }
+        let Some(blob) = words.get(type_index + 1) else {
+            return Err(anyhow!("Line {}: key is missing", i + 1));
+        };
+        let comment = words[type_index + 2..].join(" ");
+        if comment.is_empty() {
+            return Err(anyhow!("Line {}: key doesn't have a comment", i + 1));
+        }
+
+        let key = decode_ed25519_blob(blob).map_err(|e| anyhow!("Line {}: {e}", i + 1))?;
+        if keys.insert(comment.clone(), key).is_some() {
+            return Err(anyhow!(
+                "Line {}: more than one key has the comment '{comment}'",
+                i + 1
+            ));
+        }
+    }
+
+    Ok(keys)
+}
+
+fn is_key_type(word: &str) -> bool {
+    ["ssh-", "ecdsa-sha2-", "sk-"]
+        .iter()
+        .any(|prefix| word.starts_with(prefix))
+}
+
+/// Decode the base64 key blob of an `ssh-ed25519` key: the key type and then the 32 byte key,
+/// each preceded by its length as a big-endian `u32`.
+fn decode_ed25519_blob(blob: &str) -> anyhow::Result<VerifyingKey> {
+    let Ok(bytes) = STANDARD.decode(blob) else {
+        return Err(anyhow!("Couldn't decode key '{blob}' from base64"));
+    };
+
+    let mut rest = bytes.as_slice();
+    let mut next_field = || -> anyhow::Result<&[u8]> {
+        let Some((length, tail)) = rest.split_first_chunk::<4>() else {
+            return Err(anyhow!("Key is truncated"));
+        };
+        let length = u32::from_be_bytes(*length) as usize;
+        if tail.len() < length {
+            return Err(anyhow!("Key is truncated"));
+        }
+        let (field, tail) = tail.split_at(length);
+        rest = tail;
+        Ok(field)
+    };
+
+    if next_field()? != ED25519_KEY_TYPE.as_bytes() {
+        return Err(anyhow!("Key blob isn't an '{ED25519_KEY_TYPE}' key"));
+    }
+    let Ok(key) = <&[u8; ed25519_dalek::PUBLIC_KEY_LENGTH]>::try_from(next_field()?) else {
+        return Err(anyhow!(
+            "Key needs to be {} bytes long",
+            ed25519_dalek::PUBLIC_KEY_LENGTH
+        ));
+    };
+    if !rest.is_empty() {
+        return Err(anyhow!("Key has unexpected trailing data"));
+    }
+
+    VerifyingKey::from_bytes(key).map_err(|e| anyhow!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    /// Encode `key` the way `ssh-keygen` does in a `.pub` file.
+    fn ssh_blob(key: &VerifyingKey) -> String {
+        let mut blob = vec![];
+        for field in [ED25519_KEY_TYPE.as_bytes(), key.as_bytes()] {
+            blob.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            blob.extend_from_slice(field);
+        }
+        STANDARD.encode(blob)
+    }
+
+    #[test]
+    fn parses_real_authorized_keys_lines() {
+        // Generated with `ssh-keygen -t ed25519 -C alice@laptop`
+        let text = "\
+# Work laptop
+ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMLnT2+4DJFK9M+/RdUq9sOtmmqvLT7Hb7sRD1UPq6fl alice@laptop
+
+no-pty,from=\"10.0.0.1\" ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIMoawJy8Ossqi+Rt7TeD7v0aOWuXLGez7d/DJSz1ZppH bob's key
+";
+        let keys = parse_authorized_keys(text).unwrap();
+
+        assert_eq!(keys.len(), 2);
+        assert_eq!(
+            keys["alice@laptop"].as_bytes()[..4],
+            [0xc2, 0xe7, 0x4f, 0x6f]
+        );
+        assert!(keys.contains_key("bob's key"));
+    }
+
+    #[test]
+    fn keys_match_their_signing_keys() {
+        let alice = test_util::deterministic_signing_key("alice").verifying_key();
+        let path = std::env::temp_dir().join(format!("authorized_keys_{}", rand::random::<u32>()));
+        std::fs::write(&path, format!("ssh-ed25519 {} alice\n", ssh_blob(&alice))).unwrap();
+
+        let keys = verifying_keys_from_ssh(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(keys["alice"], alice);
+    }
+
+    #[test]
+    fn rejects_other_key_types_and_bad_lines() {
+        let alice = test_util::deterministic_signing_key("alice").verifying_key();
+        let error = parse_authorized_keys("ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQ bob")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("ssh-rsa"), "{error}");
+
+        for bad in [
+            format!("ssh-ed25519 {}", ssh_blob(&alice)),
+            "ssh-ed25519 not!base64 alice".to_string(),
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5 alice".to_string(),
+            "just some text".to_string(),
+            format!(
+                "ssh-ed25519 {0} alice\nssh-ed25519 {0} alice",
+                ssh_blob(&alice)
+            ),
+        ] {
+            assert!(parse_authorized_keys(&bad).is_err(), "{bad}");
+        }
+    }
+}
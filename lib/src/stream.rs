@@ -0,0 +1,553 @@
+//! Verifying a stream of documents from async code, for example the uploads to a web service.
+//!
+//! [`verify_stream`] verifies documents as they arrive, with up to `concurrency` of them in
+//! flight at once, and yields each result as soon as it is ready (so not necessarily in the
+//! order the documents arrived). Verification keys are fetched with a single async HTTP client
+//! and cached by URL, so a busy signer's key is only fetched once per stream.
+//!
+//! Only the outermost layer of each document is verified, like [`verify_with`](crate::verify_with),
+//! with the same checks as [`verify_with_options`](crate::verify_with_options): the key and any
+//! group membership are fetched up front with the async client, and then handed to the same code
+//! which verifies a layer everywhere else. Use [`verify_stream_with`] to get keys some other way.
+//!
+//! A single document too large to hold in memory can be verified as it is read with
+//! [`verify_async_reader`], as long as it was signed with Ed25519ph (see
+//! [`sign_with_threshold`](crate::sign_with_threshold)).
+
+use crate::batch::BATCH_EXTENSION;
+use crate::normalize::NORMALIZER_EXTENSION;
+use crate::offline::{
+    parse_header, parse_header_layout, prehashed_message, split_header, ALGORITHM_EXTENSION,
+    PREHASHED_ALGORITHM,
+};
+use crate::provenance::{
+    deactivated, dearmored, decode_header_with_grace, key_fetch_error, membership,
+    notify_observers, preflight_error, verify_header_with, verify_layer, ServerErrorBody,
+    SignedBody,
+};
+use crate::{
+    Base64VerifyingKey, GroupSignerDetails, SignerDetails, SignerDetailsFromServer, VerifyOptions,
+};
+use anyhow::anyhow;
+use ed25519_dalek::VerifyingKey;
+use futures::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use futures::{Future, Stream, StreamExt};
+use sha2::Digest;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Verify each document in `docs`, fetching verification keys from the documents' URLs, with at
+/// most `concurrency` documents being verified at once.
+///
+/// Like [`verify`](crate::verify), each result comes with the remainder of its document.
+pub fn verify_stream(
+    docs: impl Stream<Item = String>,
+    concurrency: usize,
+) -> impl Stream<Item = (anyhow::Result<SignerDetails>, String)> {
+    verify_stream_with_options(docs, concurrency, VerifyOptions::default())
+}
+
+/// Like [`verify_stream`], but with [`VerifyOptions`] to control how each document is verified,
+/// including the preflight timeout and root certificates used when fetching keys.
+pub fn verify_stream_with_options(
+    docs: impl Stream<Item = String>,
+    concurrency: usize,
+    options: VerifyOptions,
+) -> impl Stream<Item = (anyhow::Result<SignerDetails>, String)> {
+    let fetcher = Fetcher::new(&options);
+    let keys = fetcher.clone();
+    let cache: Arc<Mutex<HashMap<String, VerifyingKey>>> = Arc::default();
+
+    verify_each(docs, concurrency, options, fetcher, move |url| {
+        let (keys, cache) = (keys.clone(), Arc::clone(&cache));
+        async move {
+            if let Some(key) = cache.lock().unwrap().get(&url) {
+                return Ok(*key);
+            }
+            let key = keys.fetch_key(&url).await?;
+            cache.lock().unwrap().insert(url, key);
+            Ok(key)
+        }
+    })
+}
+
+/// Like [`verify_stream`], but getting the verification key for each document's URL from
+/// `resolve` rather than from the network.
+pub fn verify_stream_with<F>(
+    docs: impl Stream<Item = String>,
+    concurrency: usize,
+    resolve: impl Fn(String) -> F,
+) -> impl Stream<Item = (anyhow::Result<SignerDetails>, String)>
+where
+    F: Future<Output = anyhow::Result<VerifyingKey>>,
+{
+    let options = VerifyOptions::default();
+    let fetcher = Fetcher::new(&options);
+    verify_each(docs, concurrency, options, fetcher, resolve)
+}
+
+/// Verify the outermost layer of each document in `docs` like
+/// [`verify_with_options`](crate::verify_with_options), getting its key from `resolve` and
+/// checking group membership with `fetcher`.
+fn verify_each<F>(
+    docs: impl Stream<Item = String>,
+    concurrency: usize,
+    options: VerifyOptions,
+    fetcher: Fetcher,
+    resolve: impl Fn(String) -> F,
+) -> impl Stream<Item = (anyhow::Result<SignerDetails>, String)>
+where
+    F: Future<Output = anyhow::Result<VerifyingKey>>,
+{
+    let options = Arc::new(options);
+    docs.map(move |doc| {
+        let signed_doc = dearmored(&doc).map(Cow::into_owned);
+        let layer = signed_doc.as_deref().ok().and_then(outermost_layer);
+        let key = layer.as_ref().map(|(url, _)| resolve(url.clone()));
+        let group = layer.and_then(|(_, group)| group);
+        let (options, fetcher) = (Arc::clone(&options), fetcher.clone());
+        async move {
+            let signed_doc = match signed_doc {
+                Ok(signed_doc) => signed_doc,
+                Err(e) => return (Err(e), doc),
+            };
+            let Some((first, body)) = split_header(&signed_doc) else {
+                return (
+                    Err(anyhow!(
+                        "Document has only one line, therefore cannot be signed"
+                    )),
+                    signed_doc,
+                );
+            };
+            let key = match key {
+                Some(key) => Some(key.await),
+                None => None,
+            };
+            let member = match group {
+                Some(group) => Some(fetcher.check_membership(&group).await),
+                None => None,
+            };
+
+            // The key and membership are only asked for once the header has parsed, which is
+            // what they were fetched from
+            let (key, member) = (Cell::new(key), Cell::new(member));
+            let result = verify_header_with(
+                first,
+                body.as_bytes(),
+                &options,
+                |_| {
+                    key.take()
+                        .unwrap_or_else(|| Err(anyhow!("Document isn't signed")))
+                },
+                |_| {
+                    member
+                        .take()
+                        .unwrap_or_else(|| Err(anyhow!("Group membership wasn't checked")))
+                },
+            );
+            notify_observers(&signed_doc, &result, &options);
+            (result, body.to_string())
+        }
+    })
+    .buffer_unordered(concurrency.max(1))
+}
+
+/// The URL of the outermost layer of `signed_doc`, and the group it was signed on behalf of (if
+/// any), for fetching what verifying it will need before it is verified.
+fn outermost_layer(signed_doc: &str) -> Option<(String, Option<GroupSignerDetails>)> {
+    let (first, _) = split_header(signed_doc)?;
+    let header = parse_header(first)
+        .ok()
+        .or_else(|| parse_header_layout(first))?;
+    Some((
+        header.url.to_string(),
+        GroupSignerDetails::of(header.url, header.extensions),
+    ))
+}
+
+/// How long a single request may take, like the blocking client used by
+/// [`verify`](crate::verify).
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The async HTTP client verification keys and group memberships are fetched with, set up from
+/// [`VerifyOptions`] like the blocking client [`verify`](crate::verify) uses.
+#[derive(Clone)]
+struct Fetcher {
+    /// The client, or why it couldn't be built (which is reported by each request).
+    client: Result<reqwest::Client, String>,
+    preflight_timeout: Option<Duration>,
+}
+
+impl Fetcher {
+    fn new(options: &VerifyOptions) -> Self {
+        Self {
+            client: async_client(options).map_err(|e| e.to_string()),
+            preflight_timeout: options.preflight_timeout,
+        }
+    }
+
+    fn client(&self) -> anyhow::Result<&reqwest::Client> {
+        self.client
+            .as_ref()
+            .map_err(|e| anyhow!("Couldn't build an HTTP client: {e}"))
+    }
+
+    /// Check the server behind `url` is responding at all, if there is a preflight timeout (see
+    /// [`VerifyOptions::preflight_timeout`]).
+    async fn preflight(&self, url: &str) -> anyhow::Result<()> {
+        if let Some(timeout) = self.preflight_timeout {
+            self.client()?
+                .head(url)
+                .timeout(timeout)
+                .send()
+                .await
+                .map_err(|e| preflight_error(url, &e))?;
+        }
+        Ok(())
+    }
+
+    /// Fetch the verification key served at `url`.
+    async fn fetch_key(&self, url: &str) -> anyhow::Result<VerifyingKey> {
+        let client = self.client()?;
+        let signer_details = async {
+            self.preflight(url).await?;
+            let response = client.get(url).send().await?;
+            if response.status() == reqwest::StatusCode::GONE {
+                let body = response.json::<ServerErrorBody>().await.ok();
+                return Err(deactivated(url, body).into());
+            }
+            if !response.status().is_success() {
+                return Err(anyhow!(
+                    "GET request to {url} failed: {}",
+                    response.status()
+                ));
+            }
+            Ok(response.json::<SignerDetailsFromServer>().await?)
+        }
+        .await
+        .map_err(|e| key_fetch_error(url, e))?;
+
+        Base64VerifyingKey(signer_details.verification_key_b64).try_into()
+    }
+
+    /// Ask the group's provenance server whether the member a layer was signed on behalf of is
+    /// in the group, like [`verify`](crate::verify) does.
+    async fn check_membership(&self, group: &GroupSignerDetails) -> anyhow::Result<()> {
+        let client = self.client()?;
+        self.preflight(&group.group_url).await?;
+        let url = format!("{}/members/{}", group.group_url, group.member_id);
+        let status = client
+            .get(&url)
+            .send()
+            .await
+            .map(|response| response.status());
+        membership(group, status)
+    }
+}
+
+/// The async client for [`Fetcher`], trusting any extra root certificates in `options`.
+#[cfg(feature = "custom-ca")]
+fn async_client(options: &VerifyOptions) -> anyhow::Result<reqwest::Client> {
+    let client = options
+        .root_certificates
+        .iter()
+        .fold(
+            reqwest::Client::builder().timeout(REQUEST_TIMEOUT),
+            |builder, certificate| builder.add_root_certificate(certificate.clone()),
+        )
+        .build()?;
+    Ok(client)
+}
+
+#[cfg(not(feature = "custom-ca"))]
+fn async_client(options: &VerifyOptions) -> anyhow::Result<reqwest::Client> {
+    crate::provenance::check_no_root_certificates(options)?;
+    Ok(reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?)
+}
+
+/// The longest header line [`verify_async_reader`] will read before giving up, so that a
+/// document without a header isn't read into memory looking for the end of the line.
+const MAX_HEADER_LENGTH: u64 = 64 * 1024;
+
+/// Verify the document read from `reader`, fetching the verification key from its header's URL,
+/// without holding the document in memory.
+///
+/// The header is read first, then the rest of the document is hashed as it is read, so the
+/// layer has to have been signed with Ed25519ph (`alg=ed25519ph` in the header), which signs
+/// that hash. Layers signed with plain ed25519 (or signed over a normalized document) can't be
+/// checked this way and fail; read them into memory and use [`verify`](crate::verify) instead.
+pub async fn verify_async_reader<R: AsyncRead + Unpin>(reader: R) -> anyhow::Result<SignerDetails> {
+    verify_async_reader_with_options(reader, &VerifyOptions::default()).await
+}
+
+/// Like [`verify_async_reader`], but with [`VerifyOptions`] to control how the document is
+/// verified. The options' normalizers are never used, since normalized layers can't be verified
+/// while streaming.
+pub async fn verify_async_reader_with_options<R: AsyncRead + Unpin>(
+    reader: R,
+    options: &VerifyOptions,
+) -> anyhow::Result<SignerDetails> {
+    let mut reader = BufReader::new(reader);
+    let mut first = vec![];
+    (&mut reader)
+        .take(MAX_HEADER_LENGTH)
+        .read_until(b'\n', &mut first)
+        .await?;
+    let Some(first) = first.strip_suffix(b"\n") else {
+        return Err(anyhow!("Document doesn't start with a header line"));
+    };
+    let first = std::str::from_utf8(first.strip_suffix(b"\r").unwrap_or(first))
+        .map_err(|_| anyhow!("Document header isn't valid UTF-8"))?;
+
+    let (header, signature) = decode_header_with_grace(first, options)?;
+    if header.extension(ALGORITHM_EXTENSION) != Some(PREHASHED_ALGORITHM) {
+        return Err(anyhow!(
+            "Only layers signed with '{PREHASHED_ALGORITHM}' can be verified while streaming"
+        ));
+    }
+    for extension in [NORMALIZER_EXTENSION, BATCH_EXTENSION] {
+        if header.extension(extension).is_some() {
+            return Err(anyhow!(
+                "Layers with a '{extension}' extension can't be verified while streaming"
+            ));
+        }
+    }
+    let fetcher = Fetcher::new(options);
+    let verification_key = fetcher.fetch_key(header.url).await;
+    let member = match GroupSignerDetails::of(header.url, header.extensions) {
+        Some(group) => fetcher.check_membership(&group).await,
+        None => Ok(()),
+    };
+
+    let mut hash = prehashed_message(header.version, header.url, header.extensions, &[]);
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hash.update(&buffer[..read]);
+    }
+
+    verify_layer(
+        &header,
+        &signature,
+        SignedBody::Prehashed(&hash),
+        options,
+        |_| verification_key,
+        |_| member,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offline::{signature_to_b64, EMBEDDED_KEY_EXTENSION, PROVENANCE_VERSION};
+    use crate::{
+        format_header_with_extensions, sign, sign_with_threshold, test_util, EmbeddedKey,
+        VerifyError,
+    };
+    use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+    use futures::io::Cursor;
+    use futures::stream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn verifies_every_document_with_a_mock_resolver() {
+        let alice = test_util::deterministic_signing_key("alice");
+        let bob = test_util::deterministic_signing_key("bob");
+        let keys = HashMap::from([
+            (
+                "https://example.com/alice".to_string(),
+                alice.verifying_key(),
+            ),
+            ("https://example.com/bob".to_string(), bob.verifying_key()),
+        ]);
+        let docs = vec![
+            sign("doc 0", &alice, "https://example.com/alice"),
+            sign("doc 1", &bob, "https://example.com/bob"),
+            sign("doc 2", &alice, "https://example.com/alice").replace("doc", "fake"),
+            sign("doc 3", &bob, "https://example.com/nobody"),
+            "doc 4, unsigned".to_string(),
+            sign("doc 5", &alice, "https://example.com/bob"),
+        ];
+        let calls = AtomicUsize::new(0);
+
+        let mut results: Vec<_> = verify_stream_with(stream::iter(docs), 3, |url| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let key = keys.get(&url).copied();
+            async move { key.ok_or_else(|| anyhow!("No key for '{url}'")) }
+        })
+        .map(|(result, remainder)| (remainder, result.is_ok()))
+        .collect()
+        .await;
+        results.sort();
+
+        assert_eq!(
+            results,
+            [
+                ("doc 0".to_string(), true),
+                ("doc 1".to_string(), true),
+                ("doc 3".to_string(), false),
+                ("doc 4, unsigned".to_string(), false),
+                ("doc 5".to_string(), false),
+                ("fake 2".to_string(), false),
+            ]
+        );
+        // Unsigned documents don't need a key
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn fetches_keys_from_the_server() {
+        let signing_key = test_util::beyarkay_key();
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let mut docs: Vec<_> = (0..5)
+            .map(|i| sign(&format!("doc {i}"), &signing_key, url))
+            .collect();
+        docs.push(sign("doc 5", &signing_key, "http://localhost:1/down"));
+
+        let results: Vec<_> = verify_stream(stream::iter(docs), 2).collect().await;
+
+        assert_eq!(results.len(), 6);
+        for (result, remainder) in results {
+            if remainder == "doc 5" {
+                assert!(matches!(
+                    result.unwrap_err().downcast_ref(),
+                    Some(VerifyError::Unreachable { .. })
+                ));
+            } else {
+                assert_eq!(result.unwrap().verification_url, url);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_options_apply_to_every_document() {
+        let signing_key = test_util::beyarkay_key();
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let docs = vec![sign("doc 0", &signing_key, url)];
+
+        let results: Vec<_> = verify_stream(stream::iter(docs.clone()), 1).collect().await;
+        assert!(results[0].0.is_ok());
+
+        // The layer has no trusted timestamp, so its age is unknown
+        let options = VerifyOptions {
+            max_age: Some(Duration::from_secs(60)),
+            ..VerifyOptions::default()
+        };
+        let results: Vec<_> = verify_stream_with_options(stream::iter(docs), 1, options)
+            .collect()
+            .await;
+        assert!(results[0].0.is_err());
+
+        let options = VerifyOptions {
+            preflight_timeout: Some(Duration::from_millis(200)),
+            ..VerifyOptions::default()
+        };
+        let down = vec![sign("doc 1", &signing_key, "http://localhost:1/down")];
+        let results: Vec<_> = verify_stream_with_options(stream::iter(down), 1, options)
+            .collect()
+            .await;
+        assert!(matches!(
+            results[0].0.as_ref().unwrap_err().downcast_ref(),
+            Some(VerifyError::Unreachable { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn verifies_large_documents_while_reading_them() {
+        let signing_key = test_util::beyarkay_key();
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let doc = "0123456789abcdef".repeat(64 * 1024);
+        let signed_doc = sign_with_threshold(&doc, &signing_key, url, 0);
+
+        let details = verify_async_reader(Cursor::new(signed_doc.as_bytes()))
+            .await
+            .unwrap();
+        assert_eq!(details.verification_url, url);
+
+        let crlf = signed_doc.replacen('\n', "\r\n", 1);
+        assert!(verify_async_reader(Cursor::new(crlf.as_bytes()))
+            .await
+            .is_ok());
+        let tampered = signed_doc.replacen("0123", "3210", 1);
+        assert!(verify_async_reader(Cursor::new(tampered.as_bytes()))
+            .await
+            .is_err());
+        let truncated = &signed_doc[..signed_doc.len() - 1];
+        assert!(verify_async_reader(Cursor::new(truncated.as_bytes()))
+            .await
+            .is_err());
+
+        // Plain ed25519 signs the whole message rather than its hash, so can't be streamed
+        let plain = sign("document text here", &signing_key, url);
+        let error = verify_async_reader(Cursor::new(plain.as_bytes()))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains(PREHASHED_ALGORITHM), "{error}");
+        assert!(verify_async_reader(Cursor::new(doc.as_bytes()))
+            .await
+            .is_err());
+
+        // The layer has no trusted timestamp, so its age is unknown
+        let options = VerifyOptions {
+            max_age: Some(Duration::from_secs(60)),
+            ..VerifyOptions::default()
+        };
+        let error = verify_async_reader_with_options(Cursor::new(signed_doc.as_bytes()), &options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error.downcast_ref(), Some(VerifyError::AgeUnknown { .. })),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn checks_embedded_keys_while_reading() {
+        let signing_key = test_util::beyarkay_key();
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let imposter = test_util::deterministic_signing_key("imposter");
+        let doc = "0123456789abcdef".repeat(1024);
+
+        for (key, expected) in [(&signing_key, true), (&imposter, false)] {
+            let extensions = format!(
+                "{ALGORITHM_EXTENSION}={PREHASHED_ALGORITHM} {EMBEDDED_KEY_EXTENSION}={}",
+                URL_SAFE.encode(key.verifying_key().as_bytes())
+            );
+            let signature = key
+                .sign_prehashed(
+                    prehashed_message(PROVENANCE_VERSION, url, &extensions, doc.as_bytes()),
+                    None,
+                )
+                .unwrap();
+            let header =
+                format_header_with_extensions(url, &signature_to_b64(&signature), &extensions);
+            let signed_doc = format!("{header}\n{doc}");
+            let result = verify_async_reader(Cursor::new(signed_doc.as_bytes())).await;
+            assert_eq!(result.is_ok(), expected, "{result:?}");
+            if expected {
+                assert_eq!(result.unwrap().embedded_key, EmbeddedKey::Confirmed);
+            } else {
+                assert!(matches!(
+                    result.unwrap_err().downcast_ref(),
+                    Some(VerifyError::UrlKeyMismatch { .. })
+                ));
+            }
+        }
+
+        // Documents long enough to be prehashed by default stream with any extensions
+        let doc = "x".repeat(crate::DEFAULT_PREHASH_THRESHOLD + 1);
+        let signed_doc = crate::sign_with_embedded_key(&doc, &signing_key, url);
+        let details = verify_async_reader(Cursor::new(signed_doc.as_bytes()))
+            .await
+            .unwrap();
+        assert_eq!(details.embedded_key, EmbeddedKey::Confirmed);
+    }
+}
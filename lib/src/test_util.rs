@@ -0,0 +1,199 @@
+//! Helpers for writing reproducible tests.
+//!
+//! Only available with the `test-util` feature (or inside this crate's own tests).
+
+use crate::Base64SigningKey;
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+#[cfg(feature = "std")]
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc,
+    time::Duration,
+};
+
+/// Derive a signing key from a seed string.
+///
+/// The same seed always gives the same key, so tests using this don't need to generate random
+/// keys (or ask a provenance server to generate keys for them) and failures can be reproduced
+/// exactly. Never use this for real keys: anyone who knows the seed knows the key.
+pub fn deterministic_signing_key(seed: &str) -> SigningKey {
+    let secret_key: [u8; ed25519_dalek::SECRET_KEY_LENGTH] = Sha256::digest(seed.as_bytes()).into();
+    SigningKey::from_bytes(&secret_key)
+}
+
+/// The signing key of the `beyarkay` example user, whose verification key the provenance server
+/// in this repository serves at `http://localhost:8000/provenance/beyarkay`.
+///
+/// Tests which verify against that server sign with this. Like [`deterministic_signing_key`],
+/// it's public knowledge, so never sign anything real with it.
+pub fn beyarkay_key() -> SigningKey {
+    Base64SigningKey("-5TaFC0xFOj_hf7mlvVaLKKpVFTaXUrLDzRqaaf7gFw=".into())
+        .try_into()
+        .unwrap()
+}
+
+/// A request received by a [`mock_server`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    /// The request line and headers exactly as sent, up to and including the blank line which
+    /// ends them.
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+/// A response for a [`mock_server`] to send.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct MockResponse {
+    /// The status code, like `200`.
+    pub status: u16,
+    pub body: Vec<u8>,
+    /// If set, send only this many bytes of the body, then wait until the receiver is
+    /// signalled (or its sender dropped) before sending the rest. Gives up waiting after 5
+    /// seconds, so that a test which wrongly waits for the rest still ends.
+    pub stall: Option<(usize, mpsc::Receiver<()>)>,
+}
+
+#[cfg(feature = "std")]
+impl MockResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        MockResponse {
+            status,
+            body: body.into(),
+            stall: None,
+        }
+    }
+}
+
+/// Run an HTTP server on a local port which answers every request with `status` and `body`,
+/// returning its address. The server runs until the test process exits.
+#[cfg(feature = "std")]
+pub fn mock_server(status: u16, body: impl Into<Vec<u8>>) -> SocketAddr {
+    let body = body.into();
+    mock_server_with(move |_| MockResponse::new(status, body.clone()))
+}
+
+/// Like [`mock_server`], but answering each request with whatever `respond` returns for it.
+#[cfg(feature = "std")]
+pub fn mock_server_with(
+    respond: impl FnMut(&MockRequest) -> MockResponse + Send + 'static,
+) -> SocketAddr {
+    mock_server_over(Some, respond)
+}
+
+/// Like [`mock_server_with`], but speaking HTTP over whatever `wrap` turns each connection into
+/// (say, a TLS stream). Connections it returns `None` for are dropped.
+#[cfg(feature = "std")]
+pub fn mock_server_over<S: Read + Write>(
+    mut wrap: impl FnMut(TcpStream) -> Option<S> + Send + 'static,
+    mut respond: impl FnMut(&MockRequest) -> MockResponse + Send + 'static,
+) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Some(mut stream) = stream.ok().and_then(&mut wrap) else {
+                continue;
+            };
+            // A client which hangs up mid-request doesn't stop the server
+            if let Some(request) = read_request(&mut stream) {
+                let _ = write_response(&mut stream, respond(&request));
+            }
+        }
+    });
+
+    addr
+}
+
+/// Read a request with a body of `Content-Length` bytes (or none) from `stream`.
+#[cfg(feature = "std")]
+fn read_request(stream: &mut impl Read) -> Option<MockRequest> {
+    let mut request = vec![];
+    let mut buffer = [0; 1024];
+    let body_start = loop {
+        if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = stream.read(&mut buffer).ok().filter(|&read| read > 0)?;
+        request.extend_from_slice(&buffer[..read]);
+    };
+    let head = String::from_utf8(request[..body_start].to_vec()).ok()?;
+    let content_length: usize = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .map_or(Some(0), |(_, length)| length.trim().parse().ok())?;
+    while request.len() < body_start + content_length {
+        let read = stream.read(&mut buffer).ok().filter(|&read| read > 0)?;
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let mut request_line = head.split(' ');
+    Some(MockRequest {
+        method: request_line.next()?.to_string(),
+        path: request_line.next()?.to_string(),
+        body: request[body_start..].to_vec(),
+        head,
+    })
+}
+
+#[cfg(feature = "std")]
+fn write_response(stream: &mut impl Write, response: MockResponse) -> std::io::Result<()> {
+    let status = reqwest::StatusCode::from_u16(response.status).unwrap();
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    )?;
+    let (now, later) = match &response.stall {
+        Some((sent, _)) => response.body.split_at(*sent),
+        None => (response.body.as_slice(), &[][..]),
+    };
+    stream.write_all(now)?;
+    stream.flush()?;
+    if let Some((_, released)) = &response.stall {
+        let _ = released.recv_timeout(Duration::from_secs(5));
+    }
+    stream.write_all(later)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic_signing_key_is_deterministic() {
+        assert_eq!(
+            deterministic_signing_key("alice").to_bytes(),
+            deterministic_signing_key("alice").to_bytes()
+        );
+        assert_ne!(
+            deterministic_signing_key("alice").to_bytes(),
+            deterministic_signing_key("bob").to_bytes()
+        );
+    }
+
+    #[test]
+    fn mock_server_answers_every_request() {
+        let addr = mock_server(410, "Gone for good");
+        let response = reqwest::blocking::get(format!("http://{addr}/anything")).unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::GONE);
+        assert_eq!(response.text().unwrap(), "Gone for good");
+
+        let addr = mock_server_with(|request| {
+            let body = format!("{} {} {}", request.method, request.path, request.body.len());
+            MockResponse::new(200, body)
+        });
+        let response = reqwest::blocking::Client::new()
+            .post(format!("http://{addr}/upload"))
+            .body("x".repeat(5000))
+            .send()
+            .unwrap();
+        assert_eq!(response.text().unwrap(), "POST /upload 5000");
+    }
+}
@@ -0,0 +1,540 @@
+//! Embedding RFC 3161 timestamps, which prove a document existed before a certain time.
+//!
+//! A signer's own clock can be set to anything, so a time they write into a document proves
+//! nothing. Instead, [`sign_timestamped`] asks a timestamping authority (TSA) to sign the hash of
+//! the document along with the current time, and embeds the TSA's token in the provenance header
+//! (as `tst=<token>`). The token is covered by the signer's signature like any other header
+//! extension.
+//!
+//! When verifying, the token is checked against the TSAs in
+//! [`VerifyOptions::timestamp_authorities`](crate::VerifyOptions::timestamp_authorities), and the
+//! attested time is reported in [`SignerDetails::timestamp`](crate::SignerDetails::timestamp). A
+//! token from a TSA which isn't trusted is ignored, but a token which doesn't match the document
+//! fails verification. TSAs are trusted by pinning their public key: certificate chains aren't
+//! validated. Only SHA-256 tokens, signed with RSA (PKCS #1 v1.5) or ECDSA on P-256, are
+//! understood.
+
+use crate::offline::{signed_message_with_extensions, PROVENANCE_VERSION};
+use crate::provenance::{prehash_extensions, sign_with_extensions, VerifyError};
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE, Engine as _};
+use cmpv2::status::PkiStatus;
+use cms::cert::x509::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+use cms::cert::x509::Certificate;
+use cms::content_info::ContentInfo;
+use cms::signed_data::SignedData;
+use der::asn1::{Int, ObjectIdentifier, OctetString};
+use der::{Decode, Encode};
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime};
+use x509_tsp::{MessageImprint, TimeStampReq, TimeStampResp, TspVersion, TstInfo};
+
+/// The header extension which holds a layer's timestamp token.
+pub const TIMESTAMP_EXTENSION: &str = "tst";
+
+const ID_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const ID_SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+const ID_CT_TST_INFO: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.1.4");
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const ID_EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+
+pub use crate::provenance::TimestampAuthority;
+
+impl TimestampAuthority {
+    /// Trust the TSA with this DER-encoded `SubjectPublicKeyInfo`.
+    pub fn from_public_key_der(der: &[u8]) -> anyhow::Result<Self> {
+        SubjectPublicKeyInfoOwned::from_der(der)
+            .map_err(|e| anyhow!("Couldn't parse TSA public key: {e}"))?;
+        Ok(TimestampAuthority {
+            public_key_der: der.to_vec(),
+        })
+    }
+
+    /// Trust the TSA with this DER-encoded certificate. Only its public key is used: the
+    /// certificate's validity and issuer aren't checked.
+    pub fn from_certificate_der(der: &[u8]) -> anyhow::Result<Self> {
+        let certificate = Certificate::from_der(der)
+            .map_err(|e| anyhow!("Couldn't parse TSA certificate: {e}"))?;
+        Ok(TimestampAuthority {
+            public_key_der: certificate
+                .tbs_certificate
+                .subject_public_key_info
+                .to_der()?,
+        })
+    }
+
+    /// Returns true if `signature` is this TSA's signature over the SHA-256 hash of `message`.
+    fn verifies(&self, message: &[u8], signature: &[u8]) -> bool {
+        let Ok(public_key) = SubjectPublicKeyInfoOwned::from_der(&self.public_key_der) else {
+            return false;
+        };
+        let key_bytes = public_key.subject_public_key.raw_bytes();
+        match public_key.algorithm.oid {
+            ID_EC_PUBLIC_KEY => {
+                use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+                let (Ok(key), Ok(signature)) = (
+                    VerifyingKey::from_sec1_bytes(key_bytes),
+                    Signature::from_der(signature),
+                ) else {
+                    return false;
+                };
+                key.verify(message, &signature).is_ok()
+            }
+            RSA_ENCRYPTION => {
+                use rsa::pkcs1::DecodeRsaPublicKey;
+                use rsa::pkcs1v15::{Signature, VerifyingKey};
+                use rsa::signature::Verifier;
+                let (Ok(key), Ok(signature)) = (
+                    rsa::RsaPublicKey::from_pkcs1_der(key_bytes),
+                    Signature::try_from(signature),
+                ) else {
+                    return false;
+                };
+                VerifyingKey::<Sha256>::new(key)
+                    .verify(message, &signature)
+                    .is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Sign a document like [`crate::sign`], embedding a timestamp token from the TSA at `tsa_url`.
+pub fn sign_timestamped(
+    doc: &str,
+    signing_key: &SigningKey,
+    url: &str,
+    tsa_url: &str,
+) -> anyhow::Result<String> {
+    // The token covers the layer without itself, see `attested_time`
+    let imprint = Sha256::digest(signed_message_with_extensions(
+        PROVENANCE_VERSION,
+        url,
+        &prehash_extensions(doc.len()),
+        doc.as_bytes(),
+    ))
+    .into();
+    let token = request_timestamp(tsa_url, &imprint)?;
+
+    let token = URL_SAFE.encode(token);
+    Ok(sign_with_extensions(
+        doc,
+        signing_key,
+        url,
+        &[(TIMESTAMP_EXTENSION, &token)],
+    ))
+}
+
+/// Ask the TSA at `tsa_url` to timestamp the SHA-256 hash `imprint`, returning the DER-encoded
+/// timestamp token.
+pub fn request_timestamp(tsa_url: &str, imprint: &[u8; 32]) -> anyhow::Result<Vec<u8>> {
+    let nonce = rand::random::<u64>().to_be_bytes();
+    let request = TimeStampReq {
+        version: TspVersion::V1,
+        message_imprint: sha256_imprint(imprint)?,
+        req_policy: None,
+        nonce: Some(Int::new(&nonce)?),
+        cert_req: true,
+        extensions: None,
+    };
+
+    let response = reqwest::blocking::Client::new()
+        .post(tsa_url)
+        .header("Content-Type", "application/timestamp-query")
+        .body(request.to_der()?)
+        .send()?
+        .error_for_status()?
+        .bytes()?;
+    let response = TimeStampResp::from_der(&response)
+        .map_err(|e| anyhow!("Couldn't parse response from TSA at '{tsa_url}': {e}"))?;
+
+    if !matches!(
+        response.status.status,
+        PkiStatus::Accepted | PkiStatus::GrantedWithMods
+    ) {
+        return Err(anyhow!(
+            "TSA at '{tsa_url}' refused to timestamp the document: {:?}",
+            response.status.status
+        ));
+    }
+    let Some(token) = response.time_stamp_token else {
+        return Err(anyhow!(
+            "TSA at '{tsa_url}' didn't return a timestamp token"
+        ));
+    };
+
+    let info = decode_token(&token)?.0;
+    if info.message_imprint != request.message_imprint || info.nonce != request.nonce {
+        return Err(anyhow!(
+            "TSA at '{tsa_url}' returned a timestamp for a different request"
+        ));
+    }
+
+    Ok(token.to_der()?)
+}
+
+/// The time attested by the timestamp token in a layer's `extensions`, if it has one from a
+/// trusted TSA. Fails if the layer has a token which doesn't match the layer.
+pub(crate) fn attested_time(
+    version: &str,
+    url: &str,
+    extensions: &str,
+    body: &[u8],
+    authorities: &[TimestampAuthority],
+) -> anyhow::Result<Option<SystemTime>> {
+    let prefix = format!("{TIMESTAMP_EXTENSION}=");
+    let Some(token_b64) = extensions
+        .split(' ')
+        .find_map(|extension| extension.strip_prefix(&prefix))
+    else {
+        return Ok(None);
+    };
+    // The token was requested before it could be added to the header, so it covers the layer
+    // without itself
+    let other_extensions = extensions
+        .split(' ')
+        .filter(|extension| !extension.starts_with(&prefix))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let imprint: [u8; 32] = Sha256::digest(signed_message_with_extensions(
+        version,
+        url,
+        &other_extensions,
+        body,
+    ))
+    .into();
+
+    let Ok(token) = URL_SAFE.decode(token_b64) else {
+        return Err(anyhow!("Couldn't decode timestamp token from base64"));
+    };
+    let token = ContentInfo::from_der(&token)
+        .map_err(|e| anyhow!("Couldn't parse timestamp token: {e}"))?;
+    let (info, signed_attributes, signature) = decode_token(&token)?;
+    if info.message_imprint != sha256_imprint(&imprint)? {
+        return Err(anyhow!("Timestamp token is for a different document"));
+    }
+
+    let trusted = authorities
+        .iter()
+        .any(|authority| authority.verifies(&signed_attributes, &signature));
+    Ok(trusted.then(|| info.gen_time.to_system_time()))
+}
+
+/// Fail if `timestamp` is after `now` by more than `clock_skew`, since a TSA can't have seen
+/// the layer later than it was verified, unless one of the clocks is wrong.
+pub(crate) fn check_not_in_future(
+    url: &str,
+    timestamp: SystemTime,
+    now: SystemTime,
+    clock_skew: Duration,
+) -> Result<(), VerifyError> {
+    match timestamp.duration_since(now) {
+        Ok(ahead) if ahead > clock_skew => Err(VerifyError::TimestampInFuture {
+            url: url.to_string(),
+            ahead,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn sha256_imprint(imprint: &[u8; 32]) -> anyhow::Result<MessageImprint> {
+    Ok(MessageImprint {
+        hash_algorithm: AlgorithmIdentifierOwned {
+            oid: ID_SHA_256,
+            parameters: None,
+        },
+        hashed_message: OctetString::new(imprint.as_slice())?,
+    })
+}
+
+/// Decode a timestamp token, checking that its signed attributes cover its `TSTInfo`. Returns
+/// the `TSTInfo`, the DER-encoded signed attributes, and the TSA's signature over them.
+fn decode_token(token: &ContentInfo) -> anyhow::Result<(TstInfo, Vec<u8>, Vec<u8>)> {
+    let malformed = |e: der::Error| anyhow!("Couldn't parse timestamp token: {e}");
+    if token.content_type != ID_SIGNED_DATA {
+        return Err(anyhow!("Timestamp token isn't CMS signed data"));
+    }
+    let signed_data: SignedData = token.content.decode_as().map_err(malformed)?;
+
+    let encapsulated = &signed_data.encap_content_info;
+    let Some(content) = encapsulated
+        .econtent
+        .as_ref()
+        .filter(|_| encapsulated.econtent_type == ID_CT_TST_INFO)
+    else {
+        return Err(anyhow!("Timestamp token doesn't contain a TSTInfo"));
+    };
+    let info_der = content.decode_as::<OctetString>().map_err(malformed)?;
+    let info = TstInfo::from_der(info_der.as_bytes()).map_err(malformed)?;
+
+    let [signer] = signed_data.signer_infos.0.as_slice() else {
+        return Err(anyhow!("Timestamp token must have exactly one signer"));
+    };
+    if signer.digest_alg.oid != ID_SHA_256 {
+        return Err(anyhow!("Timestamp token isn't signed with SHA-256"));
+    }
+    let Some(signed_attributes) = &signer.signed_attrs else {
+        return Err(anyhow!("Timestamp token doesn't have signed attributes"));
+    };
+    let digest = signed_attributes
+        .iter()
+        .find(|attribute| attribute.oid == ID_MESSAGE_DIGEST)
+        .and_then(|attribute| attribute.values.get(0))
+        .and_then(|value| value.decode_as::<OctetString>().ok());
+    let expected = Sha256::digest(info_der.as_bytes());
+    if digest.as_ref().map(OctetString::as_bytes) != Some(expected.as_slice()) {
+        return Err(anyhow!(
+            "Timestamp token's signature doesn't cover its TSTInfo"
+        ));
+    }
+
+    Ok((
+        info,
+        signed_attributes.to_der()?,
+        signer.signature.as_bytes().to_vec(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, verify_with_options, VerifyOptions};
+    use cms::cert::x509::attr::Attribute;
+    use cms::content_info::CmsVersion;
+    use cms::signed_data::{EncapsulatedContentInfo, SignerIdentifier, SignerInfo, SignerInfos};
+    use der::asn1::{Any, GeneralizedTime, SetOfVec};
+    use ed25519_dalek::Signer as _;
+    use p256::pkcs8::EncodePublicKey as _;
+    use std::time::Duration;
+
+    const ECDSA_WITH_SHA_256: ObjectIdentifier =
+        ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+
+    fn tsa_key(seed: &str) -> p256::ecdsa::SigningKey {
+        p256::ecdsa::SigningKey::from_slice(&Sha256::digest(seed.as_bytes())).unwrap()
+    }
+
+    fn authority(key: &p256::ecdsa::SigningKey) -> TimestampAuthority {
+        let der = key.verifying_key().to_public_key_der().unwrap();
+        TimestampAuthority::from_public_key_der(der.as_bytes()).unwrap()
+    }
+
+    /// Do what a TSA does when asked to timestamp `request` at `time`.
+    fn timestamp(
+        key: &p256::ecdsa::SigningKey,
+        request: &TimeStampReq,
+        time: SystemTime,
+    ) -> Vec<u8> {
+        let info = TstInfo {
+            version: TspVersion::V1,
+            policy: ObjectIdentifier::new_unwrap("1.2.3.4.1"),
+            message_imprint: request.message_imprint.clone(),
+            serial_number: Int::new(&[1]).unwrap(),
+            gen_time: GeneralizedTime::from_system_time(time).unwrap(),
+            accuracy: None,
+            ordering: false,
+            nonce: request.nonce.clone(),
+            tsa: None,
+            extensions: None,
+        }
+        .to_der()
+        .unwrap();
+
+        let signed_attributes: SetOfVec<Attribute> = [Attribute {
+            oid: ID_MESSAGE_DIGEST,
+            values: [
+                Any::encode_from(&OctetString::new(Sha256::digest(&info).to_vec()).unwrap())
+                    .unwrap(),
+            ]
+            .to_vec()
+            .try_into()
+            .unwrap(),
+        }]
+        .to_vec()
+        .try_into()
+        .unwrap();
+        let signature: p256::ecdsa::Signature = key.sign(&signed_attributes.to_der().unwrap());
+
+        let signer = SignerInfo {
+            version: CmsVersion::V3,
+            sid: SignerIdentifier::SubjectKeyIdentifier(
+                OctetString::new(vec![1, 2, 3]).unwrap().into(),
+            ),
+            digest_alg: AlgorithmIdentifierOwned {
+                oid: ID_SHA_256,
+                parameters: None,
+            },
+            signed_attrs: Some(signed_attributes),
+            signature_algorithm: AlgorithmIdentifierOwned {
+                oid: ECDSA_WITH_SHA_256,
+                parameters: None,
+            },
+            signature: OctetString::new(signature.to_der().as_bytes()).unwrap(),
+            unsigned_attrs: None,
+        };
+        let signed_data = SignedData {
+            version: CmsVersion::V3,
+            digest_algorithms: [AlgorithmIdentifierOwned {
+                oid: ID_SHA_256,
+                parameters: None,
+            }]
+            .to_vec()
+            .try_into()
+            .unwrap(),
+            encap_content_info: EncapsulatedContentInfo {
+                econtent_type: ID_CT_TST_INFO,
+                econtent: Some(Any::encode_from(&OctetString::new(info).unwrap()).unwrap()),
+            },
+            certificates: None,
+            crls: None,
+            signer_infos: SignerInfos([signer].to_vec().try_into().unwrap()),
+        };
+        let token = ContentInfo {
+            content_type: ID_SIGNED_DATA,
+            content: Any::encode_from(&signed_data).unwrap(),
+        };
+
+        TimeStampResp {
+            status: cmpv2::status::PkiStatusInfo {
+                status: PkiStatus::Accepted,
+                status_string: None,
+                fail_info: None,
+            },
+            time_stamp_token: Some(token),
+        }
+        .to_der()
+        .unwrap()
+    }
+
+    /// Run a TSA which answers every request with a timestamp for `time`.
+    fn timestamping_server(key: p256::ecdsa::SigningKey, time: SystemTime) -> String {
+        let addr = test_util::mock_server_with(move |request| {
+            let request = TimeStampReq::from_der(&request.body).unwrap();
+            test_util::MockResponse::new(200, timestamp(&key, &request, time))
+        });
+
+        format!("http://{addr}/tsa")
+    }
+
+    #[test]
+    fn timestamps_are_verified_against_trusted_authorities() {
+        let tsa = tsa_key("tsa");
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let tsa_url = timestamping_server(tsa.clone(), time);
+
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let doc = "document text here";
+        let signed_doc = sign_timestamped(doc, &signing_key, url, &tsa_url).unwrap();
+        let trusting = |authorities| VerifyOptions {
+            timestamp_authorities: authorities,
+            ..Default::default()
+        };
+
+        let details = verify_with_options(&signed_doc, &trusting(vec![authority(&tsa)]))
+            .0
+            .unwrap();
+        assert_eq!(details.timestamp, Some(time));
+
+        // Documents long enough to be prehashed are timestamped too
+        let large = "x".repeat(crate::DEFAULT_PREHASH_THRESHOLD + 1);
+        let signed_large = sign_timestamped(&large, &signing_key, url, &tsa_url).unwrap();
+        let header = signed_large.lines().next().unwrap();
+        assert!(header.contains("alg=ed25519ph tst="), "{header}");
+        let details = verify_with_options(&signed_large, &trusting(vec![authority(&tsa)]))
+            .0
+            .unwrap();
+        assert_eq!(details.timestamp, Some(time));
+
+        // A token from an untrusted TSA is ignored
+        let untrusted = trusting(vec![authority(&tsa_key("other"))]);
+        let details = verify_with_options(&signed_doc, &untrusted).0.unwrap();
+        assert_eq!(details.timestamp, None);
+
+        // A token for a different document fails, even though the signer signed it
+        let other = sign_timestamped("other document", &signing_key, url, &tsa_url).unwrap();
+        let token = other
+            .split(' ')
+            .find_map(|word| word.strip_prefix("tst="))
+            .unwrap();
+        let swapped = sign_with_extensions(doc, &signing_key, url, &[(TIMESTAMP_EXTENSION, token)]);
+        assert!(
+            verify_with_options(&swapped, &trusting(vec![authority(&tsa)]))
+                .0
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn timestamps_can_be_ahead_by_at_most_the_clock_skew() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let skew = Duration::from_secs(300);
+        let check = |ahead: Duration| check_not_in_future("url", now + ahead, now, skew);
+
+        assert!(check_not_in_future("url", now - skew * 100, now, skew).is_ok());
+        assert!(check(Duration::ZERO).is_ok());
+        assert!(check(skew).is_ok());
+        let error = check(skew + Duration::from_nanos(1)).unwrap_err();
+        assert!(matches!(
+            error,
+            VerifyError::TimestampInFuture { ahead, .. } if ahead == skew + Duration::from_nanos(1)
+        ));
+        assert!(check_not_in_future("url", now + skew, now, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn layers_timestamped_in_the_future_fail() {
+        let tsa = tsa_key("tsa");
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signing_key = test_util::beyarkay_key();
+        let options = VerifyOptions {
+            timestamp_authorities: vec![authority(&tsa)],
+            ..Default::default()
+        };
+
+        // A TSA whose clock is a minute fast is within the default skew
+        let slightly_fast =
+            timestamping_server(tsa.clone(), SystemTime::now() + Duration::from_secs(60));
+        let signed_doc = sign_timestamped("doc", &signing_key, url, &slightly_fast).unwrap();
+        assert!(verify_with_options(&signed_doc, &options).0.is_ok());
+
+        let hour_ahead = timestamping_server(tsa, SystemTime::now() + Duration::from_secs(3600));
+        let signed_doc = sign_timestamped("doc", &signing_key, url, &hour_ahead).unwrap();
+        let error = verify_with_options(&signed_doc, &options).0.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(VerifyError::TimestampInFuture { .. })
+        ));
+        let lenient = VerifyOptions {
+            clock_skew: Duration::from_secs(2 * 3600),
+            ..options
+        };
+        assert!(verify_with_options(&signed_doc, &lenient).0.is_ok());
+    }
+
+    #[test]
+    fn layers_timestamped_too_long_ago_fail() {
+        let tsa = tsa_key("tsa");
+        let signed_at = SystemTime::now() - Duration::from_secs(2 * 3600);
+        let tsa_url = timestamping_server(tsa.clone(), signed_at);
+        let signing_key = test_util::beyarkay_key();
+        let url = "http://localhost:8000/provenance/beyarkay";
+        let signed_doc = sign_timestamped("doc", &signing_key, url, &tsa_url).unwrap();
+        let with_max_age = |hours: u64| VerifyOptions {
+            timestamp_authorities: vec![authority(&tsa)],
+            max_age: Some(Duration::from_secs(hours * 3600)),
+            ..Default::default()
+        };
+
+        assert!(verify_with_options(&signed_doc, &with_max_age(3)).0.is_ok());
+        let error = verify_with_options(&signed_doc, &with_max_age(1))
+            .0
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(VerifyError::TooOld { max_age, .. }) if *max_age == Duration::from_secs(3600)
+        ));
+    }
+}
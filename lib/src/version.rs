@@ -0,0 +1,369 @@
+//! Deciding which protocol versions a verifier accepts.
+//!
+//! Every provenance header records the version of the protocol it was signed with. By default
+//! only documents signed with exactly this crate's version are accepted, but a verifier in a
+//! fleet which is partway through an upgrade can accept several versions with a [`VersionSpec`].
+
+use crate::offline::PROVENANCE_VERSION;
+use alloc::{string::String, vec::Vec};
+use anyhow::anyhow;
+use core::fmt;
+use core::str::FromStr;
+
+/// The protocol versions which a verifier accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionSpec {
+    /// Any of the listed versions. An entry with fewer than three components matches every
+    /// version starting with it, so `"0.3"` accepts both `0.3.0` and `0.3.7`.
+    Exact(Vec<String>),
+    /// Any version in a semver range, like `">=0.3, <0.5"`.
+    Range(VersionRange),
+    /// Any version which is semver-compatible with this crate's version: the same major version,
+    /// or the same minor version while the major version is 0.
+    CurrentMajor,
+}
+
+impl Default for VersionSpec {
+    /// Only this crate's own version.
+    fn default() -> Self {
+        VersionSpec::Exact(alloc::vec![PROVENANCE_VERSION.into()])
+    }
+}
+
+impl VersionSpec {
+    /// Returns true if documents signed with `version` are accepted. Versions which aren't of the
+    /// form `major.minor.patch` are never accepted.
+    pub fn accepts(&self, version: &str) -> bool {
+        let Ok(version) = Version::parse(version) else {
+            return false;
+        };
+
+        match self {
+            VersionSpec::Exact(versions) => versions.iter().any(|accepted| {
+                PartialVersion::parse(accepted).is_ok_and(|accepted| accepted.matches(version))
+            }),
+            VersionSpec::Range(range) => range.accepts(version),
+            VersionSpec::CurrentMajor => {
+                let current = PartialVersion::parse(PROVENANCE_VERSION)
+                    .expect("the crate version is a valid version");
+                Comparator::new(Op::Caret, current).accepts(version)
+            }
+        }
+    }
+}
+
+/// Whether `version` is a later version of the protocol than this crate's, so that this crate
+/// can't know what it changed.
+#[cfg(feature = "std")]
+pub(crate) fn is_newer_than_current(version: &str) -> bool {
+    let current = Version::parse(PROVENANCE_VERSION).expect("the crate version is a valid version");
+    Version::parse(version).is_ok_and(|version| version > current)
+}
+
+impl fmt::Display for VersionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionSpec::Exact(versions) => write!(f, "one of [{}]", versions.join(", ")),
+            VersionSpec::Range(range) => write!(f, "{range}"),
+            VersionSpec::CurrentMajor => write!(f, "^{PROVENANCE_VERSION}"),
+        }
+    }
+}
+
+/// A semver range: a comma-separated list of comparators, all of which must match.
+///
+/// Comparators are written as an operator (`=`, `>`, `>=`, `<`, `<=`, `~` or `^`) followed by a
+/// version with one to three components. Like Cargo, a comparator without an operator is treated
+/// as `^`, and missing components are filled in sensibly: `<=0.4` accepts every `0.4.x`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRange {
+    text: String,
+    comparators: Vec<Comparator>,
+}
+
+impl VersionRange {
+    fn accepts(&self, version: Version) -> bool {
+        self.comparators
+            .iter()
+            .all(|comparator| comparator.accepts(version))
+    }
+}
+
+impl FromStr for VersionRange {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let comparators = text
+            .split(',')
+            .map(|comparator| comparator.trim().parse())
+            .collect::<anyhow::Result<Vec<Comparator>>>()?;
+
+        Ok(VersionRange {
+            text: text.trim().into(),
+            comparators,
+        })
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// A complete `major.minor.patch` version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Version(u64, u64, u64);
+
+impl Version {
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let partial = PartialVersion::parse(text)?;
+        match partial {
+            PartialVersion {
+                major,
+                minor: Some(minor),
+                patch: Some(patch),
+            } => Ok(Version(major, minor, patch)),
+            _ => Err(anyhow!("Version '{text}' must have three components")),
+        }
+    }
+}
+
+/// A version with one, two or three components, like `0`, `0.3` or `0.3.1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PartialVersion {
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+}
+
+impl PartialVersion {
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let components = text
+            .split('.')
+            .map(|component| {
+                // Leading zeros (and signs, which `parse` would accept) aren't valid semver
+                let is_numeric = !component.is_empty()
+                    && component.bytes().all(|byte| byte.is_ascii_digit())
+                    && (component == "0" || !component.starts_with('0'));
+                is_numeric.then(|| component.parse::<u64>().ok()).flatten()
+            })
+            .collect::<Option<Vec<_>>>();
+
+        let (major, minor, patch) = match components.as_deref() {
+            Some(&[major]) => (major, None, None),
+            Some(&[major, minor]) => (major, Some(minor), None),
+            Some(&[major, minor, patch]) => (major, Some(minor), Some(patch)),
+            _ => return Err(anyhow!("Couldn't parse '{text}' as a version")),
+        };
+
+        Ok(PartialVersion {
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    /// The lowest version starting with this one.
+    fn lowest(self) -> Version {
+        Version(self.major, self.minor.unwrap_or(0), self.patch.unwrap_or(0))
+    }
+
+    /// The lowest version which is higher than every version starting with this one, or `None` if
+    /// there's no such version because every component is already at its maximum.
+    ///
+    /// Components come from the caller, so they can be `u64::MAX`. Incrementing one then carries
+    /// into the component before it: nothing lies between `0.3.u64::MAX` and `0.4.0`.
+    fn after(self) -> Option<Version> {
+        let next_major = || Some(Version(self.major.checked_add(1)?, 0, 0));
+        let next_minor = |minor: u64| match minor.checked_add(1) {
+            Some(minor) => Some(Version(self.major, minor, 0)),
+            None => next_major(),
+        };
+        match (self.minor, self.patch) {
+            (Some(minor), Some(patch)) => match patch.checked_add(1) {
+                Some(patch) => Some(Version(self.major, minor, patch)),
+                None => next_minor(minor),
+            },
+            (Some(minor), None) => next_minor(minor),
+            _ => next_major(),
+        }
+    }
+
+    /// This version without its patch component.
+    fn minor_only(self) -> Self {
+        PartialVersion {
+            patch: None,
+            ..self
+        }
+    }
+
+    /// This version without its minor and patch components.
+    fn major_only(self) -> Self {
+        PartialVersion {
+            minor: None,
+            patch: None,
+            ..self
+        }
+    }
+
+    fn matches(self, version: Version) -> bool {
+        self.lowest() <= version && self.after().is_none_or(|after| version < after)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+/// A single comparator, stored as the half-open interval of versions it accepts. A missing bound
+/// is unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Comparator {
+    lowest: Option<Version>,
+    after: Option<Version>,
+}
+
+impl Comparator {
+    fn new(op: Op, version: PartialVersion) -> Self {
+        let (lowest, after) = match op {
+            Op::Exact => (Some(version.lowest()), version.after()),
+            // Nothing is higher than the highest version, or lower than `0.0.0`
+            Op::Greater => match version.after() {
+                Some(after) => (Some(after), None),
+                None => (None, Some(Version(0, 0, 0))),
+            },
+            Op::GreaterEq => (Some(version.lowest()), None),
+            Op::Less => (None, Some(version.lowest())),
+            Op::LessEq => (None, version.after()),
+            Op::Tilde => (Some(version.lowest()), version.minor_only().after()),
+            Op::Caret => {
+                let after = match (version.major, version.minor, version.patch) {
+                    (0, Some(0), Some(_)) => version.after(),
+                    (0, Some(_), _) => version.minor_only().after(),
+                    _ => version.major_only().after(),
+                };
+                (Some(version.lowest()), after)
+            }
+        };
+
+        Comparator { lowest, after }
+    }
+
+    fn accepts(&self, version: Version) -> bool {
+        self.lowest.is_none_or(|lowest| lowest <= version)
+            && self.after.is_none_or(|after| version < after)
+    }
+}
+
+impl FromStr for Comparator {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        // Longer operators first, so that `>=` isn't read as `>`
+        let operators = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            ("=", Op::Exact),
+            (">", Op::Greater),
+            ("<", Op::Less),
+            ("~", Op::Tilde),
+            ("^", Op::Caret),
+        ];
+        let (op, version) = operators
+            .iter()
+            .find_map(|(prefix, op)| text.strip_prefix(prefix).map(|rest| (*op, rest)))
+            .unwrap_or((Op::Caret, text));
+
+        Ok(Comparator::new(op, PartialVersion::parse(version.trim())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn range(text: &str) -> VersionSpec {
+        VersionSpec::Range(text.parse().unwrap())
+    }
+
+    #[test]
+    fn default_accepts_only_the_current_version() {
+        assert!(VersionSpec::default().accepts(PROVENANCE_VERSION));
+        assert!(!VersionSpec::default().accepts("0.2.0"));
+        assert!(!VersionSpec::default().accepts("<!PROVENANCE_VERSION!>"));
+    }
+
+    #[test]
+    fn exact_set_matches_prefixes() {
+        let spec = VersionSpec::Exact(vec!["0.3".into(), "0.4.1".into()]);
+        assert!(spec.accepts("0.3.0"));
+        assert!(spec.accepts("0.3.9"));
+        assert!(spec.accepts("0.4.1"));
+        assert!(!spec.accepts("0.4.0"));
+        assert!(!spec.accepts("0.2.0"));
+        assert!(!spec.accepts("0.3"));
+    }
+
+    #[test]
+    fn ranges() {
+        let spec = range(">=0.3, <0.5");
+        assert!(!spec.accepts("0.2.9"));
+        assert!(spec.accepts("0.3.0"));
+        assert!(spec.accepts("0.4.12"));
+        assert!(!spec.accepts("0.5.0"));
+
+        assert!(range("<=0.4").accepts("0.4.7"));
+        assert!(!range(">0.4").accepts("0.4.7"));
+        assert!(range(">0.4").accepts("0.5.0"));
+        assert!(range("=0.3").accepts("0.3.2"));
+        assert!(range("~1.2.3").accepts("1.2.9"));
+        assert!(!range("~1.2.3").accepts("1.3.0"));
+        assert!(range("^1.2").accepts("1.9.0"));
+        assert!(!range("^0.2").accepts("0.3.0"));
+        assert!(!range("0.0.3").accepts("0.0.4"));
+        assert_eq!(range(" >=0.3 ").to_string(), ">=0.3");
+
+        for bad in ["", ">=", "0.3.x", "1.2.3.4", "01.2", "=>1", "-1"] {
+            assert!(bad.parse::<VersionRange>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn components_at_their_maximum_dont_overflow() {
+        let max = u64::MAX;
+        let version = |major, minor, patch| alloc::format!("{major}.{minor}.{patch}");
+
+        assert!(range(&version(0, 3, max)).accepts(&version(0, 3, max)));
+        assert!(range(&alloc::format!("<={}", version(0, 3, max))).accepts(&version(0, 3, max)));
+        assert!(!range(&alloc::format!("<={}", version(0, 3, max))).accepts("0.4.0"));
+        assert!(range(&alloc::format!(">{}", version(0, 3, max))).accepts("0.4.0"));
+        assert!(range(&alloc::format!("~{max}.{max}")).accepts(&version(max, max, max)));
+        assert!(range(&alloc::format!("^{max}")).accepts(&version(max, 0, 0)));
+        assert!(range(&alloc::format!("={max}")).accepts(&version(max, max, max)));
+        assert!(VersionSpec::Exact(vec![version(max, max, max)]).accepts(&version(max, max, max)));
+
+        // Nothing is higher than the highest version
+        assert!(
+            !range(&alloc::format!(">{}", version(max, max, max))).accepts(&version(max, max, max))
+        );
+        assert!(!range(&alloc::format!(">{max}")).accepts("0.0.0"));
+    }
+
+    #[test]
+    fn current_major_is_caret_of_the_crate_version() {
+        assert_eq!(
+            VersionSpec::CurrentMajor.accepts("0.3.5"),
+            range(&alloc::format!("^{PROVENANCE_VERSION}")).accepts("0.3.5")
+        );
+        assert!(VersionSpec::CurrentMajor.accepts(PROVENANCE_VERSION));
+    }
+}
@@ -0,0 +1,312 @@
+//! Signing documents with a WebAuthn passkey instead of an exportable ed25519 key.
+//!
+//! Passkeys never reveal their private key, so they can't be used with [`crate::sign`]. Instead:
+//!
+//! 1. Compute the [`challenge`] for the document and the signer's provenance URL.
+//! 2. Ask the passkey to sign the challenge, for example with `navigator.credentials.get()` in a
+//!    browser. The passkey returns an [`Assertion`].
+//! 3. [`attach`] the assertion to the document. The assertion takes the place of the signature in
+//!    the provenance header, and the header is marked with `alg=webauthn-es256`.
+//!
+//! To verify such a layer, the signer's provenance server must advertise the passkey's public key
+//! (as `webauthn_key_b64`) alongside their usual verification key. Only ES256 passkeys (ECDSA on
+//! P-256) are supported, and the assertion must show that the user was present. The relying party
+//! and origin aren't checked: the passkey's public key being served at the layer's URL is what
+//! ties the passkey to the signer.
+
+use crate::offline::{
+    signed_message_with_extensions, ParsedHeader, ALGORITHM_EXTENSION, PROVENANCE_VERSION,
+};
+use crate::provenance::format_header_with_extensions;
+use anyhow::anyhow;
+use base64::{
+    engine::general_purpose::{URL_SAFE, URL_SAFE_NO_PAD},
+    Engine as _,
+};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// The value of the `alg` header extension for layers signed with a passkey.
+pub const ALGORITHM: &str = "webauthn-es256";
+
+/// The "user present" bit in the flags of the authenticator data.
+const USER_PRESENT: u8 = 0x01;
+
+/// The parts of a WebAuthn assertion which are needed to check its signature, as returned by the
+/// passkey (see `AuthenticatorAssertionResponse` in the WebAuthn spec).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assertion {
+    pub authenticator_data: Vec<u8>,
+    pub client_data_json: Vec<u8>,
+    /// The DER-encoded ECDSA signature over the authenticator data and the hash of the client
+    /// data.
+    pub signature: Vec<u8>,
+}
+
+impl Assertion {
+    /// Encode the assertion as a single header token: its three parts in base64, separated by
+    /// `.`.
+    pub fn to_token(&self) -> String {
+        [
+            &self.authenticator_data,
+            &self.client_data_json,
+            &self.signature,
+        ]
+        .map(|part| URL_SAFE.encode(part))
+        .join(".")
+    }
+
+    /// Decode an assertion which was encoded with [`Assertion::to_token`].
+    pub fn from_token(token: &str) -> anyhow::Result<Self> {
+        let parts = token
+            .split('.')
+            .map(|part| URL_SAFE.decode(part))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("Couldn't decode WebAuthn assertion '{token}': {e}"))?;
+        let Ok([authenticator_data, client_data_json, signature]) = <[Vec<u8>; 3]>::try_from(parts)
+        else {
+            return Err(anyhow!(
+                "WebAuthn assertion '{token}' doesn't have three parts"
+            ));
+        };
+
+        Ok(Assertion {
+            authenticator_data,
+            client_data_json,
+            signature,
+        })
+    }
+}
+
+/// The challenge a passkey must sign to give `doc` provenance from `url`.
+pub fn challenge(doc: &[u8], url: &str) -> [u8; 32] {
+    challenge_for(PROVENANCE_VERSION, url, &extensions(), doc)
+}
+
+/// Add a provenance layer to `doc`, using an assertion over [`challenge`] from the passkey whose
+/// public key is served at `url`.
+pub fn attach(doc: &str, url: &str, assertion: &Assertion) -> String {
+    format!(
+        "{}\n{doc}",
+        format_header_with_extensions(url, &assertion.to_token(), &extensions())
+    )
+}
+
+fn extensions() -> String {
+    format!("{ALGORITHM_EXTENSION}={ALGORITHM}")
+}
+
+fn challenge_for(version: &str, url: &str, extensions: &str, body: &[u8]) -> [u8; 32] {
+    Sha256::digest(signed_message_with_extensions(
+        version, url, extensions, body,
+    ))
+    .into()
+}
+
+/// Check the assertion in a layer's `header` was made over `body` by the passkey with the
+/// (SEC1-encoded, base64) public key `public_key_b64`.
+pub(crate) fn verify_layer(
+    header: &ParsedHeader<'_>,
+    body: &[u8],
+    public_key_b64: &str,
+) -> anyhow::Result<()> {
+    let assertion = Assertion::from_token(header.signature_b64)?;
+    let Ok(public_key) = URL_SAFE.decode(public_key_b64) else {
+        return Err(anyhow!(
+            "Couldn't decode passkey public key '{public_key_b64}'"
+        ));
+    };
+    let challenge = challenge_for(header.version, header.url, header.extensions, body);
+
+    verify_assertion(&assertion, &challenge, &public_key)
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+}
+
+/// Check that `assertion` is a signature over `challenge` by the passkey with the SEC1-encoded
+/// `public_key`, made while the user was present.
+pub fn verify_assertion(
+    assertion: &Assertion,
+    challenge: &[u8; 32],
+    public_key: &[u8],
+) -> anyhow::Result<()> {
+    let client_data: ClientData = serde_json::from_slice(&assertion.client_data_json)
+        .map_err(|e| anyhow!("Couldn't parse WebAuthn client data: {e}"))?;
+    if client_data.kind != "webauthn.get" {
+        return Err(anyhow!(
+            "WebAuthn client data has type '{}', not 'webauthn.get'",
+            client_data.kind
+        ));
+    }
+    if client_data.challenge != URL_SAFE_NO_PAD.encode(challenge) {
+        return Err(anyhow!(
+            "WebAuthn assertion was made for a different document"
+        ));
+    }
+
+    // The authenticator data is a 32 byte hash of the relying party ID, a byte of flags, and a
+    // 4 byte signature counter, followed by optional extensions
+    let Some(&flags) = assertion.authenticator_data.get(32) else {
+        return Err(anyhow!("WebAuthn authenticator data is too short"));
+    };
+    if flags & USER_PRESENT == 0 {
+        return Err(anyhow!(
+            "WebAuthn assertion was made without the user being present"
+        ));
+    }
+
+    let Ok(public_key) = VerifyingKey::from_sec1_bytes(public_key) else {
+        return Err(anyhow!("Passkey public key isn't a valid P-256 key"));
+    };
+    let Ok(signature) = Signature::from_der(&assertion.signature) else {
+        return Err(anyhow!("WebAuthn signature isn't a valid DER signature"));
+    };
+    let mut message = assertion.authenticator_data.clone();
+    message.extend_from_slice(&Sha256::digest(&assertion.client_data_json));
+    if public_key.verify(&message, &signature).is_err() {
+        return Err(anyhow!("WebAuthn signature could not be verified"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        request_new_key, sign_server_request, verify, verify_all, verify_with_signer_details,
+        Base64SigningKey, SignerDetailsFromServer,
+    };
+    use p256::ecdsa::{signature::Signer, SigningKey};
+
+    /// Do what a passkey does when asked to sign `challenge`.
+    fn authenticate(passkey: &SigningKey, challenge: &[u8; 32], flags: u8) -> Assertion {
+        let mut authenticator_data = Sha256::digest(b"example.com").to_vec();
+        authenticator_data.push(flags);
+        authenticator_data.extend_from_slice(&1u32.to_be_bytes());
+        let client_data_json = format!(
+            r#"{{"type":"webauthn.get","challenge":"{}","origin":"https://example.com"}}"#,
+            URL_SAFE_NO_PAD.encode(challenge)
+        )
+        .into_bytes();
+
+        let mut message = authenticator_data.clone();
+        message.extend_from_slice(&Sha256::digest(&client_data_json));
+        let signature: Signature = passkey.sign(&message);
+
+        Assertion {
+            authenticator_data,
+            client_data_json,
+            signature: signature.to_der().as_bytes().to_vec(),
+        }
+    }
+
+    fn passkey(seed: &str) -> SigningKey {
+        SigningKey::from_slice(&Sha256::digest(seed.as_bytes())).unwrap()
+    }
+
+    fn public_key(passkey: &SigningKey) -> Vec<u8> {
+        passkey
+            .verifying_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[test]
+    fn assertion_token_round_trips() {
+        let assertion = authenticate(&passkey("alice"), &[7; 32], USER_PRESENT);
+        assert_eq!(
+            Assertion::from_token(&assertion.to_token()).unwrap(),
+            assertion
+        );
+        assert!(Assertion::from_token("abc.def").is_err());
+    }
+
+    #[test]
+    fn verify_assertion_checks_everything() {
+        let alice = passkey("alice");
+        let challenge = challenge(b"document text here", "https://example.com/alice");
+        let assertion = authenticate(&alice, &challenge, USER_PRESENT);
+
+        assert!(verify_assertion(&assertion, &challenge, &public_key(&alice)).is_ok());
+
+        // The wrong document, the wrong passkey, or no user present are all rejected
+        let other_challenge = challenge_for(PROVENANCE_VERSION, "x", "", b"other");
+        assert!(verify_assertion(&assertion, &other_challenge, &public_key(&alice)).is_err());
+        assert!(verify_assertion(&assertion, &challenge, &public_key(&passkey("bob"))).is_err());
+        let absent = authenticate(&alice, &challenge, 0);
+        assert!(verify_assertion(&absent, &challenge, &public_key(&alice)).is_err());
+
+        let mut tampered = assertion.clone();
+        tampered.client_data_json = String::from_utf8(tampered.client_data_json)
+            .unwrap()
+            .replace("webauthn.get", "webauthn.create")
+            .into_bytes();
+        assert!(verify_assertion(&tampered, &challenge, &public_key(&alice)).is_err());
+    }
+
+    #[test]
+    fn passkey_layers_verify_against_the_server() {
+        let client = reqwest::blocking::Client::new();
+        let username = format!("passkey_{}", rand::random::<u32>());
+        let key_details = request_new_key("http://localhost:8000", &username, &client).unwrap();
+        let signing_key: ed25519_dalek::SigningKey =
+            Base64SigningKey(key_details.signing).try_into().unwrap();
+        let alice = passkey(&username);
+        let path = format!(
+            "/register_passkey/{username}/{}",
+            URL_SAFE.encode(public_key(&alice))
+        );
+        let response = client
+            .post(format!("http://localhost:8000{path}"))
+            .header(
+                "Authorization",
+                sign_server_request("POST", &path, "", &signing_key),
+            )
+            .send()
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let url = format!("http://localhost:8000/provenance/{username}");
+        let doc = "document text here";
+        let assertion = authenticate(&alice, &challenge(doc.as_bytes(), &url), USER_PRESENT);
+        let signed_doc = attach(doc, &url, &assertion);
+
+        let (result, remainder) = verify(&signed_doc);
+        assert_eq!(result.unwrap().verification_url, url);
+        assert_eq!(remainder, doc);
+        assert!(verify_all(&signed_doc)
+            .0
+            .iter()
+            .all(|result| result.is_ok()));
+
+        assert!(verify(&signed_doc.replace("document", "tampered"))
+            .0
+            .is_err());
+        // beyarkay has no passkey, so a passkey layer claiming to be from them can't verify
+        let beyarkay = "http://localhost:8000/provenance/beyarkay";
+        let assertion = authenticate(&alice, &challenge(doc.as_bytes(), beyarkay), USER_PRESENT);
+        assert!(verify(&attach(doc, beyarkay, &assertion)).0.is_err());
+
+        // Passkey layers can't claim to be signed on behalf of a group, since nothing would
+        // check the membership
+        let extensions = format!("{} member=employee-42", extensions());
+        let challenge = challenge_for(PROVENANCE_VERSION, &url, &extensions, doc.as_bytes());
+        let assertion = authenticate(&alice, &challenge, USER_PRESENT);
+        let header = format_header_with_extensions(&url, &assertion.to_token(), &extensions);
+        let as_member = format!("{header}\n{doc}");
+        assert!(verify(&as_member).0.is_err());
+        let signer_details: SignerDetailsFromServer =
+            client.get(&url).send().unwrap().json().unwrap();
+        assert!(verify_with_signer_details(&as_member, &signer_details)
+            .0
+            .is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use crate::{MemberId, Username};
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::Mutex,
+};
+
+/// A failure of the storage behind a [`KeyStore`], like a lost database connection.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct StorageError(pub String);
+
+/// Where the server keeps each user's signing key, along with their passkey and (for groups)
+/// members.
+///
+/// The server only talks to its keys through this trait, so that a deployment can keep them in
+/// Redis, Postgres, sled or anywhere else by implementing it and passing it to
+/// [`build_with_key_store`](crate::build_with_key_store). [`InMemoryKeyStore`] is used by
+/// default, and forgets everything when the server stops.
+pub trait KeyStore: Send + Sync {
+    /// The signing key of `username`, if they have one.
+    fn get(&self, username: &Username) -> Result<Option<SigningKey>, StorageError>;
+
+    /// Store `signing_key` and `metadata` for `username`, unless they already have a key.
+    /// Returns whether the key was stored. The check and the insert must happen atomically, so
+    /// that two requests can't both create a key for the same user.
+    fn insert(
+        &self,
+        username: Username,
+        signing_key: SigningKey,
+        metadata: HashMap<String, String>,
+    ) -> Result<bool, StorageError>;
+
+    /// The metadata `username` gave when their key was made, empty if they gave none (or don't
+    /// exist).
+    fn metadata(&self, username: &Username) -> Result<HashMap<String, String>, StorageError>;
+
+    /// Store every key in `entries` (whose usernames are all different) with no metadata, unless
+    /// some of the usernames already have a key. Returns those usernames, in which case nothing
+    /// was stored.
+    ///
+    /// The default implementation checks every username before inserting any key, so a key
+    /// made for one of the usernames in between can leave the import partly done, with the
+    /// clashing username returned. Stores which can should make the whole import atomic.
+    fn insert_all(
+        &self,
+        entries: Vec<(Username, SigningKey)>,
+    ) -> Result<Vec<Username>, StorageError> {
+        let mut existing = vec![];
+        for (username, _) in &entries {
+            if self.contains(username)? {
+                existing.push(username.clone());
+            }
+        }
+        if !existing.is_empty() {
+            return Ok(existing);
+        }
+        for (username, signing_key) in entries {
+            if !self.insert(username.clone(), signing_key, HashMap::new())? {
+                existing.push(username);
+            }
+        }
+        Ok(existing)
+    }
+
+    /// Whether `username` has a key.
+    fn contains(&self, username: &Username) -> Result<bool, StorageError> {
+        Ok(self.get(username)?.is_some())
+    }
+
+    /// The user whose verification key has the SHA-256 hash `fingerprint`, if there is one.
+    fn find_by_fingerprint(&self, fingerprint: &[u8; 32])
+        -> Result<Option<Username>, StorageError>;
+
+    /// The number of users with a key, including deactivated ones.
+    fn len(&self) -> Result<usize, StorageError>;
+
+    /// Mark `username` as deactivated because of `reason`, keeping their key. Returns false if
+    /// they don't have a key. Deactivating a user again replaces the reason.
+    fn deactivate(&self, username: &Username, reason: String) -> Result<bool, StorageError>;
+
+    /// Why `username` was deactivated, or `None` if they're active (or don't exist).
+    fn deactivation_reason(&self, username: &Username) -> Result<Option<String>, StorageError>;
+
+    /// The base64 SEC1-encoded public key of the passkey `username` registered, if any.
+    fn passkey(&self, username: &Username) -> Result<Option<String>, StorageError>;
+
+    /// Store `public_key_b64` as the passkey of `username`, unless they already have one.
+    /// Returns whether it was stored. Like [`KeyStore::insert`], this must be atomic.
+    fn insert_passkey(
+        &self,
+        username: &Username,
+        public_key_b64: String,
+    ) -> Result<bool, StorageError>;
+
+    /// Add `member_id` to the members of the group `group`. Adding a member twice is fine.
+    fn add_member(&self, group: &Username, member_id: MemberId) -> Result<(), StorageError>;
+
+    /// Whether `member_id` is a member of the group `group`.
+    fn is_member(&self, group: &Username, member_id: &MemberId) -> Result<bool, StorageError>;
+}
+
+/// Keeps keys in a map in memory.
+#[derive(Default)]
+pub struct InMemoryKeyStore {
+    keys: Mutex<HashMap<Username, SigningKey>>,
+    /// The metadata each user gave when their key was made.
+    metadata: Mutex<HashMap<Username, HashMap<String, String>>>,
+    /// The reason each deactivated user was deactivated.
+    deactivated: Mutex<HashMap<Username, String>>,
+    /// Base64 SEC1-encoded public keys of the passkeys users have registered.
+    passkeys: Mutex<HashMap<Username, String>>,
+    /// The members of each group. A group is an ordinary user whose key is shared by its members.
+    members: Mutex<HashMap<Username, HashSet<MemberId>>>,
+}
+
+impl KeyStore for InMemoryKeyStore {
+    fn get(&self, username: &Username) -> Result<Option<SigningKey>, StorageError> {
+        Ok(self.keys.lock().unwrap().get(username).cloned())
+    }
+
+    fn insert(
+        &self,
+        username: Username,
+        signing_key: SigningKey,
+        metadata: HashMap<String, String>,
+    ) -> Result<bool, StorageError> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.contains_key(&username) {
+            return Ok(false);
+        }
+        self.metadata
+            .lock()
+            .unwrap()
+            .insert(username.clone(), metadata);
+        keys.insert(username, signing_key);
+        Ok(true)
+    }
+
+    fn insert_all(
+        &self,
+        entries: Vec<(Username, SigningKey)>,
+    ) -> Result<Vec<Username>, StorageError> {
+        let mut keys = self.keys.lock().unwrap();
+        let existing: Vec<Username> = entries
+            .iter()
+            .map(|(username, _)| username)
+            .filter(|username| keys.contains_key(username))
+            .cloned()
+            .collect();
+        if existing.is_empty() {
+            keys.extend(entries);
+        }
+        Ok(existing)
+    }
+
+    fn metadata(&self, username: &Username) -> Result<HashMap<String, String>, StorageError> {
+        let metadata = self.metadata.lock().unwrap().get(username).cloned();
+        Ok(metadata.unwrap_or_default())
+    }
+
+    fn contains(&self, username: &Username) -> Result<bool, StorageError> {
+        Ok(self.keys.lock().unwrap().contains_key(username))
+    }
+
+    fn find_by_fingerprint(
+        &self,
+        fingerprint: &[u8; 32],
+    ) -> Result<Option<Username>, StorageError> {
+        let keys = self.keys.lock().unwrap();
+        let username = keys.iter().find_map(|(username, signing_key)| {
+            let hash = Sha256::digest(signing_key.verifying_key().as_bytes());
+            (hash.as_slice() == fingerprint).then(|| username.clone())
+        });
+        Ok(username)
+    }
+
+    fn len(&self) -> Result<usize, StorageError> {
+        Ok(self.keys.lock().unwrap().len())
+    }
+
+    fn deactivate(&self, username: &Username, reason: String) -> Result<bool, StorageError> {
+        if !self.contains(username)? {
+            return Ok(false);
+        }
+        self.deactivated
+            .lock()
+            .unwrap()
+            .insert(username.clone(), reason);
+        Ok(true)
+    }
+
+    fn deactivation_reason(&self, username: &Username) -> Result<Option<String>, StorageError> {
+        Ok(self.deactivated.lock().unwrap().get(username).cloned())
+    }
+
+    fn passkey(&self, username: &Username) -> Result<Option<String>, StorageError> {
+        Ok(self.passkeys.lock().unwrap().get(username).cloned())
+    }
+
+    fn insert_passkey(
+        &self,
+        username: &Username,
+        public_key_b64: String,
+    ) -> Result<bool, StorageError> {
+        match self.passkeys.lock().unwrap().entry(username.clone()) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert(public_key_b64);
+                Ok(true)
+            }
+        }
+    }
+
+    fn add_member(&self, group: &Username, member_id: MemberId) -> Result<(), StorageError> {
+        self.members
+            .lock()
+            .unwrap()
+            .entry(group.clone())
+            .or_default()
+            .insert(member_id);
+        Ok(())
+    }
+
+    fn is_member(&self, group: &Username, member_id: &MemberId) -> Result<bool, StorageError> {
+        let members = self.members.lock().unwrap();
+        Ok(members
+            .get(group)
+            .is_some_and(|members| members.contains(member_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(name: &str) -> Username {
+        Username(name.to_string())
+    }
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    /// A store behind the trait object the server uses, so only the trait's methods are tested.
+    fn store() -> Box<dyn KeyStore> {
+        Box::new(InMemoryKeyStore::default())
+    }
+
+    #[test]
+    fn keys_are_only_inserted_once() {
+        let keys = store();
+        let metadata = HashMap::from([("email".to_string(), "a@example.com".to_string())]);
+
+        assert!(keys
+            .insert(user("alice"), key(1), metadata.clone())
+            .unwrap());
+        assert!(!keys.insert(user("alice"), key(2), HashMap::new()).unwrap());
+        assert_eq!(keys.get(&user("alice")).unwrap(), Some(key(1)));
+        assert_eq!(keys.metadata(&user("alice")).unwrap(), metadata);
+        assert!(keys.contains(&user("alice")).unwrap());
+        assert!(!keys.contains(&user("bob")).unwrap());
+        assert_eq!(keys.get(&user("bob")).unwrap(), None);
+        assert!(keys.metadata(&user("bob")).unwrap().is_empty());
+        assert_eq!(keys.len().unwrap(), 1);
+    }
+
+    #[test]
+    fn imports_are_all_or_nothing() {
+        let keys = store();
+        keys.insert(user("alice"), key(1), HashMap::new()).unwrap();
+
+        let clashing = vec![(user("bob"), key(2)), (user("alice"), key(3))];
+        assert_eq!(keys.insert_all(clashing).unwrap(), [user("alice")]);
+        assert!(!keys.contains(&user("bob")).unwrap());
+
+        let fresh = vec![(user("bob"), key(2)), (user("carol"), key(3))];
+        assert!(keys.insert_all(fresh).unwrap().is_empty());
+        assert_eq!(keys.len().unwrap(), 3);
+    }
+
+    #[test]
+    fn users_are_found_by_fingerprint() {
+        let keys = store();
+        keys.insert(user("alice"), key(1), HashMap::new()).unwrap();
+        let fingerprint = Sha256::digest(key(1).verifying_key().as_bytes()).into();
+
+        assert_eq!(
+            keys.find_by_fingerprint(&fingerprint).unwrap(),
+            Some(user("alice"))
+        );
+        assert_eq!(keys.find_by_fingerprint(&[0; 32]).unwrap(), None);
+    }
+
+    #[test]
+    fn deactivated_users_keep_their_key() {
+        let keys = store();
+        keys.insert(user("alice"), key(1), HashMap::new()).unwrap();
+
+        assert!(!keys.deactivate(&user("bob"), "lost".to_string()).unwrap());
+        assert_eq!(keys.deactivation_reason(&user("alice")).unwrap(), None);
+        assert!(keys.deactivate(&user("alice"), "lost".to_string()).unwrap());
+        assert!(keys
+            .deactivate(&user("alice"), "stolen".to_string())
+            .unwrap());
+        assert_eq!(
+            keys.deactivation_reason(&user("alice")).unwrap().as_deref(),
+            Some("stolen")
+        );
+        assert_eq!(keys.get(&user("alice")).unwrap(), Some(key(1)));
+    }
+
+    #[test]
+    fn passkeys_are_only_registered_once() {
+        let keys = store();
+
+        assert_eq!(keys.passkey(&user("alice")).unwrap(), None);
+        assert!(keys
+            .insert_passkey(&user("alice"), "first".to_string())
+            .unwrap());
+        assert!(!keys
+            .insert_passkey(&user("alice"), "second".to_string())
+            .unwrap());
+        assert_eq!(
+            keys.passkey(&user("alice")).unwrap().as_deref(),
+            Some("first")
+        );
+    }
+
+    #[test]
+    fn members_belong_to_one_group() {
+        let keys = store();
+        let mallory = MemberId("mallory".to_string());
+
+        assert!(!keys.is_member(&user("acme"), &mallory).unwrap());
+        keys.add_member(&user("acme"), mallory.clone()).unwrap();
+        keys.add_member(&user("acme"), mallory.clone()).unwrap();
+        assert!(keys.is_member(&user("acme"), &mallory).unwrap());
+        assert!(!keys.is_member(&user("globex"), &mallory).unwrap());
+    }
+}
@@ -1,26 +1,320 @@
 #[macro_use]
 extern crate rocket;
-use base64::{engine::general_purpose::URL_SAFE, Engine as _};
-use ed25519_dalek::SigningKey;
-use rocket::{request::FromParam, State};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE},
+    Engine as _,
+};
+use ed25519_dalek::{Signature, SigningKey, SECRET_KEY_LENGTH};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::request::{self, FromParam, FromRequest};
+use rocket::response::{self, status::Custom, Responder};
+use rocket::{figment::Figment, Build, Request, Rocket, State};
 use serde::Serialize;
-use std::{collections::HashMap, sync::Mutex};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 
 use rocket::serde::json::Json;
 
+mod key_store;
+use key_store::{InMemoryKeyStore, KeyStore, StorageError};
+
 #[derive(Eq, Hash, PartialEq, Debug, Clone)]
-struct Username(String);
+pub struct Username(pub String);
 
 impl<'r> FromParam<'r> for Username {
-    type Error = &'r str;
+    type Error = ApiError;
 
+    /// Usernames end up in URLs, so only allow characters which never need escaping.
     fn from_param(param: &'r str) -> Result<Self, Self::Error> {
+        if !is_valid_name(param) {
+            return Err(ApiError::InvalidUsername(param.to_string()));
+        }
         Ok(Username(param.to_string()))
     }
 }
 
+/// The ID of a member of a group, which a group signs documents on behalf of.
+#[derive(Eq, Hash, PartialEq, Debug, Clone)]
+pub struct MemberId(pub String);
+
+impl<'r> FromParam<'r> for MemberId {
+    type Error = ApiError;
+
+    /// Member IDs end up in URLs and provenance headers, so they're restricted like usernames.
+    fn from_param(param: &'r str) -> Result<Self, Self::Error> {
+        if !is_valid_name(param) {
+            return Err(ApiError::InvalidMemberId(param.to_string()));
+        }
+        Ok(MemberId(param.to_string()))
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let is_valid_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.';
+    !name.is_empty() && name.len() <= 64 && name.chars().all(is_valid_char)
+}
+
+/// Everything that can go wrong while handling a request. Each error is sent to the client with
+/// an appropriate status code and a JSON body like `{"error": "Username \"bob\" not found"}`.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("Username {0:?} not found")]
+    UsernameNotFound(String),
+    #[error("Username {0:?} already exists")]
+    UsernameExists(String),
+    #[error("Username {0:?} must be 1 to 64 letters, digits, '_', '-' or '.'")]
+    InvalidUsername(String),
+    #[error("Passkey public key {0:?} isn't a base64 SEC1-encoded P-256 key")]
+    InvalidPasskey(String),
+    #[error("Member ID {0:?} must be 1 to 64 letters, digits, '_', '-' or '.'")]
+    InvalidMemberId(String),
+    #[error("Fingerprint {0:?} must be the base64 SHA-256 hash of a verification key")]
+    InvalidFingerprint(String),
+    #[error("No user has a key with fingerprint {0:?}")]
+    FingerprintNotFound(String),
+    #[error("{member:?} isn't a member of the group {group:?}")]
+    MemberNotFound { group: String, member: String },
+    #[error("Key requests must include the metadata {}", .0.join(", "))]
+    MissingMetadata(Vec<String>),
+    #[error("Metadata must be a JSON object of strings: {0}")]
+    InvalidMetadata(String),
+    #[error("Admin requests are disabled, since no `admin_token` is configured")]
+    AdminDisabled,
+    #[error("Admin requests need the admin token, as `Authorization: Bearer <token>`")]
+    NotAdmin,
+    #[error(
+        "Requests changing {0:?} must be signed with their signing key, or carry the admin token"
+    )]
+    NotAuthorized(String),
+    #[error("Username {0:?} already has a passkey")]
+    PasskeyExists(String),
+    #[error("Import rejected: {}", .0.join("; "))]
+    InvalidImport(Vec<String>),
+    #[error("Import rejected, since these usernames already exist: {}", .0.join(", "))]
+    UsernamesExist(Vec<String>),
+    #[error("Username {username:?} has been deactivated: {reason}")]
+    UserDeactivated { username: String, reason: String },
+    #[error("Key store failed: {0}")]
+    Storage(#[from] StorageError),
+}
+
+impl ApiError {
+    fn status(&self) -> Status {
+        match self {
+            ApiError::UsernameNotFound(_)
+            | ApiError::FingerprintNotFound(_)
+            | ApiError::MemberNotFound { .. } => Status::NotFound,
+            ApiError::UsernameExists(_)
+            | ApiError::UsernamesExist(_)
+            | ApiError::PasskeyExists(_) => Status::Conflict,
+            ApiError::AdminDisabled => Status::Forbidden,
+            ApiError::NotAdmin | ApiError::NotAuthorized(_) => Status::Unauthorized,
+            ApiError::UserDeactivated { .. } => Status::Gone,
+            ApiError::InvalidUsername(_)
+            | ApiError::InvalidPasskey(_)
+            | ApiError::InvalidMemberId(_)
+            | ApiError::InvalidFingerprint(_)
+            | ApiError::MissingMetadata(_)
+            | ApiError::InvalidMetadata(_)
+            | ApiError::InvalidImport(_) => Status::BadRequest,
+            ApiError::Storage(_) => Status::InternalServerError,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub error: String,
+    /// Why the user was deactivated, for 410 responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The required metadata fields which were missing, for rejected key requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing: Option<Vec<String>>,
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        let reason = match &self {
+            ApiError::UserDeactivated { reason, .. } => Some(reason.clone()),
+            _ => None,
+        };
+        let missing = match &self {
+            ApiError::MissingMetadata(missing) => Some(missing.clone()),
+            _ => None,
+        };
+        Custom(
+            status,
+            Json(ErrorBody {
+                error: self.to_string(),
+                reason,
+                missing,
+            }),
+        )
+        .respond_to(request)
+    }
+}
+
 struct AppState {
-    db: Mutex<HashMap<Username, SigningKey>>,
+    keys: Box<dyn KeyStore>,
+    /// The metadata users must give before they're issued a key.
+    required_metadata: Vec<RequiredField>,
+    /// The names of the stored metadata fields which anyone may see in a user's details. Other
+    /// fields (like an email address) are kept private.
+    public_metadata: Vec<String>,
+    /// The token which admin requests must carry, or `None` to refuse every admin request.
+    admin_token: Option<String>,
+}
+
+/// A request carrying the server's admin token, as `Authorization: Bearer <token>`.
+struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let given = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match check_admin_token(request, given) {
+            Ok(()) => request::Outcome::Success(Admin),
+            Err(e) => request::Outcome::Error((e.status(), e)),
+        }
+    }
+}
+
+/// Check `given` is the server's admin token.
+fn check_admin_token(request: &Request<'_>, given: Option<&str>) -> Result<(), ApiError> {
+    let admin_token = request
+        .rocket()
+        .state::<AppState>()
+        .and_then(|state| state.admin_token.as_deref());
+    let Some(admin_token) = admin_token else {
+        return Err(ApiError::AdminDisabled);
+    };
+    // Compare hashes, so the time taken doesn't depend on how much of the token matched
+    if given.is_some_and(|given| Sha256::digest(given) == Sha256::digest(admin_token)) {
+        Ok(())
+    } else {
+        Err(ApiError::NotAdmin)
+    }
+}
+
+/// What every signed request starts with, so that a signed request can never be mistaken for a
+/// signed document (or the other way around).
+const REQUEST_SIGNATURE_PREFIX: &str = "provenance-server request\n";
+
+/// The authority behind a request which changes a user's state: either the admin token (see
+/// [`Admin`]), or a signature made with the user's own signing key, as
+/// `Authorization: Signature <base64 signature>`.
+///
+/// The signature is over [`REQUEST_SIGNATURE_PREFIX`], then the method and path (like
+/// `POST /deactivate/alice`), a newline, and the request body. Anyone who sees a signed request
+/// can only replay that same request.
+enum Authority {
+    Admin,
+    Signed {
+        signature: Signature,
+        /// The method and path of the request, like `POST /deactivate/alice`.
+        request_line: String,
+    },
+    /// Neither the admin token nor a signature, so the request isn't authorized to change
+    /// anything.
+    Unauthenticated,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authority {
+    type Error = ApiError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let authorization = request.headers().get_one("Authorization");
+        if let Some(token) = authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+            return match check_admin_token(request, Some(token)) {
+                Ok(()) => request::Outcome::Success(Authority::Admin),
+                Err(e) => request::Outcome::Error((e.status(), e)),
+            };
+        }
+
+        let signature = authorization
+            .and_then(|value| value.strip_prefix("Signature "))
+            .and_then(|signature_b64| URL_SAFE.decode(signature_b64.trim()).ok())
+            .and_then(|bytes| Signature::from_slice(&bytes).ok());
+        match signature {
+            Some(signature) => request::Outcome::Success(Authority::Signed {
+                signature,
+                request_line: format!("{} {}", request.method(), request.uri().path()),
+            }),
+            // Which user the request needed signing by is only known to the route
+            None => request::Outcome::Success(Authority::Unauthenticated),
+        }
+    }
+}
+
+impl Authority {
+    /// Check that this request, with `body`, may change the state of `username`.
+    fn authorize(&self, username: &Username, body: &str, state: &AppState) -> Result<(), ApiError> {
+        let (signature, request_line) = match self {
+            Authority::Admin => return Ok(()),
+            Authority::Signed {
+                signature,
+                request_line,
+            } => (signature, request_line),
+            Authority::Unauthenticated => return Err(ApiError::NotAuthorized(username.0.clone())),
+        };
+        let Some(signing_key) = state.keys.get(username)? else {
+            return Err(ApiError::UsernameNotFound(username.0.clone()));
+        };
+        let message = format!("{REQUEST_SIGNATURE_PREFIX}{request_line}\n{body}");
+        signing_key
+            .verifying_key()
+            .verify_strict(message.as_bytes(), signature)
+            .map_err(|_| ApiError::NotAuthorized(username.0.clone()))
+    }
+}
+
+/// A metadata field which users must give when asking for a key, configured as either `name`
+/// (any non-blank value) or `name=value` (exactly that value, like `accepted_terms=true`).
+#[derive(Debug, Clone, PartialEq)]
+struct RequiredField {
+    name: String,
+    value: Option<String>,
+}
+
+impl RequiredField {
+    fn parse(spec: &str) -> RequiredField {
+        match spec.split_once('=') {
+            Some((name, value)) => RequiredField {
+                name: name.trim().to_string(),
+                value: Some(value.trim().to_string()),
+            },
+            None => RequiredField {
+                name: spec.trim().to_string(),
+                value: None,
+            },
+        }
+    }
+
+    fn is_satisfied_by(&self, metadata: &HashMap<String, String>) -> bool {
+        match (metadata.get(&self.name), &self.value) {
+            (Some(given), Some(value)) => given.trim() == value,
+            (Some(given), None) => !given.trim().is_empty(),
+            (None, _) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for RequiredField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={value}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
 }
 
 #[derive(Default, Debug, Serialize)]
@@ -34,21 +328,97 @@ pub struct SignerDetails {
     pub verification_url: String,
     pub verification_key_b64: String,
     pub metadata: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webauthn_key_b64: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Membership {
+    pub group: String,
+    pub member_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Deactivation {
+    pub username: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub imported: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub status: &'static str,
+    pub user_count: usize,
+    pub version: &'static str,
+}
+
+#[get("/health")]
+fn health(state: &State<AppState>) -> Result<Json<Health>, ApiError> {
+    let user_count = state.keys.len()?;
+
+    Ok(Json(Health {
+        status: "ok",
+        user_count,
+        version: env!("CARGO_PKG_VERSION"),
+    }))
 }
 
 #[get("/generate_key/<username>")]
-fn generate_key(username: Username, state: &State<AppState>) -> Result<Json<KeyDetails>, String> {
+fn generate_key(
+    username: Result<Username, ApiError>,
+    state: &State<AppState>,
+) -> Result<Json<KeyDetails>, ApiError> {
+    issue_key(username?, HashMap::new(), state)
+}
+
+/// Like `GET /generate_key/<username>`, with the metadata the server requires (see
+/// [`RequiredField`]) given as a JSON object of strings in the request body.
+#[post("/generate_key/<username>", data = "<metadata>")]
+fn generate_key_with_metadata(
+    username: Result<Username, ApiError>,
+    metadata: Result<Json<HashMap<String, String>>, rocket::serde::json::Error<'_>>,
+    state: &State<AppState>,
+) -> Result<Json<KeyDetails>, ApiError> {
+    let metadata = metadata.map_err(|e| ApiError::InvalidMetadata(e.to_string()))?;
+    issue_key(username?, metadata.into_inner(), state)
+}
+
+/// Make a key for `username`, if `metadata` has every field the server requires. Only the
+/// required fields are stored, and only those configured as public are sent to anyone who asks
+/// for the user's details.
+fn issue_key(
+    username: Username,
+    mut metadata: HashMap<String, String>,
+    state: &AppState,
+) -> Result<Json<KeyDetails>, ApiError> {
+    let missing: Vec<String> = state
+        .required_metadata
+        .iter()
+        .filter(|field| !field.is_satisfied_by(&metadata))
+        .map(|field| field.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(ApiError::MissingMetadata(missing));
+    }
+    metadata.retain(|name, _| {
+        state
+            .required_metadata
+            .iter()
+            .any(|field| field.name == *name)
+    });
+
     let mut csprng = rand::rngs::OsRng;
     let signing_key = SigningKey::generate(&mut csprng);
-    let user_exists = state.db.lock().unwrap().contains_key(&username);
-    if user_exists {
-        return Err(format!("Username {:?} already exists", username.0));
+    if !state
+        .keys
+        .insert(username.clone(), signing_key.clone(), metadata)?
+    {
+        return Err(ApiError::UsernameExists(username.0));
     }
-    state
-        .db
-        .lock()
-        .unwrap()
-        .insert(username, signing_key.clone());
 
     let verification_b64 = URL_SAFE.encode(signing_key.verifying_key().to_bytes());
     let signing_b64 = URL_SAFE.encode(signing_key.to_bytes());
@@ -60,28 +430,315 @@ fn generate_key(username: Username, state: &State<AppState>) -> Result<Json<KeyD
 }
 
 #[get("/provenance/<username>")]
-fn provenance(username: Username, state: &State<AppState>) -> Result<Json<SignerDetails>, String> {
+fn provenance(
+    username: Result<Username, ApiError>,
+    state: &State<AppState>,
+) -> Result<Json<SignerDetails>, ApiError> {
+    signer_details(username?, state)
+}
+
+/// Check whether `username` has a key, without sending their details. Like every response to a
+/// HEAD request, this has no body: the status says whether the user exists.
+#[head("/provenance/<username>")]
+fn provenance_exists(
+    username: Result<Username, ApiError>,
+    state: &State<AppState>,
+) -> Result<Status, ApiError> {
+    let username = username?;
+    if !state.keys.contains(&username)? {
+        return Err(ApiError::UsernameNotFound(username.0));
+    }
+    check_active(&username, state)?;
+    Ok(Status::Ok)
+}
+
+/// Deactivate `username`, for example because their signing key was compromised. Their key is
+/// kept, but verifiers are told they're deactivated (with a 410) instead of getting it. The
+/// request body, if any, is the reason given to verifiers.
+///
+/// Only an admin, or whoever holds the user's key, can deactivate them (see [`Authority`]).
+#[post("/deactivate/<username>", data = "<reason>")]
+fn deactivate(
+    username: Result<Username, ApiError>,
+    authority: Result<Authority, ApiError>,
+    reason: String,
+    state: &State<AppState>,
+) -> Result<Json<Deactivation>, ApiError> {
+    let username = username?;
+    authority?.authorize(&username, &reason, state)?;
+    let reason = match reason.trim() {
+        "" => "No reason given".to_string(),
+        reason => reason.to_string(),
+    };
+    if !state.keys.deactivate(&username, reason.clone())? {
+        return Err(ApiError::UsernameNotFound(username.0));
+    }
+
+    Ok(Json(Deactivation {
+        username: username.0,
+        reason,
+    }))
+}
+
+/// The largest CSV file `/import` accepts.
+const MAX_IMPORT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Import existing signing keys, for migrating users from another system without giving them new
+/// keys. Only admins can import keys.
+///
+/// The body is a CSV file with a `username,signing_key` row per user (optionally starting with
+/// that header row), where each signing key is base64. Either every row is imported or, if any
+/// row is invalid or any of the usernames already exists, none are.
+#[post("/import", data = "<csv>")]
+async fn import(
+    admin: Result<Admin, ApiError>,
+    csv: Data<'_>,
+    state: &State<AppState>,
+) -> Result<Json<ImportReport>, ApiError> {
+    admin?;
+    let csv = csv
+        .open(MAX_IMPORT_SIZE.bytes())
+        .into_string()
+        .await
+        .map_err(|e| ApiError::InvalidImport(vec![format!("Couldn't read the CSV: {e}")]))?;
+    if !csv.is_complete() {
+        return Err(ApiError::InvalidImport(vec![format!(
+            "CSV is larger than {MAX_IMPORT_SIZE} bytes"
+        )]));
+    }
+
+    let entries = parse_import(&csv)?;
+    let imported = entries.len();
+    let existing = state.keys.insert_all(entries)?;
+    if !existing.is_empty() {
+        return Err(ApiError::UsernamesExist(
+            existing.into_iter().map(|username| username.0).collect(),
+        ));
+    }
+
+    Ok(Json(ImportReport { imported }))
+}
+
+/// Parse the CSV sent to `/import`, failing with every invalid row if there are any.
+fn parse_import(csv: &str) -> Result<Vec<(Username, SigningKey)>, ApiError> {
+    let mut entries: Vec<(Username, SigningKey)> = vec![];
+    let mut problems = vec![];
+    for (index, line) in csv.lines().enumerate() {
+        let row = index + 1;
+        let line = line.trim();
+        if line.is_empty() || (row == 1 && line.eq_ignore_ascii_case("username,signing_key")) {
+            continue;
+        }
+        // Neither usernames nor base64 contain commas or quotes, so quoted fields are fine too
+        let fields: Vec<&str> = line
+            .split(',')
+            .map(|field| field.trim().trim_matches('"'))
+            .collect();
+        let [username, signing_key_b64] = fields[..] else {
+            problems.push(format!("row {row} isn't `username,signing_key`"));
+            continue;
+        };
+
+        if !is_valid_name(username) {
+            problems.push(format!(
+                "row {row}: {}",
+                ApiError::InvalidUsername(username.into())
+            ));
+            continue;
+        }
+        if entries.iter().any(|(existing, _)| existing.0 == username) {
+            problems.push(format!(
+                "row {row}: username {username:?} appears more than once"
+            ));
+            continue;
+        }
+        let signing_key = URL_SAFE
+            .decode(signing_key_b64)
+            .or_else(|_| STANDARD.decode(signing_key_b64))
+            .map_err(|_| "isn't base64".to_string())
+            .and_then(|bytes| {
+                <[u8; SECRET_KEY_LENGTH]>::try_from(bytes.as_slice())
+                    .map_err(|_| format!("is {} bytes, not {SECRET_KEY_LENGTH}", bytes.len()))
+            });
+        match signing_key {
+            Ok(bytes) => entries.push((
+                Username(username.to_string()),
+                SigningKey::from_bytes(&bytes),
+            )),
+            Err(reason) => problems.push(format!("row {row}: signing key {reason}")),
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(ApiError::InvalidImport(problems));
+    }
+    Ok(entries)
+}
+
+/// Fail with [`ApiError::UserDeactivated`] if `username` has been deactivated.
+fn check_active(username: &Username, state: &AppState) -> Result<(), ApiError> {
+    match state.keys.deactivation_reason(username)? {
+        Some(reason) => Err(ApiError::UserDeactivated {
+            username: username.0.clone(),
+            reason,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Find the user whose verification key has the given fingerprint (the base64 SHA-256 hash of
+/// the key's bytes), and send their details just like `/provenance/<username>`.
+#[get("/lookup/<fingerprint_b64>")]
+fn lookup(fingerprint_b64: &str, state: &State<AppState>) -> Result<Json<SignerDetails>, ApiError> {
+    let fingerprint: [u8; 32] = URL_SAFE
+        .decode(fingerprint_b64)
+        .ok()
+        .and_then(|fingerprint| fingerprint.try_into().ok())
+        .ok_or_else(|| ApiError::InvalidFingerprint(fingerprint_b64.to_string()))?;
+    let Some(username) = state.keys.find_by_fingerprint(&fingerprint)? else {
+        return Err(ApiError::FingerprintNotFound(fingerprint_b64.to_string()));
+    };
+
+    signer_details(username, state)
+}
+
+/// Register the public key of a passkey, so that documents signed with that passkey can be
+/// verified.
+///
+/// Only an admin, or whoever holds the user's key, can register a passkey for them (see
+/// [`Authority`]). A user's passkey can't be replaced.
+#[post("/register_passkey/<username>/<public_key_b64>")]
+fn register_passkey(
+    username: Result<Username, ApiError>,
+    public_key_b64: &str,
+    authority: Result<Authority, ApiError>,
+    state: &State<AppState>,
+) -> Result<Json<SignerDetails>, ApiError> {
+    let username = username?;
+    authority?.authorize(&username, "", state)?;
+    if !state.keys.contains(&username)? {
+        return Err(ApiError::UsernameNotFound(username.0));
+    }
+    check_active(&username, state)?;
+    // Uncompressed P-256 keys are 65 bytes, compressed ones are 33 bytes
+    let is_valid_key = URL_SAFE
+        .decode(public_key_b64)
+        .is_ok_and(|key| matches!((key.len(), key.first()), (65, Some(4)) | (33, Some(2 | 3))));
+    if !is_valid_key {
+        return Err(ApiError::InvalidPasskey(public_key_b64.to_string()));
+    }
+
+    if !state
+        .keys
+        .insert_passkey(&username, public_key_b64.to_string())?
+    {
+        return Err(ApiError::PasskeyExists(username.0));
+    }
+
+    signer_details(username, state)
+}
+
+/// Add `member_id` to the group `group`, so that documents the group signs on their behalf
+/// verify.
+///
+/// Only an admin, or whoever holds the group's key, can add members (see [`Authority`]).
+#[post("/add_member/<group>/<member_id>")]
+fn add_member(
+    group: Result<Username, ApiError>,
+    member_id: Result<MemberId, ApiError>,
+    authority: Result<Authority, ApiError>,
+    state: &State<AppState>,
+) -> Result<Json<Membership>, ApiError> {
+    let (group, member_id) = (group?, member_id?);
+    authority?.authorize(&group, "", state)?;
+    if !state.keys.contains(&group)? {
+        return Err(ApiError::UsernameNotFound(group.0));
+    }
+    check_active(&group, state)?;
+
+    state.keys.add_member(&group, member_id.clone())?;
+
+    Ok(Json(Membership {
+        group: group.0,
+        member_id: member_id.0,
+    }))
+}
+
+/// Check whether `member_id` is a member of `group`.
+#[get("/provenance/<group>/members/<member_id>")]
+fn member(
+    group: Result<Username, ApiError>,
+    member_id: Result<MemberId, ApiError>,
+    state: &State<AppState>,
+) -> Result<Json<Membership>, ApiError> {
+    let (group, member_id) = (group?, member_id?);
+    if !state.keys.is_member(&group, &member_id)? {
+        return Err(ApiError::MemberNotFound {
+            group: group.0,
+            member: member_id.0,
+        });
+    }
+
+    Ok(Json(Membership {
+        group: group.0,
+        member_id: member_id.0,
+    }))
+}
+
+/// Everything a verifier needs to know about `username`.
+fn signer_details(username: Username, state: &AppState) -> Result<Json<SignerDetails>, ApiError> {
     let base_url = "http://localhost:8000";
 
-    let binding = state.db.lock().unwrap();
-    let Some(signing_key) = binding.get(&username) else {
-        return Err(format!("Username {:?} not found", username.0));
+    let Some(signing_key) = state.keys.get(&username)? else {
+        return Err(ApiError::UsernameNotFound(username.0));
     };
+    check_active(&username, state)?;
     let verification_key_b64 = URL_SAFE.encode(signing_key.verifying_key().to_bytes());
 
-    let mut metadata: HashMap<String, String> = HashMap::new();
+    let mut metadata = state.keys.metadata(&username)?;
+    metadata.retain(|name, _| state.public_metadata.contains(name));
     metadata.insert("username".to_string(), username.clone().0);
 
     Ok(Json(SignerDetails {
-        verification_url: format!("{base_url}/{}/provenance", username.0),
+        verification_url: format!("{base_url}/provenance/{}", username.0),
         verification_key_b64,
         metadata,
+        webauthn_key_b64: state.keys.passkey(&username)?,
     }))
 }
 
-#[launch]
-fn rocket() -> _ {
-    let db = Mutex::new(HashMap::new());
+/// Build the server from its configuration, which is read by Rocket (from `Rocket.toml` or
+/// `ROCKET_` environment variables). Besides Rocket's own settings, `required_metadata` lists
+/// the fields users must give before they're issued a key, see [`RequiredField`],
+/// `public_metadata` names which of those fields are included in users' public details, and
+/// `admin_token` is the token admin requests (like `/import`) must carry.
+///
+/// Keys are kept in memory, see [`build_with_key_store`] to keep them somewhere else.
+fn build(figment: Figment) -> Rocket<Build> {
+    build_with_key_store(figment, Box::new(InMemoryKeyStore::default()))
+}
+
+/// Like [`build`], but keeping keys (and passkeys and group members) in `keys`.
+pub fn build_with_key_store(figment: Figment, keys: Box<dyn KeyStore>) -> Rocket<Build> {
+    let required_metadata = match figment.extract_inner::<Vec<String>>("required_metadata") {
+        Ok(specs) => specs
+            .iter()
+            .map(|spec| RequiredField::parse(spec))
+            .collect(),
+        Err(e) if e.missing() => vec![],
+        Err(e) => panic!("Invalid `required_metadata` configuration: {e}"),
+    };
+    let public_metadata = match figment.extract_inner::<Vec<String>>("public_metadata") {
+        Ok(names) => names,
+        Err(e) if e.missing() => vec![],
+        Err(e) => panic!("Invalid `public_metadata` configuration: {e}"),
+    };
+    let admin_token = match figment.extract_inner::<String>("admin_token") {
+        Ok(admin_token) if admin_token.is_empty() => None,
+        Ok(admin_token) => Some(admin_token),
+        Err(e) if e.missing() => None,
+        Err(e) => panic!("Invalid `admin_token` configuration: {e}"),
+    };
 
     // Keep a constant base64 signing key for the user beyarkay for testing purposes
     let base64_signing_key = "-5TaFC0xFOj_hf7mlvVaLKKpVFTaXUrLDzRqaaf7gFw=";
@@ -94,14 +751,495 @@ fn rocket() -> _ {
     let correct_length_slice = decoded_base64_slice.try_into().unwrap();
     // Convert the correct-length slice into a SigningKey
     let signing_key: SigningKey = SigningKey::from_bytes(correct_length_slice);
-    // Add the signing key to the DB
-    db.lock()
-        .unwrap()
-        .insert(Username("beyarkay".to_string()), signing_key.clone());
+    // Add the signing key to the key store, unless it already has one
+    keys.insert(
+        Username("beyarkay".to_string()),
+        signing_key.clone(),
+        HashMap::new(),
+    )
+    .expect("the key store should accept the test user");
+
+    let state = AppState {
+        keys,
+        required_metadata,
+        public_metadata,
+        admin_token,
+    };
+
+    rocket::custom(figment).manage(state).mount(
+        "/",
+        routes![
+            provenance,
+            provenance_exists,
+            generate_key,
+            generate_key_with_metadata,
+            register_passkey,
+            add_member,
+            member,
+            deactivate,
+            import,
+            lookup,
+            health
+        ],
+    )
+}
+
+#[launch]
+fn rocket() -> _ {
+    build(rocket::Config::figment())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{ContentType, Header};
+    use rocket::local::blocking::{Client, LocalResponse};
+    use rocket::serde::json::{json, serde_json, Value};
+
+    /// A client for a server which requires an email and acceptance of its terms, and only makes
+    /// the acceptance public.
+    fn client() -> Client {
+        let figment = rocket::Config::figment()
+            .merge(("required_metadata", ["email", "accepted_terms=true"]))
+            .merge(("public_metadata", ["accepted_terms"]));
+        Client::tracked(build(figment)).unwrap()
+    }
+
+    fn missing_fields(response: LocalResponse) -> Vec<String> {
+        assert_eq!(response.status(), Status::BadRequest);
+        let body: Value = response.into_json().unwrap();
+        serde_json::from_value(body["missing"].clone()).unwrap()
+    }
+
+    #[test]
+    fn keys_are_only_issued_with_the_required_metadata() {
+        let client = client();
+        let post = |username: &str, body: &str| {
+            client
+                .post(format!("/generate_key/{username}"))
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+        };
+
+        assert_eq!(
+            missing_fields(client.get("/generate_key/alice").dispatch()),
+            ["email", "accepted_terms=true"]
+        );
+        assert_eq!(
+            missing_fields(post("alice", r#"{"email": "alice@example.com"}"#)),
+            ["accepted_terms=true"]
+        );
+        assert_eq!(
+            missing_fields(post(
+                "alice",
+                r#"{"email": " ", "accepted_terms": "false"}"#
+            )),
+            ["email", "accepted_terms=true"]
+        );
+        assert_eq!(post("alice", "not json").status(), Status::BadRequest);
+        // Nothing was issued for any of the rejected requests
+        assert_eq!(
+            client.get("/provenance/alice").dispatch().status(),
+            Status::NotFound
+        );
+
+        let response = post(
+            "alice",
+            r#"{"email": "alice@example.com", "accepted_terms": "true", "shoe_size": "9"}"#,
+        );
+        assert_eq!(response.status(), Status::Ok);
+
+        // Only the required fields are kept, and only the public ones are served alongside the
+        // username
+        let state = client.rocket().state::<AppState>().unwrap();
+        assert_eq!(
+            state.keys.metadata(&Username("alice".to_string())).unwrap(),
+            HashMap::from([
+                ("email".to_string(), "alice@example.com".to_string()),
+                ("accepted_terms".to_string(), "true".to_string()),
+            ])
+        );
+        let details: Value = client
+            .get("/provenance/alice")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(
+            details["metadata"],
+            json!({
+                "username": "alice",
+                "accepted_terms": "true",
+            })
+        );
+    }
 
-    let state = AppState { db };
+    #[test]
+    fn nothing_is_required_by_default() {
+        let client = Client::tracked(build(rocket::Config::figment())).unwrap();
+        assert_eq!(
+            client.get("/generate_key/bob").dispatch().status(),
+            Status::Ok
+        );
+        let details: Value = client
+            .get("/provenance/bob")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(details["metadata"], json!({"username": "bob"}));
+    }
+
+    #[test]
+    fn head_requests_are_answered_without_a_body() {
+        let client = Client::tracked(build(rocket::Config::figment())).unwrap();
+
+        let response = client.head("/provenance/beyarkay").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_bytes().unwrap_or_default().is_empty());
+
+        let response = client.head("/provenance/nobody").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert!(response.into_bytes().unwrap_or_default().is_empty());
+    }
+
+    /// A client for a server whose admin token is `secret`.
+    fn admin_client() -> Client {
+        let figment = rocket::Config::figment().merge(("admin_token", "secret"));
+        Client::tracked(build(figment)).unwrap()
+    }
+
+    fn import<'c>(client: &'c Client, csv: &str) -> LocalResponse<'c> {
+        client
+            .post("/import")
+            .header(Header::new("Authorization", "Bearer secret"))
+            .body(csv)
+            .dispatch()
+    }
+
+    fn error(response: LocalResponse) -> String {
+        let body: Value = response.into_json().unwrap();
+        body["error"].as_str().unwrap().to_string()
+    }
+
+    fn key_b64(seed: u8) -> String {
+        URL_SAFE.encode(SigningKey::from_bytes(&[seed; 32]).to_bytes())
+    }
+
+    #[test]
+    fn only_admins_can_import_keys() {
+        let csv = format!("alice,{}", key_b64(1));
+        let client = Client::tracked(build(rocket::Config::figment())).unwrap();
+        assert_eq!(import(&client, &csv).status(), Status::Forbidden);
+
+        let client = admin_client();
+        let response = client.post("/import").body(&csv).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .post("/import")
+            .header(Header::new("Authorization", "Bearer guess"))
+            .body(&csv)
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(import(&client, &csv).status(), Status::Ok);
+    }
+
+    #[test]
+    fn valid_rows_are_imported_with_their_keys() {
+        let client = admin_client();
+        let csv = format!(
+            "username,signing_key\nalice,{}\n\n\"bob\",\"{}\"\n",
+            key_b64(1),
+            STANDARD.encode([2; 32])
+        );
+        let response = import(&client, &csv);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_json::<Value>().unwrap(),
+            json!({"imported": 2})
+        );
+
+        for (username, seed) in [("alice", 1), ("bob", 2)] {
+            let details: Value = client
+                .get(format!("/provenance/{username}"))
+                .dispatch()
+                .into_json()
+                .unwrap();
+            let verification_key = SigningKey::from_bytes(&[seed; 32]).verifying_key();
+            assert_eq!(
+                details["verification_key_b64"],
+                URL_SAFE.encode(verification_key.to_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_usernames_reject_the_whole_import() {
+        let client = admin_client();
+        let csv = format!(
+            "alice,{}\nbob,{}\nalice,{}",
+            key_b64(1),
+            key_b64(2),
+            key_b64(3)
+        );
+        let response = import(&client, &csv);
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(error(response).contains("row 3: username \"alice\" appears more than once"));
+
+        // Usernames which already have a key are rejected too, and nothing is imported
+        let csv = format!("carol,{}\nbeyarkay,{}", key_b64(4), key_b64(5));
+        let response = import(&client, &csv);
+        assert_eq!(response.status(), Status::Conflict);
+        assert!(error(response).contains("beyarkay"));
+        for username in ["alice", "bob", "carol"] {
+            let response = client.get(format!("/provenance/{username}")).dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+        }
+    }
+
+    /// Make a key for `username`, returning it.
+    fn new_user(client: &Client, username: &str) -> SigningKey {
+        let response = client.get(format!("/generate_key/{username}")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let key_b64 = response.into_json::<Value>().unwrap()["signing"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        SigningKey::from_bytes(&URL_SAFE.decode(key_b64).unwrap().try_into().unwrap())
+    }
 
-    rocket::build()
-        .manage(state)
-        .mount("/", routes![provenance, generate_key])
+    /// The `Authorization` header for the request `request_line` (like `POST /deactivate/bob`)
+    /// with `body`, signed by `signing_key`.
+    fn signed_by(signing_key: &SigningKey, request_line: &str, body: &str) -> Header<'static> {
+        use ed25519_dalek::Signer as _;
+        let message = format!("{REQUEST_SIGNATURE_PREFIX}{request_line}\n{body}");
+        let signature = signing_key.sign(message.as_bytes());
+        Header::new(
+            "Authorization",
+            format!("Signature {}", URL_SAFE.encode(signature.to_bytes())),
+        )
+    }
+
+    #[test]
+    fn passkeys_can_only_be_registered_by_the_user_or_an_admin() {
+        let client = admin_client();
+        let alice = new_user(&client, "alice");
+        let mallory = new_user(&client, "mallory");
+        let passkey = URL_SAFE.encode([4; 65]);
+        let path = format!("/register_passkey/alice/{passkey}");
+        let register = |authorization: Header<'static>| {
+            client.post(&path).header(authorization).dispatch().status()
+        };
+
+        assert_eq!(client.post(&path).dispatch().status(), Status::Unauthorized);
+        assert_eq!(
+            register(signed_by(&mallory, &format!("POST {path}"), "")),
+            Status::Unauthorized
+        );
+        // A signature is only good for the request it was made for
+        assert_eq!(
+            register(signed_by(&alice, "POST /register_passkey/alice/other", "")),
+            Status::Unauthorized
+        );
+        assert_eq!(client.get(&path).dispatch().status(), Status::NotFound);
+        let details: Value = client
+            .get("/provenance/alice")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(details.get("webauthn_key_b64"), None);
+
+        assert_eq!(
+            register(signed_by(&alice, &format!("POST {path}"), "")),
+            Status::Ok
+        );
+        let details: Value = client
+            .get("/provenance/alice")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(details["webauthn_key_b64"], passkey);
+
+        // An existing passkey is never replaced, not even by the user
+        let other_path = format!("/register_passkey/alice/{}", URL_SAFE.encode([3; 33]));
+        let response = client
+            .post(&other_path)
+            .header(signed_by(&alice, &format!("POST {other_path}"), ""))
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let details: Value = client
+            .get("/provenance/alice")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(details["webauthn_key_b64"], passkey);
+
+        // Admins can register passkeys for anyone
+        let path = format!("/register_passkey/mallory/{passkey}");
+        let response = client
+            .post(&path)
+            .header(Header::new("Authorization", "Bearer secret"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .post(format!("/register_passkey/nobody/{passkey}"))
+            .header(Header::new("Authorization", "Bearer secret"))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn users_can_only_be_deactivated_by_themselves_or_an_admin() {
+        let client = admin_client();
+        let alice = new_user(&client, "alice");
+        let mallory = new_user(&client, "mallory");
+        new_user(&client, "bob");
+        let deactivate = |username: &str, authorization: Option<Header<'static>>| {
+            let mut request = client
+                .post(format!("/deactivate/{username}"))
+                .body("Key leaked");
+            if let Some(authorization) = authorization {
+                request = request.header(authorization);
+            }
+            request.dispatch().status()
+        };
+
+        assert_eq!(deactivate("alice", None), Status::Unauthorized);
+        let request_line = "POST /deactivate/alice";
+        assert_eq!(
+            deactivate(
+                "alice",
+                Some(signed_by(&mallory, request_line, "Key leaked"))
+            ),
+            Status::Unauthorized
+        );
+        // The reason is signed too, so it can't be swapped for another
+        assert_eq!(
+            deactivate(
+                "alice",
+                Some(signed_by(&alice, request_line, "Other reason"))
+            ),
+            Status::Unauthorized
+        );
+        assert_eq!(
+            client.get("/provenance/alice").dispatch().status(),
+            Status::Ok
+        );
+
+        assert_eq!(
+            deactivate("alice", Some(signed_by(&alice, request_line, "Key leaked"))),
+            Status::Ok
+        );
+        let response = client.get("/provenance/alice").dispatch();
+        assert_eq!(response.status(), Status::Gone);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["reason"], "Key leaked");
+
+        let admin = Header::new("Authorization", "Bearer secret");
+        assert_eq!(deactivate("bob", Some(admin.clone())), Status::Ok);
+        assert_eq!(
+            client.get("/provenance/bob").dispatch().status(),
+            Status::Gone
+        );
+        assert_eq!(deactivate("nobody", Some(admin)), Status::NotFound);
+    }
+
+    #[test]
+    fn members_can_only_be_added_by_the_group_or_an_admin() {
+        let client = admin_client();
+        let acme = new_user(&client, "acme");
+        let mallory = new_user(&client, "mallory");
+        let path = "/add_member/acme/mallory";
+        let is_member = || {
+            client
+                .get("/provenance/acme/members/mallory")
+                .dispatch()
+                .status()
+        };
+
+        assert_eq!(client.post(path).dispatch().status(), Status::Unauthorized);
+        let response = client
+            .post(path)
+            .header(signed_by(&mallory, &format!("POST {path}"), ""))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(client.get(path).dispatch().status(), Status::NotFound);
+        assert_eq!(is_member(), Status::NotFound);
+
+        let response = client
+            .post(path)
+            .header(signed_by(&acme, &format!("POST {path}"), ""))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(is_member(), Status::Ok);
+
+        let response = client
+            .post("/add_member/acme/bob")
+            .header(Header::new("Authorization", "Bearer secret"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .post("/add_member/nobody/bob")
+            .header(Header::new("Authorization", "Bearer secret"))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn malformed_rows_reject_the_whole_import() {
+        let client = admin_client();
+        let csv = [
+            format!("alice,{}", key_b64(1)),
+            format!("bob,{}", URL_SAFE.encode([2; 31])),
+            "carol,not base64!".to_string(),
+            format!("dave,{},extra", key_b64(4)),
+            format!("e/ve,{}", key_b64(5)),
+        ]
+        .join("\n");
+        let response = import(&client, &csv);
+        assert_eq!(response.status(), Status::BadRequest);
+        let error = error(response);
+        assert!(
+            error.contains("row 2: signing key is 31 bytes, not 32"),
+            "{error}"
+        );
+        assert!(error.contains("row 3: signing key isn't base64"), "{error}");
+        assert!(
+            error.contains("row 4 isn't `username,signing_key`"),
+            "{error}"
+        );
+        assert!(error.contains("row 5: Username \"e/ve\""), "{error}");
+        assert!(!error.contains("row 1"), "{error}");
+
+        let response = client.get("/provenance/alice").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn keys_are_served_from_the_given_key_store() {
+        let keys = InMemoryKeyStore::default();
+        let alice = SigningKey::from_bytes(&[1; SECRET_KEY_LENGTH]);
+        keys.insert(Username("alice".to_string()), alice.clone(), HashMap::new())
+            .unwrap();
+        keys.insert_passkey(&Username("alice".to_string()), "passkey".to_string())
+            .unwrap();
+        let client = Client::tracked(build_with_key_store(
+            rocket::Config::figment(),
+            Box::new(keys),
+        ))
+        .unwrap();
+
+        let body: Value = client
+            .get("/provenance/alice")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(
+            body["verification_key_b64"],
+            URL_SAFE.encode(alice.verifying_key().to_bytes())
+        );
+        assert_eq!(body["webauthn_key_b64"], "passkey");
+        // The test user is added to the given store too
+        let response = client.get("/provenance/beyarkay").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
 }